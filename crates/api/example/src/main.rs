@@ -1,6 +1,6 @@
 use ggcat_api::{
-    ColoredQueryOutputFormat, ExtraElaboration, GGCATConfig, GGCATInstance,
-    GeneralSequenceBlockData,
+    ColoredQueryOutputFormat, ColorsCodec, ExtraElaboration, GGCATConfig, GGCATInstance,
+    GeneralSequenceBlockData, QueryOutputFilter, QueryOutputMode,
 };
 use itertools::Itertools;
 use std::{path::PathBuf, sync::Mutex};
@@ -55,15 +55,20 @@ fn main() {
             ],
             graph_file.clone(),
             Some(&["sal1".to_string(), "sal2".to_string(), "sal3".to_string()]),
+            None,
             k,
             threads_count,
             false,
             None,
             true,
+            ColorsCodec::RunLength,
             1,
             ExtraElaboration::UnitigLinks,
             None,
+            None,
             5,
+            false,
+            None,
         )
         .unwrap();
 
@@ -71,8 +76,8 @@ fn main() {
 
     let output_query = instance
         .query_graph(
-            graph_file.clone(),
-            input_query,
+            vec![graph_file.clone()],
+            vec![GeneralSequenceBlockData::FASTA((input_query, None))],
             PathBuf::from("/tmp/query-results"),
             k,
             threads_count,
@@ -80,6 +85,9 @@ fn main() {
             None,
             true,
             ColoredQueryOutputFormat::JsonLinesWithNames,
+            QueryOutputFilter::default(),
+            QueryOutputMode::MatchCounts,
+            None,
         )
         .unwrap();
 
@@ -98,6 +106,7 @@ fn main() {
             k,
             None,
             true,
+            None,
             threads_count,
             false,
             // WARNING: this function is called asynchronously from multiple threads, so it must be thread-safe.
@@ -121,7 +130,42 @@ fn main() {
         )
         .unwrap();
 
+    // The output function is called from a single thread, in the queries order
+    instance
+        .query_graph_with_callback(
+            vec![graph_file.clone()],
+            vec![GeneralSequenceBlockData::FASTA((
+                PathBuf::from("../../../example-inputs/query.fa"),
+                None,
+            ))],
+            k,
+            threads_count,
+            false,
+            None,
+            true,
+            QueryOutputFilter::default(),
+            None,
+            true,
+            |query_index, query_name, matches| {
+                println!(
+                    "Query {} '{}' matches: {}",
+                    query_index,
+                    query_name,
+                    matches
+                        .iter()
+                        .map(|(color, matched_kmers)| format!(
+                            "{}={}",
+                            color_names[*color as usize], matched_kmers
+                        ))
+                        .format(" ")
+                );
+            },
+        )
+        .unwrap();
+
     let colormap = GGCATInstance::get_colormap_file(&graph_file);
+    // Index the colormap to decode each queried subset in constant time
+    GGCATInstance::build_colormap_index(&colormap).unwrap();
     instance
         .query_colormap(colormap, vec![0, 1, 2, 3, 4], true, |subset, colors| {
             print!("Subset: {} has colors:", subset);
@@ -27,13 +27,21 @@ use std::sync::atomic::Ordering;
 use std::time::Duration;
 
 pub use crate::utils::HashType;
+pub use assembler::GraphCleaningParams;
+pub use colors::metadata::{ColorsMetadata, ColorsSelection};
+pub use colors::storage::inspect::ColormapInfo;
+pub use colors::storage::{ColormapBuildParams, ColorsCodec};
 pub use config::ColorIndexType;
+pub use dumper::{
+    ColorsEdit, ColorsEditStats, ColorsPredicate, ColorsSimilarity, GraphStats, LengthHistogramBin,
+};
 pub use io::sequences_reader::{DnaSequence, DnaSequencesFileType};
 pub use io::sequences_stream::{
     general::{DynamicSequencesStream, GeneralSequenceBlockData},
     SequenceInfo,
 };
-pub use querier::ColoredQueryOutputFormat;
+use querier::QueryResultsCallback;
+pub use querier::{ColoredQueryOutputFormat, QueryMatches, QueryOutputFilter, QueryOutputMode};
 
 pub mod debug {
     use crate::utils::HashType;
@@ -116,11 +124,17 @@ pub enum ExtraElaboration {
     FastSimplitigs,
     /// Generate fast eulertigs
     FastEulertigs,
+    /// Generate optimal matchtigs, requires the blossom5 binary
+    Matchtigs,
 }
 
 static INSTANCE: Mutex<Option<&'static GGCATInstance>> = Mutex::new(None);
 
-pub struct GGCATInstance(GGCATConfig);
+pub struct GGCATInstance(
+    GGCATConfig,
+    /// The blossom5 binary path, set by set_matchtigs_matcher_path
+    Mutex<Option<PathBuf>>,
+);
 
 fn create_tempdir(base_path: Option<PathBuf>) -> Option<PathBuf> {
     base_path.map(|t| {
@@ -130,6 +144,28 @@ fn create_tempdir(base_path: Option<PathBuf>) -> Option<PathBuf> {
     })
 }
 
+/// Creates a temporary directory whose name depends only on the output file, so that a crashed build can find its checkpoints.
+/// Fails if the directory already contains the checkpoints of a build that was not completed
+fn create_checkpoint_tempdir(base_path: &Path, output_file: &Path) -> anyhow::Result<PathBuf> {
+    let output_file =
+        std::path::absolute(output_file).unwrap_or_else(|_| output_file.to_path_buf());
+    let temp_dir = base_path.join(format!(
+        "build_graph_checkpoint_{:08x}",
+        crc32fast::hash(output_file.as_os_str().as_encoded_bytes())
+    ));
+    if temp_dir.join(assembler::CHECKPOINT_MANIFEST_NAME).exists() {
+        anyhow::bail!(
+            "The checkpoints of a previous build of {} are in {}, resume it with --resume or remove the directory to start a new build",
+            output_file.display(),
+            temp_dir.display()
+        );
+    }
+    // Start from a clean directory, removing the files left by a build that crashed before saving its first checkpoint
+    let _ = std::fs::remove_dir_all(&temp_dir);
+    let _ = create_dir_all(&temp_dir);
+    Ok(temp_dir)
+}
+
 fn remove_tempdir(temp_dir: Option<PathBuf>) {
     if let Some(temp_dir) = temp_dir {
         MemoryFs::remove_directory(&temp_dir, true);
@@ -202,10 +238,15 @@ impl GGCATInstance {
             max(1, config.total_threads_count / 4),
             8192,
         );
-        *instance = Some(Box::leak(Box::new(GGCATInstance(config))));
+        *instance = Some(Box::leak(Box::new(GGCATInstance(config, Mutex::new(None)))));
         return Ok(instance.unwrap());
     }
 
+    /// Sets the path to the blossom5 binary used to compute matchtigs, if not set it is searched in PATH
+    pub fn set_matchtigs_matcher_path(&self, matcher_path: Option<PathBuf>) {
+        *self.1.lock() = matcher_path;
+    }
+
     /// Builds a new graph from the given input streams, with the specified parameters
     pub fn build_graph(
         &self,
@@ -217,6 +258,8 @@ impl GGCATInstance {
 
         // The names of the colors, ordered by color index
         color_names: Option<&[String]>,
+        // The metadata of the colors, ordered by color index like the names
+        colors_metadata: Option<&ColorsMetadata>,
 
         // Specifies the k-mers length
         kmer_length: usize,
@@ -229,15 +272,25 @@ impl GGCATInstance {
 
         // Enable colors
         colors: bool,
+        // The encoding of the color subsets in the colormap
+        colors_codec: ColorsCodec,
 
         // Minimum multiplicity required to keep a kmer
         min_multiplicity: usize,
 
         extra_elab: ExtraElaboration,
 
+        // Remove short low coverage tips and simple bubbles, recompacting the resulting unitigs
+        graph_cleaning: Option<GraphCleaningParams>,
+
         gfa_output_version: Option<GfaVersion>,
 
         disk_optimization_level: u32,
+
+        // Save checkpoints of the completed phases in a stable temporary directory, to allow resuming the build
+        checkpoints: bool,
+        // Resume a crashed build from the checkpoints saved in this temporary directory
+        resume_dir: Option<PathBuf>,
     ) -> anyhow::Result<PathBuf> {
         PriorityScheduler::set_max_threads_count(threads_count);
 
@@ -263,7 +316,23 @@ impl GGCATInstance {
             Some(GfaVersion::V2) => GFAWriterWrapperV2::dynamic_dispatch_id(),
         };
 
-        let temp_dir = create_tempdir(self.0.temp_dir.clone());
+        let resume = resume_dir.is_some();
+        let temp_dir = if let Some(resume_dir) = resume_dir {
+            if !resume_dir.is_dir() {
+                anyhow::bail!(
+                    "Cannot resume the build, {} is not a directory",
+                    resume_dir.display()
+                );
+            }
+            Some(resume_dir)
+        } else if checkpoints {
+            match &self.0.temp_dir {
+                Some(base_path) => Some(create_checkpoint_tempdir(base_path, &output_file)?),
+                None => anyhow::bail!("Checkpoints require a temporary directory"),
+            }
+        } else {
+            create_tempdir(self.0.temp_dir.clone())
+        };
 
         let bucket_chunk_size = if disk_optimization_level == 0 {
             None
@@ -293,8 +362,12 @@ impl GGCATInstance {
             debug::DEBUG_ASSEMBLER_LAST_STEP.lock().clone(),
             input_streams,
             color_names.unwrap_or(&[]),
+            colors_metadata.unwrap_or(&ColorsMetadata::default()),
+            colors_codec,
             output_file,
             temp_dir.clone(),
+            checkpoints || resume,
+            resume,
             threads_count,
             min_multiplicity,
             *debug::BUCKETS_COUNT_LOG_FORCE.lock(),
@@ -307,24 +380,105 @@ impl GGCATInstance {
                 ExtraElaboration::Pathtigs => Some(assembler::MatchtigMode::PathTigs),
                 ExtraElaboration::FastSimplitigs => Some(assembler::MatchtigMode::FastSimpliTigs),
                 ExtraElaboration::FastEulertigs => Some(assembler::MatchtigMode::FastEulerTigs),
+                ExtraElaboration::Matchtigs => Some(assembler::MatchtigMode::MatchTigs),
                 _ => None,
             },
+            self.1.lock().clone(),
+            graph_cleaning,
             debug::DEBUG_ONLY_BSTATS.load(Ordering::Relaxed),
             bucket_chunk_size,
         )?;
 
-        remove_tempdir(temp_dir);
+        // Keep the checkpoints if the build was stopped before its completion
+        if !(checkpoints || resume) || output_file != PathBuf::new() {
+            remove_tempdir(temp_dir);
+        }
 
         Ok(output_file)
     }
 
+    /// Computes the statistics of an existing graph: unitigs count, total length, N50/NG50,
+    /// length histogram, kmers count, dead ends, branching nodes and color subsets count
+    pub fn compute_graph_stats(
+        // The input graph
+        input_graph: impl AsRef<Path>,
+        // Specifies the k-mers length
+        kmer_length: usize,
+        // Treats reverse complementary kmers as different
+        forward_only: bool,
+        // The expected genome size, used to compute the NG50
+        genome_size: Option<u64>,
+        // Directory for the temporary files of the unitigs ends
+        temp_dir: impl AsRef<Path>,
+    ) -> anyhow::Result<GraphStats> {
+        dumper::compute_graph_stats(
+            input_graph,
+            kmer_length,
+            forward_only,
+            genome_size,
+            temp_dir,
+        )
+    }
+
+    /// Renames, drops or keeps a subset of the colors of an existing colored graph, writing the edited graph
+    /// and its colormap. The kmers left without colors are removed and the unitigs are recompacted
+    pub fn edit_colors(
+        &self,
+        // The input graph
+        input_graph: impl AsRef<Path>,
+        // The edited graph, written with its colormap
+        output_graph: impl AsRef<Path>,
+        // Specifies the k-mers length
+        kmer_length: usize,
+        // Overrides the default m-mers (minimizers) length
+        minimizer_length: Option<usize>,
+        // Treats reverse complementary kmers as different
+        forward_only: bool,
+        // The threads to be used
+        threads_count: usize,
+        // The changes to the colors
+        edit: &ColorsEdit,
+    ) -> anyhow::Result<ColorsEditStats> {
+        PriorityScheduler::set_max_threads_count(threads_count);
+
+        let temp_dir = create_tempdir(self.0.temp_dir.clone());
+
+        let result = dumper::edit_colors(
+            kmer_length,
+            minimizer_length.unwrap_or(::utils::compute_best_m(kmer_length)),
+            input_graph,
+            output_graph,
+            temp_dir.clone(),
+            *debug::BUCKETS_COUNT_LOG_FORCE.lock(),
+            threads_count,
+            self.0.intermediate_compression_level,
+            forward_only,
+            edit,
+        );
+
+        remove_tempdir(temp_dir);
+        result
+    }
+
+    /// Computes the kmers shared by all the pairs of colors of a colored graph, to compare the colors
+    /// by jaccard similarity or containment
+    pub fn compute_colors_similarity(
+        // The input graph
+        input_graph: impl AsRef<Path>,
+        // Specifies the k-mers length
+        kmer_length: usize,
+    ) -> anyhow::Result<ColorsSimilarity> {
+        dumper::compute_colors_similarity(input_graph, kmer_length)
+    }
+
     /// Queries a (optionally) colored graph with a specific set of sequences as queries
     pub fn query_graph(
         &self,
-        // The input graph
-        input_graph: PathBuf,
-        // The input query as a .fasta file
-        input_query: PathBuf,
+        // The input graphs, built with the same k and hash settings. The queries are bucketed once
+        // and joined with each graph, numbering the colors in order across the graphs
+        input_graphs: Vec<PathBuf>,
+        // The input query streams, numbered in order across the streams
+        input_queries: Vec<GeneralSequenceBlockData>,
 
         // The output file
         output_file_prefix: PathBuf,
@@ -343,6 +497,15 @@ impl GGCATInstance {
 
         // Query output format
         color_output_format: ColoredQueryOutputFormat,
+
+        // Filters applied to the colors matched by each query in the colored output
+        output_filter: QueryOutputFilter,
+
+        // Information reported for each query
+        output_mode: QueryOutputMode,
+
+        // Directory of a query index built from the input graph, to avoid processing the graph again
+        query_index: Option<PathBuf>,
     ) -> anyhow::Result<PathBuf> {
         PriorityScheduler::set_max_threads_count(threads_count);
 
@@ -364,15 +527,20 @@ impl GGCATInstance {
             (merging_hash_dispatch, colors_hash),
             kmer_length,
             minimizer_length.unwrap_or(::utils::compute_best_m(kmer_length)),
+            forward_only,
             debug::DEBUG_QUERIER_FIRST_STEP.lock().clone(),
-            input_graph,
-            input_query,
+            input_graphs,
+            input_queries,
             output_file_prefix,
             temp_dir.clone(),
             *debug::BUCKETS_COUNT_LOG_FORCE.lock(),
             threads_count,
             self.0.intermediate_compression_level,
             color_output_format,
+            output_filter,
+            output_mode,
+            query_index,
+            None,
         )?;
 
         remove_tempdir(temp_dir);
@@ -380,11 +548,167 @@ impl GGCATInstance {
         Ok(output_file)
     }
 
+    /// Queries a (optionally) colored graph with a specific set of sequences as queries,
+    /// passing the matched kmers of each color to the output function instead of writing an output file
+    pub fn query_graph_with_callback(
+        &self,
+        // The input graphs, built with the same k and hash settings. The queries are bucketed once
+        // and joined with each graph, numbering the colors in order across the graphs
+        input_graphs: Vec<PathBuf>,
+        // The input query streams, numbered in order across the streams
+        input_queries: Vec<GeneralSequenceBlockData>,
+
+        // Specifies the k-mers length
+        kmer_length: usize,
+        // The threads to be used
+        threads_count: usize,
+        // Treats reverse complementary kmers as different
+        forward_only: bool,
+        // Overrides the default m-mers (minimizers) length
+        minimizer_length: Option<usize>,
+
+        // Enable colors
+        colors: bool,
+
+        // Filters applied to the colors matched by each query
+        output_filter: QueryOutputFilter,
+
+        // Directory of a query index built from the input graph, to avoid processing the graph again
+        query_index: Option<PathBuf>,
+
+        // Call the output function from a single thread at a time, in the queries order,
+        // avoiding the need for synchronization in the user code
+        single_thread_output_function: bool,
+        // Called with (query_index, query_name, [(color, matched_kmers)]) for each query.
+        // Colored queries without matches are skipped, non colored queries report all the matches with color 0
+        output_function: impl Fn(usize, &str, &[(ColorIndexType, u64)]) + Send + Sync,
+    ) -> anyhow::Result<()> {
+        PriorityScheduler::set_max_threads_count(threads_count);
+
+        let merging_hash_dispatch =
+            utils::get_hash_static_id(*debug::DEBUG_HASH_TYPE.lock(), kmer_length, forward_only);
+
+        let colors_hash = if colors {
+            ColorBundleGraphQuerying::dynamic_dispatch_id()
+        } else {
+            NonColoredManager::dynamic_dispatch_id()
+        };
+
+        let temp_dir = create_tempdir(self.0.temp_dir.clone());
+
+        querier::dynamic_dispatch::run_query(
+            (merging_hash_dispatch, colors_hash),
+            kmer_length,
+            minimizer_length.unwrap_or(::utils::compute_best_m(kmer_length)),
+            forward_only,
+            *debug::DEBUG_QUERIER_FIRST_STEP.lock(),
+            input_graphs,
+            input_queries,
+            PathBuf::new(),
+            temp_dir.clone(),
+            *debug::BUCKETS_COUNT_LOG_FORCE.lock(),
+            threads_count,
+            self.0.intermediate_compression_level,
+            ColoredQueryOutputFormat::JsonLinesWithNumbers,
+            output_filter,
+            QueryOutputMode::MatchCounts,
+            query_index,
+            Some(QueryResultsCallback {
+                output_function: &output_function,
+                single_thread_output_function,
+            }),
+        )?;
+
+        remove_tempdir(temp_dir);
+
+        Ok(())
+    }
+
+    /// Builds a query index of a (optionally) colored graph, saving its kmers
+    /// bucketed by minimizer to be reused by the queries without processing the graph again
+    pub fn build_query_index(
+        &self,
+        // The input graph
+        input_graph: PathBuf,
+        // The output index directory
+        index_dir: PathBuf,
+
+        // Specifies the k-mers length
+        kmer_length: usize,
+        // The threads to be used
+        threads_count: usize,
+        // Treats reverse complementary kmers as different, the queries using the index must use the same setting
+        forward_only: bool,
+        // Overrides the default m-mers (minimizers) length
+        minimizer_length: Option<usize>,
+
+        // Enable colors
+        colors: bool,
+    ) -> anyhow::Result<PathBuf> {
+        PriorityScheduler::set_max_threads_count(threads_count);
+
+        let merging_hash_dispatch =
+            utils::get_hash_static_id(*debug::DEBUG_HASH_TYPE.lock(), kmer_length, forward_only);
+
+        let colors_hash = if colors {
+            ColorBundleGraphQuerying::dynamic_dispatch_id()
+        } else {
+            NonColoredManager::dynamic_dispatch_id()
+        };
+
+        querier::query_index::dynamic_dispatch::build_query_index(
+            (merging_hash_dispatch, colors_hash),
+            kmer_length,
+            minimizer_length.unwrap_or(::utils::compute_best_m(kmer_length)),
+            forward_only,
+            input_graph,
+            index_dir,
+            *debug::BUCKETS_COUNT_LOG_FORCE.lock(),
+            threads_count,
+        )
+    }
+
     /// Obtains the standard colormap file path from a graph file path
     pub fn get_colormap_file(graph_file: impl AsRef<Path>) -> PathBuf {
         graph_file.as_ref().with_extension("colors.dat")
     }
 
+    /// Builds the index of a colormap, saved next to it as <graph>.colors.idx, with the offset of every
+    /// color subset. When the index exists, query_colormap decodes each subset in constant time.
+    /// The index is ignored if the colormap changes and must be built again
+    pub fn build_colormap_index(
+        // The input colormap
+        colormap_file: impl AsRef<Path>,
+    ) -> anyhow::Result<PathBuf> {
+        colors::storage::mapped::ColorsMappedReader::build(colormap_file)
+    }
+
+    /// Reads the summary of a colormap: codec, build parameters, colors and subsets counts, sizes and the subset sizes histogram
+    pub fn colormap_info(
+        // The input colormap
+        colormap_file: impl AsRef<Path>,
+    ) -> anyhow::Result<ColormapInfo> {
+        colors::storage::inspect::read_colormap_info(colormap_file)
+    }
+
+    /// Checks the structure of a colormap and that all its subsets can be decoded,
+    /// returning the list of the problems found (empty if the colormap is valid)
+    pub fn validate_colormap(
+        // The input colormap
+        colormap_file: impl AsRef<Path>,
+    ) -> anyhow::Result<Vec<String>> {
+        colors::storage::inspect::validate_colormap(colormap_file)
+    }
+
+    /// Converts a colormap written by an older ggcat release to the current format, adding
+    /// the integrity checksums. Returns false if the colormap is already up to date
+    pub fn upgrade_colormap(
+        // The input colormap, replaced by the upgraded one
+        colormap_file: impl AsRef<Path>,
+    ) -> anyhow::Result<bool> {
+        colors::storage::upgrade::upgrade_colormap(colormap_file)
+    }
+
     /// Returns an iterator over the color names in the given graph.
     /// The color indexes returned from the dump_unitigs function
     /// can be used to index this (collected) iterator.
@@ -394,10 +718,8 @@ impl GGCATInstance {
     ) -> anyhow::Result<impl Iterator<Item = String>> {
         use colors::colors_manager::ColorMapReader;
         use colors::storage::deserializer::ColorsDeserializer;
-        use colors::DefaultColorsSerializer;
 
-        let colors_deserializer =
-            ColorsDeserializer::<DefaultColorsSerializer>::new(input_colormap, true)?;
+        let colors_deserializer = ColorsDeserializer::new(input_colormap, true)?;
 
         Ok((0..colors_deserializer.colors_count()).map(move |i| {
             colors_deserializer
@@ -406,8 +728,21 @@ impl GGCATInstance {
         }))
     }
 
+    /// Reads the metadata of the colors in the given colormap, empty if it was built without metadata
+    pub fn dump_colors_metadata(
+        // The input colormap
+        input_colormap: impl AsRef<Path>,
+    ) -> anyhow::Result<ColorsMetadata> {
+        use colors::colors_manager::ColorMapReader;
+        use colors::storage::deserializer::ColorsDeserializer;
+
+        let colors_deserializer = ColorsDeserializer::new(input_colormap, true)?;
+        Ok(colors_deserializer.colors_metadata().clone())
+    }
+
     /// Queries specified color subsets of the colormap, returning
-    /// the color indices corresponding to the colors of each subset
+    /// the color indices corresponding to the colors of each subset.
+    /// The subsets are read from the colormap index, if built with build_colormap_index
     pub fn query_colormap(
         &self,
         // The input colormap
@@ -429,7 +764,7 @@ impl GGCATInstance {
 
     /// Dumps the unitigs of the given graph, optionally with colors
     /// It's not guaranteed that maximal unitigs are returned, as only kmers with the same colors subset
-    /// are returned as whole unitigs to speedup colormap reading times
+    /// are returned as whole unitigs to speedup colormap reading times, use dump_maximal_unitigs to get them
     pub fn dump_unitigs(
         &self,
         graph_input: impl AsRef<Path>,
@@ -438,6 +773,8 @@ impl GGCATInstance {
         // Overrides the default m-mers (minimizers) length
         minimizer_length: Option<usize>,
         colors: bool,
+        // Dumps only the unitigs whose colors match the predicate, requires colors
+        colors_predicate: Option<&ColorsPredicate>,
         // The threads to be used
         threads_count: usize,
 
@@ -446,6 +783,10 @@ impl GGCATInstance {
         single_thread_output_function: bool,
         output_function: impl Fn(&[u8], &[ColorIndexType], bool) + Send + Sync,
     ) -> anyhow::Result<()> {
+        if colors_predicate.is_some() && !colors {
+            anyhow::bail!("A colors predicate can be used only when dumping the colors");
+        }
+
         PriorityScheduler::set_max_threads_count(threads_count);
 
         let temp_dir = create_tempdir(self.0.temp_dir.clone());
@@ -460,6 +801,7 @@ impl GGCATInstance {
                 threads_count,
                 single_thread_output_function,
                 self.0.intermediate_compression_level,
+                colors_predicate,
                 output_function,
             )?;
         } else {
@@ -476,4 +818,146 @@ impl GGCATInstance {
         remove_tempdir(temp_dir);
         Ok(())
     }
+
+    /// Dumps the maximal unitigs of the given graph, optionally with the color subsets along each one.
+    /// The output function is called from a single thread at a time, in no particular order
+    pub fn dump_maximal_unitigs(
+        &self,
+        graph_input: impl AsRef<Path>,
+        // Specifies the k-mers length
+        kmer_length: usize,
+        // Overrides the default m-mers (minimizers) length
+        minimizer_length: Option<usize>,
+        colors: bool,
+        // The threads to be used
+        threads_count: usize,
+        // Called for each unitig with its index in the graph, its sequence and its color runs as
+        // (first kmer, last kmer + 1, color subset), empty if colors are disabled.
+        // The colors of the subsets can be read with query_colormap
+        mut output_function: impl FnMut(u64, &[u8], &[(usize, usize, ColorIndexType)]) + Send,
+    ) -> anyhow::Result<()> {
+        PriorityScheduler::set_max_threads_count(threads_count);
+
+        if !colors {
+            let mut unitig_index = 0;
+            FastaFileSequencesStream::new().read_block(
+                &(graph_input.as_ref().to_path_buf(), None),
+                false,
+                None,
+                |seq, _info| {
+                    output_function(unitig_index, seq.seq, &[]);
+                    unitig_index += 1;
+                },
+            );
+            return Ok(());
+        }
+
+        let temp_dir = create_tempdir(self.0.temp_dir.clone());
+
+        let result = dumper::dump_maximal_unitigs(
+            kmer_length,
+            minimizer_length.unwrap_or(::utils::compute_best_m(kmer_length)),
+            &graph_input,
+            temp_dir.clone(),
+            *debug::BUCKETS_COUNT_LOG_FORCE.lock(),
+            threads_count,
+            self.0.intermediate_compression_level,
+            output_function,
+        );
+
+        remove_tempdir(temp_dir);
+        result
+    }
+
+    /// Dumps the unitigs x colors presence/absence matrix of the given colored graph, identifying
+    /// the unitigs by their index in the graph. A unitig is present in a color if any of its kmers has it
+    pub fn dump_presence_matrix(
+        &self,
+        graph_input: impl AsRef<Path>,
+        // Specifies the k-mers length
+        kmer_length: usize,
+        // Overrides the default m-mers (minimizers) length
+        minimizer_length: Option<usize>,
+        // The threads to be used
+        threads_count: usize,
+        // Output a row for each distinct set of colors, with all the unitigs having it,
+        // instead of a row for each unitig
+        collapse_patterns: bool,
+        // Called for each row, with the unitigs of the row and their sorted colors
+        output_function: impl FnMut(&[u64], &[ColorIndexType]),
+    ) -> anyhow::Result<()> {
+        PriorityScheduler::set_max_threads_count(threads_count);
+
+        let temp_dir = create_tempdir(self.0.temp_dir.clone());
+
+        let result = dumper::dump_presence_matrix(
+            kmer_length,
+            minimizer_length.unwrap_or(::utils::compute_best_m(kmer_length)),
+            &graph_input,
+            temp_dir.clone(),
+            *debug::BUCKETS_COUNT_LOG_FORCE.lock(),
+            threads_count,
+            self.0.intermediate_compression_level,
+            collapse_patterns,
+            output_function,
+        );
+
+        remove_tempdir(temp_dir);
+        result
+    }
+}
+
+/// A graph kept loaded to answer many small batches of queries at low latency,
+/// without running the full query pipeline for each batch
+pub struct GGCATQueryEngine(querier::QueryEngine);
+
+impl GGCATQueryEngine {
+    /// Loads a (optionally) colored graph for querying, with its query index
+    pub fn load(
+        // The input graph
+        input_graph: impl AsRef<Path>,
+        // The query index of the graph, built with build_query_index
+        index_dir: impl AsRef<Path>,
+        // Specifies the k-mers length
+        kmer_length: usize,
+        // Treats reverse complementary kmers as different
+        forward_only: bool,
+        // Enable colors
+        colors: bool,
+        // Memory map the colormap index, saved next to the colormap on the first load,
+        // instead of decoding the color subsets in memory
+        memory_mapped: bool,
+        // Maximum number of query index buckets kept decoded in memory
+        cached_buckets: usize,
+    ) -> anyhow::Result<Self> {
+        Ok(Self(querier::QueryEngine::load(
+            input_graph,
+            index_dir,
+            kmer_length,
+            forward_only,
+            colors,
+            memory_mapped,
+            cached_buckets,
+        )?))
+    }
+
+    /// Queries a batch of sequences, returning the matches of each one in the same order.
+    /// Fails if the colors of a matched subset cannot be read
+    pub fn query(&self, queries: &[DnaSequence]) -> anyhow::Result<Vec<QueryMatches>> {
+        self.0.query(queries)
+    }
+
+    pub fn kmer_length(&self) -> usize {
+        self.0.kmer_length()
+    }
+
+    /// Names of the graph colors, ordered by color index. Empty if the graph is not colored
+    pub fn color_names(&self) -> &[String] {
+        self.0.color_names()
+    }
+
+    /// Metadata of the graph colors. Empty if the graph is not colored or has no metadata
+    pub fn colors_metadata(&self) -> &ColorsMetadata {
+        self.0.colors_metadata()
+    }
 }
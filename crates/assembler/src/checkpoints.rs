@@ -0,0 +1,658 @@
+use crate::pipeline::graph_cleaning::GraphCleaningParams;
+use crate::AssemblerStartingStep;
+use anyhow::Context;
+use colors::metadata::ColorsMetadata;
+use colors::storage::ColorsCodec;
+use config::{KEEP_FILES, PREFER_MEMORY};
+use io::sequences_stream::general::GeneralSequenceBlockData;
+use parallel_processor::buckets::{MultiChunkBucket, SingleBucket};
+use parallel_processor::memory_fs::file::internal::MemoryFileMode;
+use parallel_processor::memory_fs::file::writer::FileWriter;
+use parallel_processor::memory_fs::{MemoryFs, RemoveFileMode};
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::time::UNIX_EPOCH;
+
+pub const CHECKPOINT_MANIFEST_NAME: &str = "checkpoint.manifest";
+const CHECKPOINT_MANIFEST_HEADER: &str = "ggcat-checkpoint-manifest 1";
+const IMPORTED_FILE_SUFFIX: &str = ".resumed";
+
+/// Manifest of a checkpointed build, recording the last completed phase,
+/// the build parameters and the output buckets that are still needed by the next phases.
+/// Only the phases up to the links compaction are checkpointed, as the following ones write
+/// directly to the output file and are always run again when resuming.
+pub struct CheckpointManifest {
+    params: Vec<(String, String)>,
+    completed_step: Option<AssemblerStartingStep>,
+    buckets: Vec<(String, Vec<MultiChunkBucket>)>,
+    files: Vec<(String, PathBuf)>,
+}
+
+fn step_from_index(index: usize) -> Option<AssemblerStartingStep> {
+    Some(match index {
+        0 => AssemblerStartingStep::MinimizerBucketing,
+        1 => AssemblerStartingStep::KmersMerge,
+        2 => AssemblerStartingStep::HashesSorting,
+        3 => AssemblerStartingStep::LinksCompaction,
+        _ => return None,
+    })
+}
+
+impl CheckpointManifest {
+    pub fn new(params: Vec<(String, String)>) -> Self {
+        Self {
+            params,
+            completed_step: None,
+            buckets: vec![],
+            files: vec![],
+        }
+    }
+
+    fn manifest_path(temp_dir: &Path) -> PathBuf {
+        temp_dir.join(CHECKPOINT_MANIFEST_NAME)
+    }
+
+    pub fn load(temp_dir: &Path) -> anyhow::Result<Self> {
+        let path = Self::manifest_path(temp_dir);
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Cannot read checkpoint manifest {}", path.display()))?;
+
+        let mut lines = content.lines();
+        if lines.next() != Some(CHECKPOINT_MANIFEST_HEADER) {
+            anyhow::bail!("Invalid checkpoint manifest {}", path.display());
+        }
+
+        let mut manifest = Self::new(vec![]);
+
+        for line in lines {
+            let mut columns = line.split('\t');
+            let invalid_line = || anyhow::anyhow!("Invalid checkpoint manifest line: '{}'", line);
+
+            match columns.next() {
+                Some("param") => {
+                    let name = columns.next().ok_or_else(invalid_line)?;
+                    let value = columns.next().ok_or_else(invalid_line)?;
+                    manifest.params.push((name.to_string(), value.to_string()));
+                }
+                Some("completed") => {
+                    manifest.completed_step = Some(
+                        columns
+                            .next()
+                            .and_then(|step| step.parse().ok())
+                            .and_then(step_from_index)
+                            .ok_or_else(invalid_line)?,
+                    );
+                }
+                Some("file") => {
+                    let name = columns.next().ok_or_else(invalid_line)?;
+                    let path = columns.next().ok_or_else(invalid_line)?;
+                    manifest.files.push((name.to_string(), PathBuf::from(path)));
+                }
+                Some("bucket") => {
+                    let name = columns.next().ok_or_else(invalid_line)?;
+                    let index = columns
+                        .next()
+                        .and_then(|index| index.parse().ok())
+                        .ok_or_else(invalid_line)?;
+                    let was_compacted = columns.next().ok_or_else(invalid_line)? == "1";
+                    let bucket = MultiChunkBucket {
+                        index,
+                        chunks: columns.map(PathBuf::from).collect(),
+                        was_compacted,
+                    };
+
+                    match manifest.buckets.iter_mut().find(|(n, _)| n == name) {
+                        Some((_, buckets)) => buckets.push(bucket),
+                        None => manifest.buckets.push((name.to_string(), vec![bucket])),
+                    }
+                }
+                Some("") | None => {}
+                Some(_) => return Err(invalid_line()),
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    /// Checks that the build parameters match the ones of the checkpointed build
+    pub fn check_params(&self, params: &[(String, String)]) -> anyhow::Result<()> {
+        for (name, value) in params {
+            match self.params.iter().find(|(n, _)| n == name) {
+                Some((_, saved)) if saved == value => {}
+                Some((_, saved)) => anyhow::bail!(
+                    "Cannot resume the build, parameter '{}' differs from the checkpoint: {} (checkpoint: {})",
+                    name,
+                    value,
+                    saved
+                ),
+                None => anyhow::bail!(
+                    "Cannot resume the build, parameter '{}' is missing from the checkpoint",
+                    name
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn set_param(&mut self, name: &str, value: String) {
+        self.params.retain(|(n, _)| n != name);
+        self.params.push((name.to_string(), value));
+    }
+
+    pub fn completed_step(&self) -> Option<AssemblerStartingStep> {
+        self.completed_step.clone()
+    }
+
+    /// Returns the step from which a resumed build should start
+    pub fn resume_step(&self) -> AssemblerStartingStep {
+        match &self.completed_step {
+            None => AssemblerStartingStep::MinimizerBucketing,
+            Some(step) => step_from_index(step.clone() as usize + 1)
+                .unwrap_or(AssemblerStartingStep::ReorganizeReads),
+        }
+    }
+
+    pub fn set_multi_chunk_buckets(&mut self, name: &str, buckets: &[MultiChunkBucket]) {
+        self.remove_buckets(name);
+        self.buckets.push((
+            name.to_string(),
+            buckets
+                .iter()
+                .map(|bucket| MultiChunkBucket {
+                    index: bucket.index,
+                    chunks: bucket.chunks.clone(),
+                    was_compacted: bucket.was_compacted,
+                })
+                .collect(),
+        ));
+    }
+
+    pub fn set_buckets(&mut self, name: &str, buckets: &[SingleBucket]) {
+        self.remove_buckets(name);
+        self.buckets.push((
+            name.to_string(),
+            buckets
+                .iter()
+                .map(|bucket| MultiChunkBucket {
+                    index: bucket.index,
+                    chunks: vec![bucket.path.clone()],
+                    was_compacted: false,
+                })
+                .collect(),
+        ));
+    }
+
+    pub fn remove_buckets(&mut self, name: &str) {
+        self.buckets.retain(|(n, _)| n != name);
+    }
+
+    fn find_buckets(&self, name: &str) -> anyhow::Result<&Vec<MultiChunkBucket>> {
+        self.buckets
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, buckets)| buckets)
+            .ok_or_else(|| anyhow::anyhow!("Missing '{}' buckets in the checkpoint", name))
+    }
+
+    pub fn get_multi_chunk_buckets(&self, name: &str) -> anyhow::Result<Vec<MultiChunkBucket>> {
+        Ok(self
+            .find_buckets(name)?
+            .iter()
+            .map(|bucket| MultiChunkBucket {
+                index: bucket.index,
+                chunks: bucket.chunks.clone(),
+                was_compacted: bucket.was_compacted,
+            })
+            .collect())
+    }
+
+    pub fn get_buckets(&self, name: &str) -> anyhow::Result<Vec<SingleBucket>> {
+        self.find_buckets(name)?
+            .iter()
+            .map(|bucket| match bucket.chunks.as_slice() {
+                [path] => Ok(SingleBucket {
+                    index: bucket.index,
+                    path: path.clone(),
+                }),
+                _ => anyhow::bail!("Invalid '{}' bucket in the checkpoint", name),
+            })
+            .collect()
+    }
+
+    pub fn set_file(&mut self, name: &str, path: PathBuf) {
+        self.remove_file(name);
+        self.files.push((name.to_string(), path));
+    }
+
+    pub fn remove_file(&mut self, name: &str) {
+        self.files.retain(|(n, _)| n != name);
+    }
+
+    pub fn get_file(&self, name: &str) -> anyhow::Result<PathBuf> {
+        self.files
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, path)| path.clone())
+            .ok_or_else(|| anyhow::anyhow!("Missing '{}' file in the checkpoint", name))
+    }
+
+    /// Checks that all the files referenced by the manifest still exist
+    pub fn check_files(&self) -> anyhow::Result<()> {
+        for path in self.referenced_files() {
+            if !path.exists() {
+                anyhow::bail!(
+                    "Cannot resume the build, the checkpoint file {} is missing",
+                    path.display()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies the files of the interrupted build through the memory fs, so that they are accounted in its usage
+    /// stats like the files written by this build and can be removed by the next phases.
+    /// The manifest is updated after each copy, before removing the original file
+    pub fn import_files(&mut self, temp_dir: &Path) -> anyhow::Result<()> {
+        let paths: HashSet<_> = self.referenced_files().cloned().collect();
+        for path in paths {
+            let imported_path = imported_file_path(&path);
+            {
+                let mut file = File::open(&path).with_context(|| {
+                    format!("Cannot read the checkpoint file {}", path.display())
+                })?;
+                let mut writer = FileWriter::create(&imported_path, MemoryFileMode::DiskOnly);
+                std::io::copy(&mut file, &mut writer).with_context(|| {
+                    format!("Cannot read the checkpoint file {}", path.display())
+                })?;
+            }
+            MemoryFs::flush_all_to_disk();
+
+            self.buckets
+                .iter_mut()
+                .flat_map(|(_, buckets)| {
+                    buckets
+                        .iter_mut()
+                        .flat_map(|bucket| bucket.chunks.iter_mut())
+                })
+                .chain(self.files.iter_mut().map(|(_, path)| path))
+                .filter(|referenced| **referenced == path)
+                .for_each(|referenced| *referenced = imported_path.clone());
+            self.save(temp_dir)?;
+            let _ = std::fs::remove_file(&path);
+        }
+        Ok(())
+    }
+
+    fn referenced_files(&self) -> impl Iterator<Item = &PathBuf> {
+        self.buckets
+            .iter()
+            .flat_map(|(_, buckets)| buckets.iter().flat_map(|bucket| bucket.chunks.iter()))
+            .chain(self.files.iter().map(|(_, path)| path))
+    }
+
+    /// Atomically writes the manifest to the temporary directory
+    pub fn save(&self, temp_dir: &Path) -> anyhow::Result<()> {
+        let mut content = String::new();
+        writeln!(content, "{}", CHECKPOINT_MANIFEST_HEADER)?;
+        for (name, value) in &self.params {
+            writeln!(content, "param\t{}\t{}", name, value)?;
+        }
+        if let Some(step) = &self.completed_step {
+            writeln!(content, "completed\t{}", step.clone() as usize)?;
+        }
+        for (name, path) in &self.files {
+            writeln!(content, "file\t{}\t{}", name, path.display())?;
+        }
+        for (name, buckets) in &self.buckets {
+            for bucket in buckets {
+                write!(
+                    content,
+                    "bucket\t{}\t{}\t{}",
+                    name, bucket.index, bucket.was_compacted as u8
+                )?;
+                for chunk in &bucket.chunks {
+                    write!(content, "\t{}", chunk.display())?;
+                }
+                writeln!(content)?;
+            }
+        }
+
+        let path = Self::manifest_path(temp_dir);
+        let temp_path = path.with_extension("manifest.tmp");
+        std::fs::write(&temp_path, content)
+            .with_context(|| format!("Cannot write checkpoint manifest {}", path.display()))?;
+        std::fs::rename(&temp_path, &path)
+            .with_context(|| format!("Cannot write checkpoint manifest {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Records the completion of a step, removing all the temporary files that are not referenced anymore
+    pub fn complete_step(
+        &mut self,
+        step: AssemblerStartingStep,
+        temp_dir: &Path,
+        remove_unreferenced: bool,
+    ) -> anyhow::Result<()> {
+        MemoryFs::flush_all_to_disk();
+        self.completed_step = Some(step);
+        self.save(temp_dir)?;
+        if remove_unreferenced {
+            self.remove_unreferenced_files(temp_dir);
+        }
+        Ok(())
+    }
+
+    /// Removes the manifest after a successful build, along with all the temporary files if requested
+    pub fn finish(mut self, temp_dir: &Path, remove_files: bool) {
+        if remove_files {
+            self.buckets.clear();
+            self.files.clear();
+            self.remove_unreferenced_files(temp_dir);
+        }
+        let _ = std::fs::remove_file(Self::manifest_path(temp_dir));
+    }
+
+    /// Removes the temporary files not referenced by the manifest, left by completed or interrupted phases
+    pub fn remove_unreferenced_files(&self, temp_dir: &Path) {
+        let referenced: HashSet<_> = self.referenced_files().collect();
+
+        let Ok(entries) = std::fs::read_dir(temp_dir) else {
+            return;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if !path.is_file()
+                || referenced.contains(&path)
+                || path.file_name() == Some(CHECKPOINT_MANIFEST_NAME.as_ref())
+            {
+                continue;
+            }
+            let _ = MemoryFs::remove_file(&path, RemoveFileMode::Remove { remove_fs: true });
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+}
+
+/// Parameters identifying the inputs of a build and the options changing its checkpointed files, other than the
+/// kmers ones: each input file with its size and modification time, the colors and the graph cleaning options.
+/// Resuming is refused if any of them differs, as the checkpointed files would not match the new build
+pub fn inputs_params(
+    input_blocks: &[GeneralSequenceBlockData],
+    color_names: &[String],
+    colors_metadata: &ColorsMetadata,
+    colors_codec: ColorsCodec,
+    graph_cleaning: Option<GraphCleaningParams>,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let mut params = vec![("inputs".to_string(), input_blocks.len().to_string())];
+
+    for (index, input) in input_blocks.iter().enumerate() {
+        let (path, color) = match input {
+            GeneralSequenceBlockData::FASTA((path, color)) => (path, color),
+            GeneralSequenceBlockData::GFA() | GeneralSequenceBlockData::Dynamic(_) => {
+                anyhow::bail!("Checkpoints are supported only with input files, as the content of the input streams cannot be checked when resuming")
+            }
+        };
+        let metadata = std::fs::metadata(path)
+            .with_context(|| format!("Cannot read input file {}", path.display()))?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        params.push((
+            format!("input.{}", index),
+            format!(
+                "{:?} color {:?} size {} modified {}.{:09}",
+                path,
+                color,
+                metadata.len(),
+                modified.as_secs(),
+                modified.subsec_nanos()
+            ),
+        ));
+    }
+
+    let mut names_hasher = crc32fast::Hasher::new();
+    for name in color_names {
+        names_hasher.update(name.as_bytes());
+        names_hasher.update(b"\0");
+    }
+    params.push((
+        "color_names".to_string(),
+        format!("{} {:08x}", color_names.len(), names_hasher.finalize()),
+    ));
+    params.push((
+        "colors_metadata".to_string(),
+        format!(
+            "{:08x}",
+            crc32fast::hash(format!("{:?}", colors_metadata).as_bytes())
+        ),
+    ));
+    params.push(("colors_codec".to_string(), format!("{:?}", colors_codec)));
+    params.push((
+        "graph_cleaning".to_string(),
+        format!("{:?}", graph_cleaning),
+    ));
+
+    Ok(params)
+}
+
+/// Alternates the path of an imported file between the original one and the one with the imported suffix,
+/// to keep the names stable when resuming more times
+fn imported_file_path(path: &Path) -> PathBuf {
+    // The paths in the manifest are valid UTF-8, as they are saved as strings
+    let path = path.to_string_lossy();
+    match path.strip_suffix(IMPORTED_FILE_SUFFIX) {
+        Some(original) => PathBuf::from(original),
+        None => PathBuf::from(format!("{}{}", path, IMPORTED_FILE_SUFFIX)),
+    }
+}
+
+/// Forces all the intermediate files to be written to disk and kept until the completion of each step,
+/// restoring the previous settings when dropped
+pub struct CheckpointFilesGuard {
+    keep_files: bool,
+    prefer_memory: bool,
+}
+
+impl CheckpointFilesGuard {
+    pub fn new() -> Self {
+        Self {
+            keep_files: KEEP_FILES.swap(true, Ordering::Relaxed),
+            prefer_memory: PREFER_MEMORY.swap(false, Ordering::Relaxed),
+        }
+    }
+
+    /// Returns true if the intermediate files should be kept after the build
+    pub fn keep_files(&self) -> bool {
+        self.keep_files
+    }
+}
+
+impl Drop for CheckpointFilesGuard {
+    fn drop(&mut self) {
+        KEEP_FILES.store(self.keep_files, Ordering::Relaxed);
+        PREFER_MEMORY.store(self.prefer_memory, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_roundtrip() {
+        let temp_dir =
+            std::env::temp_dir().join(format!("ggcat-checkpoint-test-{}", std::process::id()));
+        std::fs::create_dir_all(&temp_dir).unwrap();
+
+        let params = vec![
+            ("k".to_string(), "31".to_string()),
+            ("m".to_string(), "12".to_string()),
+        ];
+        let mut manifest = CheckpointManifest::new(params.clone());
+        manifest.set_buckets(
+            "sequences",
+            &[SingleBucket {
+                index: 3,
+                path: temp_dir.join("result.3"),
+            }],
+        );
+        manifest.set_file("buckets_counters", temp_dir.join("buckets-counters.dat"));
+        manifest.completed_step = Some(AssemblerStartingStep::KmersMerge);
+        manifest.save(&temp_dir).unwrap();
+
+        let loaded = CheckpointManifest::load(&temp_dir).unwrap();
+        loaded.check_params(&params).unwrap();
+        assert!(loaded
+            .check_params(&[("k".to_string(), "27".to_string())])
+            .is_err());
+        assert_eq!(
+            loaded.completed_step(),
+            Some(AssemblerStartingStep::KmersMerge)
+        );
+        assert_eq!(loaded.resume_step(), AssemblerStartingStep::HashesSorting);
+        let sequences = loaded.get_buckets("sequences").unwrap();
+        assert_eq!(sequences.len(), 1);
+        assert_eq!(sequences[0].index, 3);
+        assert_eq!(sequences[0].path, temp_dir.join("result.3"));
+        assert_eq!(
+            loaded.get_file("buckets_counters").unwrap(),
+            temp_dir.join("buckets-counters.dat")
+        );
+        assert!(loaded.get_buckets("hashes").is_err());
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn resume_after_last_checkpointed_step() {
+        let mut manifest = CheckpointManifest::new(vec![]);
+        assert_eq!(
+            manifest.resume_step(),
+            AssemblerStartingStep::MinimizerBucketing
+        );
+        manifest.completed_step = Some(AssemblerStartingStep::LinksCompaction);
+        assert_eq!(
+            manifest.resume_step(),
+            AssemblerStartingStep::ReorganizeReads
+        );
+    }
+
+    #[test]
+    fn resumed_files_are_imported() {
+        use parallel_processor::memory_data_size::MemoryDataSize;
+
+        let temp_dir =
+            std::env::temp_dir().join(format!("ggcat-checkpoint-import-{}", std::process::id()));
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        MemoryFs::init(MemoryDataSize::from_mebioctets(64), 16, 1, 1024);
+
+        let data: Vec<u8> = (0..100000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(temp_dir.join("links.0"), &data).unwrap();
+        let mut manifest = CheckpointManifest::new(vec![]);
+        manifest.set_buckets(
+            "links",
+            &[SingleBucket {
+                index: 0,
+                path: temp_dir.join("links.0"),
+            }],
+        );
+        manifest.save(&temp_dir).unwrap();
+
+        manifest.import_files(&temp_dir).unwrap();
+        let imported = temp_dir.join("links.0.resumed");
+        assert!(!temp_dir.join("links.0").exists());
+        assert_eq!(std::fs::read(&imported).unwrap(), data);
+        assert_eq!(
+            CheckpointManifest::load(&temp_dir)
+                .unwrap()
+                .get_buckets("links")
+                .unwrap()[0]
+                .path,
+            imported
+        );
+        assert!(MemoryFs::get_stats().current_files_usage >= data.len() as u64);
+
+        // Imported again with the original name when resuming one more time
+        manifest.import_files(&temp_dir).unwrap();
+        assert_eq!(
+            manifest.get_buckets("links").unwrap()[0].path,
+            temp_dir.join("links.0")
+        );
+        assert_eq!(std::fs::read(temp_dir.join("links.0")).unwrap(), data);
+
+        MemoryFs::remove_file(
+            temp_dir.join("links.0"),
+            RemoveFileMode::Remove { remove_fs: true },
+        )
+        .unwrap();
+        let _ = std::fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn changed_inputs_are_rejected() {
+        let temp_dir =
+            std::env::temp_dir().join(format!("ggcat-checkpoint-inputs-{}", std::process::id()));
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        let input = temp_dir.join("input.fa");
+        std::fs::write(&input, ">0\nACGTACGTAC\n").unwrap();
+
+        let color_names = vec!["A".to_string(), "B".to_string()];
+        let params = |inputs: &[PathBuf], color_names: &[String], cleaning| {
+            inputs_params(
+                &inputs
+                    .iter()
+                    .map(|path| GeneralSequenceBlockData::FASTA((path.clone(), None)))
+                    .collect::<Vec<_>>(),
+                color_names,
+                &ColorsMetadata::default(),
+                ColorsCodec::RunLength,
+                cleaning,
+            )
+            .unwrap()
+        };
+
+        let inputs = vec![input.clone()];
+        let manifest = CheckpointManifest::new(params(&inputs, &color_names, None));
+        manifest
+            .check_params(&params(&inputs, &color_names, None))
+            .unwrap();
+
+        // Other input files, colors or graph cleaning options
+        assert!(manifest
+            .check_params(&params(&[input.clone(), input.clone()], &color_names, None))
+            .is_err());
+        assert!(manifest
+            .check_params(&params(&inputs, &color_names[..1], None))
+            .is_err());
+        assert!(manifest
+            .check_params(&params(
+                &inputs,
+                &color_names,
+                Some(GraphCleaningParams::default())
+            ))
+            .is_err());
+
+        // The input file was changed
+        std::fs::write(&input, ">0\nACGTACGTACGT\n").unwrap();
+        let err = manifest
+            .check_params(&params(&inputs, &color_names, None))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("input.0"), "{}", err);
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+    }
+}
@@ -1,8 +1,13 @@
 #![cfg_attr(feature = "devel-build", allow(unused_imports))]
 
+use crate::checkpoints::{inputs_params, CheckpointFilesGuard, CheckpointManifest};
 use crate::pipeline::build_unitigs::build_unitigs;
 use crate::pipeline::compute_matchtigs::MatchtigHelperTrait;
-use crate::pipeline::compute_matchtigs::{compute_matchtigs_thread, MatchtigsStorageBackend};
+use crate::pipeline::compute_matchtigs::{
+    compute_matchtigs_thread, find_matchtigs_matcher, MatchtigsStorageBackend,
+    DEFAULT_MATCHTIGS_MATCHER,
+};
+use crate::pipeline::graph_cleaning::clean_graph;
 use crate::pipeline::hashes_sorting::hashes_sorting;
 use crate::pipeline::links_compaction::links_compaction;
 use crate::pipeline::maximal_unitig_links::build_maximal_unitigs_links;
@@ -11,6 +16,8 @@ use ::dynamic_dispatch::dynamic_dispatch;
 use assembler_kmers_merge::structs::RetType;
 use colors::colors_manager::ColorsManager;
 use colors::colors_manager::ColorsMergeManager;
+use colors::metadata::ColorsMetadata;
+use colors::storage::{ColormapBuildParams, ColorsCodec};
 use config::{
     get_compression_level_info, get_memory_mode, SwapPriority, DEFAULT_PER_CPU_BUFFER_SIZE,
     INTERMEDIATE_COMPRESSION_LEVEL_FAST, INTERMEDIATE_COMPRESSION_LEVEL_SLOW, KEEP_FILES,
@@ -38,17 +45,20 @@ use parallel_processor::scheduler::PriorityScheduler;
 use parallel_processor::utils::scoped_thread_local::ScopedThreadLocal;
 use pipeline::eulertigs::build_eulertigs;
 use std::fs::remove_file;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Instant;
 
+mod checkpoints;
 mod pipeline;
 mod structs;
 
+pub use checkpoints::CHECKPOINT_MANIFEST_NAME;
 pub use pipeline::compute_matchtigs::MatchtigMode;
+pub use pipeline::graph_cleaning::GraphCleaningParams;
 
-#[derive(Clone, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub enum AssemblerStartingStep {
     MinimizerBucketing = 0,
     KmersMerge = 1,
@@ -110,8 +120,12 @@ pub fn run_assembler<
     last_step: AssemblerStartingStep,
     input_blocks: Vec<GeneralSequenceBlockData>,
     color_names: &[String],
+    colors_metadata: &ColorsMetadata,
+    colors_codec: ColorsCodec,
     output_file: PathBuf,
     temp_dir: Option<PathBuf>,
+    checkpoints: bool,
+    resume: bool,
     threads_count: usize,
     min_multiplicity: usize,
     buckets_count_log: Option<usize>,
@@ -119,16 +133,128 @@ pub fn run_assembler<
     default_compression_level: Option<u32>,
     generate_maximal_unitigs_links: bool,
     compute_tigs_mode: Option<MatchtigMode>,
+    matchtigs_matcher_path: Option<PathBuf>,
+    graph_cleaning: Option<GraphCleaningParams>,
     only_bstats: bool,
     minimizer_bucketing_chunk_size: Option<u64>,
 ) -> anyhow::Result<PathBuf> {
     let temp_dir = temp_dir.unwrap_or(PathBuf::new());
 
+    if graph_cleaning.is_some() && compute_tigs_mode.needs_simplitigs() {
+        anyhow::bail!("Graph cleaning is not supported with fast simplitigs or fast eulertigs");
+    }
+
+    // Tips and bubbles are selected by their coverage, that is known only with kmer counters
+    #[cfg(not(feature = "support_kmer_counters"))]
+    if graph_cleaning.is_some() {
+        anyhow::bail!(
+            "Graph cleaning requires the kmers coverage, please build GGCAT with the kmer-counters feature"
+        );
+    }
+
+    // The fast modes join the unitigs while they are built, without knowing their color subsets
+    if AssemblerColorsManager::COLORS_ENABLED && compute_tigs_mode.needs_simplitigs() {
+        anyhow::bail!(
+            "Fast simplitigs and fast eulertigs are not supported with colors, as they can join kmers with different color subsets, please use eulertigs, pathtigs or matchtigs"
+        );
+    }
+
+    let matchtigs_matcher_path = if compute_tigs_mode == Some(MatchtigMode::MatchTigs) {
+        match find_matchtigs_matcher(matchtigs_matcher_path.as_deref()) {
+            Some(matcher_path) => matcher_path,
+            None => anyhow::bail!(
+                "Cannot find the '{}' binary required to compute matchtigs, please install it or specify its path",
+                matchtigs_matcher_path
+                    .as_deref()
+                    .unwrap_or(Path::new(DEFAULT_MATCHTIGS_MATCHER))
+                    .display()
+            ),
+        }
+    } else {
+        PathBuf::new()
+    };
+
+    let checkpoint_params = if checkpoints || resume {
+        let mut params = vec![
+            ("k".to_string(), k.to_string()),
+            ("m".to_string(), m.to_string()),
+            ("min_multiplicity".to_string(), min_multiplicity.to_string()),
+            (
+                "hash".to_string(),
+                std::any::type_name::<MergingHash>().to_string(),
+            ),
+            (
+                "colors".to_string(),
+                std::any::type_name::<AssemblerColorsManager>().to_string(),
+            ),
+            (
+                "simplitigs".to_string(),
+                compute_tigs_mode.needs_simplitigs().to_string(),
+            ),
+        ];
+        params.extend(inputs_params(
+            &input_blocks,
+            color_names,
+            colors_metadata,
+            colors_codec,
+            graph_cleaning,
+        )?);
+        params
+    } else {
+        vec![]
+    };
+
+    // Checkpointed files must survive a crash, so they are always written to disk
+    // and the inputs of each phase are removed only after its completion
+    let files_guard = (checkpoints || resume).then(CheckpointFilesGuard::new);
+    let keep_files = files_guard
+        .as_ref()
+        .map(|guard| guard.keep_files())
+        .unwrap_or_else(|| KEEP_FILES.load(Ordering::Relaxed));
+
+    let (mut checkpoint, step) = if resume {
+        let mut checkpoint = CheckpointManifest::load(&temp_dir)?;
+        checkpoint.check_params(&checkpoint_params)?;
+        checkpoint.check_files()?;
+        if !keep_files {
+            checkpoint.remove_unreferenced_files(&temp_dir);
+        }
+        checkpoint.import_files(&temp_dir)?;
+        let step = checkpoint.resume_step();
+        (Some(checkpoint), step)
+    } else if checkpoints {
+        (Some(CheckpointManifest::new(checkpoint_params)), step)
+    } else {
+        (None, step)
+    };
+
+    if let Some(checkpoint) = &checkpoint {
+        match checkpoint.completed_step() {
+            Some(completed_step) => ggcat_logging::info!(
+                "Resuming build from checkpoint in {}, last completed step: {:?}",
+                temp_dir.display(),
+                completed_step
+            ),
+            None => ggcat_logging::info!("Saving checkpoints in {}", temp_dir.display()),
+        }
+    }
+
     PHASES_TIMES_MONITOR.write().init();
 
     let file_stats = compute_stats_from_input_blocks(&input_blocks)?;
 
-    let buckets_count_log = buckets_count_log.unwrap_or_else(|| file_stats.best_buckets_count_log);
+    let buckets_count_log = match checkpoint
+        .as_ref()
+        .and_then(|c| c.get_param("buckets_count_log"))
+    {
+        Some(buckets_count_log) => buckets_count_log.parse()?,
+        None => buckets_count_log.unwrap_or_else(|| file_stats.best_buckets_count_log),
+    };
+
+    if let Some(checkpoint) = &mut checkpoint {
+        checkpoint.set_param("buckets_count_log", buckets_count_log.to_string());
+        checkpoint.save(&temp_dir)?;
+    }
 
     if let Some(default_compression_level) = default_compression_level {
         INTERMEDIATE_COMPRESSION_LEVEL_SLOW.store(default_compression_level, Ordering::Relaxed);
@@ -137,12 +263,28 @@ pub fn run_assembler<
 
     let buckets_count = 1 << buckets_count_log;
 
-    let global_colors_table = Arc::new(
-        AssemblerColorsManager::ColorsMergeManagerType::create_colors_table(
-            output_file.with_extension("colors.dat"),
-            color_names,
-        )?,
-    );
+    // The colors table is built during the kmers merge step, if it is skipped it must already exist
+    let mut global_colors_table = if step <= AssemblerStartingStep::KmersMerge {
+        Some(Arc::new(
+            AssemblerColorsManager::ColorsMergeManagerType::create_colors_table(
+                output_file.with_extension("colors.dat"),
+                color_names,
+                colors_metadata,
+                colors_codec,
+                &ColormapBuildParams::new::<MergingHash>(k),
+            )?,
+        ))
+    } else {
+        if AssemblerColorsManager::COLORS_ENABLED
+            && !output_file.with_extension("colors.dat").exists()
+        {
+            anyhow::bail!(
+                "Cannot resume the build, the colors table {} is missing",
+                output_file.with_extension("colors.dat").display()
+            );
+        }
+        None
+    };
 
     let (buckets, counters) = if step <= AssemblerStartingStep::MinimizerBucketing {
         assembler_minimizer_bucketing::static_dispatch::minimizer_bucketing::<AssemblerColorsManager>(
@@ -154,6 +296,15 @@ pub fn run_assembler<
             m,
             minimizer_bucketing_chunk_size,
         )
+    } else if let Some(checkpoint) = &checkpoint {
+        if step <= AssemblerStartingStep::KmersMerge {
+            (
+                checkpoint.get_multi_chunk_buckets("buckets")?,
+                checkpoint.get_file("buckets_counters")?,
+            )
+        } else {
+            (vec![], PathBuf::new())
+        }
     } else {
         (
             generate_bucket_names(temp_dir.join("bucket"), buckets_count, None)
@@ -164,6 +315,18 @@ pub fn run_assembler<
         )
     };
 
+    if let Some(checkpoint) = &mut checkpoint {
+        if step <= AssemblerStartingStep::MinimizerBucketing {
+            checkpoint.set_multi_chunk_buckets("buckets", &buckets);
+            checkpoint.set_file("buckets_counters", counters.clone());
+            checkpoint.complete_step(
+                AssemblerStartingStep::MinimizerBucketing,
+                &temp_dir,
+                !keep_files,
+            )?;
+        }
+    }
+
     let fs_stats = MemoryFs::get_stats();
 
     ggcat_logging::info!(
@@ -209,7 +372,7 @@ pub fn run_assembler<
         assembler_kmers_merge::kmers_merge::<MergingHash, AssemblerColorsManager, _>(
             buckets,
             counters,
-            global_colors_table.clone(),
+            global_colors_table.clone().unwrap(),
             buckets_count,
             min_multiplicity,
             temp_dir.as_path(),
@@ -218,12 +381,40 @@ pub fn run_assembler<
             compute_tigs_mode.needs_simplitigs(),
             threads_count,
         )
+    } else if let Some(checkpoint) = &checkpoint {
+        RetType {
+            sequences: checkpoint.get_buckets("sequences")?,
+            hashes: if step <= AssemblerStartingStep::HashesSorting {
+                checkpoint.get_buckets("hashes")?
+            } else {
+                vec![]
+            },
+        }
     } else {
         RetType {
             sequences: generate_bucket_names(temp_dir.join("result"), buckets_count, None),
             hashes: generate_bucket_names(temp_dir.join("hashes"), buckets_count, None),
         }
     };
+
+    if let Some(checkpoint) = &mut checkpoint {
+        if step <= AssemblerStartingStep::KmersMerge {
+            // The colors table must be finalized before the step is recorded as completed
+            if let Some(global_colors_table) = global_colors_table.take() {
+                AssemblerColorsManager::ColorsMergeManagerType::print_color_stats(
+                    &global_colors_table,
+                );
+                drop(global_colors_table);
+            }
+
+            checkpoint.remove_buckets("buckets");
+            checkpoint.remove_file("buckets_counters");
+            checkpoint.set_buckets("sequences", &sequences);
+            checkpoint.set_buckets("hashes", &hashes);
+            checkpoint.complete_step(AssemblerStartingStep::KmersMerge, &temp_dir, !keep_files)?;
+        }
+    }
+
     if last_step <= AssemblerStartingStep::KmersMerge {
         PHASES_TIMES_MONITOR
             .write()
@@ -234,15 +425,35 @@ pub fn run_assembler<
         MemoryFs::free_memory();
     }
 
-    AssemblerColorsManager::ColorsMergeManagerType::print_color_stats(&global_colors_table);
-
-    drop(global_colors_table);
+    if let Some(global_colors_table) = global_colors_table {
+        AssemblerColorsManager::ColorsMergeManagerType::print_color_stats(&global_colors_table);
+        drop(global_colors_table);
+    }
 
     let mut links = if step <= AssemblerStartingStep::HashesSorting {
         hashes_sorting::<MergingHash, _>(hashes, temp_dir.as_path(), buckets_count)
+    } else if let Some(checkpoint) = &checkpoint {
+        if step <= AssemblerStartingStep::LinksCompaction {
+            checkpoint.get_buckets("links")?
+        } else {
+            vec![]
+        }
     } else {
         generate_bucket_names(temp_dir.join("links"), buckets_count, None)
     };
+
+    if let Some(checkpoint) = &mut checkpoint {
+        if step <= AssemblerStartingStep::HashesSorting {
+            checkpoint.remove_buckets("hashes");
+            checkpoint.set_buckets("links", &links);
+            checkpoint.complete_step(
+                AssemblerStartingStep::HashesSorting,
+                &temp_dir,
+                !keep_files,
+            )?;
+        }
+    }
+
     if last_step <= AssemblerStartingStep::HashesSorting {
         PHASES_TIMES_MONITOR
             .write()
@@ -367,10 +578,29 @@ pub fn run_assembler<
             .unwrap();
         }
         result
+    } else if let Some(checkpoint) = &checkpoint {
+        (
+            checkpoint.get_buckets("unitigs_map")?,
+            checkpoint.get_buckets("results_map")?,
+        )
     } else {
         (unames, rnames)
     };
 
+    // Last checkpointed phase, the following ones write directly to the output file
+    if let Some(checkpoint) = &mut checkpoint {
+        if step <= AssemblerStartingStep::LinksCompaction {
+            checkpoint.remove_buckets("links");
+            checkpoint.set_buckets("unitigs_map", &unitigs_map);
+            checkpoint.set_buckets("results_map", &reads_map);
+            checkpoint.complete_step(
+                AssemblerStartingStep::LinksCompaction,
+                &temp_dir,
+                !keep_files,
+            )?;
+        }
+    }
+
     if last_step <= AssemblerStartingStep::LinksCompaction {
         PHASES_TIMES_MONITOR
             .write()
@@ -390,6 +620,7 @@ pub fn run_assembler<
     let compressed_temp_unitigs_file = if generate_maximal_unitigs_links
         || compute_tigs_mode.needs_matchtigs_library()
         || compute_tigs_mode == Some(MatchtigMode::FastEulerTigs)
+        || graph_cleaning.is_some()
     {
         Some(StructuredSequenceWriter::new(
             StructSeqBinaryWriter::new(
@@ -427,7 +658,10 @@ pub fn run_assembler<
     let (reorganized_reads, _final_unitigs_bucket) = if step
         <= AssemblerStartingStep::ReorganizeReads
     {
-        if generate_maximal_unitigs_links || compute_tigs_mode.needs_temporary_tigs() {
+        if generate_maximal_unitigs_links
+            || compute_tigs_mode.needs_temporary_tigs()
+            || graph_cleaning.is_some()
+        {
             reorganize_reads::<MergingHash, AssemblerColorsManager, StructSeqBinaryWriter<_, _>>(
                 k,
                 sequences,
@@ -474,6 +708,7 @@ pub fn run_assembler<
         if generate_maximal_unitigs_links
             || compute_tigs_mode.needs_matchtigs_library()
             || compute_tigs_mode == Some(MatchtigMode::FastEulerTigs)
+            || graph_cleaning.is_some()
         {
             build_unitigs::<MergingHash, AssemblerColorsManager, StructSeqBinaryWriter<_, _>>(
                 reorganized_reads,
@@ -516,16 +751,60 @@ pub fn run_assembler<
                 &final_unitigs_file,
                 k,
             );
-        } else if generate_maximal_unitigs_links || compute_tigs_mode.needs_matchtigs_library() {
+        } else if generate_maximal_unitigs_links
+            || compute_tigs_mode.needs_matchtigs_library()
+            || graph_cleaning.is_some()
+        {
             let compressed_temp_unitigs_file = compressed_temp_unitigs_file.unwrap();
-            let temp_path = compressed_temp_unitigs_file.get_path();
+            let mut temp_path = compressed_temp_unitigs_file.get_path();
             compressed_temp_unitigs_file.finalize();
 
+            if let Some(graph_cleaning) = graph_cleaning {
+                if generate_maximal_unitigs_links || compute_tigs_mode.needs_matchtigs_library() {
+                    // Store the cleaned unitigs in a new temporary file, to allow further processing
+                    let cleaned_temp_unitigs_file = StructuredSequenceWriter::new(
+                        StructSeqBinaryWriter::new(
+                            temp_dir.join("cleaned_unitigs.tmp"),
+                            &(
+                                get_memory_mode(SwapPriority::FinalMaps),
+                                CompressedCheckpointSize::new_from_size(
+                                    MemoryDataSize::from_mebioctets(4),
+                                ),
+                                get_compression_level_info(),
+                            ),
+                            &(),
+                        ),
+                        k,
+                    );
+
+                    clean_graph::<MergingHash, AssemblerColorsManager, _>(
+                        temp_path,
+                        temp_dir.as_path(),
+                        &cleaned_temp_unitigs_file,
+                        k,
+                        graph_cleaning,
+                    );
+
+                    temp_path = cleaned_temp_unitigs_file.get_path();
+                    cleaned_temp_unitigs_file.finalize();
+                } else {
+                    clean_graph::<MergingHash, AssemblerColorsManager, _>(
+                        temp_path.clone(),
+                        temp_dir.as_path(),
+                        &final_unitigs_file,
+                        k,
+                        graph_cleaning,
+                    );
+                }
+            }
+
             if let Some(compute_tigs_mode) = compute_tigs_mode.get_matchtigs_mode() {
                 let matchtigs_backend = MatchtigsStorageBackend::new();
 
                 let matchtigs_receiver = matchtigs_backend.get_receiver();
 
+                let matchtigs_temp_dir = temp_dir.clone();
+
                 let handle = std::thread::Builder::new()
                     .name("greedy_matchtigs".to_string())
                     .spawn(move || {
@@ -535,7 +814,9 @@ pub fn run_assembler<
                             matchtigs_receiver,
                             &final_unitigs_file,
                             compute_tigs_mode,
-                        );
+                            &matchtigs_temp_dir,
+                            &matchtigs_matcher_path,
+                        )
                     })
                     .unwrap();
 
@@ -550,7 +831,7 @@ pub fn run_assembler<
                     k,
                 );
 
-                handle.join().unwrap();
+                handle.join().unwrap()?;
             } else if generate_maximal_unitigs_links {
                 final_unitigs_file.finalize();
 
@@ -565,6 +846,8 @@ pub fn run_assembler<
                     OutputMode::Backend<_, _>,
                 >(temp_path, temp_dir.as_path(), &final_unitigs_file, k);
                 final_unitigs_file.finalize();
+            } else {
+                final_unitigs_file.finalize();
             }
         } else {
             final_unitigs_file.finalize();
@@ -573,6 +856,11 @@ pub fn run_assembler<
         final_unitigs_file.finalize();
     }
 
+    if let Some(checkpoint) = checkpoint {
+        checkpoint.finish(&temp_dir, !keep_files);
+    }
+    drop(files_guard);
+
     let _ = std::fs::remove_dir(temp_dir.as_path());
 
     PHASES_TIMES_MONITOR
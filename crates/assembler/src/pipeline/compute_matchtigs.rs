@@ -1,4 +1,6 @@
-use crate::pipeline::maximal_unitig_links::maximal_unitig_index::DoubleMaximalUnitigLinks;
+use crate::pipeline::maximal_unitig_links::maximal_unitig_index::{
+    DoubleMaximalUnitigLinks, MaximalUnitigIndex, MaximalUnitigLink,
+};
 use colors::colors_manager::color_types::PartialUnitigsColorStructure;
 use colors::colors_manager::{color_types, ColorsManager, ColorsMergeManager};
 use config::DEFAULT_OUTPUT_BUFFER_SIZE;
@@ -16,15 +18,17 @@ use io::concurrent::structured_sequences::{
 };
 use io::concurrent::temp_reads::extra_data::SequenceExtraDataTempBufferManagement;
 use libmatchtigs::{
-    EulertigAlgorithm, EulertigAlgorithmConfiguration, MatchtigEdgeData, PathtigAlgorithm,
+    EulertigAlgorithm, EulertigAlgorithmConfiguration, HeapType, MatchtigAlgorithm,
+    MatchtigAlgorithmConfiguration, MatchtigEdgeData, NodeWeightArrayType, PathtigAlgorithm,
 };
 use libmatchtigs::{GreedytigAlgorithm, GreedytigAlgorithmConfiguration, TigAlgorithm};
 use parallel_processor::phase_times_monitor::PHASES_TIMES_MONITOR;
 use std::fmt::Debug;
 use std::ops::Deref;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use traitgraph_algo::dijkstra::DijkstraWeightedEdgeData;
+use utils::vec_slice::VecSlice;
 
 #[cfg(feature = "support_kmer_counters")]
 use io::concurrent::structured_sequences::SequenceAbundance;
@@ -148,17 +152,18 @@ impl<ColorInfo: IdentSequenceWriter> DijkstraWeightedEdgeData<usize> for UnitigE
 }
 
 pub struct StructuredUnitigsStorage<ColorInfo: IdentSequenceWriter> {
-    first_sequence_index: usize,
-    sequences: Vec<(
+    pub(crate) first_sequence_index: usize,
+    pub(crate) sequences: Vec<(
         CompressedReadIndipendent,
         ColorInfo,
         SequenceAbundanceType,
         DoubleMaximalUnitigLinks,
     )>,
 
-    sequences_buffer: Vec<u8>,
-    links_buffer: <DoubleMaximalUnitigLinks as SequenceExtraDataTempBufferManagement>::TempBuffer,
-    color_buffer: ColorInfo::TempBuffer,
+    pub(crate) sequences_buffer: Vec<u8>,
+    pub(crate) links_buffer:
+        <DoubleMaximalUnitigLinks as SequenceExtraDataTempBufferManagement>::TempBuffer,
+    pub(crate) color_buffer: ColorInfo::TempBuffer,
 }
 
 impl<ColorInfo: IdentSequenceWriter> StructuredUnitigsStorage<ColorInfo> {
@@ -311,10 +316,28 @@ pub enum MatchtigMode {
     FastEulerTigs,
     EulerTigs,
     GreedyTigs,
-    // MatchTigs,
+    MatchTigs,
     PathTigs,
 }
 
+/// Default command used to run the blossom5 matcher needed to compute optimal matchtigs
+pub const DEFAULT_MATCHTIGS_MATCHER: &str = "blossom5";
+
+/// Finds the blossom5 matcher binary, either from an explicit path or by searching it in the PATH
+pub fn find_matchtigs_matcher(matcher_path: Option<&Path>) -> Option<PathBuf> {
+    let matcher_path = matcher_path.unwrap_or(Path::new(DEFAULT_MATCHTIGS_MATCHER));
+
+    if matcher_path.components().count() > 1 {
+        return matcher_path.is_file().then(|| matcher_path.to_path_buf());
+    }
+
+    std::env::var_os("PATH").and_then(|paths| {
+        std::env::split_paths(&paths)
+            .map(|dir| dir.join(matcher_path))
+            .find(|path| path.is_file())
+    })
+}
+
 pub trait MatchtigHelperTrait {
     fn needs_simplitigs(&self) -> bool;
     fn needs_temporary_tigs(&self) -> bool;
@@ -330,6 +353,7 @@ impl MatchtigHelperTrait for Option<MatchtigMode> {
     fn needs_temporary_tigs(&self) -> bool {
         *self == Some(MatchtigMode::EulerTigs)
             || *self == Some(MatchtigMode::GreedyTigs)
+            || *self == Some(MatchtigMode::MatchTigs)
             || *self == Some(MatchtigMode::PathTigs)
             || *self == Some(MatchtigMode::FastEulerTigs)
     }
@@ -337,6 +361,7 @@ impl MatchtigHelperTrait for Option<MatchtigMode> {
     fn needs_matchtigs_library(&self) -> bool {
         *self == Some(MatchtigMode::EulerTigs)
             || *self == Some(MatchtigMode::GreedyTigs)
+            || *self == Some(MatchtigMode::MatchTigs)
             || *self == Some(MatchtigMode::PathTigs)
     }
 
@@ -349,6 +374,209 @@ impl MatchtigHelperTrait for Option<MatchtigMode> {
     }
 }
 
+/// The segments of the maximal unitigs split at the color subsets boundaries
+struct ColorSegments<C> {
+    /// For each unitig, the first segment index, the segments count and the colors at its ends
+    unitigs: Vec<(usize, usize, C, C)>,
+    segments_count: usize,
+}
+
+impl<C: Copy + PartialEq> ColorSegments<C> {
+    fn new() -> Self {
+        Self {
+            unitigs: vec![],
+            segments_count: 0,
+        }
+    }
+
+    /// Adds the next unitig, with its (color, kmers count) runs
+    fn add_unitig(&mut self, runs: &[(C, usize)]) -> anyhow::Result<()> {
+        let (Some(first), Some(last)) = (runs.first(), runs.last()) else {
+            anyhow::bail!("Unitig {} has no colors", self.unitigs.len());
+        };
+        self.unitigs
+            .push((self.segments_count, runs.len(), first.0, last.0));
+        self.segments_count += runs.len();
+        Ok(())
+    }
+
+    fn first_segment(&self, unitig: usize) -> usize {
+        self.unitigs[unitig].0
+    }
+
+    /// Returns the segment reached by a link to a unitig side, if its kmers have the given color
+    fn linked_segment(&self, unitig: usize, is_end: bool, color: C) -> Option<usize> {
+        let (first_segment, segments_count, begin_color, end_color) = self.unitigs[unitig];
+        let (segment, segment_color) = if is_end {
+            (first_segment + segments_count - 1, end_color)
+        } else {
+            (first_segment, begin_color)
+        };
+        (segment_color == color).then_some(segment)
+    }
+}
+
+/// Splits the maximal unitigs at the color subsets boundaries, keeping only the links between
+/// unitig ends having the same color subset. This allows the tigs to never join kmers with different colors.
+/// Each storage is replaced by the storage of its segments, so that only one storage at a time is duplicated
+fn split_color_subsets<CX: ColorsManager>(
+    mut storages: Vec<Arc<StructuredUnitigsStorage<PartialUnitigsColorStructure<CX>>>>,
+    k: usize,
+) -> anyhow::Result<Vec<Arc<StructuredUnitigsStorage<PartialUnitigsColorStructure<CX>>>>> {
+    storages.retain(|s| !s.sequences.is_empty());
+    storages.sort_unstable_by_key(|s| s.first_sequence_index);
+
+    let mut runs = vec![];
+    let mut segments = ColorSegments::new();
+
+    for storage in &storages {
+        for (_, color, _, _) in &storage.sequences {
+            CX::ColorsMergeManagerType::get_color_runs(color, &storage.color_buffer, &mut runs)?;
+            segments.add_unitig(&runs)?;
+        }
+    }
+
+    let mut temp_color = color_types::ColorsMergeManagerType::<CX>::alloc_unitig_color_structure();
+    let mut temp_color_buffer = color_types::PartialUnitigsColorStructure::<CX>::new_temp_buffer();
+    let mut bases = vec![];
+
+    let mut results = Vec::with_capacity(storages.len());
+
+    for storage in storages {
+        let mut result = StructuredUnitigsStorage::new();
+        result.first_sequence_index = segments.first_segment(storage.first_sequence_index);
+
+        for (index, sequence) in storage.sequences.iter().enumerate() {
+            let (read, color, _abundance, links) = sequence;
+            let read = read.as_reference(&storage.sequences_buffer);
+            let first_segment = segments.first_segment(storage.first_sequence_index + index);
+
+            CX::ColorsMergeManagerType::get_color_runs(color, &storage.color_buffer, &mut runs)?;
+            let unitig_runs = runs.len();
+
+            let mut kmers_offset = 0;
+            for (run_index, &(run_color, kmers_count)) in runs.iter().enumerate() {
+                let segment_read =
+                    read.sub_slice(kmers_offset..(kmers_offset + kmers_count + k - 1));
+                bases.clear();
+                bases.extend(segment_read.as_bases_iter());
+
+                color_types::ColorsMergeManagerType::<CX>::reset_unitig_color_structure(
+                    &mut temp_color,
+                );
+                CX::ColorsMergeManagerType::join_structures::<false>(
+                    &mut temp_color,
+                    color,
+                    &storage.color_buffer,
+                    kmers_offset,
+                    Some(kmers_count),
+                );
+                let segment_color =
+                    color_types::ColorsMergeManagerType::<CX>::encode_part_unitigs_colors(
+                        &mut temp_color,
+                        &mut temp_color_buffer,
+                    );
+
+                // Keep only the links at the unitig ends that reach a segment with the same color subset
+                let links_start = result.links_buffer.len();
+                for (side_segment, is_begin) in [(0, true), (unitig_runs - 1, false)] {
+                    if run_index != side_segment {
+                        continue;
+                    }
+                    for link in &links.links {
+                        for entry in link.entries.get_slice(&storage.links_buffer) {
+                            if entry.flags.flip_current() != is_begin {
+                                continue;
+                            }
+                            if let Some(other_segment) = segments.linked_segment(
+                                entry.index() as usize,
+                                entry.flags.flip_other(),
+                                run_color,
+                            ) {
+                                result.links_buffer.push(MaximalUnitigIndex::new(
+                                    other_segment as u64,
+                                    0,
+                                    entry.flags,
+                                ));
+                            }
+                        }
+                    }
+                }
+
+                let is_self_complemental = if unitig_runs == 1 {
+                    links.is_self_complemental
+                } else {
+                    segment_read
+                        .sub_slice(0..(k - 1))
+                        .as_reverse_complement_bases_iter()
+                        .eq(segment_read
+                            .sub_slice(
+                                (segment_read.get_length() - k + 1)..segment_read.get_length(),
+                            )
+                            .as_bases_iter())
+                };
+
+                let segment_links = DoubleMaximalUnitigLinks {
+                    links: [
+                        MaximalUnitigLink::new(
+                            (first_segment + run_index) as u64,
+                            VecSlice::new(links_start, result.links_buffer.len() - links_start),
+                        ),
+                        MaximalUnitigLink::new((first_segment + run_index) as u64, VecSlice::EMPTY),
+                    ],
+                    is_self_complemental,
+                };
+
+                // Abundances are not available for the single kmers, so they are approximated using the unitig mean
+                #[cfg(feature = "support_kmer_counters")]
+                let segment_abundance = {
+                    let unitig_kmers = read.get_length() - k + 1;
+                    let mean = _abundance.sum as f64 / unitig_kmers as f64;
+                    SequenceAbundance {
+                        first: if run_index == 0 {
+                            _abundance.first
+                        } else {
+                            mean.round() as u64
+                        },
+                        sum: (mean * kmers_count as f64).round() as u64,
+                        last: if run_index == unitig_runs - 1 {
+                            _abundance.last
+                        } else {
+                            mean.round() as u64
+                        },
+                    }
+                };
+
+                let segment_read =
+                    CompressedReadIndipendent::from_plain(&bases, &mut result.sequences_buffer);
+                let segment_color = PartialUnitigsColorStructure::<CX>::copy_extra_from(
+                    segment_color,
+                    &temp_color_buffer,
+                    &mut result.color_buffer,
+                );
+
+                result.sequences.push((
+                    segment_read,
+                    segment_color,
+                    match () {
+                        #[cfg(feature = "support_kmer_counters")]
+                        () => segment_abundance,
+                        #[cfg(not(feature = "support_kmer_counters"))]
+                        () => (),
+                    },
+                    segment_links,
+                ));
+
+                kmers_offset += kmers_count;
+            }
+        }
+
+        results.push(Arc::new(result));
+    }
+
+    Ok(results)
+}
+
 pub fn compute_matchtigs_thread<
     CX: ColorsManager,
     BK: StructuredSequenceBackend<PartialUnitigsColorStructure<CX>, ()>,
@@ -358,8 +586,16 @@ pub fn compute_matchtigs_thread<
     input_data: Receiver<Arc<StructuredUnitigsStorage<PartialUnitigsColorStructure<CX>>>>,
     out_file: &StructuredSequenceWriter<PartialUnitigsColorStructure<CX>, (), BK>,
     mode: MatchtigMode,
-) {
-    let iterator = input_data
+    temp_dir: &Path,
+    matcher_path: &Path,
+) -> anyhow::Result<()> {
+    let storages: Vec<_> = if CX::COLORS_ENABLED {
+        split_color_subsets::<CX>(input_data.into_iter().collect(), k)?
+    } else {
+        input_data.into_iter().collect()
+    };
+
+    let iterator = storages
         .into_iter()
         .map(|storage| {
             (0..storage.sequences.len())
@@ -378,9 +614,14 @@ pub fn compute_matchtigs_thread<
 
     #[cfg(feature = "support_kmer_counters")]
     {
-        if matches!(mode, MatchtigMode::GreedyTigs) {
+        if matches!(mode, MatchtigMode::GreedyTigs | MatchtigMode::MatchTigs) {
             ggcat_logging::warn!(
-                "Abundancies support with greedy matchtigs is not accurate for merged unitigs!"
+                "Abundancies support with matchtigs is not accurate for merged unitigs!"
+            );
+        }
+        if CX::COLORS_ENABLED {
+            ggcat_logging::warn!(
+                "Abundancies support is not accurate for unitigs split at color subsets boundaries!"
             );
         }
     }
@@ -392,6 +633,7 @@ pub fn compute_matchtigs_thread<
     let phase_name = match mode {
         MatchtigMode::EulerTigs => "eulertigs",
         MatchtigMode::GreedyTigs => "greedy matchtigs",
+        MatchtigMode::MatchTigs => "matchtigs",
         MatchtigMode::PathTigs => "pathtigs",
         MatchtigMode::FastSimpliTigs => unreachable!(),
         MatchtigMode::FastEulerTigs => unreachable!(),
@@ -427,12 +669,17 @@ pub fn compute_matchtigs_thread<
             &GreedytigAlgorithmConfiguration::new(threads_count, k),
         ),
         MatchtigMode::PathTigs => PathtigAlgorithm::compute_tigs(&mut graph, &()),
-        // MatchtigMode::MatchTigs => {
-        //     MatchtigAlgorithm::compute_tigs(
-        //         &mut graph,
-        //         &MatchtigAlgorithmConfiguration::new(threads_count, k),
-        //     )
-        // },
+        MatchtigMode::MatchTigs => MatchtigAlgorithm::compute_tigs(
+            &mut graph,
+            &MatchtigAlgorithmConfiguration {
+                threads: threads_count,
+                k,
+                node_weight_array_type: NodeWeightArrayType::HashbrownHashMap,
+                heap_type: HeapType::StdBinaryHeap,
+                matching_file_prefix: &temp_dir.join("matchtigs"),
+                matcher_path,
+            },
+        ),
         MatchtigMode::EulerTigs => {
             EulertigAlgorithm::compute_tigs(&mut graph, &EulertigAlgorithmConfiguration { k })
         }
@@ -454,7 +701,13 @@ pub fn compute_matchtigs_thread<
     let mut final_color_extra_buffer =
         color_types::PartialUnitigsColorStructure::<CX>::new_temp_buffer();
 
-    for walk in tigs.iter() {
+    // With colors, the walks are split at the dummy edges, as they can join kmers with different color subsets
+    let walks = tigs.iter().flat_map(|walk| {
+        walk.split(|edge| CX::COLORS_ENABLED && graph.edge_data(*edge).is_dummy())
+            .filter(|walk| !walk.is_empty())
+    });
+
+    for walk in walks {
         // Reset the colors
         color_types::ColorsMergeManagerType::<CX>::reset_unitig_color_structure(
             &mut final_unitig_color,
@@ -519,11 +772,6 @@ pub fn compute_matchtigs_thread<
                 Some(handle) => handle,
                 None => {
                     // The edge is dummy
-
-                    if CX::COLORS_ENABLED {
-                        // TODO: Skip colors data
-                        panic!("Matchtigs are not supported with colors");
-                    }
                     assert!(edge_data.is_dummy());
                     continue;
                 }
@@ -588,4 +836,43 @@ pub fn compute_matchtigs_thread<
             abundance,
         );
     }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn color_segments_links_splitting() {
+        let mut segments = ColorSegments::new();
+        // Unitig 0 has colors 1 then 2, unitig 1 has color 2 and unitig 2 has colors 3, 1 and 2
+        segments.add_unitig(&[(1, 5), (2, 3)]).unwrap();
+        segments.add_unitig(&[(2, 4)]).unwrap();
+        segments.add_unitig(&[(3, 2), (1, 1), (2, 7)]).unwrap();
+
+        assert_eq!(segments.segments_count, 6);
+        assert_eq!(segments.first_segment(0), 0);
+        assert_eq!(segments.first_segment(1), 2);
+        assert_eq!(segments.first_segment(2), 3);
+
+        // The end of unitig 0 and the begin of unitig 1 have the same color
+        assert_eq!(segments.linked_segment(0, true, 2), Some(1));
+        assert_eq!(segments.linked_segment(1, false, 2), Some(2));
+        // A single segment unitig is linked at both ends
+        assert_eq!(segments.linked_segment(1, true, 2), Some(2));
+
+        // Links to a different color are dropped
+        assert_eq!(segments.linked_segment(0, false, 2), None);
+        assert_eq!(segments.linked_segment(2, false, 1), None);
+        assert_eq!(segments.linked_segment(2, false, 3), Some(3));
+        assert_eq!(segments.linked_segment(2, true, 2), Some(5));
+    }
+
+    #[test]
+    fn color_segments_without_colors() {
+        let mut segments = ColorSegments::<u32>::new();
+        assert!(segments.add_unitig(&[]).is_err());
+    }
 }
@@ -0,0 +1,559 @@
+use crate::pipeline::compute_matchtigs::{MatchtigsStorageBackend, StructuredUnitigsStorage};
+use crate::pipeline::maximal_unitig_links::build_maximal_unitigs_links;
+use colors::colors_manager::color_types::PartialUnitigsColorStructure;
+use colors::colors_manager::{color_types, ColorsManager, ColorsMergeManager};
+use config::DEFAULT_OUTPUT_BUFFER_SIZE;
+use hashes::HashFunctionFactory;
+use io::concurrent::structured_sequences::concurrent::FastaWriterConcurrentBuffer;
+use io::concurrent::structured_sequences::{
+    IdentSequenceWriter, StructuredSequenceBackend, StructuredSequenceWriter,
+};
+use io::concurrent::temp_reads::extra_data::SequenceExtraDataTempBufferManagement;
+use parallel_processor::phase_times_monitor::PHASES_TIMES_MONITOR;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use structs::unitigs_graph::{compact_unitigs_chains, UnitigSide, UnitigsGraph, BEGIN, END};
+
+#[cfg(feature = "support_kmer_counters")]
+use io::concurrent::structured_sequences::SequenceAbundance;
+
+/// Parameters of the optional graph cleaning step, removing tips and simple bubbles
+#[derive(Copy, Clone, Debug)]
+pub struct GraphCleaningParams {
+    /// Maximum length (in bases) of tips and bubble branches that can be removed, defaults to 2k
+    pub max_length: Option<usize>,
+    /// A tip or a bubble branch has a low coverage if its average kmer coverage
+    /// is lower than this fraction of the coverage of the competing branch
+    pub coverage_ratio: f64,
+}
+
+impl Default for GraphCleaningParams {
+    fn default() -> Self {
+        Self {
+            max_length: None,
+            coverage_ratio: 0.5,
+        }
+    }
+}
+
+/// The unitigs of a graph being cleaned
+trait CleaningUnitigs {
+    fn unitigs_count(&self) -> usize;
+
+    fn bases_count(&self, unitig: usize) -> usize;
+
+    fn is_self_complemental(&self, unitig: usize) -> bool;
+
+    /// The average kmer coverage of the unitig, if known
+    fn coverage(&self, unitig: usize, k: usize) -> Option<f64>;
+
+    /// Returns the unitig sides linked to the given side of a unitig, including the removed ones
+    fn links(&self, unitig: usize, side: usize) -> Vec<UnitigSide>;
+}
+
+/// The maximal unitigs with their links, as received from the links computation
+struct StoredUnitigs<ColorInfo: IdentSequenceWriter> {
+    storages: Vec<Arc<StructuredUnitigsStorage<ColorInfo>>>,
+}
+
+impl<ColorInfo: IdentSequenceWriter> StoredUnitigs<ColorInfo> {
+    fn new(mut storages: Vec<Arc<StructuredUnitigsStorage<ColorInfo>>>) -> Self {
+        storages.retain(|s| !s.sequences.is_empty());
+        storages.sort_unstable_by_key(|s| s.first_sequence_index);
+        Self { storages }
+    }
+
+    #[inline]
+    fn storage(&self, unitig: usize) -> (&StructuredUnitigsStorage<ColorInfo>, usize) {
+        let storage_index = self
+            .storages
+            .partition_point(|s| s.first_sequence_index <= unitig)
+            - 1;
+        let storage = &self.storages[storage_index];
+        (storage, unitig - storage.first_sequence_index)
+    }
+}
+
+impl<ColorInfo: IdentSequenceWriter> CleaningUnitigs for StoredUnitigs<ColorInfo> {
+    fn unitigs_count(&self) -> usize {
+        self.storages
+            .last()
+            .map(|s| s.first_sequence_index + s.sequences.len())
+            .unwrap_or(0)
+    }
+
+    fn bases_count(&self, unitig: usize) -> usize {
+        let (storage, index) = self.storage(unitig);
+        storage.sequences[index].0.bases_count()
+    }
+
+    fn is_self_complemental(&self, unitig: usize) -> bool {
+        let (storage, index) = self.storage(unitig);
+        storage.sequences[index].3.is_self_complemental
+    }
+
+    #[cfg_attr(not(feature = "support_kmer_counters"), allow(unused_variables))]
+    fn coverage(&self, unitig: usize, k: usize) -> Option<f64> {
+        #[cfg(feature = "support_kmer_counters")]
+        {
+            let (storage, index) = self.storage(unitig);
+            let (sequence, _, abundance, _) = &storage.sequences[index];
+            Some(abundance.sum as f64 / (sequence.bases_count() - k + 1) as f64)
+        }
+        #[cfg(not(feature = "support_kmer_counters"))]
+        {
+            None
+        }
+    }
+
+    fn links(&self, unitig: usize, side: usize) -> Vec<UnitigSide> {
+        let (storage, index) = self.storage(unitig);
+        storage.sequences[index]
+            .3
+            .links
+            .iter()
+            .flat_map(|link| link.entries.get_slice(&storage.links_buffer))
+            .filter(|entry| entry.flags.flip_current() == (side == BEGIN))
+            .map(|entry| {
+                (
+                    entry.index() as usize,
+                    if entry.flags.flip_other() { END } else { BEGIN },
+                )
+            })
+            .collect()
+    }
+}
+
+struct CleaningGraph<U: CleaningUnitigs> {
+    unitigs: U,
+    removed: Vec<bool>,
+}
+
+impl<U: CleaningUnitigs> CleaningGraph<U> {
+    fn new(unitigs: U) -> Self {
+        Self {
+            removed: vec![false; unitigs.unitigs_count()],
+            unitigs,
+        }
+    }
+
+    fn bases_count(&self, unitig: usize) -> usize {
+        self.unitigs.bases_count(unitig)
+    }
+
+    fn coverage(&self, unitig: usize, k: usize) -> Option<f64> {
+        self.unitigs.coverage(unitig, k)
+    }
+}
+
+impl<U: CleaningUnitigs> UnitigsGraph for CleaningGraph<U> {
+    fn unitigs_count(&self) -> usize {
+        self.removed.len()
+    }
+
+    fn is_removed(&self, unitig: usize) -> bool {
+        self.removed[unitig]
+    }
+
+    fn links(&self, unitig: usize, side: usize) -> Vec<UnitigSide> {
+        let mut result = self.unitigs.links(unitig, side);
+        result.retain(|(other, _)| !self.removed[*other]);
+        result.sort_unstable();
+        result.dedup();
+        result
+    }
+
+    fn is_self_complemental(&self, unitig: usize) -> bool {
+        self.unitigs.is_self_complemental(unitig)
+    }
+}
+
+/// Returns true if the coverage is lower than `coverage_ratio` times the competing coverage.
+/// Unknown coverages are never low, so that no kmers are removed without a coverage check
+#[inline]
+fn is_low_coverage(
+    coverage: Option<f64>,
+    competing_coverage: Option<f64>,
+    coverage_ratio: f64,
+) -> bool {
+    match (coverage, competing_coverage) {
+        (Some(coverage), Some(competing_coverage)) => {
+            coverage < competing_coverage * coverage_ratio
+        }
+        _ => false,
+    }
+}
+
+fn remove_tips<U: CleaningUnitigs>(
+    graph: &mut CleaningGraph<U>,
+    k: usize,
+    max_length: usize,
+    coverage_ratio: f64,
+) -> usize {
+    let mut removed_tips = 0;
+
+    for unitig in 0..graph.removed.len() {
+        if graph.removed[unitig] || graph.bases_count(unitig) >= max_length {
+            continue;
+        }
+
+        let begin_links = graph.links(unitig, BEGIN);
+        let end_links = graph.links(unitig, END);
+
+        let junction = match (begin_links.as_slice(), end_links.as_slice()) {
+            (&[], &[junction]) | (&[junction], &[]) => junction,
+            _ => continue,
+        };
+
+        if junction.0 == unitig {
+            continue;
+        }
+
+        // A tip is removed only if it hangs from a branching node,
+        // so that the linear ends of the graph are always kept
+        let siblings_coverage: Option<Vec<_>> = graph
+            .links(junction.0, junction.1)
+            .into_iter()
+            .filter(|(sibling, _)| *sibling != unitig)
+            .map(|(sibling, _)| graph.coverage(sibling, k))
+            .collect();
+
+        let best_sibling_coverage = siblings_coverage
+            .and_then(|coverages| coverages.into_iter().max_by(|a, b| a.total_cmp(b)));
+
+        if is_low_coverage(
+            graph.coverage(unitig, k),
+            best_sibling_coverage,
+            coverage_ratio,
+        ) {
+            graph.removed[unitig] = true;
+            removed_tips += 1;
+        }
+    }
+
+    removed_tips
+}
+
+fn pop_bubbles<U: CleaningUnitigs>(
+    graph: &mut CleaningGraph<U>,
+    k: usize,
+    max_length: usize,
+    coverage_ratio: f64,
+) -> usize {
+    let mut popped_bubbles = 0;
+
+    for unitig in 0..graph.removed.len() {
+        if graph.removed[unitig] || graph.bases_count(unitig) >= max_length {
+            continue;
+        }
+
+        let (source, sink) = match (
+            graph.links(unitig, BEGIN).as_slice(),
+            graph.links(unitig, END).as_slice(),
+        ) {
+            (&[source], &[sink]) => (source, sink),
+            _ => continue,
+        };
+
+        if source.0 == unitig || sink.0 == unitig {
+            continue;
+        }
+
+        // Look for another simple branch connecting the same source and sink,
+        // removing the branch only if its coverage is low compared to the other one
+        let coverage = graph.coverage(unitig, k);
+        let is_weaker_branch = graph
+            .links(source.0, source.1)
+            .into_iter()
+            .filter(|(other, _)| *other != unitig && *other != source.0 && *other != sink.0)
+            .filter(|&(other, other_side)| {
+                graph.links(other, other_side).len() == 1
+                    && graph.links(other, 1 - other_side).as_slice() == [sink]
+            })
+            .any(|(other, _)| is_low_coverage(coverage, graph.coverage(other, k), coverage_ratio));
+
+        if is_weaker_branch {
+            graph.removed[unitig] = true;
+            popped_bubbles += 1;
+        }
+    }
+
+    popped_bubbles
+}
+
+/// Removes short low coverage tips and simple bubbles from the maximal unitigs stored in `in_file`,
+/// writing the recompacted unitigs (with their colors) to `out_file`.
+/// The output does not contain links, as they must be recomputed after the cleaning.
+pub fn clean_graph<
+    MH: HashFunctionFactory,
+    CX: ColorsManager,
+    BK: StructuredSequenceBackend<PartialUnitigsColorStructure<CX>, ()>,
+>(
+    in_file: PathBuf,
+    temp_dir: &Path,
+    out_file: &StructuredSequenceWriter<PartialUnitigsColorStructure<CX>, (), BK>,
+    k: usize,
+    params: GraphCleaningParams,
+) {
+    let storage_backend = MatchtigsStorageBackend::new();
+    let storage_receiver = storage_backend.get_receiver();
+
+    // Links are needed to find the graph topology
+    build_maximal_unitigs_links::<MH, CX, MatchtigsStorageBackend<_>>(
+        in_file,
+        temp_dir,
+        &StructuredSequenceWriter::new(storage_backend, k),
+        k,
+    );
+
+    PHASES_TIMES_MONITOR
+        .write()
+        .start_phase("phase: graph cleaning".to_string());
+
+    let mut graph = CleaningGraph::new(StoredUnitigs::new(storage_receiver.into_iter().collect()));
+
+    let max_length = params.max_length.unwrap_or(2 * k);
+    let removed_tips = remove_tips(&mut graph, k, max_length, params.coverage_ratio);
+    let popped_bubbles = pop_bubbles(&mut graph, k, max_length, params.coverage_ratio);
+
+    ggcat_logging::info!(
+        "Graph cleaning removed {} tips and {} bubbles",
+        removed_tips,
+        popped_bubbles
+    );
+
+    // Recompact the remaining unitigs
+    let mut output_buffer =
+        FastaWriterConcurrentBuffer::new(out_file, DEFAULT_OUTPUT_BUFFER_SIZE, true);
+
+    let mut read_buffer = Vec::new();
+
+    let mut final_unitig_color =
+        color_types::ColorsMergeManagerType::<CX>::alloc_unitig_color_structure();
+    let mut final_color_extra_buffer =
+        color_types::PartialUnitigsColorStructure::<CX>::new_temp_buffer();
+
+    let mut merged_unitigs = 0;
+
+    compact_unitigs_chains(&graph, |chain| {
+        merged_unitigs += chain.len() - 1;
+
+        color_types::ColorsMergeManagerType::<CX>::reset_unitig_color_structure(
+            &mut final_unitig_color,
+        );
+        color_types::PartialUnitigsColorStructure::<CX>::clear_temp_buffer(
+            &mut final_color_extra_buffer,
+        );
+        read_buffer.clear();
+
+        #[cfg(feature = "support_kmer_counters")]
+        let mut abundance = SequenceAbundance {
+            first: 0,
+            sum: 0,
+            last: 0,
+        };
+
+        for (position, &(unitig, forward)) in chain.iter().enumerate() {
+            let (storage, index) = graph.unitigs.storage(unitig);
+            let (sequence, color, _abundance, _) = &storage.sequences[index];
+            let sequence = sequence.as_reference(&storage.sequences_buffer);
+
+            let bases_offset = if position == 0 { 0 } else { k - 1 };
+
+            if forward {
+                read_buffer.extend(sequence.as_bases_iter().skip(bases_offset));
+                CX::ColorsMergeManagerType::join_structures::<false>(
+                    &mut final_unitig_color,
+                    color,
+                    &storage.color_buffer,
+                    0,
+                    None,
+                );
+            } else {
+                read_buffer.extend(
+                    sequence
+                        .as_reverse_complement_bases_iter()
+                        .skip(bases_offset),
+                );
+                CX::ColorsMergeManagerType::join_structures::<true>(
+                    &mut final_unitig_color,
+                    color,
+                    &storage.color_buffer,
+                    0,
+                    None,
+                );
+            }
+
+            #[cfg(feature = "support_kmer_counters")]
+            {
+                let (first, last) = if forward {
+                    (_abundance.first, _abundance.last)
+                } else {
+                    (_abundance.last, _abundance.first)
+                };
+                if position == 0 {
+                    abundance.first = first;
+                }
+                abundance.sum += _abundance.sum;
+                abundance.last = last;
+            }
+        }
+
+        let writable_color = color_types::ColorsMergeManagerType::<CX>::encode_part_unitigs_colors(
+            &mut final_unitig_color,
+            &mut final_color_extra_buffer,
+        );
+
+        output_buffer.add_read(
+            &read_buffer,
+            None,
+            writable_color,
+            &final_color_extra_buffer,
+            (),
+            &(),
+            #[cfg(feature = "support_kmer_counters")]
+            abundance,
+        );
+    });
+
+    ggcat_logging::info!("Graph cleaning merged {} unitigs", merged_unitigs);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const K: usize = 31;
+
+    /// A graph with the given (bases, coverage) unitigs and (side, side) links
+    struct TestUnitigs {
+        unitigs: Vec<(usize, Option<f64>)>,
+        links: Vec<(UnitigSide, UnitigSide)>,
+    }
+
+    impl CleaningUnitigs for TestUnitigs {
+        fn unitigs_count(&self) -> usize {
+            self.unitigs.len()
+        }
+
+        fn bases_count(&self, unitig: usize) -> usize {
+            self.unitigs[unitig].0
+        }
+
+        fn is_self_complemental(&self, _unitig: usize) -> bool {
+            false
+        }
+
+        fn coverage(&self, unitig: usize, _k: usize) -> Option<f64> {
+            self.unitigs[unitig].1
+        }
+
+        fn links(&self, unitig: usize, side: usize) -> Vec<UnitigSide> {
+            self.links
+                .iter()
+                .filter_map(|&(first, second)| {
+                    if first == (unitig, side) {
+                        Some(second)
+                    } else if second == (unitig, side) {
+                        Some(first)
+                    } else {
+                        None
+                    }
+                })
+                .collect()
+        }
+    }
+
+    fn chains<U: CleaningUnitigs>(graph: &CleaningGraph<U>) -> Vec<Vec<(usize, bool)>> {
+        let mut chains = vec![];
+        compact_unitigs_chains(graph, |chain| chains.push(chain.to_vec()));
+        chains
+    }
+
+    /// 0 -> 1 with a tip 0 -> 2
+    fn tip_graph(tip_coverage: Option<f64>) -> CleaningGraph<TestUnitigs> {
+        CleaningGraph::new(TestUnitigs {
+            unitigs: vec![(100, Some(20.0)), (100, Some(20.0)), (40, tip_coverage)],
+            links: vec![((0, END), (1, BEGIN)), ((0, END), (2, BEGIN))],
+        })
+    }
+
+    /// 0 -> 1 -> 3 and 0 -> 2 -> 3
+    fn bubble_graph(coverages: [Option<f64>; 2]) -> CleaningGraph<TestUnitigs> {
+        CleaningGraph::new(TestUnitigs {
+            unitigs: vec![
+                (100, Some(20.0)),
+                (61, coverages[0]),
+                (61, coverages[1]),
+                (100, Some(20.0)),
+            ],
+            links: vec![
+                ((0, END), (1, BEGIN)),
+                ((0, END), (2, BEGIN)),
+                ((1, END), (3, BEGIN)),
+                ((2, END), (3, BEGIN)),
+            ],
+        })
+    }
+
+    #[test]
+    fn low_coverage_tip_removal() {
+        let mut graph = tip_graph(Some(2.0));
+        assert_eq!(remove_tips(&mut graph, K, 2 * K, 0.5), 1);
+        assert_eq!(graph.removed, vec![false, false, true]);
+        assert_eq!(chains(&graph), vec![vec![(0, true), (1, true)]]);
+    }
+
+    #[test]
+    fn tips_are_kept_without_low_coverage() {
+        // High coverage tip
+        let mut graph = tip_graph(Some(15.0));
+        assert_eq!(remove_tips(&mut graph, K, 2 * K, 0.5), 0);
+
+        // Unknown coverage
+        let mut graph = tip_graph(None);
+        assert_eq!(remove_tips(&mut graph, K, 2 * K, 0.5), 0);
+
+        // Longer than the maximum length
+        let mut graph = tip_graph(Some(2.0));
+        assert_eq!(remove_tips(&mut graph, K, 40, 0.5), 0);
+        assert_eq!(chains(&graph).len(), 3);
+    }
+
+    #[test]
+    fn linear_ends_are_not_tips() {
+        let mut graph = CleaningGraph::new(TestUnitigs {
+            unitigs: vec![(40, Some(1.0)), (100, Some(20.0))],
+            links: vec![((0, END), (1, BEGIN))],
+        });
+        assert_eq!(remove_tips(&mut graph, K, 2 * K, 0.5), 0);
+    }
+
+    #[test]
+    fn low_coverage_bubble_popping() {
+        let mut graph = bubble_graph([Some(20.0), Some(3.0)]);
+        assert_eq!(pop_bubbles(&mut graph, K, 2 * K, 0.5), 1);
+        assert_eq!(graph.removed, vec![false, false, true, false]);
+        assert_eq!(chains(&graph), vec![vec![(0, true), (1, true), (3, true)]]);
+    }
+
+    #[test]
+    fn bubbles_are_kept_without_low_coverage() {
+        // Similar coverages, as in two alleles
+        let mut graph = bubble_graph([Some(20.0), Some(15.0)]);
+        assert_eq!(pop_bubbles(&mut graph, K, 2 * K, 0.5), 0);
+
+        // Unknown coverages
+        let mut graph = bubble_graph([None, None]);
+        assert_eq!(pop_bubbles(&mut graph, K, 2 * K, 0.5), 0);
+        assert_eq!(chains(&graph).len(), 4);
+    }
+
+    #[test]
+    fn only_one_bubble_branch_is_popped() {
+        // Both branches are below the ratio of each other, only the first weaker one is removed
+        let mut graph = bubble_graph([Some(20.0), Some(15.0)]);
+        assert_eq!(pop_bubbles(&mut graph, K, 2 * K, 2.0), 1);
+        assert_eq!(graph.removed.iter().filter(|r| **r).count(), 1);
+    }
+}
@@ -31,6 +31,7 @@ use parallel_processor::buckets::{
 use parallel_processor::execution_manager::memory_tracker::MemoryTracker;
 use parallel_processor::phase_times_monitor::PHASES_TIMES_MONITOR;
 use std::cmp::min;
+use std::collections::HashSet;
 use std::marker::PhantomData;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicU64;
@@ -223,6 +224,7 @@ pub fn kmers_merge<MH: HashFunctionFactory, CX: ColorsManager, P: AsRef<Path> +
     if compute_simplitigs {
         KmersTransform::<ParallelKmersMergeFactory<MH, CX, true>>::new(
             file_inputs,
+            HashSet::new(),
             out_directory.as_ref(),
             buckets_counters_path,
             buckets_count,
@@ -235,6 +237,7 @@ pub fn kmers_merge<MH: HashFunctionFactory, CX: ColorsManager, P: AsRef<Path> +
     } else {
         KmersTransform::<ParallelKmersMergeFactory<MH, CX, false>>::new(
             file_inputs,
+            HashSet::new(),
             out_directory.as_ref(),
             buckets_counters_path,
             buckets_count,
@@ -294,7 +297,7 @@ mod tests {
         let counters = Path::new(TEMP_DIR).join("buckets-counters.dat");
 
         let global_colors_table = Arc::new(
-            <<NonColoredManager as ColorsManager>::ColorsMergeManagerType as ColorsMergeManager>::create_colors_table("", &[]).unwrap(),
+            <<NonColoredManager as ColorsManager>::ColorsMergeManagerType as ColorsMergeManager>::create_colors_table("", &[], &Default::default(), Default::default(), &Default::default()).unwrap(),
         );
 
         let k = 63;
@@ -125,6 +125,33 @@ int main(int argc, char const *argv[])
 
     std::cout << "Output query file: " << output_query << std::endl;
 
+    // The output function is called from a single thread, in the queries order
+    bool query_succeeded = instance->query_graph_with_callback(
+        graph_file,
+        Slice<std::string>(&input_query, 1),
+        k,
+        threads_count,
+        true,
+        [&](size_t query_index, Slice<char> query_name, Slice<uint32_t> colors, Slice<uint64_t> matched_kmers)
+        {
+            std::cout << "Query " << query_index << " '";
+            std::cout.write(query_name.data, query_name.size);
+            std::cout << "' matches:";
+            for (size_t i = 0; i < colors.size; i++)
+            {
+                std::cout << " " << color_names[colors.data[i]] << "=" << matched_kmers.data[i];
+            }
+            std::cout << std::endl;
+        },
+        false,
+        true);
+
+    if (!query_succeeded)
+    {
+        std::cerr << "Query failed" << std::endl;
+        return 1;
+    }
+
     std::mutex print_kmer_lock;
 
     auto file_color_names =
@@ -169,6 +196,8 @@ int main(int argc, char const *argv[])
         true);
 
     auto colormap = GGCATInstance::get_colormap_file(graph_file);
+    // Index the colormap to decode each queried subset in constant time
+    GGCATInstance::build_colormap_index(colormap);
 
     uint32_t query_subsets[] = {0, 1, 2, 3, 4};
     instance->query_colormap(colormap, query_subsets, 5, true, [&](uint32_t subset, Slice<uint32_t> colors)
@@ -194,5 +223,20 @@ int main(int argc, char const *argv[])
         true,
         Slice<std::string>(color_names.data(), color_names.size()));
 
+    // Querying using in-memory data, the queries are numbered in the order of the streams
+    std::string output_stream_query = instance->query_graph_from_streams<MemorySequencesReader>(
+        "/tmp/advanced-building.fa",
+        Slice<void *>((void **)sequences, 4),
+        "/tmp/advanced-query-results",
+        k,
+        threads_count,
+        false,
+        true,
+        ColoredQueryOutputFormat_JsonLinesWithNumbers,
+        -1,
+        true);
+
+    std::cout << "Output streams query file: " << output_stream_query << std::endl;
+
     return 0;
 }
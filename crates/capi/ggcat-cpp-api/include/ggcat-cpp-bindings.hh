@@ -721,6 +721,8 @@ std::size_t align_of() {
 } // namespace rust
 
 struct GGCATConfigFFI;
+struct ColorsMetadataFFI;
+struct ColorsPredicateFFI;
 struct InputStreamFFI;
 struct GGCATInstanceFFI;
 
@@ -759,6 +761,37 @@ struct GGCATConfigFFI final {
 };
 #endif // CXXBRIDGE1_STRUCT_GGCATConfigFFI
 
+#ifndef CXXBRIDGE1_STRUCT_ColorsMetadataFFI
+#define CXXBRIDGE1_STRUCT_ColorsMetadataFFI
+// Metadata of the colors, with the values of each color in the keys order
+struct ColorsMetadataFFI final {
+  ::rust::Vec<::rust::String> keys;
+  // The values of all the colors, keys_count values for each color. Missing values are empty
+  ::rust::Vec<::rust::String> values;
+
+  using IsRelocatable = ::std::true_type;
+};
+#endif // CXXBRIDGE1_STRUCT_ColorsMetadataFFI
+
+#ifndef CXXBRIDGE1_STRUCT_ColorsPredicateFFI
+#define CXXBRIDGE1_STRUCT_ColorsPredicateFFI
+// Predicate on the colors of the dumped unitigs, the empty conditions are ignored
+struct ColorsPredicateFFI final {
+  // Colors that must all be present
+  ::rust::Vec<::std::uint32_t> all_of;
+  // Colors of which at least one must be present
+  ::rust::Vec<::std::uint32_t> any_of;
+  // Colors that must all be absent
+  ::rust::Vec<::std::uint32_t> none_of;
+  // Minimum number of colors of at_least_of that must be present
+  ::std::size_t at_least;
+  // Colors counted by at_least, all the colors if empty
+  ::rust::Vec<::std::uint32_t> at_least_of;
+
+  using IsRelocatable = ::std::true_type;
+};
+#endif // CXXBRIDGE1_STRUCT_ColorsPredicateFFI
+
 #ifndef CXXBRIDGE1_STRUCT_InputStreamFFI
 #define CXXBRIDGE1_STRUCT_InputStreamFFI
 struct InputStreamFFI final {
@@ -787,26 +820,48 @@ private:
 // Creates a new GGCATInstance. If an instance already exists, it will be returned, ignoring the new config.
 ::GGCATInstanceFFI const *ggcat_create(::GGCATConfigFFI config) noexcept;
 
+// Sets the path to the blossom5 binary used to compute matchtigs, if empty it is searched in PATH
+void ggcat_set_matchtigs_matcher_path(::GGCATInstanceFFI const &instance, ::rust::String matcher_path) noexcept;
+
 // Builds a new graph from the given input files, with the specified parameters
 ::rust::String ggcat_build_from_files(::GGCATInstanceFFI const &instance, ::rust::Slice<::rust::String const> input_files, ::rust::String output_file, ::rust::Slice<::rust::String const> color_names, ::std::size_t kmer_length, ::std::size_t threads_count, bool forward_only, ::std::size_t minimizer_length, bool colors, ::std::size_t min_multiplicity, ::std::size_t extra_elab, ::std::uint32_t gfa_output_version, ::std::uint32_t disk_optimization_level) noexcept;
 
 // Builds a new graph from the given input streams, with the specified parameters
 ::rust::String ggcat_build_from_streams(::GGCATInstanceFFI const &instance, ::rust::Slice<::InputStreamFFI const> input_streams, ::rust::String output_file, ::rust::Slice<::rust::String const> color_names, ::std::size_t kmer_length, ::std::size_t threads_count, bool forward_only, ::std::size_t minimizer_length, bool colors, ::std::size_t min_multiplicity, ::std::size_t extra_elab, ::std::uint32_t gfa_output_version, ::std::uint32_t disk_optimization_level) noexcept;
 
-// Queries a (optionally) colored graph with a specific set of sequences as queries
-::rust::String ggcat_query_graph(::GGCATInstanceFFI const &instance, ::rust::String input_graph, ::rust::String input_query, ::rust::String output_file_prefix, ::std::size_t kmer_length, ::std::size_t threads_count, bool forward_only, ::std::size_t minimizer_length, bool colors, ::std::size_t color_output_format) noexcept;
+// Queries a (optionally) colored graph with the sequences of the given files as queries
+::rust::String ggcat_query_graph(::GGCATInstanceFFI const &instance, ::rust::String input_graph, ::rust::Slice<::rust::String const> input_queries, ::rust::String output_file_prefix, ::std::size_t kmer_length, ::std::size_t threads_count, bool forward_only, ::std::size_t minimizer_length, bool colors, ::std::size_t color_output_format, ::std::size_t output_mode, ::rust::String query_index) noexcept;
+
+// Queries a (optionally) colored graph with the sequences of the given files as queries,
+// passing the matched kmers of each color to the output function instead of writing an output file.
+// Returns false on failure
+bool ggcat_query_graph_with_callback(::GGCATInstanceFFI const &instance, ::rust::String input_graph, ::rust::Slice<::rust::String const> input_queries, ::std::size_t kmer_length, ::std::size_t threads_count, bool forward_only, ::std::size_t minimizer_length, bool colors, ::rust::String query_index, bool single_thread_output_function, ::std::size_t output_function_context, ::std::size_t output_function_ptr) noexcept;
+
+// Queries a (optionally) colored graph with the sequences of the given streams as queries
+::rust::String ggcat_query_graph_from_streams(::GGCATInstanceFFI const &instance, ::rust::String input_graph, ::rust::Slice<::InputStreamFFI const> input_streams, ::rust::String output_file_prefix, ::std::size_t kmer_length, ::std::size_t threads_count, bool forward_only, ::std::size_t minimizer_length, bool colors, ::std::size_t color_output_format, ::std::size_t output_mode, ::rust::String query_index) noexcept;
+
+// Builds a query index of a (optionally) colored graph, saving its kmers
+// bucketed by minimizer to be reused by the queries without processing the graph again
+::rust::String ggcat_build_query_index(::GGCATInstanceFFI const &instance, ::rust::String input_graph, ::rust::String index_dir, ::std::size_t kmer_length, ::std::size_t threads_count, ::std::size_t minimizer_length, bool colors, bool forward_only) noexcept;
 
 ::rust::String ggcat_get_colormap_file(::rust::String graph_file) noexcept;
 
+// Builds the index of a colormap, saved next to it, to decode each color subset in constant time.
+// Returns the index file path, or an empty string on failure
+::rust::String ggcat_build_colormap_index(::rust::String colormap) noexcept;
+
 // Returns a vector of color names in the given graph.
 // The color indexes returned from the dump_unitigs function
 // can be used to index this vector.
 ::rust::Vec<::rust::String> ggcat_dump_colors(::rust::String input_colormap) noexcept;
 
+// Returns the metadata of the colors in the given colormap, empty if it was built without metadata
+::ColorsMetadataFFI ggcat_dump_colors_metadata(::rust::String input_colormap) noexcept;
+
 // Dumps the unitigs of the given graph, optionally with colors
 // It's not guaranteed that maximal unitigs are returned, as only kmers with the same colors subset
 // are returned as whole unitigs to speedup colormap reading times
-void ggcat_dump_unitigs(::GGCATInstanceFFI const &instance, ::rust::String graph_input, ::std::size_t kmer_length, ::std::size_t minimizer_length, bool colors, ::std::size_t threads_count, bool single_thread_output_function, ::std::size_t output_function_context, ::std::size_t output_function_ptr) noexcept;
+void ggcat_dump_unitigs(::GGCATInstanceFFI const &instance, ::rust::String graph_input, ::std::size_t kmer_length, ::std::size_t minimizer_length, bool colors, ::std::size_t threads_count, bool single_thread_output_function, ::std::size_t output_function_context, ::std::size_t output_function_ptr, ::ColorsPredicateFFI colors_predicate) noexcept;
 
 // Queries specified color subsets of the colormap, returning
 // the color indices corresponding to the colors of each subset
@@ -38,12 +38,28 @@ namespace ggcat
         ExtraElaborationStep_Eulertigs = 3,
         /// Generate pathtigs
         ExtraElaborationStep_Pathtigs = 4,
+        /// Generate optimal matchtigs, requires the blossom5 binary
+        ExtraElaborationStep_Matchtigs = 5,
     };
 
     enum ColoredQueryOutputFormat
     {
         ColoredQueryOutputFormat_JsonLinesWithNumbers = 0,
         ColoredQueryOutputFormat_JsonLinesWithNames = 1,
+        /// Long format table, with a row for each (query, color) match
+        ColoredQueryOutputFormat_Tsv = 2,
+        /// Sparse query by color matrix of the matched kmers
+        ColoredQueryOutputFormat_MatrixMarket = 3,
+    };
+
+    enum QueryOutputMode
+    {
+        /// Number of query kmers matched by each color
+        QueryOutputMode_MatchCounts = 0,
+        /// Run-length encoded segments of the matched kmer positions, with their colors
+        QueryOutputMode_KmerPositions = 1,
+        /// Graph unitigs matched by the query kmers, with their orientation, in the query order
+        QueryOutputMode_MatchedUnitigs = 2,
     };
 
     enum DnaSequencesFileType
@@ -86,6 +102,30 @@ namespace ggcat
         // void estimated_base_count(void *block);
     };
 
+    // Metadata of the colors, stored in the colormap at build time
+    struct ColorsMetadata
+    {
+        // The metadata keys
+        std::vector<std::string> keys;
+        // The values of each color, ordered by color index and then in the keys order. Missing values are empty
+        std::vector<std::vector<std::string>> values;
+    };
+
+    // Predicate on the colors of the dumped unitigs, all the non empty conditions must be satisfied
+    struct ColorsPredicate
+    {
+        // Colors that must all be present
+        std::vector<uint32_t> all_of;
+        // Colors of which at least one must be present
+        std::vector<uint32_t> any_of;
+        // Colors that must all be absent
+        std::vector<uint32_t> none_of;
+        // Minimum number of colors of at_least_of that must be present
+        size_t at_least = 0;
+        // Colors counted by at_least, all the colors if empty
+        std::vector<uint32_t> at_least_of;
+    };
+
     // Main config of GGCAT. This config is global and should be passed to GGCATInstance::create
     struct GGCATConfig final
     {
@@ -143,7 +183,8 @@ namespace ggcat
             size_t threads_count,
             bool single_thread_output_function,
             uintptr_t context,
-            uintptr_t output_function);
+            uintptr_t output_function,
+            const ColorsPredicate &colors_predicate);
 
         void query_colormap_internal(
             std::string colormap_file,
@@ -153,6 +194,37 @@ namespace ggcat
             uintptr_t context,
             uintptr_t output_function);
 
+        bool query_graph_with_callback_internal(
+            std::string input_graph,
+            Slice<std::string> input_queries,
+            size_t kmer_length,
+            size_t threads_count,
+            bool forward_only,
+            bool colors,
+            size_t minimizer_length,
+            std::string query_index,
+            bool single_thread_output_function,
+            uintptr_t context,
+            uintptr_t output_function);
+
+        template <typename F>
+        static void query_results_output_function_bridge(
+            uintptr_t context,
+            size_t query_index,
+            uintptr_t name_ptr,
+            size_t name_len,
+            uintptr_t col_ptr,
+            uintptr_t matched_kmers_ptr,
+            size_t matches_len)
+        {
+            F *output_function = reinterpret_cast<F *>(context);
+            (*output_function)(
+                query_index,
+                Slice<char>((char *)name_ptr, name_len),
+                Slice<uint32_t>((uint32_t *)col_ptr, matches_len),
+                Slice<uint64_t>((uint64_t *)matched_kmers_ptr, matches_len));
+        }
+
         template <typename F>
         static void unitigs_dump_output_function_bridge(
             uintptr_t context,
@@ -182,6 +254,40 @@ namespace ggcat
             );
         }
 
+        template <typename S>
+        static std::vector<__InputStreamBlockData> get_input_stream_blocks(Slice<void *> input_streams)
+        {
+            thread_local std::unique_ptr<StreamReader> stream_reader = nullptr;
+            thread_local std::pair<void (*)(uintptr_t, DnaSequence, SequenceInfo), uintptr_t> callback_data;
+
+            std::vector<__InputStreamBlockData> input_stream_blocks;
+
+            for (size_t i = 0; i < input_streams.size; i++)
+            {
+                input_stream_blocks.push_back({[](uintptr_t block, bool copy_ident_data, size_t partial_read_copyback, void (*callback)(uintptr_t callback_context, DnaSequence sequence, SequenceInfo info), uintptr_t callback_context)
+                                               {
+                                                   callback_data.first = callback;
+                                                   callback_data.second = callback_context;
+
+                                                   if (stream_reader == nullptr)
+                                                   {
+                                                       stream_reader = std::unique_ptr<S>(new S());
+                                                   }
+
+                                                   StreamReader *local_stream_reader = stream_reader.get();
+
+                                                   local_stream_reader->read_block((void *)block, copy_ident_data, partial_read_copyback, [](DnaSequence sequence, SequenceInfo info)
+                                                                                   { callback_data.first(callback_data.second, sequence, info); });
+                                               },
+                                               [](uintptr_t block) -> uint64_t
+                                               {
+                                                   return S::estimated_base_count((void *)block);
+                                               },
+                                               (uintptr_t)input_streams.data[i]});
+            }
+            return input_stream_blocks;
+        }
+
         std::string build_graph_internal_ffi(
             Slice<__InputStreamBlockData> input_streams,
             std::string output_file,
@@ -196,9 +302,25 @@ namespace ggcat
             bool output_gfa,
             uint32_t disk_optimization_level);
 
+        std::string query_graph_internal_ffi(
+            std::string input_graph,
+            Slice<__InputStreamBlockData> input_streams,
+            std::string output_file_prefix,
+            size_t kmer_length,
+            size_t threads_count,
+            bool forward_only,
+            bool colors,
+            size_t color_output_format,
+            size_t minimizer_length,
+            size_t output_mode,
+            std::string query_index);
+
     public:
         static GGCATInstance *create(GGCATConfig config);
 
+        /// Sets the path to the blossom5 binary used to compute matchtigs, if empty it is searched in PATH
+        void set_matchtigs_matcher_path(std::string matcher_path);
+
         /// Builds a new graph from the given input files, with the specified parameters
         std::string build_graph_from_files(
             // The input files
@@ -277,34 +399,7 @@ namespace ggcat
             uint32_t disk_optimization_level = 5)
         {
 
-            thread_local std::unique_ptr<StreamReader> stream_reader = nullptr;
-            thread_local std::pair<void (*)(uintptr_t, DnaSequence, SequenceInfo), uintptr_t> callback_data;
-
-            std::vector<__InputStreamBlockData> input_stream_blocks;
-
-            for (size_t i = 0; i < input_streams.size; i++)
-            {
-                input_stream_blocks.push_back({[](uintptr_t block, bool copy_ident_data, size_t partial_read_copyback, void (*callback)(uintptr_t callback_context, DnaSequence sequence, SequenceInfo info), uintptr_t callback_context)
-                                               {
-                                                   callback_data.first = callback;
-                                                   callback_data.second = callback_context;
-
-                                                   if (stream_reader == nullptr)
-                                                   {
-                                                       stream_reader = std::unique_ptr<S>(new S());
-                                                   }
-
-                                                   StreamReader *local_stream_reader = stream_reader.get();
-
-                                                   local_stream_reader->read_block((void *)block, copy_ident_data, partial_read_copyback, [](DnaSequence sequence, SequenceInfo info)
-                                                                                   { callback_data.first(callback_data.second, sequence, info); });
-                                               },
-                                               [](uintptr_t block) -> uint64_t
-                                               {
-                                                   return S::estimated_base_count((void *)block);
-                                               },
-                                               (uintptr_t)input_streams.data[i]});
-            }
+            std::vector<__InputStreamBlockData> input_stream_blocks = get_input_stream_blocks<S>(input_streams);
 
             return build_graph_internal_ffi(Slice<__InputStreamBlockData>(input_stream_blocks.data(),
                                                                           input_stream_blocks.size()),
@@ -342,11 +437,156 @@ namespace ggcat
             // Query output format
             size_t color_output_format = ColoredQueryOutputFormat_JsonLinesWithNumbers,
             // Overrides the default m-mers (minimizers) length
-            size_t minimizer_length = -1);
+            size_t minimizer_length = -1,
+            // Information reported for each query
+            size_t output_mode = QueryOutputMode_MatchCounts,
+            // Directory of a query index built from the input graph, if empty the graph is processed again
+            std::string query_index = "");
+
+        /// Queries a (optionally) colored graph with the sequences of the given files as queries, numbered in order across the files
+        std::string query_graph_from_files(
+            // The input graph
+            std::string input_graph,
+            // The input queries as .fasta or .fastq files
+            Slice<std::string> input_queries,
+
+            // The output file
+            std::string output_file_prefix,
+
+            // Specifies the k-mers length
+            size_t kmer_length,
+            // The threads to be used
+            size_t threads_count,
+            // Treats reverse complementary kmers as different
+            bool forward_only = false,
+            // Enable colors
+            bool colors = false,
+            // Query output format
+            size_t color_output_format = ColoredQueryOutputFormat_JsonLinesWithNumbers,
+            // Overrides the default m-mers (minimizers) length
+            size_t minimizer_length = -1,
+            // Information reported for each query
+            size_t output_mode = QueryOutputMode_MatchCounts,
+            // Directory of a query index built from the input graph, if empty the graph is processed again
+            std::string query_index = "");
+
+        /// Queries a (optionally) colored graph with the sequences of the given files as queries, numbered in order across the files,
+        /// passing the matched kmers of each color to the output function instead of writing an output file.
+        /// Colored queries without matches are skipped, non colored queries report all the matches with color 0.
+        /// Returns false on failure
+        template <typename F>
+        bool query_graph_with_callback(
+            // The input graph
+            std::string input_graph,
+            // The input queries as .fasta or .fastq files
+            Slice<std::string> input_queries,
+
+            // Specifies the k-mers length
+            size_t kmer_length,
+            // The threads to be used
+            size_t threads_count,
+
+            // Call the output function from a single thread at a time, in the queries order,
+            // avoiding the need for synchronization in the user code
+            bool single_thread_output_function,
+
+            // The callback to be called for each query, arguments: (size_t query_index, Slice<char> query_name, Slice<uint32_t> colors, Slice<uint64_t> matched_kmers)
+            F output_function,
+            // Treats reverse complementary kmers as different
+            bool forward_only = false,
+            // Enable colors
+            bool colors = false,
+            // Overrides the default m-mers (minimizers) length
+            size_t minimizer_length = -1,
+            // Directory of a query index built from the input graph, if empty the graph is processed again
+            std::string query_index = "")
+        {
+            auto bridge_ptr = GGCATInstance::query_results_output_function_bridge<F>;
+            return this->query_graph_with_callback_internal(input_graph,
+                                                            input_queries,
+                                                            kmer_length,
+                                                            threads_count,
+                                                            forward_only,
+                                                            colors,
+                                                            minimizer_length,
+                                                            query_index,
+                                                            single_thread_output_function,
+                                                            (uintptr_t)&output_function, reinterpret_cast<uintptr_t>(bridge_ptr));
+        }
+
+        /// Queries a (optionally) colored graph with the sequences of the given streams as queries, numbered in order across the streams.
+        template <typename S>
+        std::string query_graph_from_streams(
+            // The input graph
+            std::string input_graph,
+            // The input query streams
+            Slice<void *> input_streams,
+
+            // The output file
+            std::string output_file_prefix,
+
+            // Specifies the k-mers length
+            size_t kmer_length,
+            // The threads to be used
+            size_t threads_count,
+            // Treats reverse complementary kmers as different
+            bool forward_only = false,
+            // Enable colors
+            bool colors = false,
+            // Query output format
+            size_t color_output_format = ColoredQueryOutputFormat_JsonLinesWithNumbers,
+            // Overrides the default m-mers (minimizers) length
+            size_t minimizer_length = -1,
+            // Information reported for each query
+            size_t output_mode = QueryOutputMode_MatchCounts,
+            // Directory of a query index built from the input graph, if empty the graph is processed again
+            std::string query_index = "")
+        {
+            std::vector<__InputStreamBlockData> input_stream_blocks = get_input_stream_blocks<S>(input_streams);
+
+            return query_graph_internal_ffi(input_graph,
+                                            Slice<__InputStreamBlockData>(input_stream_blocks.data(),
+                                                                          input_stream_blocks.size()),
+                                            output_file_prefix,
+                                            kmer_length,
+                                            threads_count,
+                                            forward_only,
+                                            colors,
+                                            color_output_format,
+                                            minimizer_length,
+                                            output_mode,
+                                            query_index);
+        }
+
+        /// Builds a query index of a (optionally) colored graph, saving its kmers
+        /// bucketed by minimizer to be reused by the queries without processing the graph again
+        std::string build_query_index(
+            // The input graph
+            std::string input_graph,
+            // The output index directory
+            std::string index_dir,
+
+            // Specifies the k-mers length
+            size_t kmer_length,
+            // The threads to be used
+            size_t threads_count,
+            // Enable colors
+            bool colors = false,
+            // Overrides the default m-mers (minimizers) length
+            size_t minimizer_length = -1,
+            // Treats reverse complementary kmers as different
+            bool forward_only = false);
 
         // Obtains the standard colormap file path from a graph file path
         static std::string get_colormap_file(std::string graph_file);
 
+        /// Builds the index of a colormap, saved next to it, used by query_colormap
+        /// to decode each color subset in constant time.
+        /// Returns the index file path, or an empty string on failure
+        static std::string build_colormap_index(
+            // The input colormap
+            std::string colormap_file);
+
         /// Returns a vector of color names in the given graph.
         /// The color indexes returned from the dump_unitigs function
         /// can be used to index this vector.
@@ -354,6 +594,12 @@ namespace ggcat
             // The input colormap
             std::string input_colormap);
 
+        /// Returns the metadata of the colors in the given graph,
+        /// empty if the colormap was built without metadata
+        static ColorsMetadata dump_colors_metadata(
+            // The input colormap
+            std::string input_colormap);
+
         /// Dumps the unitigs of the given graph, optionally with colors
         /// It's not guaranteed that maximal unitigs are returned, as only kmers with the same colors subset
         /// are returned as whole unitigs to speedup colormap reading times
@@ -375,7 +621,9 @@ namespace ggcat
             // Enable colors
             bool colors = false,
             // Overrides the default m-mers (minimizers) length
-            size_t minimizer_length = -1)
+            size_t minimizer_length = -1,
+            // Dumps only the unitigs whose colors match the predicate, ignored without colors
+            ColorsPredicate colors_predicate = ColorsPredicate())
         {
             auto bridge_ptr = GGCATInstance::unitigs_dump_output_function_bridge<F>;
             this->dump_unitigs_internal(graph_input,
@@ -384,7 +632,8 @@ namespace ggcat
                                         colors,
                                         threads_count,
                                         single_thread_output_function,
-                                        (uintptr_t)&output_function, reinterpret_cast<uintptr_t>(bridge_ptr));
+                                        (uintptr_t)&output_function, reinterpret_cast<uintptr_t>(bridge_ptr),
+                                        colors_predicate);
         }
 
         /// Queries specified color subsets of the colormap, returning
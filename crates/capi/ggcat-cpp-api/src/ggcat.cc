@@ -31,6 +31,11 @@ GGCATInstance *GGCATInstance::create(GGCATConfig config)
     return &instance;
 }
 
+void GGCATInstance::set_matchtigs_matcher_path(std::string matcher_path)
+{
+    ggcat_set_matchtigs_matcher_path(*ffi_instance, rust::String(matcher_path.c_str()));
+}
+
 std::string GGCATInstance::build_graph_from_files(
     Slice<std::string> input_files,
     std::string output_file,
@@ -75,6 +80,22 @@ std::string GGCATInstance::build_graph_from_files(
     return std::string(rust_str.c_str());
 }
 
+static std::vector<InputStreamFFI> to_ffi_input_streams(Slice<__InputStreamBlockData> input_streams)
+{
+    std::vector<InputStreamFFI> ffi_input_streams;
+
+    for (size_t i = 0; i < input_streams.size; i++)
+    {
+        InputStreamFFI ffi_input_stream;
+        ffi_input_stream.block_data = input_streams.data[i].block_data;
+        ffi_input_stream.virtual_read_block = (uintptr_t)input_streams.data[i].read_block;
+        ffi_input_stream.virtual_estimated_base_count = (uintptr_t)input_streams.data[i].estimated_base_count;
+
+        ffi_input_streams.push_back(ffi_input_stream);
+    }
+    return ffi_input_streams;
+}
+
 std::string GGCATInstance::build_graph_internal_ffi(
     Slice<__InputStreamBlockData> input_streams,
     std::string output_file,
@@ -89,17 +110,7 @@ std::string GGCATInstance::build_graph_internal_ffi(
     bool output_gfa,
     uint32_t disk_optimization_level)
 {
-    std::vector<InputStreamFFI> ffi_input_streams;
-
-    for (size_t i = 0; i < input_streams.size; i++)
-    {
-        InputStreamFFI ffi_input_stream;
-        ffi_input_stream.block_data = input_streams.data[i].block_data;
-        ffi_input_stream.virtual_read_block = (uintptr_t)input_streams.data[i].read_block;
-        ffi_input_stream.virtual_estimated_base_count = (uintptr_t)input_streams.data[i].estimated_base_count;
-
-        ffi_input_streams.push_back(ffi_input_stream);
-    }
+    std::vector<InputStreamFFI> ffi_input_streams = to_ffi_input_streams(input_streams);
 
     std::vector<rust::String> ffi_color_names;
 
@@ -133,18 +144,139 @@ std::string GGCATInstance::query_graph(
     bool forward_only,
     bool colors,
     size_t color_output_format,
-    size_t minimizer_length)
+    size_t minimizer_length,
+    size_t output_mode,
+    std::string query_index)
+{
+    return query_graph_from_files(input_graph,
+                                  Slice<std::string>(&input_query, 1),
+                                  output_file_prefix,
+                                  kmer_length,
+                                  threads_count,
+                                  forward_only,
+                                  colors,
+                                  color_output_format,
+                                  minimizer_length,
+                                  output_mode,
+                                  query_index);
+}
+
+std::string GGCATInstance::query_graph_from_files(
+    std::string input_graph,
+    Slice<std::string> input_queries,
+    std::string output_file_prefix,
+    size_t kmer_length,
+    size_t threads_count,
+    bool forward_only,
+    bool colors,
+    size_t color_output_format,
+    size_t minimizer_length,
+    size_t output_mode,
+    std::string query_index)
 {
+    std::vector<rust::String> ffi_input_queries;
+
+    for (size_t i = 0; i < input_queries.size; i++)
+    {
+        ffi_input_queries.push_back(rust::String(input_queries.data[i].c_str()));
+    }
+
     auto rust_str = ggcat_query_graph(*ffi_instance,
                                       rust::String(input_graph.c_str()),
-                                      rust::String(input_query.c_str()),
+                                      rust::Slice<const rust::String>(ffi_input_queries.data(), ffi_input_queries.size()),
                                       rust::String(output_file_prefix.c_str()),
                                       kmer_length,
                                       threads_count,
                                       forward_only,
                                       minimizer_length,
                                       colors,
-                                      color_output_format);
+                                      color_output_format,
+                                      output_mode,
+                                      rust::String(query_index.c_str()));
+    return std::string(rust_str.c_str());
+}
+
+bool GGCATInstance::query_graph_with_callback_internal(
+    std::string input_graph,
+    Slice<std::string> input_queries,
+    size_t kmer_length,
+    size_t threads_count,
+    bool forward_only,
+    bool colors,
+    size_t minimizer_length,
+    std::string query_index,
+    bool single_thread_output_function,
+    uintptr_t context,
+    uintptr_t output_function)
+{
+    std::vector<rust::String> ffi_input_queries;
+
+    for (size_t i = 0; i < input_queries.size; i++)
+    {
+        ffi_input_queries.push_back(rust::String(input_queries.data[i].c_str()));
+    }
+
+    return ggcat_query_graph_with_callback(*ffi_instance,
+                                           rust::String(input_graph.c_str()),
+                                           rust::Slice<const rust::String>(ffi_input_queries.data(), ffi_input_queries.size()),
+                                           kmer_length,
+                                           threads_count,
+                                           forward_only,
+                                           minimizer_length,
+                                           colors,
+                                           rust::String(query_index.c_str()),
+                                           single_thread_output_function,
+                                           context,
+                                           output_function);
+}
+
+std::string GGCATInstance::query_graph_internal_ffi(
+    std::string input_graph,
+    Slice<__InputStreamBlockData> input_streams,
+    std::string output_file_prefix,
+    size_t kmer_length,
+    size_t threads_count,
+    bool forward_only,
+    bool colors,
+    size_t color_output_format,
+    size_t minimizer_length,
+    size_t output_mode,
+    std::string query_index)
+{
+    std::vector<InputStreamFFI> ffi_input_streams = to_ffi_input_streams(input_streams);
+
+    auto rust_str = ggcat_query_graph_from_streams(*ffi_instance,
+                                                   rust::String(input_graph.c_str()),
+                                                   rust::Slice<const InputStreamFFI>(ffi_input_streams.data(), ffi_input_streams.size()),
+                                                   rust::String(output_file_prefix.c_str()),
+                                                   kmer_length,
+                                                   threads_count,
+                                                   forward_only,
+                                                   minimizer_length,
+                                                   colors,
+                                                   color_output_format,
+                                                   output_mode,
+                                                   rust::String(query_index.c_str()));
+    return std::string(rust_str.c_str());
+}
+
+std::string GGCATInstance::build_query_index(
+    std::string input_graph,
+    std::string index_dir,
+    size_t kmer_length,
+    size_t threads_count,
+    bool colors,
+    size_t minimizer_length,
+    bool forward_only)
+{
+    auto rust_str = ggcat_build_query_index(*ffi_instance,
+                                            rust::String(input_graph.c_str()),
+                                            rust::String(index_dir.c_str()),
+                                            kmer_length,
+                                            threads_count,
+                                            minimizer_length,
+                                            colors,
+                                            forward_only);
     return std::string(rust_str.c_str());
 }
 
@@ -154,6 +286,12 @@ std::string GGCATInstance::get_colormap_file(std::string graph_file)
     return rust_str.c_str();
 }
 
+std::string GGCATInstance::build_colormap_index(std::string colormap_file)
+{
+    auto rust_str = ggcat_build_colormap_index(rust::String(colormap_file.c_str()));
+    return rust_str.c_str();
+}
+
 std::vector<std::string> GGCATInstance::dump_colors(
     std::string input_colormap)
 {
@@ -169,6 +307,31 @@ std::vector<std::string> GGCATInstance::dump_colors(
     return colors;
 }
 
+ColorsMetadata GGCATInstance::dump_colors_metadata(
+    std::string input_colormap)
+{
+    auto metadata_ffi = ggcat_dump_colors_metadata(rust::String(input_colormap.c_str()));
+
+    ColorsMetadata metadata;
+
+    for (auto &key : metadata_ffi.keys)
+    {
+        metadata.keys.push_back(key.c_str());
+    }
+
+    for (size_t i = 0; i < metadata_ffi.values.size(); i += metadata.keys.size())
+    {
+        std::vector<std::string> color_values;
+        for (size_t j = 0; j < metadata.keys.size(); j++)
+        {
+            color_values.push_back(metadata_ffi.values[i + j].c_str());
+        }
+        metadata.values.push_back(color_values);
+    }
+
+    return metadata;
+}
+
 void GGCATInstance::dump_unitigs_internal(
     std::string graph_input,
     size_t kmer_length,
@@ -177,9 +340,28 @@ void GGCATInstance::dump_unitigs_internal(
     size_t threads_count,
     bool single_thread_output_function,
     uintptr_t context,
-    uintptr_t output_function)
+    uintptr_t output_function,
+    const ColorsPredicate &colors_predicate)
 
 {
+    auto to_rust_vec = [](const std::vector<uint32_t> &colors)
+    {
+        auto rust_vec = rust::Vec<uint32_t>();
+        rust_vec.reserve(colors.size());
+        for (auto color : colors)
+        {
+            rust_vec.push_back(color);
+        }
+        return rust_vec;
+    };
+
+    ColorsPredicateFFI colors_predicate_ffi = {
+        to_rust_vec(colors_predicate.all_of),
+        to_rust_vec(colors_predicate.any_of),
+        to_rust_vec(colors_predicate.none_of),
+        colors_predicate.at_least,
+        to_rust_vec(colors_predicate.at_least_of),
+    };
 
     ggcat_dump_unitigs(*ffi_instance,
                        rust::String(graph_input.c_str()),
@@ -189,7 +371,8 @@ void GGCATInstance::dump_unitigs_internal(
                        threads_count,
                        single_thread_output_function,
                        context,
-                       output_function);
+                       output_function,
+                       std::move(colors_predicate_ffi));
 }
 
 void GGCATInstance::query_colormap_internal(
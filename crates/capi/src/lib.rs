@@ -4,8 +4,8 @@ use std::sync::Arc;
 use std::{mem::transmute, path::PathBuf};
 
 use ggcat_api::{
-    ColorIndexType, DnaSequence, DnaSequencesFileType, DynamicSequencesStream, GfaVersion,
-    SequenceInfo,
+    ColorIndexType, ColorsCodec, ColorsPredicate, DnaSequence, DnaSequencesFileType,
+    DynamicSequencesStream, GfaVersion, QueryOutputFilter, QueryOutputMode, SequenceInfo,
 };
 use ggcat_api::{ExtraElaboration, GGCATConfig, GGCATInstance, GeneralSequenceBlockData};
 
@@ -55,6 +55,17 @@ fn ggcat_create(config: ffi::GGCATConfigFFI) -> *const GGCATInstanceFFI {
     unsafe { std::mem::transmute(instance) }
 }
 
+/// Sets the path to the blossom5 binary used to compute matchtigs, if empty it is searched in PATH
+fn ggcat_set_matchtigs_matcher_path(instance: &'static GGCATInstanceFFI, matcher_path: String) {
+    instance
+        .0
+        .set_matchtigs_matcher_path(if matcher_path.is_empty() {
+            None
+        } else {
+            Some(PathBuf::from(matcher_path))
+        });
+}
+
 fn ggcat_build(
     instance: &'static GGCATInstanceFFI,
     // The input blocks
@@ -95,6 +106,7 @@ fn ggcat_build(
     const EXTRA_ELABORATION_STEP_GREEDY_MATCHTIGS: usize = 2;
     const EXTRA_ELABORATION_STEP_EULERTIGS: usize = 3;
     const EXTRA_ELABORATION_STEP_PATHTIGS: usize = 4;
+    const EXTRA_ELABORATION_STEP_MATCHTIGS: usize = 5;
 
     instance
         .0
@@ -106,6 +118,7 @@ fn ggcat_build(
             } else {
                 None
             },
+            None,
             kmer_length,
             threads_count,
             forward_only,
@@ -115,6 +128,7 @@ fn ggcat_build(
                 Some(minimizer_length)
             },
             colors,
+            ColorsCodec::RunLength,
             min_multiplicity,
             match extra_elab {
                 EXTRA_ELABORATION_STEP_NONE => ExtraElaboration::None,
@@ -122,8 +136,10 @@ fn ggcat_build(
                 EXTRA_ELABORATION_STEP_GREEDY_MATCHTIGS => ExtraElaboration::GreedyMatchtigs,
                 EXTRA_ELABORATION_STEP_EULERTIGS => ExtraElaboration::Eulertigs,
                 EXTRA_ELABORATION_STEP_PATHTIGS => ExtraElaboration::Pathtigs,
+                EXTRA_ELABORATION_STEP_MATCHTIGS => ExtraElaboration::Matchtigs,
                 _ => panic!("Invalid extra_elab value: {}", extra_elab),
             },
+            None,
             match gfa_output_version {
                 0 => None,
                 1 => Some(GfaVersion::V1),
@@ -131,6 +147,8 @@ fn ggcat_build(
                 _ => panic!("Invalid gfa_output_version value: {}", gfa_output_version),
             },
             disk_optimization_level,
+            false,
+            None,
         )
         .unwrap_or_default()
         .to_str()
@@ -200,6 +218,90 @@ fn ggcat_build_from_files(
     )
 }
 
+struct SequencesStreamFFI {
+    // extern "C" void (*read_block)(uintptr_t block, bool copy_ident_data, size_t partial_read_copyback, uintptr_t callback, uintptr_t callback_context);
+    //      with void callback(uintptr_t callback_context, DnaSequenceFFI sequence, SequenceInfoFFI info);
+    virtual_read_block: extern "C" fn(
+        block: usize,
+        copy_ident_data: bool,
+        partial_read_copyback: usize,
+        callback: extern "C" fn(
+            callback_context: usize,
+            sequence: DnaSequenceFFI,
+            info: SequenceInfoFFI,
+        ),
+        callback_context: usize,
+    ),
+    // extern "C" uint64_t (*estimated_base_count)(uintptr_t block);
+    virtual_estimated_base_count: extern "C" fn(block: usize) -> u64,
+}
+
+impl DynamicSequencesStream for SequencesStreamFFI {
+    fn read_block(
+        &self,
+        block: usize,
+        copy_ident_data: bool,
+        partial_read_copyback: Option<usize>,
+        mut callback: &mut dyn FnMut(DnaSequence, SequenceInfo),
+    ) {
+        extern "C" fn callback_wrapper(
+            callback_ptr: usize,
+            sequence: DnaSequenceFFI,
+            info: SequenceInfoFFI,
+        ) {
+            let callback =
+                unsafe { &mut *(callback_ptr as *mut &mut dyn FnMut(DnaSequence, SequenceInfo)) };
+            callback(
+                DnaSequence {
+                    ident_data: unsafe {
+                        from_raw_parts(sequence.ident_data, sequence.ident_data_len)
+                    },
+                    seq: unsafe { from_raw_parts(sequence.seq, sequence.seq_len) },
+                    format: match sequence.format {
+                        DnaSequencesFileTypeFFI::FASTA => DnaSequencesFileType::FASTA,
+                        DnaSequencesFileTypeFFI::FASTQ => DnaSequencesFileType::FASTQ,
+                        DnaSequencesFileTypeFFI::GFA => DnaSequencesFileType::GFA,
+                        DnaSequencesFileTypeFFI::BINARY => DnaSequencesFileType::BINARY,
+                    },
+                },
+                SequenceInfo {
+                    color: Some(info.color),
+                },
+            );
+        }
+
+        let callback_ptr = (&mut callback) as *mut _ as usize;
+        (self.virtual_read_block)(
+            block,
+            copy_ident_data,
+            partial_read_copyback.unwrap_or(0),
+            callback_wrapper,
+            callback_ptr,
+        );
+    }
+
+    fn estimated_base_count(&self, block: usize) -> u64 {
+        (self.virtual_estimated_base_count)(block)
+    }
+}
+
+fn input_streams_to_blocks(input_streams: &[ffi::InputStreamFFI]) -> Vec<GeneralSequenceBlockData> {
+    input_streams
+        .iter()
+        .map(|s| {
+            GeneralSequenceBlockData::Dynamic((
+                Arc::new(unsafe {
+                    SequencesStreamFFI {
+                        virtual_read_block: transmute(s.virtual_read_block),
+                        virtual_estimated_base_count: transmute(s.virtual_estimated_base_count),
+                    }
+                }),
+                s.block_data,
+            ))
+        })
+        .collect()
+}
+
 fn ggcat_build_from_streams(
     instance: &'static GGCATInstanceFFI,
     // The input streams
@@ -235,90 +337,9 @@ fn ggcat_build_from_streams(
     // Sets the level of disk optimization
     disk_optimization_level: u32,
 ) -> String {
-    struct SequencesStreamFFI {
-        // extern "C" void (*read_block)(uintptr_t block, bool copy_ident_data, size_t partial_read_copyback, uintptr_t callback, uintptr_t callback_context);
-        //      with void callback(uintptr_t callback_context, DnaSequenceFFI sequence, SequenceInfoFFI info);
-        virtual_read_block: extern "C" fn(
-            block: usize,
-            copy_ident_data: bool,
-            partial_read_copyback: usize,
-            callback: extern "C" fn(
-                callback_context: usize,
-                sequence: DnaSequenceFFI,
-                info: SequenceInfoFFI,
-            ),
-            callback_context: usize,
-        ),
-        // extern "C" uint64_t (*estimated_base_count)(uintptr_t block);
-        virtual_estimated_base_count: extern "C" fn(block: usize) -> u64,
-    }
-
-    impl DynamicSequencesStream for SequencesStreamFFI {
-        fn read_block(
-            &self,
-            block: usize,
-            copy_ident_data: bool,
-            partial_read_copyback: Option<usize>,
-            mut callback: &mut dyn FnMut(DnaSequence, SequenceInfo),
-        ) {
-            extern "C" fn callback_wrapper(
-                callback_ptr: usize,
-                sequence: DnaSequenceFFI,
-                info: SequenceInfoFFI,
-            ) {
-                let callback = unsafe {
-                    &mut *(callback_ptr as *mut &mut dyn FnMut(DnaSequence, SequenceInfo))
-                };
-                callback(
-                    DnaSequence {
-                        ident_data: unsafe {
-                            from_raw_parts(sequence.ident_data, sequence.ident_data_len)
-                        },
-                        seq: unsafe { from_raw_parts(sequence.seq, sequence.seq_len) },
-                        format: match sequence.format {
-                            DnaSequencesFileTypeFFI::FASTA => DnaSequencesFileType::FASTA,
-                            DnaSequencesFileTypeFFI::FASTQ => DnaSequencesFileType::FASTQ,
-                            DnaSequencesFileTypeFFI::GFA => DnaSequencesFileType::GFA,
-                            DnaSequencesFileTypeFFI::BINARY => DnaSequencesFileType::BINARY,
-                        },
-                    },
-                    SequenceInfo {
-                        color: Some(info.color),
-                    },
-                );
-            }
-
-            let callback_ptr = (&mut callback) as *mut _ as usize;
-            (self.virtual_read_block)(
-                block,
-                copy_ident_data,
-                partial_read_copyback.unwrap_or(0),
-                callback_wrapper,
-                callback_ptr,
-            );
-        }
-
-        fn estimated_base_count(&self, block: usize) -> u64 {
-            (self.virtual_estimated_base_count)(block)
-        }
-    }
-
     ggcat_build(
         instance,
-        input_streams
-            .iter()
-            .map(|s| {
-                GeneralSequenceBlockData::Dynamic((
-                    Arc::new(unsafe {
-                        SequencesStreamFFI {
-                            virtual_read_block: transmute(s.virtual_read_block),
-                            virtual_estimated_base_count: transmute(s.virtual_estimated_base_count),
-                        }
-                    }),
-                    s.block_data,
-                ))
-            })
-            .collect(),
+        input_streams_to_blocks(input_streams),
         output_file,
         color_names,
         kmer_length,
@@ -333,14 +354,13 @@ fn ggcat_build_from_streams(
     )
 }
 
-/// Queries a (optionally) colored graph with a specific set of sequences as queries
-fn ggcat_query_graph(
+fn ggcat_query(
     instance: &'static GGCATInstanceFFI,
 
     // The input graph
     input_graph: String,
-    // The input query as a .fasta file
-    input_query: String,
+    // The input query blocks
+    input_blocks: Vec<GeneralSequenceBlockData>,
 
     // The output file
     output_file_prefix: String,
@@ -359,15 +379,27 @@ fn ggcat_query_graph(
 
     // Query output format
     color_output_format: usize,
+
+    // Information reported for each query
+    output_mode: usize,
+
+    // Directory of a query index built from the input graph, if empty the graph is processed again
+    query_index: String,
 ) -> String {
     const COLORED_QUERY_OUTPUT_FORMAT_JSON_LINES_WITH_NUMBERS: usize = 0;
     const COLORED_QUERY_OUTPUT_FORMAT_JSON_LINES_WITH_NAMES: usize = 1;
+    const COLORED_QUERY_OUTPUT_FORMAT_TSV: usize = 2;
+    const COLORED_QUERY_OUTPUT_FORMAT_MATRIX_MARKET: usize = 3;
+
+    const QUERY_OUTPUT_MODE_MATCH_COUNTS: usize = 0;
+    const QUERY_OUTPUT_MODE_KMER_POSITIONS: usize = 1;
+    const QUERY_OUTPUT_MODE_MATCHED_UNITIGS: usize = 2;
 
     instance
         .0
         .query_graph(
-            PathBuf::from(input_graph),
-            PathBuf::from(input_query),
+            vec![PathBuf::from(input_graph)],
+            input_blocks,
             PathBuf::from(output_file_prefix),
             kmer_length,
             threads_count,
@@ -385,8 +417,245 @@ fn ggcat_query_graph(
                 COLORED_QUERY_OUTPUT_FORMAT_JSON_LINES_WITH_NAMES => {
                     ggcat_api::ColoredQueryOutputFormat::JsonLinesWithNames
                 }
+                COLORED_QUERY_OUTPUT_FORMAT_TSV => ggcat_api::ColoredQueryOutputFormat::Tsv,
+                COLORED_QUERY_OUTPUT_FORMAT_MATRIX_MARKET => {
+                    ggcat_api::ColoredQueryOutputFormat::MatrixMarket
+                }
                 _ => panic!("Invalid color_output_format value: {}", color_output_format),
             },
+            QueryOutputFilter::default(),
+            match output_mode {
+                QUERY_OUTPUT_MODE_MATCH_COUNTS => QueryOutputMode::MatchCounts,
+                QUERY_OUTPUT_MODE_KMER_POSITIONS => QueryOutputMode::KmerPositions,
+                QUERY_OUTPUT_MODE_MATCHED_UNITIGS => QueryOutputMode::MatchedUnitigs,
+                _ => panic!("Invalid output_mode value: {}", output_mode),
+            },
+            if query_index.is_empty() {
+                None
+            } else {
+                Some(PathBuf::from(query_index))
+            },
+        )
+        .unwrap_or_default()
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+/// Queries a (optionally) colored graph with the sequences of the given files as queries
+fn ggcat_query_graph(
+    instance: &'static GGCATInstanceFFI,
+
+    // The input graph
+    input_graph: String,
+    // The input queries as .fasta or .fastq files
+    input_queries: &[String],
+
+    // The output file
+    output_file_prefix: String,
+
+    // Specifies the k-mers length
+    kmer_length: usize,
+    // The threads to be used
+    threads_count: usize,
+    // Treats reverse complementary kmers as different
+    forward_only: bool,
+    // Overrides the default m-mers (minimizers) length
+    minimizer_length: usize,
+
+    // Enable colors
+    colors: bool,
+
+    // Query output format
+    color_output_format: usize,
+
+    // Information reported for each query
+    output_mode: usize,
+
+    // Directory of a query index built from the input graph, if empty the graph is processed again
+    query_index: String,
+) -> String {
+    ggcat_query(
+        instance,
+        input_graph,
+        input_queries
+            .iter()
+            .map(|f| GeneralSequenceBlockData::FASTA((PathBuf::from(f), None)))
+            .collect(),
+        output_file_prefix,
+        kmer_length,
+        threads_count,
+        forward_only,
+        minimizer_length,
+        colors,
+        color_output_format,
+        output_mode,
+        query_index,
+    )
+}
+
+/// Queries a (optionally) colored graph with the sequences of the given files as queries,
+/// passing the matched kmers of each color to the output function instead of writing an output file.
+/// Returns false on failure
+fn ggcat_query_graph_with_callback(
+    instance: &'static GGCATInstanceFFI,
+
+    // The input graph
+    input_graph: String,
+    // The input queries as .fasta or .fastq files
+    input_queries: &[String],
+
+    // Specifies the k-mers length
+    kmer_length: usize,
+    // The threads to be used
+    threads_count: usize,
+    // Treats reverse complementary kmers as different
+    forward_only: bool,
+    // Overrides the default m-mers (minimizers) length
+    minimizer_length: usize,
+
+    // Enable colors
+    colors: bool,
+
+    // Directory of a query index built from the input graph, if empty the graph is processed again
+    query_index: String,
+
+    // Call the output function from a single thread at a time, in the queries order,
+    // avoiding the need for synchronization in the user code
+    single_thread_output_function: bool,
+
+    output_function_context: usize,
+    output_function_ptr: usize,
+) -> bool {
+    let output_function: extern "C" fn(usize, usize, usize, usize, usize, usize, usize) =
+        unsafe { transmute(output_function_ptr) };
+
+    instance
+        .0
+        .query_graph_with_callback(
+            vec![PathBuf::from(input_graph)],
+            input_queries
+                .iter()
+                .map(|f| GeneralSequenceBlockData::FASTA((PathBuf::from(f), None)))
+                .collect(),
+            kmer_length,
+            threads_count,
+            forward_only,
+            if minimizer_length == usize::MAX {
+                None
+            } else {
+                Some(minimizer_length)
+            },
+            colors,
+            QueryOutputFilter::default(),
+            if query_index.is_empty() {
+                None
+            } else {
+                Some(PathBuf::from(query_index))
+            },
+            single_thread_output_function,
+            |query_index, query_name, matches| {
+                let (colors, matched_kmers): (Vec<_>, Vec<_>) = matches.iter().copied().unzip();
+                output_function(
+                    output_function_context,
+                    query_index,
+                    query_name.as_ptr() as usize,
+                    query_name.len(),
+                    colors.as_ptr() as usize,
+                    matched_kmers.as_ptr() as usize,
+                    matches.len(),
+                );
+            },
+        )
+        .is_ok()
+}
+
+/// Queries a (optionally) colored graph with the sequences of the given streams as queries
+fn ggcat_query_graph_from_streams(
+    instance: &'static GGCATInstanceFFI,
+
+    // The input graph
+    input_graph: String,
+    // The input query streams, read once to collect the query names and once to query the graph
+    input_streams: &[ffi::InputStreamFFI],
+
+    // The output file
+    output_file_prefix: String,
+
+    // Specifies the k-mers length
+    kmer_length: usize,
+    // The threads to be used
+    threads_count: usize,
+    // Treats reverse complementary kmers as different
+    forward_only: bool,
+    // Overrides the default m-mers (minimizers) length
+    minimizer_length: usize,
+
+    // Enable colors
+    colors: bool,
+
+    // Query output format
+    color_output_format: usize,
+
+    // Information reported for each query
+    output_mode: usize,
+
+    // Directory of a query index built from the input graph, if empty the graph is processed again
+    query_index: String,
+) -> String {
+    ggcat_query(
+        instance,
+        input_graph,
+        input_streams_to_blocks(input_streams),
+        output_file_prefix,
+        kmer_length,
+        threads_count,
+        forward_only,
+        minimizer_length,
+        colors,
+        color_output_format,
+        output_mode,
+        query_index,
+    )
+}
+
+/// Builds a query index of a (optionally) colored graph, saving its kmers
+/// bucketed by minimizer to be reused by the queries without processing the graph again
+fn ggcat_build_query_index(
+    instance: &'static GGCATInstanceFFI,
+
+    // The input graph
+    input_graph: String,
+    // The output index directory
+    index_dir: String,
+
+    // Specifies the k-mers length
+    kmer_length: usize,
+    // The threads to be used
+    threads_count: usize,
+    // Overrides the default m-mers (minimizers) length
+    minimizer_length: usize,
+
+    // Enable colors
+    colors: bool,
+
+    // Treats reverse complementary kmers as different
+    forward_only: bool,
+) -> String {
+    instance
+        .0
+        .build_query_index(
+            PathBuf::from(input_graph),
+            PathBuf::from(index_dir),
+            kmer_length,
+            threads_count,
+            forward_only,
+            if minimizer_length == usize::MAX {
+                None
+            } else {
+                Some(minimizer_length)
+            },
+            colors,
         )
         .unwrap_or_default()
         .to_str()
@@ -402,6 +671,19 @@ pub fn ggcat_get_colormap_file(graph_file: String) -> String {
         .to_string()
 }
 
+/// Builds the index of a colormap, saved next to it, to decode each color subset in constant time.
+/// Returns the index file path, or an empty string on failure
+pub fn ggcat_build_colormap_index(
+    // The input colormap
+    colormap: String,
+) -> String {
+    GGCATInstance::build_colormap_index(colormap)
+        .unwrap_or_default()
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
 /// Returns a vector of color names in the given graph.
 /// The color indexes returned from the dump_unitigs function
 /// can be used to index this vector.
@@ -414,6 +696,27 @@ pub fn ggcat_dump_colors(
         .unwrap_or_default()
 }
 
+/// Returns the metadata of the colors in the given colormap, empty if it was built without metadata
+pub fn ggcat_dump_colors_metadata(
+    // The input colormap
+    input_colormap: String,
+) -> ffi::ColorsMetadataFFI {
+    let metadata = &GGCATInstance::dump_colors_metadata(input_colormap).unwrap_or_default();
+    ffi::ColorsMetadataFFI {
+        keys: metadata.keys().to_vec(),
+        values: (0..metadata.colors_count())
+            .flat_map(|color| {
+                metadata.keys().iter().map(move |key| {
+                    metadata
+                        .get(color as ColorIndexType, key)
+                        .unwrap_or_default()
+                        .to_string()
+                })
+            })
+            .collect(),
+    }
+}
+
 /// Dumps the unitigs of the given graph, optionally with colors
 /// It's not guaranteed that maximal unitigs are returned, as only kmers with the same colors subset
 /// are returned as whole unitigs to speedup colormap reading times
@@ -436,10 +739,21 @@ fn ggcat_dump_unitigs(
 
     output_function_context: usize,
     output_function_ptr: usize,
+
+    // Dumps only the unitigs whose colors match the predicate, ignored without colors
+    colors_predicate: ffi::ColorsPredicateFFI,
 ) {
     let output_function: extern "C" fn(usize, usize, usize, usize, usize, bool) =
         unsafe { transmute(output_function_ptr) };
 
+    let colors_predicate = ColorsPredicate {
+        all_of: colors_predicate.all_of,
+        any_of: colors_predicate.any_of,
+        none_of: colors_predicate.none_of,
+        at_least: colors_predicate.at_least,
+        at_least_of: colors_predicate.at_least_of,
+    };
+
     let _ = instance.0.dump_unitigs(
         PathBuf::from(graph_input),
         kmer_length,
@@ -449,6 +763,7 @@ fn ggcat_dump_unitigs(
             Some(minimizer_length)
         },
         colors,
+        colors.then_some(&colors_predicate),
         threads_count,
         single_thread_output_function,
         |sequence, colors, same_colors| {
@@ -564,6 +879,27 @@ mod ffi {
         pub disk_optimization_level: u32,
     }
 
+    /// Metadata of the colors, with the values of each color in the keys order
+    pub struct ColorsMetadataFFI {
+        pub keys: Vec<String>,
+        /// The values of all the colors, keys_count values for each color. Missing values are empty
+        pub values: Vec<String>,
+    }
+
+    /// Predicate on the colors of the dumped unitigs, the empty conditions are ignored
+    pub struct ColorsPredicateFFI {
+        /// Colors that must all be present
+        pub all_of: Vec<u32>,
+        /// Colors of which at least one must be present
+        pub any_of: Vec<u32>,
+        /// Colors that must all be absent
+        pub none_of: Vec<u32>,
+        /// Minimum number of colors of at_least_of that must be present
+        pub at_least: usize,
+        /// Colors counted by at_least, all the colors if empty
+        pub at_least_of: Vec<u32>,
+    }
+
     pub struct InputStreamFFI {
         // extern "C" void (*read_block)(uintptr_t block, bool copy_ident_data, size_t partial_read_copyback, uintptr_t callback, uintptr_t callback_context);
         //      with void callback(uintptr_t callback_context, DnaSequenceFFI sequence, SequenceInfoFFI info);
@@ -579,6 +915,13 @@ mod ffi {
         /// Creates a new GGCATInstance. If an instance already exists, it will be returned, ignoring the new config.
         fn ggcat_create(config: GGCATConfigFFI) -> *const GGCATInstanceFFI;
 
+        /// Sets the path to the blossom5 binary used to compute matchtigs, if empty it is searched in PATH
+        fn ggcat_set_matchtigs_matcher_path(
+            instance: &'static GGCATInstanceFFI,
+            // The path to the blossom5 binary
+            matcher_path: String,
+        );
+
         /// Builds a new graph from the given input files, with the specified parameters
         fn ggcat_build_from_files(
             instance: &'static GGCATInstanceFFI,
@@ -653,14 +996,83 @@ mod ffi {
             disk_optimization_level: u32,
         ) -> String;
 
-        /// Queries a (optionally) colored graph with a specific set of sequences as queries
+        /// Queries a (optionally) colored graph with the sequences of the given files as queries
         fn ggcat_query_graph(
             instance: &'static GGCATInstanceFFI,
 
             // The input graph
             input_graph: String,
-            // The input query as a .fasta file
-            input_query: String,
+            // The input queries as .fasta or .fastq files
+            input_queries: &[String],
+
+            // The output file
+            output_file_prefix: String,
+
+            // Specifies the k-mers length
+            kmer_length: usize,
+            // The threads to be used
+            threads_count: usize,
+            // Treats reverse complementary kmers as different
+            forward_only: bool,
+            // Overrides the default m-mers (minimizers) length
+            minimizer_length: usize,
+
+            // Enable colors
+            colors: bool,
+
+            // Query output format
+            color_output_format: usize,
+
+            // Information reported for each query
+            output_mode: usize,
+
+            // Directory of a query index built from the input graph, if empty the graph is processed again
+            query_index: String,
+        ) -> String;
+
+        /// Queries a (optionally) colored graph with the sequences of the given files as queries,
+        /// passing the matched kmers of each color to the output function instead of writing an output file.
+        /// Returns false on failure
+        fn ggcat_query_graph_with_callback(
+            instance: &'static GGCATInstanceFFI,
+
+            // The input graph
+            input_graph: String,
+            // The input queries as .fasta or .fastq files
+            input_queries: &[String],
+
+            // Specifies the k-mers length
+            kmer_length: usize,
+            // The threads to be used
+            threads_count: usize,
+            // Treats reverse complementary kmers as different
+            forward_only: bool,
+            // Overrides the default m-mers (minimizers) length
+            minimizer_length: usize,
+
+            // Enable colors
+            colors: bool,
+
+            // Directory of a query index built from the input graph, if empty the graph is processed again
+            query_index: String,
+
+            // Call the output function from a single thread at a time, in the queries order,
+            // avoiding the need for synchronization in the user code
+            single_thread_output_function: bool,
+
+            output_function_context: usize,
+            // extern "C" fn(context: usize, query_index: usize, name_ptr: usize, name_len: usize, col_ptr: usize, matched_kmers_ptr: usize, matches_len: usize),
+            output_function_ptr: usize,
+        ) -> bool;
+
+        /// Queries a (optionally) colored graph with the sequences of the given streams as queries
+        fn ggcat_query_graph_from_streams(
+            instance: &'static GGCATInstanceFFI,
+
+            // The input graph
+            input_graph: String,
+            // The input query streams, read once to collect the query names and once to query the graph
+            input_streams: &[InputStreamFFI],
 
             // The output file
             output_file_prefix: String,
@@ -679,10 +1091,47 @@ mod ffi {
 
             // Query output format
             color_output_format: usize,
+
+            // Information reported for each query
+            output_mode: usize,
+
+            // Directory of a query index built from the input graph, if empty the graph is processed again
+            query_index: String,
+        ) -> String;
+
+        /// Builds a query index of a (optionally) colored graph, saving its kmers
+        /// bucketed by minimizer to be reused by the queries without processing the graph again
+        fn ggcat_build_query_index(
+            instance: &'static GGCATInstanceFFI,
+
+            // The input graph
+            input_graph: String,
+            // The output index directory
+            index_dir: String,
+
+            // Specifies the k-mers length
+            kmer_length: usize,
+            // The threads to be used
+            threads_count: usize,
+            // Overrides the default m-mers (minimizers) length
+            minimizer_length: usize,
+
+            // Enable colors
+            colors: bool,
+
+            // Treats reverse complementary kmers as different
+            forward_only: bool,
         ) -> String;
 
         fn ggcat_get_colormap_file(graph_file: String) -> String;
 
+        /// Builds the index of a colormap, saved next to it, to decode each color subset in constant time.
+        /// Returns the index file path, or an empty string on failure
+        fn ggcat_build_colormap_index(
+            // The input colormap
+            colormap: String,
+        ) -> String;
+
         /// Returns a vector of color names in the given graph.
         /// The color indexes returned from the dump_unitigs function
         /// can be used to index this vector.
@@ -691,6 +1140,12 @@ mod ffi {
             input_colormap: String,
         ) -> Vec<String>;
 
+        /// Returns the metadata of the colors in the given colormap, empty if it was built without metadata
+        pub fn ggcat_dump_colors_metadata(
+            // The input colormap
+            input_colormap: String,
+        ) -> ColorsMetadataFFI;
+
         /// Dumps the unitigs of the given graph, optionally with colors
         /// It's not guaranteed that maximal unitigs are returned, as only kmers with the same colors subset
         /// are returned as whole unitigs to speedup colormap reading times
@@ -713,6 +1168,9 @@ mod ffi {
             output_function_context: usize,
             // extern "C" fn(context: usize, seq_ptr: usize, seq_len: usize, col_ptr: usize, col_len: usize, same_colors: bool),
             output_function_ptr: usize,
+
+            // Dumps only the unitigs whose colors match the predicate, ignored without colors
+            colors_predicate: ColorsPredicateFFI,
         );
 
         /// Queries specified color subsets of the colormap, returning
@@ -3,10 +3,16 @@ extern crate alloc;
 // extern crate test;
 
 // mod benchmarks;
+mod serve;
 
 use ahash::HashMap;
-use ggcat_api::{ExtraElaboration, GGCATConfig, GGCATInstance, GfaVersion};
+use ggcat_api::{
+    ColorsCodec, ColorsEdit, ColorsMetadata, ColorsPredicate, ColorsSelection, ExtraElaboration,
+    GGCATConfig, GGCATInstance, GGCATQueryEngine, GfaVersion, GraphCleaningParams,
+    QueryOutputFilter,
+};
 use ggcat_logging::UnrecoverableErrorLogging;
+use itertools::Itertools;
 use std::fs::File;
 use std::io::{BufReader, BufWriter, Write};
 use std::panic;
@@ -50,10 +56,19 @@ arg_enum! {
     }
 }
 
+arg_enum! {
+    /// Encoding of the color subsets in the colormap
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub enum ColormapCodec {
+        RunLength,
+        Roaring,
+    }
+}
+
 use ::utils::compute_best_m;
-use colors::colors_manager::ColorMapReader;
+use colors::colors_manager::{tsv_list_item_escape, ColorMapReader};
 use colors::storage::deserializer::ColorsDeserializer;
-use colors::DefaultColorsSerializer;
+use colors::storage::mapped::ColorsMappedReader;
 use config::ColorIndexType;
 use io::sequences_stream::general::GeneralSequenceBlockData;
 use parallel_processor::memory_fs::MemoryFs;
@@ -64,8 +79,21 @@ use structopt::clap::{arg_enum, ArgGroup};
 enum CliArgs {
     Build(AssemblerArgs),
     Query(QueryArgs),
+    Index(IndexArgs),
+    Serve(ServeArgs),
     DumpColors(DumpColorsArgs),
     Matches(MatchesArgs),
+    Stats(StatsArgs),
+    /// Rename, drop or keep a subset of the colors of a colored graph
+    EditColors(EditColorsArgs),
+    /// Export the unitigs x colors presence/absence matrix of a colored graph
+    PresenceMatrix(PresenceMatrixArgs),
+    /// Compute the all-vs-all kmers similarity matrix of the colors of a colored graph
+    Similarity(SimilarityArgs),
+    /// Dump the unitigs of a colored graph whose colors match a predicate
+    DumpUnitigs(DumpUnitigsArgs),
+    /// Inspect a colormap file
+    Colormap(ColormapArgs),
     // Utils(CmdUtilsArgs),
 }
 
@@ -76,6 +104,10 @@ struct MatchesArgs {
 
     /// Debug print matches of a color index (in hexadecimal)
     match_color: String,
+
+    /// Memory map the colormap index, saved next to the colormap as <graph>.colors.idx on the first run
+    #[structopt(long)]
+    pub mmap: bool,
 }
 
 #[derive(StructOpt, Debug)]
@@ -148,6 +180,15 @@ struct AssemblerArgs {
     #[structopt(short, long)]
     pub colors: bool,
 
+    /// TSV sample sheet with the metadata of the colors, stored in the colormap. The header line names
+    /// the metadata keys after the first column, and each line starts with a color name followed by its values
+    #[structopt(long = "colors-metadata")]
+    pub colors_metadata: Option<PathBuf>,
+
+    /// Encoding of the color subsets in the colormap, the roaring bitmaps are usually smaller with many colors
+    #[structopt(long = "colormap-codec", default_value = "RunLength")]
+    pub colormap_codec: ColormapCodec,
+
     /// Minimum multiplicity required to keep a kmer
     #[structopt(short = "s", long = "min-multiplicity", default_value = "2")]
     pub min_multiplicity: usize,
@@ -195,6 +236,27 @@ struct AssemblerArgs {
     #[structopt(long = "pathtigs", group = "output-mode")]
     pub pathtigs: bool,
 
+    /// Generate optimal matchtigs instead of maximal unitigs, requires the blossom5 binary
+    #[structopt(long = "matchtigs", group = "output-mode")]
+    pub matchtigs: bool,
+
+    /// Path to the blossom5 binary used to compute matchtigs (searched in PATH if not specified)
+    #[structopt(long = "blossom5-path")]
+    pub blossom5_path: Option<PathBuf>,
+
+    /// Remove short low coverage tips and simple bubbles from the graph, recompacting the resulting unitigs.
+    /// Requires the kmer-counters feature
+    #[structopt(long = "clean-graph")]
+    pub clean_graph: bool,
+
+    /// Maximum length of the tips and bubble branches removed by the graph cleaning (default 2k)
+    #[structopt(long = "clean-max-length")]
+    pub clean_max_length: Option<usize>,
+
+    /// Tips and bubble branches with a coverage lower than this fraction of the competing branch are removed
+    #[structopt(long = "clean-coverage-ratio", default_value = "0.5")]
+    pub clean_coverage_ratio: f64,
+
     #[structopt(flatten)]
     pub common_args: CommonArgs,
 
@@ -206,9 +268,177 @@ struct AssemblerArgs {
     #[structopt(long = "gfa-v2")]
     pub gfa_output_v2: bool,
 
+    /// Write a JSON statistics report of the graph next to the output file (<output>.stats.json)
+    #[structopt(long = "stats")]
+    pub stats: bool,
+
     /// Sets the level of disk optimization (0 disabled)
     #[structopt(long = "disk-optimization-level", default_value = "5")]
     pub disk_optimization_level: u32,
+
+    /// Save a checkpoint after each completed phase up to the links compaction in a stable temporary directory, to allow resuming the build with --resume
+    #[structopt(long = "checkpoint")]
+    pub checkpoint: bool,
+
+    /// Resume a crashed build from the checkpoints saved in this temporary directory
+    #[structopt(long = "resume")]
+    pub resume: Option<PathBuf>,
+}
+
+#[derive(StructOpt, Debug)]
+struct StatsArgs {
+    /// The input graph
+    pub input_graph: PathBuf,
+
+    /// Specifies the k-mers length
+    #[structopt(short, long = "kmer-length")]
+    pub kmer_length: usize,
+
+    /// Treats reverse complementary kmers as different
+    #[structopt(short = "f", long)]
+    pub forward_only: bool,
+
+    /// The expected genome size, used to compute the NG50
+    #[structopt(short = "g", long = "genome-size")]
+    pub genome_size: Option<u64>,
+
+    /// Directory for temporary files (default .temp_files)
+    #[structopt(short = "t", long = "temp-dir", default_value = ".temp_files")]
+    pub temp_dir: PathBuf,
+
+    /// Write the JSON report to this file instead of the standard output
+    #[structopt(short = "o", long = "output-file")]
+    pub output_file: Option<PathBuf>,
+}
+
+#[derive(StructOpt, Debug)]
+struct EditColorsArgs {
+    /// The input graph
+    pub input_graph: PathBuf,
+
+    /// The edited graph, written with its colormap
+    #[structopt(short = "o", long = "output-file")]
+    pub output_file: PathBuf,
+
+    /// The new names of the colors, in a file with format <COLOR_NAME><TAB><NEW_NAME>
+    #[structopt(long = "rename")]
+    pub rename: Option<PathBuf>,
+
+    /// Remove these colors from the graph
+    #[structopt(long = "drop-colors")]
+    pub drop_colors: Vec<String>,
+
+    /// Remove the colors listed in these files, one name for each line
+    #[structopt(long = "drop-colors-list")]
+    pub drop_colors_list: Vec<PathBuf>,
+
+    /// Keep only these colors in the graph
+    #[structopt(long = "keep-colors")]
+    pub keep_colors: Vec<String>,
+
+    /// Keep only the colors listed in these files, one name for each line
+    #[structopt(long = "keep-colors-list")]
+    pub keep_colors_list: Vec<PathBuf>,
+
+    #[structopt(flatten)]
+    pub common_args: CommonArgs,
+}
+
+arg_enum! {
+    /// Format of the presence/absence matrix output
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub enum PresenceMatrixFormat {
+        Rtab,
+        Sparse,
+    }
+}
+
+#[derive(StructOpt, Debug)]
+struct PresenceMatrixArgs {
+    /// The input graph
+    pub input_graph: PathBuf,
+
+    /// The output matrix file
+    #[structopt(
+        short = "o",
+        long = "output-file",
+        default_value = "presence_matrix.rtab"
+    )]
+    pub output_file: PathBuf,
+
+    /// Output format: Rtab writes a row of 0/1 values for each unitig with a column for each color,
+    /// Sparse writes a <UNITIG><TAB><COLOR_INDEX> line for each color present in each unitig
+    #[structopt(long = "format", default_value = "Rtab")]
+    pub format: PresenceMatrixFormat,
+
+    /// Write a row for each distinct set of colors instead of each unitig, saving the pattern of each unitig in <output-file>.patterns.tsv
+    #[structopt(long = "collapse-patterns")]
+    pub collapse_patterns: bool,
+
+    #[structopt(flatten)]
+    pub common_args: CommonArgs,
+}
+
+arg_enum! {
+    /// Similarity measure between two colors
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub enum SimilarityMetric {
+        Jaccard,
+        Containment,
+        Intersection,
+    }
+}
+
+#[derive(StructOpt, Debug)]
+struct SimilarityArgs {
+    /// The input graph
+    pub input_graph: PathBuf,
+
+    /// Specifies the k-mers length
+    #[structopt(short, long = "kmer-length")]
+    pub kmer_length: usize,
+
+    /// Similarity measure: Jaccard, Containment (the fraction of the kmers of the row color present in the column color)
+    /// or Intersection (the number of shared kmers)
+    #[structopt(long = "metric", default_value = "Jaccard")]
+    pub metric: SimilarityMetric,
+
+    /// Write the matrix to this file instead of the standard output
+    #[structopt(short = "o", long = "output-file")]
+    pub output_file: Option<PathBuf>,
+}
+
+#[derive(StructOpt, Debug)]
+struct DumpUnitigsArgs {
+    /// The input graph
+    pub input_graph: PathBuf,
+
+    /// The output FASTA file, each unitig is written with the indexes of its colors as a CI:B:I,<COLOR_INDEX>,... tag
+    #[structopt(short = "o", long = "output-file", default_value = "unitigs.fa")]
+    pub output_file: PathBuf,
+
+    /// Dump only the unitigs present in all these colors
+    #[structopt(long = "all-of")]
+    pub all_of: Vec<String>,
+
+    /// Dump only the unitigs present in at least one of these colors
+    #[structopt(long = "any-of")]
+    pub any_of: Vec<String>,
+
+    /// Dump only the unitigs absent from all these colors
+    #[structopt(long = "none-of")]
+    pub none_of: Vec<String>,
+
+    /// Dump only the unitigs present in at least this number of the --at-least-of colors, or of all the colors if not specified
+    #[structopt(long = "at-least", default_value = "0")]
+    pub at_least: usize,
+
+    /// The colors counted by --at-least
+    #[structopt(long = "at-least-of")]
+    pub at_least_of: Vec<String>,
+
+    #[structopt(flatten)]
+    pub common_args: CommonArgs,
 }
 
 #[derive(StructOpt, Debug)]
@@ -217,12 +447,79 @@ struct DumpColorsArgs {
     output_file: PathBuf,
 }
 
+#[derive(StructOpt, Debug)]
+enum ColormapArgs {
+    /// Show the codec, the build parameters, the colors and subsets counts, the sizes and the subset sizes histogram
+    Info(ColormapInfoArgs),
+    /// Check the colormap structure, its checksums and that all its subsets can be decoded
+    Validate(ColormapValidateArgs),
+    /// Print the given subsets, or all of them, with their color names
+    Subsets(ColormapSubsetsArgs),
+    /// Convert a colormap built by an older release to the current format, adding the integrity checksums
+    Upgrade(ColormapUpgradeArgs),
+}
+
+#[derive(StructOpt, Debug)]
+struct ColormapInfoArgs {
+    /// The input colormap
+    input_colormap: PathBuf,
+}
+
+#[derive(StructOpt, Debug)]
+struct ColormapValidateArgs {
+    /// The input colormap
+    input_colormap: PathBuf,
+}
+
+#[derive(StructOpt, Debug)]
+struct ColormapUpgradeArgs {
+    /// The input colormap, replaced by the upgraded one
+    input_colormap: PathBuf,
+}
+
+arg_enum! {
+    /// Format of the colormap subsets output
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub enum ColormapSubsetsFormat {
+        Tsv,
+        JsonLines,
+    }
+}
+
+#[derive(StructOpt, Debug)]
+struct ColormapSubsetsArgs {
+    /// The input colormap
+    input_colormap: PathBuf,
+
+    /// The subsets to print, all the subsets if not specified
+    subsets: Vec<ColorIndexType>,
+
+    #[structopt(long, default_value = "Tsv")]
+    pub format: ColormapSubsetsFormat,
+
+    /// Write the subsets to this file instead of the standard output
+    #[structopt(short = "o", long = "output-file")]
+    pub output_file: Option<PathBuf>,
+}
+
 arg_enum! {
     /// Format of the queries output
     #[derive(Copy, Clone, Debug, PartialEq, Eq)]
     pub enum ColoredQueryOutputFormat {
         JsonLinesWithNumbers,
         JsonLinesWithNames,
+        Tsv,
+        MatrixMarket,
+    }
+}
+
+arg_enum! {
+    /// Information reported for each query
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub enum QueryOutputMode {
+        MatchCounts,
+        KmerPositions,
+        MatchedUnitigs,
     }
 }
 
@@ -231,8 +528,15 @@ struct QueryArgs {
     /// The input graph
     pub input_graph: PathBuf,
 
-    /// The input query as a .fasta file
-    pub input_query: PathBuf,
+    /// The input queries as .fasta or .fastq files (optionally gzipped), numbered in order across the files
+    #[structopt(required = true)]
+    pub input_queries: Vec<PathBuf>,
+
+    /// Additional colored graphs, built with the same k and hash settings, to be queried along with
+    /// the input graph. The colors are numbered in order across the graphs, and the color names
+    /// found in more than one graph are prefixed with the graph path
+    #[structopt(long = "graph")]
+    pub additional_graphs: Vec<PathBuf>,
 
     /// Enable colors
     #[structopt(short, long)]
@@ -244,6 +548,33 @@ struct QueryArgs {
     #[structopt(long = "colored-query-output-format")]
     pub colored_query_output_format: Option<ColoredQueryOutputFormat>,
 
+    /// Minimum fraction of the query kmers that must be matched by a color to be written in the colored output
+    #[structopt(long = "min-match-fraction", default_value = "0")]
+    pub min_match_fraction: f64,
+
+    /// Minimum number of query kmers that must be matched by a color to be written in the colored output
+    #[structopt(long = "min-matched-kmers", default_value = "0")]
+    pub min_matched_kmers: u64,
+
+    /// Write only the N colors with the most matched kmers for each query in the colored output
+    #[structopt(long = "top-colors")]
+    pub top_colors: Option<usize>,
+
+    /// Write only the colors whose metadata matches all the given keys, as key=value or key!=value.
+    /// Repeating a key with = keeps the colors matching any of its values
+    #[structopt(long = "select-colors")]
+    pub select_colors: Vec<String>,
+
+    /// Information reported for each query: the matched kmers count of each color, the
+    /// run-length encoded segments of the matched kmer positions with their colors (requires colors),
+    /// or the graph unitigs matched by the query kmers with their orientation
+    #[structopt(long = "output-mode", default_value = "MatchCounts")]
+    pub output_mode: QueryOutputMode,
+
+    /// Use the query index built from the input graph in this directory, avoiding to process the graph again
+    #[structopt(long = "index")]
+    pub index: Option<PathBuf>,
+
     #[structopt(short = "x", long, default_value = "MinimizerBucketing")]
     pub step: QuerierStartingStep,
 
@@ -251,6 +582,64 @@ struct QueryArgs {
     pub common_args: CommonArgs,
 }
 
+#[derive(StructOpt, Debug)]
+struct IndexArgs {
+    /// The input graph
+    pub input_graph: PathBuf,
+
+    /// Enable colors
+    #[structopt(short, long)]
+    pub colors: bool,
+
+    /// The directory where the query index is saved
+    #[structopt(short = "o", long = "output-dir", default_value = "query-index")]
+    pub output_dir: PathBuf,
+
+    #[structopt(flatten)]
+    pub common_args: CommonArgs,
+}
+
+#[derive(StructOpt, Debug)]
+struct ServeArgs {
+    /// The input graph
+    pub input_graph: PathBuf,
+
+    /// Specifies the k-mers length
+    #[structopt(short, long = "kmer-length")]
+    pub kmer_length: usize,
+
+    /// Enable colors
+    #[structopt(short, long)]
+    pub colors: bool,
+
+    /// Treats reverse complementary kmers as different
+    #[structopt(short = "f", long)]
+    pub forward_only: bool,
+
+    /// The query index of the graph, built with the index command
+    #[structopt(long)]
+    pub index: PathBuf,
+
+    /// Memory map the colormap index, saved next to the colormap as <graph>.colors.idx on the first run
+    #[structopt(long)]
+    pub mmap: bool,
+
+    /// Maximum number of query index buckets kept decoded in memory
+    #[structopt(long, default_value = "64")]
+    pub cached_buckets: usize,
+
+    /// Maximum number of connections handled at the same time
+    #[structopt(long, default_value = "16")]
+    pub max_connections: usize,
+
+    /// The address where the query server listens
+    #[structopt(short = "l", long, default_value = "127.0.0.1:8080")]
+    pub listen: String,
+
+    #[structopt(short = "j", long, default_value = "16")]
+    pub threads_count: usize,
+}
+
 // #[cfg(feature = "mem-analysis")]
 // use parallel_processor::debug_allocator::{debug_print_allocations, DebugAllocator};
 //
@@ -262,6 +651,7 @@ fn initialize(
     args: &CommonArgs,
     out_file: &PathBuf,
     disk_optimization_level: u32,
+    matchtigs_matcher_path: Option<PathBuf>,
 ) -> &'static GGCATInstance {
     let instance = GGCATInstance::create(GGCATConfig {
         temp_dir: Some(args.temp_dir.clone()),
@@ -294,7 +684,9 @@ fn initialize(
 
     // #[cfg(feature = "mem-analysis")]
     // debug_print_allocations("/tmp/allocations", Duration::from_secs(5));
-    instance.unwrap()
+    let instance = instance.unwrap();
+    instance.set_matchtigs_matcher_path(matchtigs_matcher_path);
+    instance
 }
 
 fn convert_assembler_step(step: AssemblerStartingStep) -> assembler::AssemblerStartingStep {
@@ -425,6 +817,15 @@ fn run_assembler_from_args(instance: &GGCATInstance, args: AssemblerArgs) {
         exit(1);
     }
 
+    let colors_metadata = match &args.colors_metadata {
+        Some(_) if !args.colors => {
+            println!("ERROR: Colors metadata requires colors (-c)!");
+            exit(1);
+        }
+        Some(sheet) => Some(ColorsMetadata::from_tsv(sheet, &color_names).unwrap()),
+        None => None,
+    };
+
     let inputs = inputs
         .into_iter()
         .map(|x| GeneralSequenceBlockData::FASTA(x))
@@ -439,11 +840,16 @@ fn run_assembler_from_args(instance: &GGCATInstance, args: AssemblerArgs) {
             inputs,
             args.output_file,
             Some(&color_names),
+            colors_metadata.as_ref(),
             args.common_args.kmer_length,
             args.common_args.threads_count,
             args.common_args.forward_only,
             args.common_args.minimizer_length,
             args.colors,
+            match args.colormap_codec {
+                ColormapCodec::RunLength => ColorsCodec::RunLength,
+                ColormapCodec::Roaring => ColorsCodec::Roaring,
+            },
             args.min_multiplicity,
             if args.generate_maximal_unitigs_links {
                 ExtraElaboration::UnitigLinks
@@ -453,6 +859,8 @@ fn run_assembler_from_args(instance: &GGCATInstance, args: AssemblerArgs) {
                 ExtraElaboration::Eulertigs
             } else if args.pathtigs {
                 ExtraElaboration::Pathtigs
+            } else if args.matchtigs {
+                ExtraElaboration::Matchtigs
             } else if args.fast_simplitigs {
                 ExtraElaboration::FastSimplitigs
             } else if args.fast_eulertigs {
@@ -460,6 +868,14 @@ fn run_assembler_from_args(instance: &GGCATInstance, args: AssemblerArgs) {
             } else {
                 ExtraElaboration::None
             },
+            if args.clean_graph {
+                Some(GraphCleaningParams {
+                    max_length: args.clean_max_length,
+                    coverage_ratio: args.clean_coverage_ratio,
+                })
+            } else {
+                None
+            },
             if args.gfa_output_v1 {
                 Some(GfaVersion::V1)
             } else if args.gfa_output_v2 {
@@ -468,10 +884,26 @@ fn run_assembler_from_args(instance: &GGCATInstance, args: AssemblerArgs) {
                 None
             },
             args.disk_optimization_level,
+            args.checkpoint,
+            args.resume,
         )
         .unwrap();
 
     println!("Final output saved to: {}", output_file.display());
+
+    if args.stats && output_file != PathBuf::new() {
+        let stats_file = output_file.with_extension("stats.json");
+        GGCATInstance::compute_graph_stats(
+            &output_file,
+            args.common_args.kmer_length,
+            args.common_args.forward_only,
+            None,
+            &args.common_args.temp_dir,
+        )
+        .and_then(|stats| stats.write_json(&stats_file))
+        .unwrap();
+        println!("Graph statistics saved to: {}", stats_file.display());
+    }
 }
 
 fn convert_querier_step(step: QuerierStartingStep) -> querier::QuerierStartingStep {
@@ -488,8 +920,13 @@ fn run_querier_from_args(instance: &GGCATInstance, args: QueryArgs) -> PathBuf {
 
     instance
         .query_graph(
-            args.input_graph,
-            args.input_query,
+            std::iter::once(args.input_graph)
+                .chain(args.additional_graphs)
+                .collect(),
+            args.input_queries
+                .into_iter()
+                .map(|query| GeneralSequenceBlockData::FASTA((query, None)))
+                .collect(),
             args.output_file_prefix,
             args.common_args.kmer_length,
             args.common_args.threads_count,
@@ -506,13 +943,130 @@ fn run_querier_from_args(instance: &GGCATInstance, args: QueryArgs) -> PathBuf {
                 ColoredQueryOutputFormat::JsonLinesWithNames => {
                     querier::ColoredQueryOutputFormat::JsonLinesWithNames
                 }
+                ColoredQueryOutputFormat::Tsv => querier::ColoredQueryOutputFormat::Tsv,
+                ColoredQueryOutputFormat::MatrixMarket => {
+                    querier::ColoredQueryOutputFormat::MatrixMarket
+                }
             },
+            QueryOutputFilter {
+                min_match_fraction: args.min_match_fraction,
+                min_matched_kmers: args.min_matched_kmers,
+                top_colors: args.top_colors,
+                colors_selection: if args.select_colors.is_empty() {
+                    None
+                } else {
+                    Some(ColorsSelection::new(&args.select_colors).unwrap())
+                },
+            },
+            match args.output_mode {
+                QueryOutputMode::MatchCounts => querier::QueryOutputMode::MatchCounts,
+                QueryOutputMode::KmerPositions => querier::QueryOutputMode::KmerPositions,
+                QueryOutputMode::MatchedUnitigs => querier::QueryOutputMode::MatchedUnitigs,
+            },
+            args.index,
         )
         .unwrap()
 }
 
 instrumenter::global_setup_instrumenter!();
 
+fn run_colormap_command(args: ColormapArgs) {
+    match args {
+        ColormapArgs::Info(args) => {
+            let info = GGCATInstance::colormap_info(&args.input_colormap).unwrap();
+            println!("{}", info.to_json());
+        }
+        ColormapArgs::Validate(args) => {
+            let errors = GGCATInstance::validate_colormap(&args.input_colormap).unwrap();
+            if errors.is_empty() {
+                println!("Colormap {} is valid", args.input_colormap.display());
+            } else {
+                for error in &errors {
+                    println!("Error: {}", error);
+                }
+                exit(1);
+            }
+        }
+        ColormapArgs::Upgrade(args) => {
+            if GGCATInstance::upgrade_colormap(&args.input_colormap).unwrap() {
+                println!(
+                    "Colormap {} upgraded to the current format",
+                    args.input_colormap.display()
+                );
+            } else {
+                println!(
+                    "Colormap {} is already up to date",
+                    args.input_colormap.display()
+                );
+            }
+        }
+        ColormapArgs::Subsets(args) => {
+            let mut colors_deserializer =
+                ColorsDeserializer::new(&args.input_colormap, true).unwrap();
+            let subsets_count = colors_deserializer.colors_subsets_count();
+
+            if let Some(subset) = args.subsets.iter().find(|s| **s as u64 >= subsets_count) {
+                println!(
+                    "Error: color subset {} not found, the colormap has {} subsets",
+                    subset, subsets_count
+                );
+                exit(1);
+            }
+
+            let subsets: Vec<_> = if args.subsets.is_empty() {
+                (0..subsets_count as ColorIndexType).collect()
+            } else {
+                args.subsets
+            };
+
+            let mut output: BufWriter<Box<dyn Write>> = BufWriter::new(match &args.output_file {
+                Some(output_file) => Box::new(File::create(output_file).unwrap()),
+                None => Box::new(std::io::stdout()),
+            });
+
+            if args.format == ColormapSubsetsFormat::Tsv {
+                writeln!(output, "subset\tcolor_indexes\tcolor_names").unwrap();
+            }
+
+            let mut colors = Vec::new();
+            for subset in subsets {
+                colors_deserializer
+                    .get_color_mappings(subset, &mut colors)
+                    .unwrap();
+                match args.format {
+                    ColormapSubsetsFormat::Tsv => writeln!(
+                        output,
+                        "{}\t{}\t{}",
+                        subset,
+                        colors.iter().join(","),
+                        colors
+                            .iter()
+                            .map(|c| tsv_list_item_escape(
+                                colors_deserializer.get_color_name(*c, false)
+                            ))
+                            .join(",")
+                    ),
+                    ColormapSubsetsFormat::JsonLines => writeln!(
+                        output,
+                        "{{\"subset\":{},\"color_indexes\":[{}],\"color_names\":[{}]}}",
+                        subset,
+                        colors.iter().join(","),
+                        colors
+                            .iter()
+                            .map(|c| format!(
+                                "\"{}\"",
+                                colors_deserializer.get_color_name(*c, true)
+                            ))
+                            .join(",")
+                    ),
+                }
+                .unwrap();
+            }
+            output.flush().unwrap();
+        }
+    }
+}
+
 fn main() {
     let args: CliArgs = CliArgs::from_args();
 
@@ -539,20 +1093,34 @@ fn main() {
                 &args.common_args,
                 &args.output_file,
                 args.disk_optimization_level,
+                args.blossom5_path.clone(),
             );
 
             run_assembler_from_args(&instance, args);
         }
         CliArgs::Matches(args) => {
             let colors_file = args.input_file.with_extension("colors.dat");
-            let mut colors_deserializer =
-                ColorsDeserializer::<DefaultColorsSerializer>::new(colors_file, true).unwrap();
+            let mut colors_deserializer = ColorsDeserializer::new(&colors_file, true).unwrap();
 
             let mut colors = Vec::new();
 
             let color = ColorIndexType::from_str_radix(&args.match_color, 16)
                 .expect("Invalid color, please use hex format");
-            colors_deserializer.get_color_mappings(color, &mut colors);
+            if args.mmap {
+                let mapped_colormap = ColorsMappedReader::open_or_build(&colors_file).unwrap();
+                if !mapped_colormap.get_color_mappings(color, &mut colors) {
+                    println!(
+                        "Error: color subset {:x} not found, the colormap has {} subsets",
+                        color,
+                        mapped_colormap.subsets_count()
+                    );
+                    exit(1);
+                }
+            } else {
+                colors_deserializer
+                    .get_color_mappings(color, &mut colors)
+                    .unwrap();
+            }
 
             for color in colors {
                 println!(
@@ -568,30 +1136,92 @@ fn main() {
                 println!("Warning: colored query output format is specified, but the graph is not colored");
             }
 
+            if !args.colors && args.output_mode == QueryOutputMode::KmerPositions {
+                println!("Error: the kmer positions output mode requires colors (-c)");
+                exit(1);
+            }
+
             let _guard = instrumenter::initialize_tracing(
                 args.output_file_prefix.with_extension("tracing.json"),
                 &["ix86arch::INSTRUCTION_RETIRED", "ix86arch::LLC_MISSES"],
             );
 
-            let instance = initialize(&args.common_args, &args.output_file_prefix, 0);
+            let instance = initialize(&args.common_args, &args.output_file_prefix, 0, None);
 
             let output_file_name = run_querier_from_args(&instance, args);
             println!("Final output saved to: {}", output_file_name.display());
         }
+        CliArgs::Index(args) => {
+            let instance = initialize(&args.common_args, &args.output_dir, 0, None);
+
+            let index_dir = instance
+                .build_query_index(
+                    args.input_graph,
+                    args.output_dir,
+                    args.common_args.kmer_length,
+                    args.common_args.threads_count,
+                    args.common_args.forward_only,
+                    args.common_args.minimizer_length,
+                    args.colors,
+                )
+                .unwrap();
+            println!("Query index saved to: {}", index_dir.display());
+        }
+        CliArgs::Serve(args) => {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(args.threads_count)
+                .build_global()
+                .unwrap();
+
+            let engine = GGCATQueryEngine::load(
+                &args.input_graph,
+                &args.index,
+                args.kmer_length,
+                args.forward_only,
+                args.colors,
+                args.mmap,
+                args.cached_buckets,
+            )
+            .unwrap();
+            println!(
+                "Loaded {} with the query index {}",
+                args.input_graph.display(),
+                args.index.display()
+            );
+
+            serve::run_server(&engine, &args.listen, args.max_connections).unwrap();
+        }
         CliArgs::DumpColors(args) => {
             let output_file_name = args.output_file.with_extension("jsonl");
 
             let mut output_file = BufWriter::new(File::create(&output_file_name).unwrap());
 
+            let colors_metadata =
+                GGCATInstance::dump_colors_metadata(&args.input_colormap).unwrap();
+
             for (color_idx, color_name) in GGCATInstance::dump_colors(args.input_colormap)
                 .unwrap()
                 .enumerate()
             {
-                writeln!(
-                    output_file,
-                    "{{\"color_index\":{}, \"color_name\":\"{}\" }}",
-                    color_idx, color_name,
-                )
+                if colors_metadata.is_empty() {
+                    writeln!(
+                        output_file,
+                        "{{\"color_index\":{}, \"color_name\":\"{}\" }}",
+                        color_idx, color_name,
+                    )
+                } else {
+                    let metadata: serde_json::Map<_, _> = colors_metadata
+                        .color_values(color_idx as ColorIndexType)
+                        .map(|(key, value)| (key.to_string(), value.into()))
+                        .collect();
+                    writeln!(
+                        output_file,
+                        "{{\"color_index\":{}, \"color_name\":\"{}\", \"metadata\":{} }}",
+                        color_idx,
+                        color_name,
+                        serde_json::Value::Object(metadata),
+                    )
+                }
                 .unwrap();
             }
 
@@ -600,6 +1230,317 @@ fn main() {
 
             return; // Skip final memory deallocation
         }
+        CliArgs::Stats(args) => {
+            let stats = GGCATInstance::compute_graph_stats(
+                &args.input_graph,
+                args.kmer_length,
+                args.forward_only,
+                args.genome_size,
+                &args.temp_dir,
+            )
+            .unwrap();
+
+            match args.output_file {
+                Some(output_file) => {
+                    stats.write_json(&output_file).unwrap();
+                    println!("Stats written to {}", output_file.display());
+                }
+                None => println!("{}", stats.to_json()),
+            }
+
+            return; // Skip final memory deallocation
+        }
+        CliArgs::EditColors(args) => {
+            let read_names = |names: Vec<String>, lists: Vec<PathBuf>| {
+                names.into_iter().chain(lists.into_iter().flat_map(|list| {
+                    std::fs::read_to_string(&list)
+                        .log_unrecoverable_error_with_data(
+                            "Error while opening colors list file",
+                            list.display(),
+                        )
+                        .unwrap()
+                        .lines()
+                        .filter(|line| !line.trim().is_empty())
+                        .map(|line| line.to_string())
+                        .collect::<Vec<_>>()
+                }))
+            };
+
+            let edit = ColorsEdit {
+                rename: args
+                    .rename
+                    .map(|rename| ColorsEdit::read_renames(rename).unwrap())
+                    .unwrap_or_default(),
+                drop: read_names(args.drop_colors, args.drop_colors_list).collect(),
+                keep: read_names(args.keep_colors, args.keep_colors_list).collect(),
+            };
+
+            if !edit.drop.is_empty() && !edit.keep.is_empty() {
+                println!("ERROR: Colors can be either dropped or kept, not both!");
+                exit(1);
+            }
+
+            let instance = initialize(&args.common_args, &args.output_file, 0, None);
+            let stats = instance
+                .edit_colors(
+                    &args.input_graph,
+                    &args.output_file,
+                    args.common_args.kmer_length,
+                    args.common_args.minimizer_length,
+                    args.common_args.forward_only,
+                    args.common_args.threads_count,
+                    &edit,
+                )
+                .unwrap();
+
+            println!(
+                "Edited graph written to {}: {} colors ({} removed), {} color subsets, {} kmers removed, {} unitigs ({} merged)",
+                args.output_file.display(),
+                stats.colors_count,
+                stats.removed_colors,
+                stats.subsets_count,
+                stats.removed_kmers,
+                stats.unitigs_count,
+                stats.merged_unitigs
+            );
+
+            return; // Skip final memory deallocation
+        }
+        CliArgs::Similarity(args) => {
+            let similarity =
+                GGCATInstance::compute_colors_similarity(&args.input_graph, args.kmer_length)
+                    .unwrap();
+
+            let mut output: Box<dyn Write> = match &args.output_file {
+                Some(output_file) => Box::new(BufWriter::new(
+                    File::create(output_file)
+                        .log_unrecoverable_error_with_data(
+                            "Cannot create similarity file",
+                            output_file.display(),
+                        )
+                        .unwrap(),
+                )),
+                None => Box::new(BufWriter::new(std::io::stdout().lock())),
+            };
+
+            writeln!(output, "color\t{}", similarity.color_names().join("\t")).unwrap();
+            for (first, name) in similarity.color_names().iter().enumerate() {
+                write!(output, "{}", name).unwrap();
+                for second in 0..similarity.colors_count() {
+                    let (first, second) = (first as ColorIndexType, second as ColorIndexType);
+                    match args.metric {
+                        SimilarityMetric::Jaccard => {
+                            write!(output, "\t{:.6}", similarity.jaccard(first, second))
+                        }
+                        SimilarityMetric::Containment => {
+                            write!(output, "\t{:.6}", similarity.containment(first, second))
+                        }
+                        SimilarityMetric::Intersection => {
+                            write!(output, "\t{}", similarity.intersection(first, second))
+                        }
+                    }
+                    .unwrap();
+                }
+                writeln!(output).unwrap();
+            }
+            output.flush().unwrap();
+
+            if let Some(output_file) = &args.output_file {
+                println!("Similarity matrix written to {}", output_file.display());
+            }
+
+            return; // Skip final memory deallocation
+        }
+        CliArgs::DumpUnitigs(args) => {
+            let instance = initialize(&args.common_args, &args.output_file, 0, None);
+
+            let color_names: Vec<_> =
+                GGCATInstance::dump_colors(args.input_graph.with_extension("colors.dat"))
+                    .unwrap()
+                    .collect();
+
+            let to_indexes = |names: &[String]| -> Vec<ColorIndexType> {
+                names
+                    .iter()
+                    .map(|name| match color_names.iter().position(|c| c == name) {
+                        Some(index) => index as ColorIndexType,
+                        None => {
+                            println!("Error: color {} not found in the graph", name);
+                            exit(1);
+                        }
+                    })
+                    .collect()
+            };
+
+            let colors_predicate = ColorsPredicate {
+                all_of: to_indexes(&args.all_of),
+                any_of: to_indexes(&args.any_of),
+                none_of: to_indexes(&args.none_of),
+                at_least: args.at_least,
+                at_least_of: to_indexes(&args.at_least_of),
+            };
+
+            let output = parking_lot::Mutex::new((
+                BufWriter::new(
+                    File::create(&args.output_file)
+                        .log_unrecoverable_error_with_data(
+                            "Cannot create unitigs file",
+                            args.output_file.display(),
+                        )
+                        .unwrap(),
+                ),
+                0u64,
+            ));
+
+            instance
+                .dump_unitigs(
+                    &args.input_graph,
+                    args.common_args.kmer_length,
+                    args.common_args.minimizer_length,
+                    true,
+                    Some(&colors_predicate),
+                    args.common_args.threads_count,
+                    true,
+                    |sequence, colors, _| {
+                        let mut output = output.lock();
+                        let (output, unitigs_count) = &mut *output;
+                        writeln!(
+                            output,
+                            ">{} LN:i:{} CI:B:I,{}",
+                            unitigs_count,
+                            sequence.len(),
+                            colors.iter().join(",")
+                        )
+                        .unwrap();
+                        output.write_all(sequence).unwrap();
+                        writeln!(output).unwrap();
+                        *unitigs_count += 1;
+                    },
+                )
+                .unwrap();
+
+            let (mut output, unitigs_count) = output.into_inner();
+            output.flush().unwrap();
+
+            println!(
+                "{} unitigs written to {}",
+                unitigs_count,
+                args.output_file.display()
+            );
+
+            return; // Skip final memory deallocation
+        }
+        CliArgs::PresenceMatrix(args) => {
+            let instance = initialize(&args.common_args, &args.output_file, 0, None);
+
+            let color_names: Vec<_> =
+                GGCATInstance::dump_colors(args.input_graph.with_extension("colors.dat"))
+                    .unwrap()
+                    .collect();
+
+            let mut output = BufWriter::new(
+                File::create(&args.output_file)
+                    .log_unrecoverable_error_with_data(
+                        "Cannot create presence matrix file",
+                        args.output_file.display(),
+                    )
+                    .unwrap(),
+            );
+            let patterns_file = args.output_file.with_extension("patterns.tsv");
+            let mut patterns_output = args.collapse_patterns.then(|| {
+                BufWriter::new(
+                    File::create(&patterns_file)
+                        .log_unrecoverable_error_with_data(
+                            "Cannot create patterns file",
+                            patterns_file.display(),
+                        )
+                        .unwrap(),
+                )
+            });
+
+            let row_name = if args.collapse_patterns {
+                "pattern"
+            } else {
+                "unitig"
+            };
+            match args.format {
+                PresenceMatrixFormat::Rtab => {
+                    writeln!(output, "{}\t{}", row_name, color_names.join("\t")).unwrap()
+                }
+                PresenceMatrixFormat::Sparse => writeln!(output, "{}\tcolor", row_name).unwrap(),
+            }
+            if let Some(patterns_output) = &mut patterns_output {
+                writeln!(patterns_output, "unitig\tpattern").unwrap();
+            }
+
+            let mut rows_count = 0u64;
+            let mut unitigs_count = 0u64;
+            let mut presence_row = vec![false; color_names.len()];
+
+            instance
+                .dump_presence_matrix(
+                    &args.input_graph,
+                    args.common_args.kmer_length,
+                    args.common_args.minimizer_length,
+                    args.common_args.threads_count,
+                    args.collapse_patterns,
+                    |unitigs, colors| {
+                        let row = if args.collapse_patterns {
+                            rows_count
+                        } else {
+                            unitigs[0]
+                        };
+
+                        match args.format {
+                            PresenceMatrixFormat::Rtab => {
+                                presence_row.fill(false);
+                                for color in colors {
+                                    presence_row[*color as usize] = true;
+                                }
+                                write!(output, "{}", row).unwrap();
+                                for present in &presence_row {
+                                    write!(output, "\t{}", *present as u8).unwrap();
+                                }
+                                writeln!(output).unwrap();
+                            }
+                            PresenceMatrixFormat::Sparse => {
+                                for color in colors {
+                                    writeln!(output, "{}\t{}", row, color).unwrap();
+                                }
+                            }
+                        }
+
+                        if let Some(patterns_output) = &mut patterns_output {
+                            for unitig in unitigs {
+                                writeln!(patterns_output, "{}\t{}", unitig, rows_count).unwrap();
+                            }
+                        }
+
+                        rows_count += 1;
+                        unitigs_count += unitigs.len() as u64;
+                    },
+                )
+                .unwrap();
+
+            output.flush().unwrap();
+            if let Some(mut patterns_output) = patterns_output {
+                patterns_output.flush().unwrap();
+                println!("Unitigs patterns written to {}", patterns_file.display());
+            }
+            println!(
+                "Presence matrix written to {}: {} unitigs, {} rows, {} colors",
+                args.output_file.display(),
+                unitigs_count,
+                rows_count,
+                color_names.len()
+            );
+
+            return; // Skip final memory deallocation
+        }
+        CliArgs::Colormap(args) => {
+            run_colormap_command(args);
+            return; // Skip final memory deallocation
+        }
     }
 
     // Ensure termination
@@ -0,0 +1,413 @@
+use ggcat_api::{ColorIndexType, DnaSequence, DnaSequencesFileType, GGCATQueryEngine};
+use serde_json::{json, Map, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{sync_channel, TrySendError};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Maximum accepted size of a request body
+const MAX_REQUEST_SIZE: usize = 16 * 1024 * 1024;
+
+/// Maximum accepted size of the request line and headers
+const MAX_HEADERS_SIZE: u64 = 64 * 1024;
+
+/// Time after which a connection that is not sending or receiving data is closed
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Status {
+    Ok,
+    BadRequest,
+    NotFound,
+    PayloadTooLarge,
+    HeadersTooLarge,
+    ServiceUnavailable,
+    InternalServerError,
+}
+
+impl Status {
+    fn code(self) -> u16 {
+        match self {
+            Status::Ok => 200,
+            Status::BadRequest => 400,
+            Status::NotFound => 404,
+            Status::PayloadTooLarge => 413,
+            Status::HeadersTooLarge => 431,
+            Status::ServiceUnavailable => 503,
+            Status::InternalServerError => 500,
+        }
+    }
+
+    fn reason(self) -> &'static str {
+        match self {
+            Status::Ok => "OK",
+            Status::BadRequest => "Bad Request",
+            Status::NotFound => "Not Found",
+            Status::PayloadTooLarge => "Payload Too Large",
+            Status::HeadersTooLarge => "Request Header Fields Too Large",
+            Status::ServiceUnavailable => "Service Unavailable",
+            Status::InternalServerError => "Internal Server Error",
+        }
+    }
+}
+
+/// Serves the queries over a minimal HTTP/JSON protocol, handling at most max_connections connections at
+/// the same time with a fixed pool of threads. As many connections can wait to be handled, the other ones
+/// are refused with a 503 status:
+/// - `GET /info` returns the kmer length and the color names of the graph, with their metadata
+/// - `POST /query` with a `{"queries":[{"name":"q1", "sequence":"ACGT..."}, ...]}` body
+///   returns the matches of each query in the same order
+pub fn run_server(
+    engine: &GGCATQueryEngine,
+    address: &str,
+    max_connections: usize,
+) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(address)
+        .map_err(|err| anyhow::anyhow!("Cannot listen on {}: {}", address, err))?;
+    println!("Listening on http://{}", listener.local_addr()?);
+
+    let (connections_sender, connections_receiver) = sync_channel::<TcpStream>(max_connections);
+    let connections_receiver = Mutex::new(connections_receiver);
+
+    std::thread::scope(|scope| {
+        for _ in 0..max_connections.max(1) {
+            scope.spawn(|| loop {
+                let Ok(stream) = connections_receiver.lock().unwrap().recv() else {
+                    break;
+                };
+                if let Err(err) = handle_connection(engine, stream) {
+                    ggcat_logging::warn!("Query connection error: {}", err);
+                }
+            });
+        }
+
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            if stream.set_read_timeout(Some(CONNECTION_TIMEOUT)).is_err()
+                || stream.set_write_timeout(Some(CONNECTION_TIMEOUT)).is_err()
+            {
+                continue;
+            }
+
+            if let Err(TrySendError::Full(stream)) = connections_sender.try_send(stream) {
+                let _ = write_response(
+                    stream,
+                    Status::ServiceUnavailable,
+                    &json!({ "error": "Too many connections, retry later" }),
+                );
+            }
+        }
+    });
+
+    Ok(())
+}
+
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+/// Reads a request, or the status and the error to answer with if the request is not accepted
+fn read_request(reader: &mut impl BufRead) -> std::io::Result<Result<Request, (Status, Value)>> {
+    let mut headers_reader = reader.by_ref().take(MAX_HEADERS_SIZE);
+    let mut read_line = |line: &mut String| -> std::io::Result<bool> {
+        line.clear();
+        headers_reader.read_line(line)?;
+        if line.ends_with('\n') {
+            Ok(true)
+        } else if headers_reader.limit() == 0 {
+            Ok(false)
+        } else {
+            Err(std::io::ErrorKind::UnexpectedEof.into())
+        }
+    };
+    let headers_too_large = (
+        Status::HeadersTooLarge,
+        json!({ "error": "The request headers are too large" }),
+    );
+
+    let mut request_line = String::new();
+    if !read_line(&mut request_line)? {
+        return Ok(Err(headers_too_large));
+    }
+    let mut request_parts = request_line.split_whitespace();
+    let method = request_parts.next().unwrap_or("").to_string();
+    let path = request_parts.next().unwrap_or("").to_string();
+
+    let mut content_length = None;
+    let mut header = String::new();
+    loop {
+        if !read_line(&mut header)? {
+            return Ok(Err(headers_too_large));
+        }
+        if header.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = Some(value.trim().parse::<usize>());
+            }
+        }
+    }
+
+    let content_length = match content_length {
+        None => 0,
+        Some(Ok(content_length)) if content_length <= MAX_REQUEST_SIZE => content_length,
+        Some(Ok(_)) => {
+            return Ok(Err((
+                Status::PayloadTooLarge,
+                json!({ "error": "The request is too large" }),
+            )))
+        }
+        Some(Err(_)) => {
+            return Ok(Err((
+                Status::BadRequest,
+                json!({ "error": "Invalid Content-Length header" }),
+            )))
+        }
+    };
+
+    // The body grows with the received data, up to the declared length
+    let mut body = vec![];
+    reader
+        .by_ref()
+        .take(content_length as u64)
+        .read_to_end(&mut body)?;
+    if body.len() != content_length {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(Ok(Request { method, path, body }))
+}
+
+fn handle_connection(engine: &GGCATQueryEngine, stream: TcpStream) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let (status, response) = match read_request(&mut reader)? {
+        Ok(request) => handle_request(engine, &request.method, &request.path, &request.body),
+        Err(error) => error,
+    };
+
+    write_response(stream, status, &response)
+}
+
+fn handle_request(
+    engine: &GGCATQueryEngine,
+    method: &str,
+    path: &str,
+    body: &[u8],
+) -> (Status, Value) {
+    match (method, path) {
+        ("GET", "/info") => {
+            let mut info = json!({
+                "k": engine.kmer_length(),
+                "colors": engine.color_names(),
+            });
+            let metadata = engine.colors_metadata();
+            if !metadata.is_empty() {
+                info["colors_metadata"] = (0..engine.color_names().len())
+                    .map(|color| {
+                        metadata
+                            .color_values(color as ColorIndexType)
+                            .map(|(key, value)| (key.to_string(), Value::from(value)))
+                            .collect::<Map<_, _>>()
+                    })
+                    .collect();
+            }
+            (Status::Ok, info)
+        }
+        ("POST", "/query") => match parse_queries(body) {
+            Ok(queries) => match query_response(engine, &queries) {
+                Ok(response) => (Status::Ok, response),
+                Err(err) => (
+                    Status::InternalServerError,
+                    json!({ "error": err.to_string() }),
+                ),
+            },
+            Err(err) => (Status::BadRequest, json!({ "error": err.to_string() })),
+        },
+        _ => (
+            Status::NotFound,
+            json!({ "error": "Unknown request, use GET /info or POST /query" }),
+        ),
+    }
+}
+
+/// Parses the (name, sequence) pairs of the queries, naming the unnamed ones by their index
+fn parse_queries(body: &[u8]) -> anyhow::Result<Vec<(String, String)>> {
+    let request: Value = serde_json::from_slice(body)?;
+    let queries = request
+        .get("queries")
+        .and_then(Value::as_array)
+        .ok_or_else(|| anyhow::anyhow!("Missing 'queries' array"))?;
+
+    queries
+        .iter()
+        .enumerate()
+        .map(|(index, query)| {
+            let sequence = query
+                .get("sequence")
+                .and_then(Value::as_str)
+                .ok_or_else(|| anyhow::anyhow!("Missing 'sequence' of query {}", index))?;
+            let name = match query.get("name") {
+                Some(Value::String(name)) => name.clone(),
+                _ => index.to_string(),
+            };
+            Ok((name, sequence.to_string()))
+        })
+        .collect()
+}
+
+fn query_response(
+    engine: &GGCATQueryEngine,
+    queries: &[(String, String)],
+) -> anyhow::Result<Value> {
+    let sequences: Vec<_> = queries
+        .iter()
+        .map(|(_, sequence)| DnaSequence {
+            ident_data: &[],
+            seq: sequence.as_bytes(),
+            format: DnaSequencesFileType::FASTA,
+        })
+        .collect();
+
+    let results = engine
+        .query(&sequences)?
+        .into_iter()
+        .zip(queries)
+        .map(|(matches, (name, _))| {
+            let mut result = json!({
+                "query_name": name,
+                "query_kmers": matches.query_kmers,
+                "matched_kmers": matches.matched_kmers,
+            });
+            if !engine.color_names().is_empty() {
+                result["matches"] = Value::Object(
+                    matches
+                        .color_matches
+                        .iter()
+                        .map(|(color, count)| (color.to_string(), json!(count)))
+                        .collect::<Map<_, _>>(),
+                );
+            }
+            result
+        })
+        .collect::<Vec<_>>();
+
+    Ok(json!({ "results": results }))
+}
+
+fn write_response(mut stream: impl Write, status: Status, response: &Value) -> std::io::Result<()> {
+    let body = response.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status.code(),
+        status.reason(),
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn read(request: &[u8]) -> std::io::Result<Result<Request, (Status, Value)>> {
+        read_request(&mut Cursor::new(request))
+    }
+
+    #[test]
+    fn request_parsing() {
+        let request = read(b"POST /query HTTP/1.1\r\nHost: x\r\nContent-Length: 4\r\n\r\nbody")
+            .unwrap()
+            .ok()
+            .unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/query");
+        assert_eq!(request.body, b"body");
+
+        let request = read(b"GET /info HTTP/1.1\r\n\r\n").unwrap().ok().unwrap();
+        assert_eq!(
+            (request.method.as_str(), request.path.as_str()),
+            ("GET", "/info")
+        );
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn rejected_requests() {
+        let status = |request: &[u8]| read(request).unwrap().err().unwrap().0;
+
+        let too_large = format!(
+            "POST /query HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_REQUEST_SIZE + 1
+        );
+        assert_eq!(status(too_large.as_bytes()), Status::PayloadTooLarge);
+        assert_eq!(
+            status(b"POST /query HTTP/1.1\r\nContent-Length: ten\r\n\r\n"),
+            Status::BadRequest
+        );
+
+        let long_header = format!(
+            "GET /info HTTP/1.1\r\nX-Long: {}\r\n\r\n",
+            "a".repeat(MAX_HEADERS_SIZE as usize)
+        );
+        assert_eq!(status(long_header.as_bytes()), Status::HeadersTooLarge);
+
+        // Connections closed before sending the whole request are dropped
+        assert!(read(b"POST /query HTTP/1.1\r\nContent-Length: 10\r\n\r\nbody").is_err());
+        assert!(read(b"GET /info HTTP/1.1\r\n").is_err());
+    }
+
+    #[test]
+    fn response_status_line() {
+        for (status, line) in [
+            (Status::Ok, "HTTP/1.1 200 OK\r\n"),
+            (Status::BadRequest, "HTTP/1.1 400 Bad Request\r\n"),
+            (Status::NotFound, "HTTP/1.1 404 Not Found\r\n"),
+            (
+                Status::PayloadTooLarge,
+                "HTTP/1.1 413 Payload Too Large\r\n",
+            ),
+            (
+                Status::HeadersTooLarge,
+                "HTTP/1.1 431 Request Header Fields Too Large\r\n",
+            ),
+            (
+                Status::ServiceUnavailable,
+                "HTTP/1.1 503 Service Unavailable\r\n",
+            ),
+        ] {
+            let mut response = vec![];
+            write_response(&mut response, status, &json!({})).unwrap();
+            let response = String::from_utf8(response).unwrap();
+            assert!(response.starts_with(line), "{}", response);
+            assert!(response.ends_with("\r\n\r\n{}"));
+        }
+    }
+
+    #[test]
+    fn queries_parsing() {
+        let queries =
+            parse_queries(br#"{"queries":[{"name":"q1","sequence":"ACGT"},{"sequence":"TTTT"}]}"#)
+                .unwrap();
+        assert_eq!(
+            queries,
+            vec![
+                ("q1".to_string(), "ACGT".to_string()),
+                ("1".to_string(), "TTTT".to_string())
+            ]
+        );
+
+        assert!(parse_queries(br#"{"queries":[{"name":"q1"}]}"#).is_err());
+        assert!(parse_queries(br#"{"sequences":[]}"#).is_err());
+        assert!(parse_queries(b"not json").is_err());
+    }
+}
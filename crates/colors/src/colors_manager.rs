@@ -1,4 +1,6 @@
+use crate::metadata::{ColorsMetadata, EMPTY_COLORS_METADATA};
 use crate::parsers::SingleSequenceInfo;
+use crate::storage::{ColormapBuildParams, ColorsCodec};
 use config::{BucketIndexType, ColorCounterType, ColorIndexType};
 use dynamic_dispatch::dynamic_dispatch;
 use hashbrown::HashMap;
@@ -14,6 +16,7 @@ use rustc_hash::FxHashMap;
 use std::cmp::min;
 use std::fmt::Debug;
 use std::hash::Hash;
+use std::io::Write;
 use std::ops::Range;
 use std::path::Path;
 use structs::map_entry::MapEntry;
@@ -67,10 +70,65 @@ pub trait MinimizerBucketingSeqColorData:
     }
 }
 
+/// Writes a string escaped to be the content of a JSON string, without the quotes
+pub fn write_json_escaped(buffer: &mut Vec<u8>, value: &str) {
+    for c in value.chars() {
+        match c {
+            '"' => buffer.extend_from_slice(b"\\\""),
+            '\\' => buffer.extend_from_slice(b"\\\\"),
+            '\n' => buffer.extend_from_slice(b"\\n"),
+            '\r' => buffer.extend_from_slice(b"\\r"),
+            '\t' => buffer.extend_from_slice(b"\\t"),
+            c if (c as u32) < 0x20 => write!(buffer, "\\u{:04x}", c as u32).unwrap(),
+            c => {
+                let mut utf8 = [0; 4];
+                buffer.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+            }
+        }
+    }
+}
+
+/// Returns a string escaped to be the content of a JSON string, without the quotes
+pub fn json_escape(value: &str) -> String {
+    let mut buffer = Vec::with_capacity(value.len());
+    write_json_escaped(&mut buffer, value);
+    String::from_utf8(buffer).unwrap()
+}
+
+fn write_tsv_escaped_chars(buffer: &mut Vec<u8>, value: &str, escape_commas: bool) {
+    for c in value.chars() {
+        match c {
+            '\\' => buffer.extend_from_slice(b"\\\\"),
+            '\n' => buffer.extend_from_slice(b"\\n"),
+            '\r' => buffer.extend_from_slice(b"\\r"),
+            '\t' => buffer.extend_from_slice(b"\\t"),
+            ',' if escape_commas => buffer.extend_from_slice(b"\\,"),
+            c => {
+                let mut utf8 = [0; 4];
+                buffer.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+            }
+        }
+    }
+}
+
+/// Writes a string escaped to be a TSV field, with backslash escapes for the tabs, the newlines and the backslashes
+pub fn write_tsv_escaped(buffer: &mut Vec<u8>, value: &str) {
+    write_tsv_escaped_chars(buffer, value, false);
+}
+
+/// Returns a string escaped to be an item of a comma separated list in a TSV field,
+/// escaping also the commas
+pub fn tsv_list_item_escape(value: &str) -> String {
+    let mut buffer = Vec::with_capacity(value.len());
+    write_tsv_escaped_chars(&mut buffer, value, true);
+    String::from_utf8(buffer).unwrap()
+}
+
 pub trait ColorMapReader {
     fn get_color_name(&self, index: ColorIndexType, json_escaped: bool) -> &str;
     fn colors_count(&self) -> usize;
     fn colors_subsets_count(&self) -> u64;
+    fn colors_metadata(&self) -> &ColorsMetadata;
 }
 
 impl ColorMapReader for () {
@@ -85,6 +143,10 @@ impl ColorMapReader for () {
     fn colors_subsets_count(&self) -> u64 {
         0
     }
+
+    fn colors_metadata(&self) -> &ColorsMetadata {
+        &EMPTY_COLORS_METADATA
+    }
 }
 
 /// Helper trait to manage colors parsing from different sources (actually 2, color from file or color from annotated dbg graph)
@@ -123,10 +185,14 @@ pub trait ColorsMergeManager: Sized {
     type GlobalColorsTableWriter: Sync + Send + 'static;
     type GlobalColorsTableReader: ColorMapReader + Sync + Send + 'static;
 
-    /// Creates a new colors table at the given path
+    /// Creates a new colors table at the given path, with the names and metadata of the colors,
+    /// encoding the subsets with the given codec and saving the parameters of the graph build
     fn create_colors_table(
         path: impl AsRef<Path>,
         color_names: &[String],
+        colors_metadata: &ColorsMetadata,
+        codec: ColorsCodec,
+        build_params: &ColormapBuildParams,
     ) -> anyhow::Result<Self::GlobalColorsTableWriter>;
 
     /// Creates a new colors table at the given path
@@ -216,6 +282,13 @@ pub trait ColorsMergeManager: Sized {
 
     fn pop_base(target: &mut Self::TempUnitigColorStructure);
 
+    /// Returns the runs of consecutive kmers sharing the same color subset, as (color, kmers count) pairs
+    fn get_color_runs(
+        src: &Self::PartialUnitigsColorStructure,
+        src_buffer: &<Self::PartialUnitigsColorStructure as SequenceExtraDataTempBufferManagement>::TempBuffer,
+        runs: &mut Vec<(Self::SingleKmerColorDataType, usize)>,
+    ) -> anyhow::Result<()>;
+
     /// Encodes partial unitig colors into the extra data structure
     fn encode_part_unitigs_colors(
         ts: &mut Self::TempUnitigColorStructure,
@@ -275,3 +348,28 @@ pub trait ColorsManager: 'static + Clone + Debug + Sync + Send + Sized {
         SingleKmerColorDataType = Self::SingleKmerColorDataType,
     >;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_escaping() {
+        assert_eq!(json_escape("sample_1.fa"), "sample_1.fa");
+        assert_eq!(
+            json_escape("a \"b\" c\\d\te\nf\rg\u{1}h"),
+            "a \\\"b\\\" c\\\\d\\te\\nf\\rg\\u0001h"
+        );
+        assert_eq!(json_escape("àé 🧬"), "àé 🧬");
+    }
+
+    #[test]
+    fn tsv_escaping() {
+        let mut buffer = vec![];
+        write_tsv_escaped(&mut buffer, "a\tb\nc\\d,e\rf");
+        assert_eq!(String::from_utf8(buffer).unwrap(), r"a\tb\nc\\d,e\rf");
+        assert_eq!(tsv_list_item_escape("sample_1.fa"), "sample_1.fa");
+        assert_eq!(tsv_list_item_escape("a,b\tc\\"), r"a\,b\tc\\");
+        assert_eq!(tsv_list_item_escape("àé 🧬"), "àé 🧬");
+    }
+}
@@ -1,6 +1,8 @@
-// use crate::storage::roaring::ColorsStorage;
+use crate::metadata::ColorsMetadata;
+use crate::storage::roaring::RoaringColorsSerializer;
+use crate::storage::run_length::RunLengthColorsSerializer;
 use crate::storage::serializer::ColorsSerializer;
-use crate::storage::ColorsSerializerTrait;
+use crate::storage::{ColormapBuildParams, ColorsCodec};
 use config::ColorIndexType;
 use dashmap::DashMap;
 use hashes::dummy_hasher::DummyHasherBuilder;
@@ -9,22 +11,49 @@ use siphasher::sip128::{Hasher128, SipHasher13};
 use std::hash::Hash;
 use std::path::Path;
 
-pub struct ColorsMemMapWriter<C: ColorsSerializerTrait> {
+// A single storage exists for each colormap, boxing it is not needed
+#[allow(clippy::large_enum_variant)]
+enum ColorsStorage {
+    RunLength(ColorsSerializer<RunLengthColorsSerializer>),
+    Roaring(ColorsSerializer<RoaringColorsSerializer>),
+}
+
+pub struct ColorsMemMapWriter {
     colors: DashMap<u128, ColorIndexType, DummyHasherBuilder>,
-    colors_storage: ColorsSerializer<C>,
+    colors_storage: ColorsStorage,
     hash_keys: (u64, u64),
 }
 
-impl<C: ColorsSerializerTrait> ColorsMemMapWriter<C> {
-    pub fn new(file: impl AsRef<Path>, color_names: &[String]) -> anyhow::Result<Self> {
+impl ColorsMemMapWriter {
+    pub fn new(
+        file: impl AsRef<Path>,
+        color_names: &[String],
+        colors_metadata: &ColorsMetadata,
+        codec: ColorsCodec,
+        build_params: &ColormapBuildParams,
+    ) -> anyhow::Result<Self> {
         let mut rng = thread_rng();
+        let colors_storage = match codec {
+            ColorsCodec::RunLength => ColorsStorage::RunLength(ColorsSerializer::new(
+                file,
+                color_names,
+                colors_metadata,
+                build_params,
+            )?),
+            ColorsCodec::Roaring => ColorsStorage::Roaring(ColorsSerializer::new(
+                file,
+                color_names,
+                colors_metadata,
+                build_params,
+            )?),
+        };
         Ok(Self {
             colors: DashMap::with_hasher_and_shard_amount(
                 DummyHasherBuilder,
                 // Increase the number of shards to decrease stall while inserting new colors
                 rayon::current_num_threads() * 8,
             ),
-            colors_storage: ColorsSerializer::new(file, color_names)?,
+            colors_storage,
             hash_keys: (rng.next_u64(), rng.next_u64()),
         })
     }
@@ -42,7 +71,10 @@ impl<C: ColorsSerializerTrait> ColorsMemMapWriter<C> {
         match self.colors.entry(hash) {
             dashmap::Entry::Occupied(occupied_entry) => *occupied_entry.get(),
             dashmap::Entry::Vacant(vacant_entry) => {
-                let color = self.colors_storage.serialize_colors(colors);
+                let color = match &self.colors_storage {
+                    ColorsStorage::RunLength(storage) => storage.serialize_colors(colors),
+                    ColorsStorage::Roaring(storage) => storage.serialize_colors(colors),
+                };
                 vacant_entry.insert(color);
                 color
             }
@@ -50,6 +82,9 @@ impl<C: ColorsSerializerTrait> ColorsMemMapWriter<C> {
     }
 
     pub fn print_stats(&self) {
-        self.colors_storage.print_stats();
+        match &self.colors_storage {
+            ColorsStorage::RunLength(storage) => storage.print_stats(),
+            ColorsStorage::Roaring(storage) => storage.print_stats(),
+        }
     }
 }
@@ -1,13 +1,10 @@
-use crate::storage::run_length::RunLengthColorsSerializer;
-
 pub mod bundles;
 pub mod colors_manager;
 pub mod colors_memmap_writer;
 pub mod managers;
+pub mod metadata;
 pub mod non_colored;
 pub mod parsers;
 pub mod storage;
 
 pub(crate) mod async_slice_queue;
-
-pub type DefaultColorsSerializer = RunLengthColorsSerializer;
@@ -1,6 +1,7 @@
 use crate::colors_manager::ColorsMergeManager;
 use crate::colors_memmap_writer::ColorsMemMapWriter;
-use crate::DefaultColorsSerializer;
+use crate::metadata::ColorsMetadata;
+use crate::storage::{ColormapBuildParams, ColorsCodec};
 use atoi::{FromRadix10, FromRadix16};
 use bstr::ByteSlice;
 use byteorder::ReadBytesExt;
@@ -139,14 +140,17 @@ fn get_entry_color(entry: &MapEntry<HashMapTempColorIndex>) -> ColorIndexType {
 
 impl ColorsMergeManager for MultipleColorsManager {
     type SingleKmerColorDataType = ColorIndexType;
-    type GlobalColorsTableWriter = ColorsMemMapWriter<DefaultColorsSerializer>;
+    type GlobalColorsTableWriter = ColorsMemMapWriter;
     type GlobalColorsTableReader = ();
 
     fn create_colors_table(
         path: impl AsRef<Path>,
         color_names: &[String],
+        colors_metadata: &ColorsMetadata,
+        codec: ColorsCodec,
+        build_params: &ColormapBuildParams,
     ) -> anyhow::Result<Self::GlobalColorsTableWriter> {
-        ColorsMemMapWriter::new(path, color_names)
+        ColorsMemMapWriter::new(path, color_names, colors_metadata, codec, build_params)
     }
 
     fn open_colors_table(_path: impl AsRef<Path>) -> anyhow::Result<Self::GlobalColorsTableReader> {
@@ -505,6 +509,21 @@ impl ColorsMergeManager for MultipleColorsManager {
         }
     }
 
+    fn get_color_runs(
+        src: &Self::PartialUnitigsColorStructure,
+        src_buffer: &<Self::PartialUnitigsColorStructure as SequenceExtraDataTempBufferManagement>::TempBuffer,
+        runs: &mut Vec<(Self::SingleKmerColorDataType, usize)>,
+    ) -> anyhow::Result<()> {
+        runs.clear();
+        for color in &src_buffer.colors[src.slice.clone()] {
+            match runs.last_mut() {
+                Some(last) if last.0 == color.color => last.1 += color.counter,
+                _ => runs.push((color.color, color.counter)),
+            }
+        }
+        Ok(())
+    }
+
     fn encode_part_unitigs_colors(
         ts: &mut Self::TempUnitigColorStructure,
         colors_buffer: &mut <Self::PartialUnitigsColorStructure as SequenceExtraDataTempBufferManagement>::TempBuffer,
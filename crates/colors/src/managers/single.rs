@@ -1,6 +1,7 @@
 use crate::colors_manager::ColorsMergeManager;
+use crate::metadata::ColorsMetadata;
 use crate::storage::deserializer::ColorsDeserializer;
-use crate::DefaultColorsSerializer;
+use crate::storage::{ColormapBuildParams, ColorsCodec};
 use byteorder::ReadBytesExt;
 use config::{ColorCounterType, ColorIndexType};
 use hashbrown::HashMap;
@@ -23,11 +24,14 @@ pub struct SingleColorManager;
 impl ColorsMergeManager for SingleColorManager {
     type SingleKmerColorDataType = ColorIndexType;
     type GlobalColorsTableWriter = ();
-    type GlobalColorsTableReader = ColorsDeserializer<DefaultColorsSerializer>;
+    type GlobalColorsTableReader = ColorsDeserializer;
 
     fn create_colors_table(
         _path: impl AsRef<Path>,
         _color_names: &[String],
+        _colors_metadata: &ColorsMetadata,
+        _codec: ColorsCodec,
+        _build_params: &ColormapBuildParams,
     ) -> anyhow::Result<Self::GlobalColorsTableWriter> {
         Ok(())
     }
@@ -127,6 +131,14 @@ impl ColorsMergeManager for SingleColorManager {
         panic!("Unsupported!");
     }
 
+    fn get_color_runs(
+        _src: &Self::PartialUnitigsColorStructure,
+        _src_buffer: &<Self::PartialUnitigsColorStructure as SequenceExtraDataTempBufferManagement>::TempBuffer,
+        _runs: &mut Vec<(Self::SingleKmerColorDataType, usize)>,
+    ) -> anyhow::Result<()> {
+        anyhow::bail!("Color runs are not supported when querying a graph");
+    }
+
     fn encode_part_unitigs_colors(
         _ts: &mut Self::TempUnitigColorStructure,
         _colors_buffer: &mut <Self::PartialUnitigsColorStructure as SequenceExtraDataTempBufferManagement>::TempBuffer,
@@ -0,0 +1,378 @@
+use crate::colors_manager::ColorMapReader;
+use config::ColorIndexType;
+use ggcat_logging::UnrecoverableErrorLogging;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Metadata of the colors, as a table with a value for each color and key
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ColorsMetadata {
+    keys: Vec<String>,
+    /// The values of each color, in the keys order. Missing values are empty
+    values: Vec<Vec<String>>,
+}
+
+pub(crate) static EMPTY_COLORS_METADATA: ColorsMetadata = ColorsMetadata {
+    keys: Vec::new(),
+    values: Vec::new(),
+};
+
+impl ColorsMetadata {
+    /// Creates the metadata table from the values of each color, in the keys order
+    pub fn new(keys: Vec<String>, values: Vec<Vec<String>>) -> anyhow::Result<Self> {
+        if let Some(color) = values.iter().position(|v| v.len() != keys.len()) {
+            anyhow::bail!(
+                "Color {} has {} metadata values instead of {}",
+                color,
+                values[color].len(),
+                keys.len()
+            );
+        }
+        Ok(Self { keys, values })
+    }
+
+    /// Reads a TSV sample sheet, with a header line naming the keys after the first column
+    /// and a line for each color, starting with the color name.
+    /// The colors missing from the sheet have empty values
+    pub fn from_tsv(path: impl AsRef<Path>, color_names: &[String]) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let sheet = std::fs::read_to_string(path)
+            .log_unrecoverable_error_with_data("Cannot read colors metadata", path.display())?;
+        let mut lines = sheet.lines().filter(|l| !l.trim().is_empty());
+
+        let Some(header) = lines.next() else {
+            anyhow::bail!("Colors metadata {} is empty", path.display());
+        };
+        let keys: Vec<_> = header.split('\t').skip(1).map(|k| k.to_string()).collect();
+
+        let colors: HashMap<_, _> = color_names
+            .iter()
+            .enumerate()
+            .map(|(index, name)| (name.as_str(), index))
+            .collect();
+        let mut values = vec![vec![String::new(); keys.len()]; color_names.len()];
+
+        for line in lines {
+            let mut fields = line.split('\t');
+            let name = fields.next().unwrap();
+            let Some(&color) = colors.get(name) else {
+                anyhow::bail!(
+                    "Unknown color {} in colors metadata {}",
+                    name,
+                    path.display()
+                );
+            };
+            let fields: Vec<_> = fields.map(|f| f.to_string()).collect();
+            if fields.len() != keys.len() {
+                anyhow::bail!(
+                    "Color {} has {} metadata values instead of {} in {}",
+                    name,
+                    fields.len(),
+                    keys.len(),
+                    path.display()
+                );
+            }
+            values[color] = fields;
+        }
+
+        Ok(Self { keys, values })
+    }
+
+    /// Joins the metadata of several colormaps, numbering the colors in order.
+    /// The keys are the union of the keys of all the colormaps
+    pub fn concat<'a>(parts: impl Iterator<Item = (&'a ColorsMetadata, usize)> + Clone) -> Self {
+        let mut keys: Vec<String> = vec![];
+        for (metadata, _) in parts.clone() {
+            for key in &metadata.keys {
+                if !keys.contains(key) {
+                    keys.push(key.clone());
+                }
+            }
+        }
+
+        let mut values = vec![];
+        for (metadata, colors_count) in parts {
+            for color in 0..colors_count {
+                values.push(
+                    keys.iter()
+                        .map(|key| {
+                            metadata
+                                .get(color as ColorIndexType, key)
+                                .unwrap_or_default()
+                                .to_string()
+                        })
+                        .collect(),
+                );
+            }
+        }
+        Self { keys, values }
+    }
+
+    /// Keeps only the rows of the given colors, renumbering them in the given order
+    pub fn select_colors(&self, colors: &[ColorIndexType]) -> Self {
+        if self.is_empty() {
+            return Self::default();
+        }
+        Self {
+            keys: self.keys.clone(),
+            values: colors
+                .iter()
+                .map(|color| {
+                    self.values
+                        .get(*color as usize)
+                        .cloned()
+                        .unwrap_or_else(|| vec![String::new(); self.keys.len()])
+                })
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    /// Returns the value of a key for a color, None if the key or the value are missing
+    pub fn get(&self, color: ColorIndexType, key: &str) -> Option<&str> {
+        let key_index = self.keys.iter().position(|k| k == key)?;
+        self.values
+            .get(color as usize)
+            .map(|v| v[key_index].as_str())
+            .filter(|v| !v.is_empty())
+    }
+
+    /// Returns the (key, value) pairs of a color, skipping the missing values
+    pub fn color_values(&self, color: ColorIndexType) -> impl Iterator<Item = (&str, &str)> {
+        self.keys
+            .iter()
+            .zip(self.values.get(color as usize).into_iter().flatten())
+            .filter(|(_, v)| !v.is_empty())
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Number of colors with a row in the table
+    pub fn colors_count(&self) -> usize {
+        self.values.len()
+    }
+}
+
+/// Selection of the colors by their metadata, from a list of `key=value` and `key!=value` conditions.
+/// A color is selected if, for each key, it matches any of the `key=value` conditions
+/// and none of the `key!=value` ones
+#[derive(Clone, Debug, Default)]
+pub struct ColorsSelection {
+    /// The key, the value and whether the value must be equal
+    conditions: Vec<(String, String, bool)>,
+}
+
+impl ColorsSelection {
+    pub fn new(conditions: &[impl AsRef<str>]) -> anyhow::Result<Self> {
+        let conditions = conditions
+            .iter()
+            .map(|condition| {
+                let condition = condition.as_ref();
+                if let Some((key, value)) = condition.split_once("!=") {
+                    Ok((key.to_string(), value.to_string(), false))
+                } else if let Some((key, value)) = condition.split_once('=') {
+                    Ok((key.to_string(), value.to_string(), true))
+                } else {
+                    Err(anyhow::anyhow!(
+                        "Invalid colors selection {}, expected key=value or key!=value",
+                        condition
+                    ))
+                }
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { conditions })
+    }
+
+    /// Returns for each color of the colormap whether it is selected
+    pub fn select(&self, colormap: &impl ColorMapReader) -> anyhow::Result<Vec<bool>> {
+        let metadata = colormap.colors_metadata();
+        if let Some((key, _, _)) = self
+            .conditions
+            .iter()
+            .find(|(key, _, _)| !metadata.keys.contains(key))
+        {
+            anyhow::bail!(
+                "Unknown colors metadata key {}, the available keys are: [{}]",
+                key,
+                metadata.keys.join(", ")
+            );
+        }
+
+        Ok((0..colormap.colors_count())
+            .map(|color| {
+                let color = color as ColorIndexType;
+                self.conditions
+                    .iter()
+                    .filter(|(_, _, equal)| !*equal)
+                    .all(|(key, value, _)| metadata.get(color, key) != Some(value.as_str()))
+                    && metadata.keys.iter().all(|key| {
+                        let mut equal_conditions = self
+                            .conditions
+                            .iter()
+                            .filter(|(k, _, equal)| *equal && k == key)
+                            .peekable();
+                        equal_conditions.peek().is_none()
+                            || equal_conditions
+                                .any(|(_, value, _)| metadata.get(color, key) == Some(value))
+                    })
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    struct TestColorMap {
+        metadata: ColorsMetadata,
+    }
+
+    impl ColorMapReader for TestColorMap {
+        fn get_color_name(&self, _index: ColorIndexType, _json_escaped: bool) -> &str {
+            ""
+        }
+
+        fn colors_count(&self) -> usize {
+            self.metadata.colors_count()
+        }
+
+        fn colors_subsets_count(&self) -> u64 {
+            0
+        }
+
+        fn colors_metadata(&self) -> &ColorsMetadata {
+            &self.metadata
+        }
+    }
+
+    fn test_colormap() -> TestColorMap {
+        let values = [
+            ["human", "blood", "2020"],
+            ["human", "liver", "2021"],
+            ["mouse", "blood", "2020"],
+            ["mouse", "liver", ""],
+            ["rat", "blood", "2021"],
+        ];
+        TestColorMap {
+            metadata: ColorsMetadata::new(
+                vec!["species".into(), "tissue".into(), "year".into()],
+                values
+                    .iter()
+                    .map(|row| row.iter().map(|v| v.to_string()).collect())
+                    .collect(),
+            )
+            .unwrap(),
+        }
+    }
+
+    fn select(conditions: &[&str]) -> Vec<bool> {
+        ColorsSelection::new(conditions)
+            .unwrap()
+            .select(&test_colormap())
+            .unwrap()
+    }
+
+    fn temp_sheet(name: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "ggcat-metadata-{}-{}.tsv",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn colors_selection() {
+        assert_eq!(select(&[]), [true; 5]);
+        assert_eq!(
+            select(&["species=human"]),
+            [true, true, false, false, false]
+        );
+        // Any of the values of the same key
+        assert_eq!(
+            select(&["species=human", "species=rat"]),
+            [true, true, false, false, true]
+        );
+        // All the keys
+        assert_eq!(
+            select(&["species=human", "species=mouse", "tissue=blood"]),
+            [true, false, true, false, false]
+        );
+        assert_eq!(
+            select(&["species!=mouse"]),
+            [true, true, false, false, true]
+        );
+        assert_eq!(
+            select(&["tissue=blood", "year!=2020"]),
+            [false, false, false, false, true]
+        );
+        // Missing values do not match, and are not excluded
+        assert_eq!(select(&["year=2021"]), [false, true, false, false, true]);
+        assert_eq!(select(&["year!=2021"]), [true, false, true, true, false]);
+    }
+
+    #[test]
+    fn invalid_colors_selection() {
+        assert!(ColorsSelection::new(&["species"]).is_err());
+        let err = ColorsSelection::new(&["country=it"])
+            .unwrap()
+            .select(&test_colormap())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("species, tissue, year"), "{}", err);
+    }
+
+    #[test]
+    fn metadata_from_tsv() {
+        let color_names: Vec<String> = vec!["a.fa".into(), "b.fa".into(), "c.fa".into()];
+        let path = temp_sheet(
+            "sheet",
+            "sample\tspecies\tyear\n\nc.fa\tmouse\t2020\na.fa\thuman\t\n",
+        );
+        let metadata = ColorsMetadata::from_tsv(&path, &color_names).unwrap();
+        assert_eq!(metadata.keys(), ["species", "year"]);
+        assert_eq!(metadata.colors_count(), 3);
+        assert_eq!(metadata.get(0, "species"), Some("human"));
+        assert_eq!(metadata.get(0, "year"), None);
+        assert_eq!(metadata.get(1, "species"), None);
+        assert_eq!(
+            metadata.color_values(2).collect::<Vec<_>>(),
+            [("species", "mouse"), ("year", "2020")]
+        );
+        assert_eq!(metadata.get(2, "country"), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn invalid_metadata_tsv() {
+        let color_names: Vec<String> = vec!["a.fa".into(), "b.fa".into()];
+        for (name, content, error) in [
+            ("empty", "\n", "is empty"),
+            (
+                "unknown",
+                "sample\tspecies\nd.fa\thuman\n",
+                "Unknown color d.fa",
+            ),
+            (
+                "values",
+                "sample\tspecies\tyear\na.fa\thuman\n",
+                "has 1 metadata values instead of 2",
+            ),
+        ] {
+            let path = temp_sheet(name, content);
+            let err = ColorsMetadata::from_tsv(&path, &color_names).err().unwrap();
+            assert!(err.to_string().contains(error), "{}", err);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
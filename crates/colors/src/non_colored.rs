@@ -1,7 +1,9 @@
 use crate::colors_manager::{
     ColorsManager, ColorsMergeManager, ColorsParser, MinimizerBucketingSeqColorData,
 };
+use crate::metadata::ColorsMetadata;
 use crate::parsers::SingleSequenceInfo;
+use crate::storage::{ColormapBuildParams, ColorsCodec};
 use config::{BucketIndexType, ColorCounterType};
 use dynamic_dispatch::dynamic_dispatch;
 use hashbrown::HashMap;
@@ -126,6 +128,9 @@ impl ColorsMergeManager for NonColoredManager {
     fn create_colors_table(
         _path: impl AsRef<Path>,
         _color_names: &[String],
+        _colors_metadata: &ColorsMetadata,
+        _codec: ColorsCodec,
+        _build_params: &ColormapBuildParams,
     ) -> anyhow::Result<Self::GlobalColorsTableWriter> {
         Ok(())
     }
@@ -224,6 +229,16 @@ impl ColorsMergeManager for NonColoredManager {
     #[inline(always)]
     fn pop_base(_target: &mut Self::TempUnitigColorStructure) {}
 
+    #[inline(always)]
+    fn get_color_runs(
+        _src: &Self::PartialUnitigsColorStructure,
+        _src_buffer: &<Self::PartialUnitigsColorStructure as SequenceExtraDataTempBufferManagement>::TempBuffer,
+        runs: &mut Vec<(Self::SingleKmerColorDataType, usize)>,
+    ) -> anyhow::Result<()> {
+        runs.clear();
+        Ok(())
+    }
+
     #[inline(always)]
     fn encode_part_unitigs_colors(
         _ts: &mut Self::TempUnitigColorStructure,
@@ -1,21 +1,130 @@
-use crate::storage::serializer::ColorsFlushProcessing;
+use crate::storage::roaring::RoaringColorsSerializer;
+use crate::storage::run_length::RunLengthColorsSerializer;
+use crate::storage::serializer::{ColorsFileHeader, ColorsFlushProcessing};
 use config::ColorIndexType;
-use std::io::Read;
+use ggcat_logging::UnrecoverableErrorLogging;
+use hashes::HashFunctionFactory;
+use std::fs::File;
+use std::path::Path;
 
 pub mod deserializer;
+pub mod inspect;
+pub mod mapped;
 pub mod roaring;
 pub mod run_length;
 pub mod serializer;
+pub mod upgrade;
 
 pub trait ColorsSerializerTrait: 'static {
     const MAGIC: [u8; 16];
 
-    fn decode_color(reader: impl Read, out_vec: Option<&mut Vec<ColorIndexType>>);
-    // fn decode_colors(reader: impl Read) -> ;
-
     fn new(writer: ColorsFlushProcessing, checkpoint_distance: usize, colors_count: u64) -> Self;
     fn serialize_colors(&self, colors: &[ColorIndexType]) -> ColorIndexType;
     fn get_subsets_count(&self) -> u64;
     fn print_stats(&self);
     fn finalize(self) -> ColorsFlushProcessing;
 }
+
+/// Encoding of the colors subsets in the colormap file
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ColorsCodec {
+    /// Each subset is run-length encoded as the differences between its colors
+    #[default]
+    RunLength,
+    /// Each chunk of subsets is stored as a single roaring bitmap
+    Roaring,
+}
+
+impl ColorsCodec {
+    pub fn magic(&self) -> [u8; 16] {
+        match self {
+            ColorsCodec::RunLength => RunLengthColorsSerializer::MAGIC,
+            ColorsCodec::Roaring => RoaringColorsSerializer::MAGIC,
+        }
+    }
+
+    /// Returns the codec of a colormap file from the magic of its header
+    pub fn from_magic(magic: &[u8; 16]) -> Option<Self> {
+        [ColorsCodec::RunLength, ColorsCodec::Roaring]
+            .into_iter()
+            .find(|codec| &codec.magic() == magic)
+    }
+}
+
+/// Parameters of the graph build saved in the colormap, to detect when it is used with another graph
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ColormapBuildParams {
+    /// The k-mers length, zero if unknown
+    pub k: u64,
+    /// The name of the hash function used to merge the k-mers, empty if unknown
+    pub hash_type: String,
+}
+
+impl ColormapBuildParams {
+    pub fn new<MergingHash: HashFunctionFactory>(k: usize) -> Self {
+        Self {
+            k: k as u64,
+            hash_type: MergingHash::NAME.to_string(),
+        }
+    }
+
+    /// Reads the parameters saved in a colormap file
+    pub fn read(colormap_file: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let mut file = File::open(colormap_file.as_ref()).log_unrecoverable_error_with_data(
+            "Cannot open colors file",
+            colormap_file.as_ref().display(),
+        )?;
+        Ok(ColorsFileHeader::read(&mut file, colormap_file.as_ref())?.build_params())
+    }
+
+    /// Checks that a graph with the given parameters can be used with the colormap.
+    /// The parameters that are unknown, in colormaps upgraded from older versions, are not checked
+    pub fn check_graph(
+        &self,
+        colormap_file: impl AsRef<Path>,
+        k: usize,
+        hash_type: Option<&str>,
+    ) -> anyhow::Result<()> {
+        if self.k != 0 && self.k != k as u64 {
+            anyhow::bail!(
+                "Colormap {} was built with k={}, but the graph is read with k={}",
+                colormap_file.as_ref().display(),
+                self.k,
+                k
+            );
+        }
+        if let Some(hash_type) = hash_type {
+            if !self.hash_type.is_empty() && self.hash_type != hash_type {
+                anyhow::bail!(
+                    "Colormap {} was built with the {} hash, but the graph is read with the {} hash",
+                    colormap_file.as_ref().display(),
+                    self.hash_type,
+                    hash_type
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes a colormap with the given colors subsets, numbered in order
+#[cfg(test)]
+pub(crate) fn write_test_colormap<SI: ColorsSerializerTrait>(
+    path: &Path,
+    color_names: &[String],
+    subsets: &[Vec<ColorIndexType>],
+) {
+    let serializer = serializer::ColorsSerializer::<SI>::new(
+        path,
+        color_names,
+        &crate::metadata::ColorsMetadata::default(),
+        &ColormapBuildParams {
+            k: 31,
+            hash_type: "test".to_string(),
+        },
+    )
+    .unwrap();
+    for (index, subset) in subsets.iter().enumerate() {
+        assert_eq!(serializer.serialize_colors(subset), index as ColorIndexType);
+    }
+}
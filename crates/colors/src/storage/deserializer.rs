@@ -1,124 +1,136 @@
-use crate::colors_manager::ColorMapReader;
+use crate::colors_manager::{json_escape, ColorMapReader};
+use crate::metadata::ColorsMetadata;
+use crate::storage::roaring::RoaringColorsSerializer;
+use crate::storage::run_length::RunLengthColorsSerializer;
 use crate::storage::serializer::{ColorsFileHeader, ColorsIndexEntry, ColorsIndexMap};
-use crate::storage::ColorsSerializerTrait;
+use crate::storage::{ColormapBuildParams, ColorsCodec};
+use anyhow::Context;
 use config::ColorIndexType;
-use desse::Desse;
-use desse::DesseSized;
 use ggcat_logging::UnrecoverableErrorLogging;
-use replace_with::replace_with_or_abort;
+use roaring::RoaringBitmap;
 use std::fs::File;
-use std::io::{BufReader, Read, Seek, SeekFrom};
-use std::marker::PhantomData;
-use std::path::Path;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 
-pub struct ColorsDeserializer<DS: ColorsSerializerTrait> {
-    colormap_file: lz4::Decoder<BufReader<File>>,
+pub struct ColorsDeserializer {
+    path: PathBuf,
+    codec: ColorsCodec,
+    file: File,
+    colormap_file: lz4::Decoder<Cursor<Vec<u8>>>,
+    colors_count: u64,
+    build_params: ColormapBuildParams,
     color_names: Vec<String>,
+    colors_metadata: ColorsMetadata,
     json_escaped_color_names: Vec<String>,
     colors_index: ColorsIndexMap,
+    chunks_end_offsets: Vec<u64>,
     current_chunk: ColorsIndexEntry,
     current_chunk_size: ColorIndexType,
     current_index: ColorIndexType,
-    _phantom: PhantomData<DS>,
+    /// The decoded current chunk, for the roaring codec
+    current_bitmap: RoaringBitmap,
 }
 
-unsafe impl<DS: ColorsSerializerTrait> Sync for ColorsDeserializer<DS> {}
-unsafe impl<DS: ColorsSerializerTrait> Send for ColorsDeserializer<DS> {}
+unsafe impl Sync for ColorsDeserializer {}
+unsafe impl Send for ColorsDeserializer {}
 
-impl<DS: ColorsSerializerTrait> ColorsDeserializer<DS> {
+impl ColorsDeserializer {
     pub fn new(path: impl AsRef<Path>, read_color_names: bool) -> anyhow::Result<Self> {
         let mut file = File::open(path.as_ref()).log_unrecoverable_error_with_data(
             "Cannot open colors file",
             path.as_ref().display(),
         )?;
 
-        let mut header_buffer = [0; ColorsFileHeader::SIZE];
-        file.read_exact(&mut header_buffer)
-            .log_unrecoverable_error_with_data("Cannot read header", path.as_ref().display())?;
-
-        let header: ColorsFileHeader = ColorsFileHeader::deserialize_from(&header_buffer);
-        if header.magic != DS::MAGIC {
-            anyhow::Result::Err(anyhow::anyhow!(
-                "Header mismatch: expected {:?}, got {:?}",
-                DS::MAGIC,
-                header.magic
-            ))
-            .log_unrecoverable_error_with_data(
-                "Colors file is corrupted",
-                path.as_ref().display(),
-            )?;
+        // A corrupted colormap is reported to the caller, without aborting
+        let header = ColorsFileHeader::read(&mut file, path.as_ref())?;
+        let codec = ColorsCodec::from_magic(&header.magic).unwrap();
+
+        let colors_index = ColorsIndexMap::read(&mut file, &header, path.as_ref())?;
+        if colors_index.pairs.is_empty() {
+            anyhow::bail!("Colormap {} has no chunks", path.as_ref().display());
         }
+        let chunks_end_offsets = colors_index.chunks_end_offsets(header.index_offset);
 
-        let color_names = if read_color_names {
-            let mut compressed_stream = lz4::Decoder::new(BufReader::new(file))
-                .log_unrecoverable_error_with_data(
-                    "Cannot create LZ4 decoder",
-                    path.as_ref().display(),
-                )?;
-
-            let color_names: Vec<String> = bincode::deserialize_from(&mut compressed_stream)
-                .log_unrecoverable_error_with_data(
-                    "Cannot deserialize color names",
-                    path.as_ref().display(),
-                )?;
-            file = compressed_stream.finish().0.into_inner();
-            color_names
+        let (color_names, colors_metadata) = if read_color_names {
+            header.read_colors(&mut file, &colors_index, path.as_ref())?
         } else {
-            Vec::new()
+            Default::default()
         };
 
-        let colors_index: ColorsIndexMap = {
-            file.seek(SeekFrom::Start(header.index_offset))
-                .log_unrecoverable_error_with_data(
-                    "Cannot seek color map",
-                    path.as_ref().display(),
-                )?;
-            bincode::deserialize_from(&mut file).log_unrecoverable_error_with_data(
-                "Cannot deserialize color index",
-                path.as_ref().display(),
-            )?
-        };
+        let json_escaped_color_names = color_names.iter().map(|name| json_escape(name)).collect();
 
         let first_chunk = colors_index.pairs[0];
-        file.seek(SeekFrom::Start(first_chunk.file_offset))
-            .log_unrecoverable_error_with_data("Cannot seek color map", path.as_ref().display())?;
-
-        let current_chunk_size = colors_index
-            .pairs
-            .get(1)
-            .map(|p| p.start_index)
-            .unwrap_or(colors_index.subsets_count as ColorIndexType)
-            - first_chunk.start_index;
-
-        let json_escaped_color_names = color_names
-            .iter()
-            .map(|s| s.replace("\"", "\\\"").replace("\\", "\\\\"))
-            .collect();
-
-        Ok(Self {
-            colormap_file: lz4::Decoder::new(BufReader::new(file)).unwrap(),
+        let mut deserializer = Self {
+            path: path.as_ref().to_path_buf(),
+            codec,
+            file,
+            colormap_file: lz4::Decoder::new(Cursor::new(vec![]))?,
+            colors_count: header.colors_count,
+            build_params: header.build_params(),
             color_names,
+            colors_metadata,
             json_escaped_color_names,
             colors_index,
+            chunks_end_offsets,
             current_chunk: first_chunk,
-            current_chunk_size,
+            current_chunk_size: 0,
             current_index: first_chunk.start_index,
-            _phantom: Default::default(),
-        })
+            current_bitmap: RoaringBitmap::new(),
+        };
+        deserializer.load_chunk(0)?;
+
+        Ok(deserializer)
     }
 
-    fn maybe_change_block(&mut self, target_color: ColorIndexType) {
+    /// Returns the codec used to encode the colors subsets
+    pub fn codec(&self) -> ColorsCodec {
+        self.codec
+    }
+
+    /// Returns the parameters of the graph build saved in the colormap
+    pub fn build_params(&self) -> &ColormapBuildParams {
+        &self.build_params
+    }
+
+    /// Reads a chunk checking its checksum, and prepares it to be decoded.
+    /// The current chunk is left unchanged if the chunk cannot be read
+    fn load_chunk(&mut self, chunk_index: usize) -> anyhow::Result<()> {
+        let chunk = self.colors_index.pairs[chunk_index];
+        let end_offset = self.chunks_end_offsets[chunk_index];
+        let mut compressed = vec![0; end_offset.saturating_sub(chunk.file_offset) as usize];
+        self.file
+            .seek(SeekFrom::Start(chunk.file_offset))
+            .and_then(|_| self.file.read_exact(&mut compressed))
+            .with_context(|| format!("Cannot read colors chunk of {}", self.path.display()))?;
+        if crc32fast::hash(&compressed) != chunk.checksum {
+            anyhow::bail!(
+                "Colormap {} is corrupted, the checksum of the chunk starting at subset {} does not match",
+                self.path.display(),
+                chunk.start_index
+            );
+        }
+
+        let mut colormap_file = lz4::Decoder::new(Cursor::new(compressed))?;
+        if self.codec == ColorsCodec::Roaring {
+            self.current_bitmap = RoaringColorsSerializer::decode_chunk(&mut colormap_file)?;
+        }
+        self.colormap_file = colormap_file;
+        self.current_chunk = chunk;
+        self.current_chunk_size = self
+            .colors_index
+            .pairs
+            .get(chunk_index + 1)
+            .map(|p| p.start_index)
+            .unwrap_or(self.colors_index.subsets_count as ColorIndexType)
+            - chunk.start_index;
+        self.current_index = chunk.start_index;
+        Ok(())
+    }
+
+    fn maybe_change_block(&mut self, target_color: ColorIndexType) -> anyhow::Result<()> {
         if target_color < self.current_index
             || target_color >= (self.current_chunk.start_index + self.current_chunk_size)
         {
-            // ggcat_logging::info!(
-            //     "Changing chunk {} < {} || {} >= {} + {}!",
-            //     target_color,
-            //     self.current_index,
-            //     target_color,
-            //     self.current_chunk.start_index,
-            //     self.current_chunk_size
-            // );
             // Requested color is outside of chunk range, update the current chunk
             let new_chunk_index = self
                 .colors_index
@@ -126,43 +138,43 @@ impl<DS: ColorsSerializerTrait> ColorsDeserializer<DS> {
                 .partition_point(|x| x.start_index <= target_color)
                 - 1;
 
-            self.current_chunk = self.colors_index.pairs[new_chunk_index];
-            self.current_chunk_size = self
-                .colors_index
-                .pairs
-                .get(new_chunk_index + 1)
-                .map(|p| p.start_index)
-                .unwrap_or(self.colors_index.subsets_count as ColorIndexType)
-                - self.current_chunk.start_index;
-            self.current_index = self.current_chunk.start_index;
-
-            replace_with_or_abort(&mut self.colormap_file, |colormap_file| {
-                let mut buffered_file = colormap_file.finish().0;
-                assert_ne!(self.current_chunk.file_offset, 0);
-                buffered_file
-                    .seek(SeekFrom::Start(self.current_chunk.file_offset))
-                    .unwrap();
-                lz4::Decoder::new(buffered_file).unwrap()
-            });
+            self.load_chunk(new_chunk_index)?;
         }
+        Ok(())
     }
 
-    pub fn get_color_mappings(&mut self, color: ColorIndexType, out_vec: &mut Vec<ColorIndexType>) {
-        self.maybe_change_block(color);
+    /// Decodes the colors of a subset, failing if its chunk is corrupted
+    pub fn get_color_mappings(
+        &mut self,
+        color: ColorIndexType,
+        out_vec: &mut Vec<ColorIndexType>,
+    ) -> anyhow::Result<()> {
+        self.maybe_change_block(color)?;
+
+        if self.codec == ColorsCodec::Roaring {
+            RoaringColorsSerializer::decode_color(
+                &self.current_bitmap,
+                self.colors_count,
+                color - self.current_chunk.start_index,
+                out_vec,
+            );
+            return Ok(());
+        }
 
         while self.current_index < color {
             // Skip the colors
-            DS::decode_color(&mut self.colormap_file, None);
+            RunLengthColorsSerializer::decode_color(&mut self.colormap_file, None);
             self.current_index += 1;
         }
 
         // Decode the requested color
-        DS::decode_color(&mut self.colormap_file, Some(out_vec));
+        RunLengthColorsSerializer::decode_color(&mut self.colormap_file, Some(out_vec));
         self.current_index += 1;
+        Ok(())
     }
 }
 
-impl<DS: ColorsSerializerTrait> ColorMapReader for ColorsDeserializer<DS> {
+impl ColorMapReader for ColorsDeserializer {
     fn get_color_name(&self, index: ColorIndexType, json_escaped: bool) -> &str {
         if json_escaped {
             &self.json_escaped_color_names[index as usize]
@@ -178,4 +190,101 @@ impl<DS: ColorsSerializerTrait> ColorMapReader for ColorsDeserializer<DS> {
     fn colors_subsets_count(&self) -> u64 {
         self.colors_index.subsets_count as u64
     }
+
+    fn colors_metadata(&self) -> &ColorsMetadata {
+        &self.colors_metadata
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::run_length::RunLengthColorsSerializer;
+    use crate::storage::write_test_colormap;
+
+    fn temp_colormap(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "ggcat-deserializer-{}-{}.colors.dat",
+            name,
+            std::process::id()
+        ))
+    }
+
+    fn test_subsets() -> Vec<Vec<ColorIndexType>> {
+        // More than a chunk of subsets
+        (0..25000)
+            .map(|subset| vec![subset % 10, 10 + subset % 7])
+            .collect()
+    }
+
+    /// Writes a colormap, returning its header and index
+    fn write_colormap(path: &Path) -> (ColorsFileHeader, ColorsIndexMap) {
+        let color_names: Vec<_> = (0..20).map(|color| format!("sample_{}", color)).collect();
+        write_test_colormap::<RunLengthColorsSerializer>(path, &color_names, &test_subsets());
+        let mut file = File::open(path).unwrap();
+        let header = ColorsFileHeader::read(&mut file, path).unwrap();
+        let index = ColorsIndexMap::read(&mut file, &header, path).unwrap();
+        assert!(index.pairs.len() > 1);
+        (header, index)
+    }
+
+    fn flip_byte(path: &Path, offset: u64) {
+        let mut data = std::fs::read(path).unwrap();
+        data[offset as usize] ^= 0x10;
+        std::fs::write(path, &data).unwrap();
+    }
+
+    #[test]
+    fn corrupted_header_or_index_is_rejected() {
+        let path = temp_colormap("header");
+        write_colormap(&path);
+        // Colors count
+        flip_byte(&path, 32);
+        let err = ColorsDeserializer::new(&path, true).err().unwrap();
+        assert!(err.to_string().contains("header checksum"), "{}", err);
+
+        let (header, _) = write_colormap(&path);
+        flip_byte(&path, header.index_offset + 2);
+        let err = ColorsDeserializer::new(&path, true).err().unwrap();
+        assert!(err.to_string().contains("index checksum"), "{}", err);
+
+        let (header, index) = write_colormap(&path);
+        flip_byte(&path, index.pairs[0].file_offset - 2);
+        let err = ColorsDeserializer::new(&path, true).err().unwrap();
+        assert!(err.to_string().contains("color names checksum"), "{}", err);
+        assert!(ColorsDeserializer::new(&path, false).is_ok());
+
+        write_colormap(&path);
+        let data = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &data[..header.total_size as usize - 1]).unwrap();
+        let err = ColorsDeserializer::new(&path, true).err().unwrap();
+        assert!(err.to_string().contains("truncated"), "{}", err);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corrupted_chunk_is_reported() {
+        let path = temp_colormap("chunk");
+        let (_, index) = write_colormap(&path);
+        let last_chunk = *index.pairs.iter().max_by_key(|p| p.file_offset).unwrap();
+        flip_byte(&path, last_chunk.file_offset + 4);
+
+        let subsets = test_subsets();
+        let mut deserializer = ColorsDeserializer::new(&path, false).unwrap();
+        let mut colors = vec![];
+        deserializer.get_color_mappings(1, &mut colors).unwrap();
+        assert_eq!(colors, subsets[1]);
+
+        let err = deserializer
+            .get_color_mappings(last_chunk.start_index, &mut colors)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("checksum of the chunk"), "{}", err);
+
+        // The chunks read before are still decoded
+        colors.clear();
+        deserializer.get_color_mappings(2, &mut colors).unwrap();
+        assert_eq!(colors, subsets[2]);
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,519 @@
+use crate::colors_manager::json_escape;
+use crate::storage::roaring::RoaringColorsSerializer;
+use crate::storage::run_length::ColorIndexSerializer;
+use crate::storage::serializer::{ColorsFileHeader, ColorsIndexEntry, ColorsIndexMap};
+use crate::storage::{ColormapBuildParams, ColorsCodec};
+use config::ColorIndexType;
+use desse::{Desse, DesseSized};
+use ggcat_logging::UnrecoverableErrorLogging;
+use itertools::Itertools;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Summary of a colormap file
+#[derive(Clone, Debug)]
+pub struct ColormapInfo {
+    pub codec: ColorsCodec,
+    pub version: u64,
+    pub build_params: ColormapBuildParams,
+    pub colors_count: u64,
+    /// The keys of the colors metadata
+    pub metadata_keys: Vec<String>,
+    pub subsets_count: u64,
+    pub chunks_count: u64,
+    pub file_size: u64,
+    /// Size of the compressed subsets chunks
+    pub compressed_size: u64,
+    /// Size of the subsets chunks before the compression
+    pub uncompressed_size: u64,
+    /// Counts of the subsets with colors count in [min, max], using power of two bins
+    pub subset_sizes_histogram: Vec<(u64, u64, u64)>,
+}
+
+impl ColormapInfo {
+    /// Formats the summary as a JSON object
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        writeln!(json, "{{").unwrap();
+        writeln!(json, "  \"codec\": \"{:?}\",", self.codec).unwrap();
+        writeln!(json, "  \"version\": {},", self.version).unwrap();
+        if self.build_params.k != 0 {
+            writeln!(json, "  \"k\": {},", self.build_params.k).unwrap();
+        } else {
+            writeln!(json, "  \"k\": null,").unwrap();
+        }
+        if !self.build_params.hash_type.is_empty() {
+            writeln!(
+                json,
+                "  \"hash_type\": \"{}\",",
+                self.build_params.hash_type
+            )
+            .unwrap();
+        } else {
+            writeln!(json, "  \"hash_type\": null,").unwrap();
+        }
+        writeln!(json, "  \"colors_count\": {},", self.colors_count).unwrap();
+        writeln!(
+            json,
+            "  \"metadata_keys\": [{}],",
+            self.metadata_keys
+                .iter()
+                .map(|k| format!("\"{}\"", json_escape(k)))
+                .join(", ")
+        )
+        .unwrap();
+        writeln!(json, "  \"subsets_count\": {},", self.subsets_count).unwrap();
+        writeln!(json, "  \"chunks_count\": {},", self.chunks_count).unwrap();
+        writeln!(json, "  \"file_size\": {},", self.file_size).unwrap();
+        writeln!(json, "  \"compressed_size\": {},", self.compressed_size).unwrap();
+        writeln!(json, "  \"uncompressed_size\": {},", self.uncompressed_size).unwrap();
+        writeln!(json, "  \"subset_sizes_histogram\": [").unwrap();
+        for (i, (min, max, count)) in self.subset_sizes_histogram.iter().enumerate() {
+            writeln!(
+                json,
+                "    {{ \"min\": {}, \"max\": {}, \"count\": {} }}{}",
+                min,
+                max,
+                count,
+                if i + 1 < self.subset_sizes_histogram.len() {
+                    ","
+                } else {
+                    ""
+                }
+            )
+            .unwrap();
+        }
+        writeln!(json, "  ]").unwrap();
+        write!(json, "}}").unwrap();
+        json
+    }
+}
+
+/// Colormap file opened to be inspected, without assuming that it is well formed
+struct RawColormap {
+    file: File,
+    file_size: u64,
+    header: ColorsFileHeader,
+    codec: Option<ColorsCodec>,
+    index: Option<ColorsIndexMap>,
+    index_checksum: u32,
+}
+
+impl RawColormap {
+    fn open(path: &Path) -> anyhow::Result<Self> {
+        let mut file = File::open(path)
+            .log_unrecoverable_error_with_data("Cannot open colors file", path.display())?;
+        let file_size = file.metadata()?.len();
+
+        let mut header_buffer = [0; ColorsFileHeader::SIZE];
+        file.read_exact(&mut header_buffer)
+            .log_unrecoverable_error_with_data("Cannot read header", path.display())?;
+        let header = ColorsFileHeader::deserialize_from(&header_buffer);
+
+        let (index, index_checksum) = if header.index_offset < file_size {
+            let mut data = vec![0; (file_size - header.index_offset) as usize];
+            file.seek(SeekFrom::Start(header.index_offset))?;
+            file.read_exact(&mut data)?;
+            (bincode::deserialize(&data).ok(), crc32fast::hash(&data))
+        } else {
+            (None, 0)
+        };
+
+        Ok(Self {
+            file,
+            file_size,
+            codec: ColorsCodec::from_magic(&header.magic),
+            header,
+            index,
+            index_checksum,
+        })
+    }
+
+    /// Returns the index entry, the subsets count and the end offset of each chunk
+    fn chunks(&self, index: &ColorsIndexMap) -> Vec<(ColorsIndexEntry, u64, u64)> {
+        index
+            .pairs
+            .iter()
+            .zip(index.chunks_end_offsets(self.header.index_offset))
+            .enumerate()
+            .map(|(i, (pair, end_offset))| {
+                let end_index = index
+                    .pairs
+                    .get(i + 1)
+                    .map(|p| p.start_index as u64)
+                    .unwrap_or(index.subsets_count);
+                (
+                    *pair,
+                    end_index.saturating_sub(pair.start_index as u64),
+                    end_offset,
+                )
+            })
+            .collect()
+    }
+
+    /// Reads and decompresses a chunk, checking its checksum
+    fn read_chunk(&mut self, chunk: &ColorsIndexEntry, end_offset: u64) -> anyhow::Result<Vec<u8>> {
+        let mut compressed = vec![0; end_offset.saturating_sub(chunk.file_offset) as usize];
+        self.file.seek(SeekFrom::Start(chunk.file_offset))?;
+        self.file.read_exact(&mut compressed)?;
+        if crc32fast::hash(&compressed) != chunk.checksum {
+            anyhow::bail!("the checksum does not match");
+        }
+
+        let mut data = vec![];
+        lz4::Decoder::new(Cursor::new(compressed))?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Decodes all the subsets of a chunk, checking that each one is well formed
+    fn decode_chunk(
+        &self,
+        codec: ColorsCodec,
+        data: &[u8],
+        start_index: ColorIndexType,
+        subsets_count: u64,
+        mut output: impl FnMut(&[ColorIndexType]),
+    ) -> anyhow::Result<()> {
+        let colors_count = self.header.colors_count;
+        let mut colors = vec![];
+        let mut check_subset = |subset: u64, colors: &[ColorIndexType]| {
+            let subset = start_index as u64 + subset;
+            if colors.is_empty() {
+                anyhow::bail!("subset {} is empty", subset);
+            }
+            if colors.windows(2).any(|w| w[0] >= w[1]) {
+                anyhow::bail!("subset {} colors are not sorted", subset);
+            }
+            if *colors.last().unwrap() as u64 >= colors_count {
+                anyhow::bail!("subset {} has colors out of range", subset);
+            }
+            output(colors);
+            Ok(())
+        };
+
+        match codec {
+            ColorsCodec::RunLength => {
+                let mut reader = Cursor::new(data);
+                for subset in 0..subsets_count {
+                    ColorIndexSerializer::deserialize_colors(&mut reader, &mut colors)
+                        .ok_or_else(|| anyhow::anyhow!("subset {} is truncated", subset))?;
+                    check_subset(subset, &colors)?;
+                }
+                if reader.position() != data.len() as u64 {
+                    anyhow::bail!("trailing data after the last subset");
+                }
+            }
+            ColorsCodec::Roaring => {
+                let bitmap = RoaringColorsSerializer::decode_chunk(data)?;
+                if bitmap
+                    .max()
+                    .is_some_and(|max| max as u64 >= subsets_count * colors_count)
+                {
+                    anyhow::bail!("colors after the last subset");
+                }
+                for subset in 0..subsets_count {
+                    RoaringColorsSerializer::decode_color(
+                        &bitmap,
+                        colors_count,
+                        subset as ColorIndexType,
+                        &mut colors,
+                    );
+                    check_subset(subset, &colors)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads the summary of a colormap, decoding all its subsets to compute their sizes
+pub fn read_colormap_info(path: impl AsRef<Path>) -> anyhow::Result<ColormapInfo> {
+    let path = path.as_ref();
+    let mut colormap = RawColormap::open(path)?;
+    colormap.header.check_version(path)?;
+
+    let Some(codec) = colormap.codec else {
+        anyhow::bail!(
+            "Unknown colors codec magic {:?} in {}",
+            colormap.header.magic,
+            path.display()
+        );
+    };
+    let Some(index) = colormap.index.take() else {
+        anyhow::bail!("Cannot read the subsets index of {}", path.display());
+    };
+
+    let (_, colors_metadata) = colormap
+        .header
+        .read_colors(&mut colormap.file, &index, path)?;
+
+    let chunks = colormap.chunks(&index);
+    let mut sizes = vec![];
+    for (chunk, subsets_count, end_offset) in &chunks {
+        let start_index = chunk.start_index;
+        colormap
+            .read_chunk(chunk, *end_offset)
+            .and_then(|data| {
+                colormap.decode_chunk(codec, &data, start_index, *subsets_count, |colors| {
+                    sizes.push(colors.len() as u64)
+                })
+            })
+            .map_err(|err| {
+                anyhow::anyhow!(
+                    "Cannot decode the chunk starting at subset {} of {}: {}",
+                    start_index,
+                    path.display(),
+                    err
+                )
+            })?;
+    }
+
+    sizes.sort_unstable();
+    let mut subset_sizes_histogram: Vec<(u64, u64, u64)> = vec![];
+    for size in sizes {
+        let min = 1u64 << (63 - size.max(1).leading_zeros());
+        match subset_sizes_histogram.last_mut() {
+            Some((last_min, _, count)) if *last_min == min => *count += 1,
+            _ => subset_sizes_histogram.push((min, min * 2 - 1, 1)),
+        }
+    }
+
+    Ok(ColormapInfo {
+        codec,
+        version: colormap.header.version,
+        build_params: colormap.header.build_params(),
+        colors_count: colormap.header.colors_count,
+        metadata_keys: colors_metadata.keys().to_vec(),
+        subsets_count: index.subsets_count,
+        chunks_count: chunks.len() as u64,
+        file_size: colormap.file_size,
+        compressed_size: chunks
+            .iter()
+            .map(|(chunk, _, end)| end - chunk.file_offset)
+            .sum(),
+        uncompressed_size: colormap.header.total_uncompressed_size,
+        subset_sizes_histogram,
+    })
+}
+
+/// Checks the structure of a colormap and that all its subsets can be decoded.
+/// Returns the list of the problems found, empty if the colormap is valid
+pub fn validate_colormap(path: impl AsRef<Path>) -> anyhow::Result<Vec<String>> {
+    let mut colormap = RawColormap::open(path.as_ref())?;
+    let header = &colormap.header;
+    let mut errors = vec![];
+
+    if let Err(err) = header.check_version(path.as_ref()) {
+        // The rest of the file cannot be read with a different layout
+        errors.push(err.to_string());
+        return Ok(errors);
+    }
+    if header.compute_checksum() != header.header_checksum {
+        errors.push("The header checksum does not match".to_string());
+    }
+    if header.total_size != colormap.file_size {
+        errors.push(format!(
+            "File size {} does not match the size {} in the header",
+            colormap.file_size, header.total_size
+        ));
+    }
+    let codec = colormap.codec;
+    if codec.is_none() {
+        errors.push(format!("Unknown colors codec magic {:?}", header.magic));
+    }
+    let Some(index) = colormap.index.take() else {
+        errors.push(format!(
+            "Cannot read the subsets index at offset {}",
+            header.index_offset
+        ));
+        return Ok(errors);
+    };
+
+    if colormap.index_checksum != header.index_checksum {
+        errors.push("The index checksum does not match".to_string());
+    }
+    if index.subsets_count != header.subsets_count {
+        errors.push(format!(
+            "Index subsets count {} does not match the count {} in the header",
+            index.subsets_count, header.subsets_count
+        ));
+    }
+    if index.pairs.first().map(|p| p.start_index) != Some(0) {
+        errors.push("The index does not start from subset 0".to_string());
+    }
+    for pair in index.pairs.windows(2) {
+        if pair[0].start_index >= pair[1].start_index {
+            errors.push(format!(
+                "Index entries are not increasing: subset {} after {}",
+                pair[1].start_index, pair[0].start_index
+            ));
+        }
+    }
+    let mut offsets: Vec<_> = index.pairs.iter().map(|p| p.file_offset).collect();
+    offsets.sort_unstable();
+    if offsets.windows(2).any(|w| w[0] == w[1]) {
+        errors.push("Several index entries point to the same chunk".to_string());
+    }
+    if offsets
+        .iter()
+        .any(|o| *o < ColorsFileHeader::SIZE as u64 || *o >= header.index_offset)
+    {
+        errors.push("Index entries point outside the chunks data".to_string());
+    }
+    if index
+        .pairs
+        .last()
+        .is_some_and(|p| p.start_index as u64 > index.subsets_count)
+    {
+        errors.push("Index entries point after the last subset".to_string());
+    }
+
+    let Some(codec) = codec else {
+        return Ok(errors);
+    };
+    if !errors.is_empty() {
+        // The chunks cannot be located reliably
+        return Ok(errors);
+    }
+
+    match colormap
+        .header
+        .read_colors(&mut colormap.file, &index, path.as_ref())
+    {
+        Ok((color_names, colors_metadata)) => {
+            if color_names.len() as u64 != colormap.header.colors_count {
+                errors.push(format!(
+                    "Colormap has {} color names instead of {}",
+                    color_names.len(),
+                    colormap.header.colors_count
+                ));
+            }
+            if !colors_metadata.is_empty() && colors_metadata.colors_count() != color_names.len() {
+                errors.push(format!(
+                    "Colors metadata has {} colors instead of {}",
+                    colors_metadata.colors_count(),
+                    color_names.len()
+                ));
+            }
+        }
+        Err(err) => errors.push(err.to_string()),
+    }
+
+    let mut uncompressed_size = 0;
+    for (chunk, subsets_count, end_offset) in colormap.chunks(&index) {
+        let result = colormap.read_chunk(&chunk, end_offset).and_then(|data| {
+            uncompressed_size += data.len() as u64;
+            colormap.decode_chunk(codec, &data, chunk.start_index, subsets_count, |_| {})
+        });
+        if let Err(err) = result {
+            errors.push(format!(
+                "Cannot decode the chunk starting at subset {}: {}",
+                chunk.start_index, err
+            ));
+        }
+    }
+
+    // The size is meaningful only if all the chunks were decoded
+    if errors.is_empty() && uncompressed_size != colormap.header.total_uncompressed_size {
+        errors.push(format!(
+            "Decoded size {} does not match the size {} in the header",
+            uncompressed_size, colormap.header.total_uncompressed_size
+        ));
+    }
+
+    Ok(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::roaring::RoaringColorsSerializer;
+    use crate::storage::run_length::RunLengthColorsSerializer;
+    use crate::storage::{write_test_colormap, ColorsSerializerTrait};
+    use std::path::PathBuf;
+
+    fn temp_colormap(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "ggcat-inspect-{}-{}.colors.dat",
+            name,
+            std::process::id()
+        ))
+    }
+
+    fn write_colormap<SI: ColorsSerializerTrait>(path: &Path) {
+        let color_names: Vec<_> = (0..20).map(|color| format!("sample_{}", color)).collect();
+        let subsets: Vec<Vec<ColorIndexType>> = (0..300)
+            .map(|subset| (0..20).filter(|color| (subset + color) % 3 != 0).collect())
+            .collect();
+        write_test_colormap::<SI>(path, &color_names, &subsets);
+    }
+
+    fn first_chunk_offset(path: &Path) -> u64 {
+        let colormap = RawColormap::open(path).unwrap();
+        colormap
+            .index
+            .unwrap()
+            .pairs
+            .iter()
+            .map(|p| p.file_offset)
+            .min()
+            .unwrap()
+    }
+
+    #[test]
+    fn valid_colormaps() {
+        let path = temp_colormap("valid");
+        write_colormap::<RoaringColorsSerializer>(&path);
+        assert_eq!(validate_colormap(&path).unwrap(), Vec::<String>::new());
+        write_colormap::<RunLengthColorsSerializer>(&path);
+        assert_eq!(validate_colormap(&path).unwrap(), Vec::<String>::new());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corrupted_chunk_is_reported() {
+        let path = temp_colormap("chunk");
+        write_colormap::<RunLengthColorsSerializer>(&path);
+        let offset = first_chunk_offset(&path) as usize;
+        let mut data = std::fs::read(&path).unwrap();
+        data[offset + 8] ^= 0xff;
+        std::fs::write(&path, &data).unwrap();
+
+        let errors = validate_colormap(&path).unwrap();
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(errors[0].starts_with("Cannot decode the chunk starting at subset 0"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corrupted_header_is_reported() {
+        let path = temp_colormap("header");
+        write_colormap::<RoaringColorsSerializer>(&path);
+        let mut data = std::fs::read(&path).unwrap();
+        // Subsets count
+        data[40] ^= 0x01;
+        std::fs::write(&path, &data).unwrap();
+
+        let errors = validate_colormap(&path).unwrap();
+        assert!(errors.contains(&"The header checksum does not match".to_string()));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn truncated_colormap_is_reported() {
+        let path = temp_colormap("truncated");
+        write_colormap::<RoaringColorsSerializer>(&path);
+        let data = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &data[..data.len() - 10]).unwrap();
+
+        let errors = validate_colormap(&path).unwrap();
+        assert!(errors[0].starts_with(&format!(
+            "File size {} does not match the size {}",
+            data.len() - 10,
+            data.len()
+        )));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,335 @@
+use crate::colors_manager::ColorMapReader;
+use crate::storage::deserializer::ColorsDeserializer;
+use crate::storage::run_length::ColorIndexSerializer;
+use crate::storage::serializer::ColorsFileHeader;
+use config::ColorIndexType;
+use desse::{Desse, DesseSized};
+use filebuffer::FileBuffer;
+use ggcat_logging::UnrecoverableErrorLogging;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const COLORS_INDEX_MAGIC: &[u8; 8] = b"GGCATCMI";
+const COLORS_INDEX_VERSION: u32 = 2;
+const COLORS_INDEX_HEADER_SIZE: usize = 48;
+
+/// Identifies the colormap an index was built from, the checksums change with any change of its subsets
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct ColormapIdentity {
+    size: u64,
+    header_checksum: u32,
+    index_checksum: u32,
+}
+
+impl ColormapIdentity {
+    fn read(colormap_file: &Path) -> Option<Self> {
+        let mut file = File::open(colormap_file).ok()?;
+        let mut header_buffer = [0; ColorsFileHeader::SIZE];
+        file.read_exact(&mut header_buffer).ok()?;
+        let header = ColorsFileHeader::deserialize_from(&header_buffer);
+        Some(Self {
+            size: file.metadata().ok()?.len(),
+            header_checksum: header.header_checksum,
+            index_checksum: header.index_checksum,
+        })
+    }
+}
+
+/// Fixed size header of the colormap index file
+struct ColorsIndexHeader {
+    colormap: ColormapIdentity,
+    colors_count: u64,
+    subsets_count: u64,
+}
+
+impl ColorsIndexHeader {
+    fn encode(&self) -> [u8; COLORS_INDEX_HEADER_SIZE] {
+        let mut header = [0; COLORS_INDEX_HEADER_SIZE];
+        header[..8].copy_from_slice(COLORS_INDEX_MAGIC);
+        header[8..12].copy_from_slice(&COLORS_INDEX_VERSION.to_le_bytes());
+        header[16..24].copy_from_slice(&self.colormap.size.to_le_bytes());
+        header[24..32].copy_from_slice(&self.colors_count.to_le_bytes());
+        header[32..40].copy_from_slice(&self.subsets_count.to_le_bytes());
+        header[40..44].copy_from_slice(&self.colormap.header_checksum.to_le_bytes());
+        header[44..48].copy_from_slice(&self.colormap.index_checksum.to_le_bytes());
+        header
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() < COLORS_INDEX_HEADER_SIZE
+            || &data[..8] != COLORS_INDEX_MAGIC
+            || data[8..12] != COLORS_INDEX_VERSION.to_le_bytes()
+        {
+            return None;
+        }
+        Some(Self {
+            colormap: ColormapIdentity {
+                size: u64::from_le_bytes(data[16..24].try_into().unwrap()),
+                header_checksum: u32::from_le_bytes(data[40..44].try_into().unwrap()),
+                index_checksum: u32::from_le_bytes(data[44..48].try_into().unwrap()),
+            },
+            colors_count: u64::from_le_bytes(data[24..32].try_into().unwrap()),
+            subsets_count: u64::from_le_bytes(data[32..40].try_into().unwrap()),
+        })
+    }
+
+    /// Position of the encoded subsets, after the header and the subsets offsets
+    fn data_offset(subsets_count: u64) -> usize {
+        COLORS_INDEX_HEADER_SIZE + (subsets_count as usize + 1) * size_of::<u64>()
+    }
+}
+
+/// Memory mapped index of a colormap, saved next to it, with the offset of every color subset.
+/// Each subset is decoded independently, so the reader can be shared across threads
+pub struct ColorsMappedReader {
+    file: FileBuffer,
+    colors_count: u64,
+    subsets_count: u64,
+}
+
+impl ColorsMappedReader {
+    /// The file where the index of a colormap is saved
+    pub fn get_index_file(colormap_file: impl AsRef<Path>) -> PathBuf {
+        colormap_file.as_ref().with_extension("idx")
+    }
+
+    /// Opens the index of a colormap, returning None if it does not exist or if it was built from another colormap
+    pub fn open(colormap_file: impl AsRef<Path>) -> Option<Self> {
+        let colormap = ColormapIdentity::read(colormap_file.as_ref())?;
+        let file = FileBuffer::open(Self::get_index_file(colormap_file)).ok()?;
+        let header = ColorsIndexHeader::decode(&file)?;
+
+        let data_offset = ColorsIndexHeader::data_offset(header.subsets_count);
+        if header.colormap != colormap || file.len() < data_offset {
+            return None;
+        }
+
+        let reader = Self {
+            file,
+            colors_count: header.colors_count,
+            subsets_count: header.subsets_count,
+        };
+        (reader.file.len() == data_offset + reader.get_offset(header.subsets_count as usize))
+            .then_some(reader)
+    }
+
+    /// Opens the index of a colormap, building it first if it does not exist or is outdated
+    pub fn open_or_build(colormap_file: impl AsRef<Path>) -> anyhow::Result<Self> {
+        if let Some(reader) = Self::open(colormap_file.as_ref()) {
+            return Ok(reader);
+        }
+        Self::build(colormap_file.as_ref())?;
+        Self::open(colormap_file.as_ref())
+            .ok_or_else(|| anyhow::anyhow!("Invalid index file"))
+            .log_unrecoverable_error_with_data(
+                "Cannot open colormap index",
+                colormap_file.as_ref().display(),
+            )
+    }
+
+    /// Builds the index of a colormap, decoding all its subsets.
+    /// The offsets and the encoded subsets are written at the same time, through two handles of the index file
+    pub fn build(colormap_file: impl AsRef<Path>) -> anyhow::Result<PathBuf> {
+        let colormap_file = colormap_file.as_ref();
+        let mut deserializer = ColorsDeserializer::new(colormap_file, true)?;
+        // The colormap is read after opening it, as the deserializer checks its header
+        let colormap = ColormapIdentity::read(colormap_file)
+            .ok_or_else(|| anyhow::anyhow!("Cannot read colormap header"))
+            .log_unrecoverable_error_with_data(
+                "Cannot open colors file",
+                colormap_file.display(),
+            )?;
+        let subsets_count = deserializer.colors_subsets_count();
+
+        let index_file = Self::get_index_file(colormap_file);
+        let temp_file = index_file.with_extension("idx.tmp");
+        let mut offsets_writer =
+            BufWriter::new(File::create(&temp_file).log_unrecoverable_error_with_data(
+                "Cannot create colormap index",
+                temp_file.display(),
+            )?);
+        let mut data_writer = BufWriter::new(
+            OpenOptions::new()
+                .write(true)
+                .open(&temp_file)
+                .log_unrecoverable_error_with_data(
+                    "Cannot create colormap index",
+                    temp_file.display(),
+                )?,
+        );
+
+        offsets_writer.write_all(
+            &ColorsIndexHeader {
+                colormap,
+                colors_count: deserializer.colors_count() as u64,
+                subsets_count,
+            }
+            .encode(),
+        )?;
+        data_writer.seek(SeekFrom::Start(
+            ColorsIndexHeader::data_offset(subsets_count) as u64,
+        ))?;
+
+        let mut data_size = 0;
+        let mut colors = vec![];
+        let mut encoded_colors = vec![];
+        for subset in 0..subsets_count {
+            offsets_writer.write_all(&(data_size as u64).to_le_bytes())?;
+            deserializer.get_color_mappings(subset as ColorIndexType, &mut colors)?;
+            encoded_colors.clear();
+            ColorIndexSerializer::serialize_colors(&mut encoded_colors, &colors);
+            data_writer.write_all(&encoded_colors)?;
+            data_size += encoded_colors.len();
+        }
+        offsets_writer.write_all(&(data_size as u64).to_le_bytes())?;
+        offsets_writer.flush()?;
+        data_writer.flush()?;
+        drop(offsets_writer);
+        drop(data_writer);
+
+        std::fs::rename(&temp_file, &index_file).log_unrecoverable_error_with_data(
+            "Cannot create colormap index",
+            index_file.display(),
+        )?;
+        Ok(index_file)
+    }
+
+    fn get_offset(&self, index: usize) -> usize {
+        let position = COLORS_INDEX_HEADER_SIZE + index * size_of::<u64>();
+        u64::from_le_bytes(self.file[position..position + 8].try_into().unwrap()) as usize
+    }
+
+    pub fn colors_count(&self) -> u64 {
+        self.colors_count
+    }
+
+    pub fn subsets_count(&self) -> u64 {
+        self.subsets_count
+    }
+
+    /// Decodes the colors of a subset, returning false if the subset does not exist
+    pub fn get_color_mappings(
+        &self,
+        subset: ColorIndexType,
+        out_vec: &mut Vec<ColorIndexType>,
+    ) -> bool {
+        if subset as u64 >= self.subsets_count {
+            return false;
+        }
+        let data_offset = ColorsIndexHeader::data_offset(self.subsets_count);
+        let start = data_offset + self.get_offset(subset as usize);
+        let end = data_offset + self.get_offset(subset as usize + 1);
+        ColorIndexSerializer::deserialize_colors(&self.file[start..end], out_vec).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::roaring::RoaringColorsSerializer;
+    use crate::storage::run_length::RunLengthColorsSerializer;
+    use crate::storage::write_test_colormap;
+
+    fn temp_colormap(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "ggcat-mapped-{}-{}.colors.dat",
+            name,
+            std::process::id()
+        ))
+    }
+
+    fn color_names() -> Vec<String> {
+        (0..40).map(|color| format!("sample_{}", color)).collect()
+    }
+
+    fn test_subsets(seed: ColorIndexType) -> Vec<Vec<ColorIndexType>> {
+        (0..500)
+            .map(|subset| {
+                (0..40)
+                    .filter(|color| (subset * 7 + color * seed) % 5 < 2)
+                    .collect()
+            })
+            .filter(|subset: &Vec<_>| !subset.is_empty())
+            .collect()
+    }
+
+    fn remove_colormap(colormap_file: &Path) {
+        std::fs::remove_file(colormap_file).unwrap();
+        let _ = std::fs::remove_file(ColorsMappedReader::get_index_file(colormap_file));
+    }
+
+    #[test]
+    fn mapped_subsets_lookup() {
+        let colormap_file = temp_colormap("lookup");
+        let subsets = test_subsets(3);
+        write_test_colormap::<RoaringColorsSerializer>(&colormap_file, &color_names(), &subsets);
+
+        assert!(ColorsMappedReader::open(&colormap_file).is_none());
+        let reader = ColorsMappedReader::open_or_build(&colormap_file).unwrap();
+        assert_eq!(reader.colors_count(), 40);
+        assert_eq!(reader.subsets_count(), subsets.len() as u64);
+
+        let mut colors = vec![];
+        for (subset, expected) in subsets.iter().enumerate().rev() {
+            assert!(reader.get_color_mappings(subset as ColorIndexType, &mut colors));
+            assert_eq!(&colors, expected);
+        }
+        assert!(!reader.get_color_mappings(subsets.len() as ColorIndexType, &mut colors));
+
+        // The built index is reused
+        assert!(ColorsMappedReader::open(&colormap_file).is_some());
+        remove_colormap(&colormap_file);
+    }
+
+    #[test]
+    fn index_of_another_colormap_is_rejected() {
+        let colormap_file = temp_colormap("outdated");
+        write_test_colormap::<RunLengthColorsSerializer>(
+            &colormap_file,
+            &color_names(),
+            &test_subsets(3),
+        );
+        ColorsMappedReader::build(&colormap_file).unwrap();
+
+        // The colormap is replaced, the index is made to match its size
+        let subsets = test_subsets(11);
+        write_test_colormap::<RunLengthColorsSerializer>(&colormap_file, &color_names(), &subsets);
+        let index_file = ColorsMappedReader::get_index_file(&colormap_file);
+        let mut index = std::fs::read(&index_file).unwrap();
+        let colormap_size = std::fs::metadata(&colormap_file).unwrap().len();
+        index[16..24].copy_from_slice(&colormap_size.to_le_bytes());
+        std::fs::write(&index_file, &index).unwrap();
+
+        assert!(ColorsMappedReader::open(&colormap_file).is_none());
+
+        // The index is built again from the new colormap
+        let reader = ColorsMappedReader::open_or_build(&colormap_file).unwrap();
+        let mut colors = vec![];
+        for (subset, expected) in subsets.iter().enumerate() {
+            assert!(reader.get_color_mappings(subset as ColorIndexType, &mut colors));
+            assert_eq!(&colors, expected);
+        }
+        remove_colormap(&colormap_file);
+    }
+
+    #[test]
+    fn truncated_index_is_rejected() {
+        let colormap_file = temp_colormap("truncated");
+        write_test_colormap::<RunLengthColorsSerializer>(
+            &colormap_file,
+            &color_names(),
+            &test_subsets(3),
+        );
+        let index_file = ColorsMappedReader::build(&colormap_file).unwrap();
+        assert!(ColorsMappedReader::open(&colormap_file).is_some());
+
+        let index = std::fs::read(&index_file).unwrap();
+        std::fs::write(&index_file, &index[..index.len() - 1]).unwrap();
+        assert!(ColorsMappedReader::open(&colormap_file).is_none());
+
+        std::fs::write(&index_file, &index[..COLORS_INDEX_HEADER_SIZE + 4]).unwrap();
+        assert!(ColorsMappedReader::open(&colormap_file).is_none());
+        remove_colormap(&colormap_file);
+    }
+}
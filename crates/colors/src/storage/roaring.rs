@@ -1,4 +1,3 @@
-#![allow(warnings)]
 use crate::storage::serializer::ColorsFlushProcessing;
 use crate::storage::ColorsSerializerTrait;
 use config::ColorIndexType;
@@ -6,150 +5,254 @@ use io::chunks_writer::ChunksWriter;
 use parking_lot::Mutex;
 use roaring::RoaringBitmap;
 use std::io::Read;
-use std::sync::atomic::{AtomicU32, Ordering};
 
-struct RoaringBitmapInstance {
+/// A chunk of consecutive subsets, each one stored in the bitmap
+/// as the range [local_index * colors_count, (local_index + 1) * colors_count)
+struct RoaringBitmapChunk {
     bitmap: RoaringBitmap,
-    offset: ColorIndexType,
-    colors_count: u64,
-    checkpoint_distance: u64,
-    stride: ColorIndexType,
-    last_color: ColorIndexType,
+    start_index: ColorIndexType,
+    subsets_count: ColorIndexType,
 }
 
-impl RoaringBitmapInstance {
-    fn new(
-        colors_count: u64,
-        checkpoint_distance: u64,
-        offset: ColorIndexType,
-        stride: ColorIndexType,
-    ) -> Self {
-        todo!("Fix meaning of 'stride'!");
+impl RoaringBitmapChunk {
+    fn new(start_index: ColorIndexType) -> Self {
         Self {
             bitmap: RoaringBitmap::new(),
-            offset,
-            colors_count,
-            checkpoint_distance,
-            stride,
-            last_color: 0,
-        }
-    }
-
-    fn try_append(
-        &mut self,
-        color_index: ColorIndexType,
-        colors: impl Iterator<Item = ColorIndexType>,
-        writer: &ColorsFlushProcessing,
-    ) -> bool {
-        let base_color = color_index - self.offset;
-
-        // Another append is in queue and the current is not the first one
-        if base_color > self.last_color + self.stride {
-            return false;
-        }
-
-        self.last_color = base_color;
-
-        assert_eq!(base_color % self.stride, 0);
-        let strided_color = base_color / self.stride;
-
-        let local_position = strided_color * (self.colors_count as u32);
-
-        self.bitmap
-            .append(colors.map(|c| local_position + c))
-            .unwrap();
-
-        // Flush the partial bitmap
-        if strided_color >= self.checkpoint_distance as u32 {
-            ggcat_logging::info!("Flushing with offset: {}", self.offset);
-            self.flush(writer);
+            start_index,
+            subsets_count: 0,
         }
-
-        true
     }
 
-    fn flush(&mut self, writer: &ColorsFlushProcessing) {
+    fn flush(self, writer: &ColorsFlushProcessing) {
         let mut pdata = writer.start_processing();
         self.bitmap
             .serialize_into(writer.get_stream(&mut pdata))
             .unwrap();
-        writer.end_processing(pdata, self.offset);
-        self.offset += self.last_color;
-        self.last_color = 0;
-        self.bitmap.clear();
+        writer.end_processing(pdata, self.start_index);
     }
 }
 
 pub struct RoaringColorsSerializer {
     colors_count: u64,
-    roaring_bitmaps: Vec<Mutex<RoaringBitmapInstance>>,
+    chunk_size: ColorIndexType,
+    current_chunk: Mutex<RoaringBitmapChunk>,
     writer: ColorsFlushProcessing,
-    colors_index: AtomicU32,
 }
 
-impl ColorsSerializerTrait for RoaringColorsSerializer {
-    const MAGIC: [u8; 16] = *b"GGCAT_CMAP_ROARG";
+impl RoaringColorsSerializer {
+    /// Number of subsets in each chunk, the positions of all the subsets of a chunk must fit in the 32 bit bitmap
+    fn chunk_size(checkpoint_distance: usize, colors_count: u64) -> ColorIndexType {
+        let max_chunk_size = (ColorIndexType::MAX as u64 + 1) / colors_count.max(1);
+        (checkpoint_distance as u64).min(max_chunk_size).max(1) as ColorIndexType
+    }
 
-    // FIXME: Implement!
-    fn decode_color(_reader: impl Read, _out_vec: Option<&mut Vec<u32>>) {
-        todo!()
+    /// Reads a chunk written by the serializer
+    pub fn decode_chunk(reader: impl Read) -> std::io::Result<RoaringBitmap> {
+        RoaringBitmap::deserialize_from(reader)
     }
 
-    fn new(writer: ColorsFlushProcessing, checkpoint_distance: usize, colors_count: u64) -> Self {
-        todo!("Fix meaning of 'stride'!");
-        let stride = rayon::current_num_threads() as ColorIndexType;
+    /// Copies the colors of the subset at the given position inside a decoded chunk
+    pub fn decode_color(
+        chunk: &RoaringBitmap,
+        colors_count: u64,
+        local_index: ColorIndexType,
+        out_vec: &mut Vec<ColorIndexType>,
+    ) {
+        let start = local_index * colors_count as ColorIndexType;
+        out_vec.clear();
+        out_vec.extend(
+            chunk
+                .range(start..start + colors_count as ColorIndexType)
+                .map(|c| c - start),
+        );
+    }
+}
+
+impl ColorsSerializerTrait for RoaringColorsSerializer {
+    const MAGIC: [u8; 16] = *b"GGCAT_CMAP_ROARG";
 
+    fn new(writer: ColorsFlushProcessing, checkpoint_distance: usize, colors_count: u64) -> Self {
         Self {
-            roaring_bitmaps: (0..stride)
-                .map(|off| {
-                    Mutex::new(RoaringBitmapInstance::new(
-                        colors_count,
-                        checkpoint_distance as u64,
-                        off,
-                        stride,
-                    ))
-                })
-                .collect(),
-            writer,
-            colors_index: AtomicU32::new(0),
             colors_count,
+            chunk_size: Self::chunk_size(checkpoint_distance, colors_count),
+            current_chunk: Mutex::new(RoaringBitmapChunk::new(0)),
+            writer,
         }
     }
 
     fn serialize_colors(&self, colors: &[ColorIndexType]) -> ColorIndexType {
-        let color_index = self.colors_index.fetch_add(1, Ordering::Relaxed);
+        let mut chunk = self.current_chunk.lock();
 
-        let target_bitmap = color_index % self.roaring_bitmaps.len() as ColorIndexType;
+        let color_index = chunk.start_index + chunk.subsets_count;
+        let local_position = chunk.subsets_count * self.colors_count as ColorIndexType;
 
-        loop {
-            let mut bitmap_lock = self.roaring_bitmaps[target_bitmap as usize].lock();
-            if bitmap_lock.try_append(color_index, colors.iter().copied(), &self.writer) {
-                break;
-            }
-            drop(bitmap_lock);
-            std::thread::yield_now();
+        chunk
+            .bitmap
+            .append(colors.iter().map(|c| local_position + c))
+            .expect("Colors must be sorted");
+        chunk.subsets_count += 1;
+
+        if chunk.subsets_count == self.chunk_size {
+            let full_chunk =
+                std::mem::replace(&mut *chunk, RoaringBitmapChunk::new(color_index + 1));
+            drop(chunk);
+            full_chunk.flush(&self.writer);
         }
 
         color_index
     }
 
     fn get_subsets_count(&self) -> u64 {
-        self.colors_index.load(Ordering::Relaxed) as u64
+        let chunk = self.current_chunk.lock();
+        (chunk.start_index + chunk.subsets_count) as u64
     }
 
     fn print_stats(&self) {
         ggcat_logging::info!(
-            "Subsets count: {} witn {} colors",
+            "Total color subsets: {} with {} colors",
             self.get_subsets_count(),
             self.colors_count
         );
     }
 
-    fn finalize(mut self) -> ColorsFlushProcessing {
-        for bitmap in self.roaring_bitmaps {
-            bitmap.lock().flush(&mut self.writer);
+    fn finalize(self) -> ColorsFlushProcessing {
+        let chunk = self.current_chunk.into_inner();
+        // Always write the last chunk, so that the index is never empty
+        if chunk.subsets_count > 0 || chunk.start_index == 0 {
+            chunk.flush(&self.writer);
         }
-
         self.writer
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::RoaringColorsSerializer;
+    use crate::colors_manager::ColorMapReader;
+    use crate::storage::deserializer::ColorsDeserializer;
+    use crate::storage::run_length::RunLengthColorsSerializer;
+    use crate::storage::{write_test_colormap, ColorsCodec, ColorsSerializerTrait};
+    use config::{ColorIndexType, COLORS_SINGLE_BATCH_SIZE};
+    use roaring::RoaringBitmap;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    const COLORS_COUNT: u64 = 50;
+
+    fn temp_colormap(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "ggcat-roaring-{}-{}.colors.dat",
+            name,
+            std::process::id()
+        ))
+    }
+
+    /// Subsets spanning several chunks, with single color, full and sparse subsets
+    fn test_subsets() -> Vec<Vec<ColorIndexType>> {
+        let mut state = 0x9e3779b97f4a7c15u64;
+        (0..COLORS_SINGLE_BATCH_SIZE * 2 + 1234)
+            .map(|index| match index % 5 {
+                0 => vec![(index % COLORS_COUNT) as ColorIndexType],
+                1 => (0..COLORS_COUNT as ColorIndexType).collect(),
+                2 => vec![COLORS_COUNT as ColorIndexType - 1],
+                _ => (0..COLORS_COUNT as ColorIndexType)
+                    .filter(|_| {
+                        state ^= state << 13;
+                        state ^= state >> 7;
+                        state ^= state << 17;
+                        state % 3 == 0
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Writes the subsets in a colormap with the given codec, reading them back in a scattered order
+    fn colormap_round_trip<SI: ColorsSerializerTrait>(codec: ColorsCodec) {
+        let path = temp_colormap(&format!("{:?}", codec));
+        let color_names: Vec<_> = (0..COLORS_COUNT)
+            .map(|color| format!("sample_{}", color))
+            .collect();
+        let subsets = test_subsets();
+
+        write_test_colormap::<SI>(&path, &color_names, &subsets);
+
+        let mut deserializer = ColorsDeserializer::new(&path, true).unwrap();
+        assert_eq!(deserializer.codec(), codec);
+        assert_eq!(deserializer.colors_count(), COLORS_COUNT as usize);
+        assert_eq!(deserializer.colors_subsets_count(), subsets.len() as u64);
+        assert_eq!(deserializer.get_color_name(7, false), "sample_7");
+
+        // Jumps back and forth across the chunks
+        let mut colors = vec![];
+        for index in (0..subsets.len())
+            .step_by(7)
+            .chain((0..subsets.len()).rev().step_by(13))
+        {
+            deserializer
+                .get_color_mappings(index as ColorIndexType, &mut colors)
+                .unwrap();
+            assert_eq!(colors, subsets[index], "subset {}", index);
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn roaring_colormap_round_trip() {
+        colormap_round_trip::<RoaringColorsSerializer>(ColorsCodec::Roaring);
+    }
+
+    #[test]
+    fn run_length_colormap_round_trip() {
+        colormap_round_trip::<RunLengthColorsSerializer>(ColorsCodec::RunLength);
+    }
+
+    #[test]
+    fn chunk_positions_fit_the_bitmap() {
+        assert_eq!(RoaringColorsSerializer::chunk_size(20000, 400), 20000);
+        assert_eq!(RoaringColorsSerializer::chunk_size(20000, 1 << 20), 1 << 12);
+        // The last position of the chunk is the largest bitmap value
+        let colors_count = 1_000_000;
+        let chunk_size = RoaringColorsSerializer::chunk_size(20000, colors_count) as u64;
+        assert!(chunk_size * colors_count - 1 <= ColorIndexType::MAX as u64);
+        assert!((chunk_size + 1) * colors_count - 1 > ColorIndexType::MAX as u64);
+        // A single subset is always stored, even with more colors than the bitmap positions
+        assert_eq!(RoaringColorsSerializer::chunk_size(20000, 1 << 33), 1);
+    }
+
+    #[test]
+    fn colors_chunk_encoding_test() {
+        let colors_count = 400;
+        let subsets: &[&[ColorIndexType]] = &[
+            &[0, 1, 2, 3, 4, 5, 6, 7],
+            &[0],
+            &[399],
+            &[1, 2, 5, 10, 15, 30, 45],
+            &[1, 100, 200, 300, 399],
+        ];
+
+        let mut bitmap = RoaringBitmap::new();
+        for (index, colors) in subsets.iter().enumerate() {
+            let local_position = index as ColorIndexType * colors_count;
+            bitmap
+                .append(colors.iter().map(|c| local_position + c))
+                .unwrap();
+        }
+
+        let mut buffer = Vec::new();
+        bitmap.serialize_into(&mut buffer).unwrap();
+        let chunk = RoaringColorsSerializer::decode_chunk(Cursor::new(buffer)).unwrap();
+
+        let mut des_colors = Vec::new();
+        for (index, colors) in subsets.iter().enumerate() {
+            RoaringColorsSerializer::decode_color(
+                &chunk,
+                colors_count as u64,
+                index as ColorIndexType,
+                &mut des_colors,
+            );
+            assert_eq!(*colors, des_colors.as_slice());
+        }
+    }
+}
@@ -102,14 +102,8 @@ pub struct RunLengthColorsSerializer {
     async_buffer: AsyncSliceQueue<u8, ColorsFlushProcessing>,
 }
 
-thread_local! {
-    static TEMP_COLOR_BUFFER: UnsafeCell<Vec<u8>> = const { UnsafeCell::new(Vec::new()) };
-}
-
-impl ColorsSerializerTrait for RunLengthColorsSerializer {
-    const MAGIC: [u8; 16] = *b"GGCAT_CMAP_RNLEN";
-
-    fn decode_color(mut reader: impl Read, out_vec: Option<&mut Vec<u32>>) {
+impl RunLengthColorsSerializer {
+    pub fn decode_color(mut reader: impl Read, out_vec: Option<&mut Vec<ColorIndexType>>) {
         match out_vec {
             None => {
                 ColorIndexSerializer::deserialize_colors_diffs(&mut reader, |_| {});
@@ -119,6 +113,14 @@ impl ColorsSerializerTrait for RunLengthColorsSerializer {
             }
         }
     }
+}
+
+thread_local! {
+    static TEMP_COLOR_BUFFER: UnsafeCell<Vec<u8>> = const { UnsafeCell::new(Vec::new()) };
+}
+
+impl ColorsSerializerTrait for RunLengthColorsSerializer {
+    const MAGIC: [u8; 16] = *b"GGCAT_CMAP_RNLEN";
 
     fn new(writer: ColorsFlushProcessing, checkpoint_distance: usize, _colors_count: u64) -> Self {
         Self {
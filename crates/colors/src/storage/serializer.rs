@@ -1,4 +1,5 @@
-use crate::storage::ColorsSerializerTrait;
+use crate::metadata::ColorsMetadata;
+use crate::storage::{ColormapBuildParams, ColorsCodec, ColorsSerializerTrait};
 use config::DEFAULT_OUTPUT_BUFFER_SIZE;
 use config::{ColorIndexType, COLORS_SINGLE_BATCH_SIZE};
 use desse::{Desse, DesseSized};
@@ -7,15 +8,17 @@ use io::chunks_writer::ChunksWriter;
 use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use std::fs::File;
-use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::io::{BufWriter, Cursor, Read, Seek, SeekFrom, Write};
 use std::mem::ManuallyDrop;
 use std::ops::{Deref, DerefMut};
 use std::path::Path;
 use std::sync::atomic::{AtomicU64, Ordering};
 
-const STORAGE_VERSION: u64 = 1;
+pub(crate) const STORAGE_VERSION: u64 = 3;
+/// The oldest version that can be read, older colormaps must be upgraded
+pub(crate) const MIN_STORAGE_VERSION: u64 = 2;
 
-#[derive(Debug, Desse, DesseSized, Default)]
+#[derive(Debug, Desse, DesseSized, Default, Clone, Copy)]
 pub(crate) struct ColorsFileHeader {
     pub magic: [u8; 16],
     pub version: u64,
@@ -24,12 +27,147 @@ pub(crate) struct ColorsFileHeader {
     pub subsets_count: u64,
     pub total_size: u64,
     pub total_uncompressed_size: u64,
+    pub k: u64,
+    pub hash_type: [u8; 32],
+    /// Crc32 of the compressed color names and metadata, of the index and of the header itself
+    pub color_names_checksum: u32,
+    pub index_checksum: u32,
+    pub header_checksum: u32,
+}
+
+impl ColorsFileHeader {
+    pub(crate) fn compute_checksum(&self) -> u32 {
+        crc32fast::hash(
+            &Self {
+                header_checksum: 0,
+                ..*self
+            }
+            .serialize(),
+        )
+    }
+
+    pub(crate) fn build_params(&self) -> ColormapBuildParams {
+        ColormapBuildParams {
+            k: self.k,
+            hash_type: String::from_utf8_lossy(&self.hash_type)
+                .trim_end_matches('\0')
+                .to_string(),
+        }
+    }
+
+    /// Checks that the colormap format can be read, suggesting how to convert older colormaps
+    pub(crate) fn check_version(&self, path: &Path) -> anyhow::Result<()> {
+        if self.version < MIN_STORAGE_VERSION {
+            anyhow::bail!(
+                "Colormap {} has the format version {} without integrity checksums, convert it to version {} with `ggcat colormap upgrade {}`",
+                path.display(),
+                self.version,
+                STORAGE_VERSION,
+                path.display()
+            );
+        }
+        if self.version > STORAGE_VERSION {
+            anyhow::bail!(
+                "Colormap {} has the format version {}, that is supported only by newer ggcat releases (this one reads up to version {})",
+                path.display(),
+                self.version,
+                STORAGE_VERSION
+            );
+        }
+        Ok(())
+    }
+
+    /// Reads the header of a colormap, checking its version and that the file is complete
+    pub(crate) fn read(file: &mut File, path: &Path) -> anyhow::Result<Self> {
+        let mut header_buffer = [0; Self::SIZE];
+        file.read_exact(&mut header_buffer)
+            .log_unrecoverable_error_with_data("Cannot read header", path.display())?;
+        let header = Self::deserialize_from(&header_buffer);
+
+        if ColorsCodec::from_magic(&header.magic).is_none() {
+            anyhow::bail!(
+                "Colormap {} has an unknown colors codec magic {:?}",
+                path.display(),
+                header.magic
+            );
+        }
+        header.check_version(path)?;
+        if header.compute_checksum() != header.header_checksum {
+            anyhow::bail!(
+                "Colormap {} is corrupted, the header checksum does not match",
+                path.display()
+            );
+        }
+        let file_size = file.metadata()?.len();
+        if file_size != header.total_size {
+            anyhow::bail!(
+                "Colormap {} is truncated or corrupted, its size is {} instead of {}",
+                path.display(),
+                file_size,
+                header.total_size
+            );
+        }
+        Ok(header)
+    }
+
+    /// Reads the compressed color names and metadata, checking their checksum.
+    /// The metadata is empty for the colormaps written before it was supported
+    pub(crate) fn read_colors(
+        &self,
+        file: &mut File,
+        index: &ColorsIndexMap,
+        path: &Path,
+    ) -> anyhow::Result<(Vec<String>, ColorsMetadata)> {
+        let end_offset = index
+            .pairs
+            .iter()
+            .map(|p| p.file_offset)
+            .min()
+            .unwrap_or(self.index_offset);
+        let mut compressed = vec![0; end_offset.saturating_sub(Self::SIZE as u64) as usize];
+        file.seek(SeekFrom::Start(Self::SIZE as u64))?;
+        file.read_exact(&mut compressed)
+            .log_unrecoverable_error_with_data("Cannot read color names", path.display())?;
+        if crc32fast::hash(&compressed) != self.color_names_checksum {
+            anyhow::bail!(
+                "Colormap {} is corrupted, the color names checksum does not match",
+                path.display()
+            );
+        }
+        let mut compressed_stream = lz4::Decoder::new(Cursor::new(compressed))?;
+        let color_names = bincode::deserialize_from(&mut compressed_stream)
+            .log_unrecoverable_error_with_data("Cannot deserialize color names", path.display())?;
+        let colors_metadata = if self.version >= 3 {
+            bincode::deserialize_from(&mut compressed_stream).log_unrecoverable_error_with_data(
+                "Cannot deserialize colors metadata",
+                path.display(),
+            )?
+        } else {
+            ColorsMetadata::default()
+        };
+        Ok((color_names, colors_metadata))
+    }
+
+    /// Compresses the color names and metadata, as stored after the header
+    pub(crate) fn encode_colors(
+        color_names: &[String],
+        colors_metadata: &ColorsMetadata,
+    ) -> anyhow::Result<Vec<u8>> {
+        let mut colors_stream = lz4::EncoderBuilder::new().level(4).build(vec![])?;
+        bincode::serialize_into(&mut colors_stream, color_names)?;
+        bincode::serialize_into(&mut colors_stream, colors_metadata)?;
+        let (compressed, res) = colors_stream.finish();
+        res?;
+        Ok(compressed)
+    }
 }
 
 #[derive(Clone, Copy, Serialize, Deserialize, Ord, PartialOrd, Eq, PartialEq)]
 pub struct ColorsIndexEntry {
     pub start_index: ColorIndexType,
     pub file_offset: u64,
+    /// Crc32 of the compressed chunk
+    pub checksum: u32,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -38,13 +176,66 @@ pub(crate) struct ColorsIndexMap {
     pub subsets_count: u64,
 }
 
+impl ColorsIndexMap {
+    /// Reads the index of a colormap, checking its checksum
+    pub(crate) fn read(
+        file: &mut File,
+        header: &ColorsFileHeader,
+        path: &Path,
+    ) -> anyhow::Result<Self> {
+        let mut data = vec![0; header.total_size.saturating_sub(header.index_offset) as usize];
+        file.seek(SeekFrom::Start(header.index_offset))
+            .log_unrecoverable_error_with_data("Cannot seek color map", path.display())?;
+        file.read_exact(&mut data)
+            .log_unrecoverable_error_with_data("Cannot read color index", path.display())?;
+        if crc32fast::hash(&data) != header.index_checksum {
+            anyhow::bail!(
+                "Colormap {} is corrupted, the index checksum does not match",
+                path.display()
+            );
+        }
+        Ok(bincode::deserialize(&data)
+            .log_unrecoverable_error_with_data("Cannot deserialize color index", path.display())?)
+    }
+
+    /// Writes the index, returning its checksum
+    pub(crate) fn write(&self, writer: &mut impl Write) -> std::io::Result<u32> {
+        let data = bincode::serialize(self).unwrap();
+        writer.write_all(&data)?;
+        Ok(crc32fast::hash(&data))
+    }
+
+    /// Returns the end offset of each chunk, as the start of the following chunk in the file or of the index
+    pub(crate) fn chunks_end_offsets(&self, index_offset: u64) -> Vec<u64> {
+        let mut offsets: Vec<_> = self.pairs.iter().map(|p| p.file_offset).collect();
+        offsets.sort_unstable();
+
+        self.pairs
+            .iter()
+            .map(|pair| {
+                offsets
+                    .get(offsets.partition_point(|o| *o <= pair.file_offset))
+                    .copied()
+                    .unwrap_or(index_offset)
+            })
+            .collect()
+    }
+}
+
 pub struct ColorsSerializer<SI: ColorsSerializerTrait> {
     colors_count: u64,
+    build_params: ColormapBuildParams,
+    color_names_checksum: u32,
     serializer_impl: ManuallyDrop<SI>,
 }
 
 impl<SI: ColorsSerializerTrait> ColorsSerializer<SI> {
-    pub fn new(file: impl AsRef<Path>, color_names: &[String]) -> anyhow::Result<Self> {
+    pub fn new(
+        file: impl AsRef<Path>,
+        color_names: &[String],
+        colors_metadata: &ColorsMetadata,
+        build_params: &ColormapBuildParams,
+    ) -> anyhow::Result<Self> {
         let mut colormap_file = File::create(file.as_ref()).log_unrecoverable_error_with_data(
             "Cannot create colormap file",
             file.as_ref().display(),
@@ -57,23 +248,19 @@ impl<SI: ColorsSerializerTrait> ColorsSerializer<SI> {
                 file.as_ref().display(),
             )?;
 
-        colormap_file = {
-            let mut color_names_stream = lz4::EncoderBuilder::new()
-                .level(4)
-                .build(colormap_file)
-                .unwrap();
-            bincode::serialize_into(&mut color_names_stream, color_names)
+        let color_names_checksum = {
+            let compressed = ColorsFileHeader::encode_colors(color_names, colors_metadata)
                 .log_unrecoverable_error_with_data(
                     "Cannot serialize color names",
                     file.as_ref().display(),
                 )?;
-
-            let (cf, res) = color_names_stream.finish();
-            res.log_unrecoverable_error_with_data(
-                "Cannot finish color names stream",
-                file.as_ref().display(),
-            )?;
-            cf
+            colormap_file
+                .write_all(&compressed)
+                .log_unrecoverable_error_with_data(
+                    "Cannot write color names",
+                    file.as_ref().display(),
+                )?;
+            crc32fast::hash(&compressed)
         };
 
         let file_offset = colormap_file
@@ -99,6 +286,8 @@ impl<SI: ColorsSerializerTrait> ColorsSerializer<SI> {
 
         Ok(Self {
             colors_count,
+            build_params: build_params.clone(),
+            color_names_checksum,
             serializer_impl: ManuallyDrop::new(SI::new(
                 color_processor,
                 COLORS_SINGLE_BATCH_SIZE as usize,
@@ -117,10 +306,6 @@ impl<SI: ColorsSerializerTrait> ColorsSerializer<SI> {
     }
 }
 
-fn bincode_serialize_ref<S: Write, D: Serialize>(ser: &mut S, data: &D) {
-    bincode::serialize_into(ser, data).unwrap();
-}
-
 impl<SI: ColorsSerializerTrait> Drop for ColorsSerializer<SI> {
     fn drop(&mut self) {
         let subsets_count = self.serializer_impl.get_subsets_count();
@@ -137,28 +322,34 @@ impl<SI: ColorsSerializerTrait> Drop for ColorsSerializer<SI> {
 
         let index_position = colors_file.stream_position().unwrap();
 
-        bincode_serialize_ref(colors_file, index_map);
+        let index_checksum = index_map.write(colors_file).unwrap();
         colors_file.flush().unwrap();
 
         let total_size = colors_file.stream_position().unwrap();
         colors_file.seek(SeekFrom::Start(0)).unwrap();
 
-        colors_file
-            .write_all(
-                &ColorsFileHeader {
-                    magic: SI::MAGIC,
-                    version: STORAGE_VERSION,
-                    index_offset: index_position,
-                    colors_count: self.colors_count,
-                    subsets_count,
-                    total_size,
-                    total_uncompressed_size: chunks_writer
-                        .uncompressed_size
-                        .load(Ordering::Relaxed),
-                }
-                .serialize()[..],
-            )
-            .unwrap();
+        let mut hash_type = [0; 32];
+        let hash_type_len = self.build_params.hash_type.len().min(hash_type.len());
+        hash_type[..hash_type_len]
+            .copy_from_slice(&self.build_params.hash_type.as_bytes()[..hash_type_len]);
+
+        let mut header = ColorsFileHeader {
+            magic: SI::MAGIC,
+            version: STORAGE_VERSION,
+            index_offset: index_position,
+            colors_count: self.colors_count,
+            subsets_count,
+            total_size,
+            total_uncompressed_size: chunks_writer.uncompressed_size.load(Ordering::Relaxed),
+            k: self.build_params.k,
+            hash_type,
+            color_names_checksum: self.color_names_checksum,
+            index_checksum,
+            header_checksum: 0,
+        };
+        header.header_checksum = header.compute_checksum();
+
+        colors_file.write_all(&header.serialize()[..]).unwrap();
 
         colors_file.flush().unwrap();
     }
@@ -226,6 +417,7 @@ impl ChunksWriter for ColorsFlushProcessing {
         file_lock.1.pairs.push(ColorsIndexEntry {
             start_index,
             file_offset,
+            checksum: crc32fast::hash(&data),
         });
     }
 }
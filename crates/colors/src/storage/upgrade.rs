@@ -0,0 +1,276 @@
+use crate::metadata::ColorsMetadata;
+use crate::storage::serializer::{
+    ColorsFileHeader, ColorsIndexEntry, ColorsIndexMap, MIN_STORAGE_VERSION, STORAGE_VERSION,
+};
+use crate::storage::ColorsCodec;
+use config::ColorIndexType;
+use desse::{Desse, DesseSized};
+use ggcat_logging::UnrecoverableErrorLogging;
+use serde::Deserialize;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Header of the colormaps with the format version 1, without checksums and build parameters
+#[derive(Desse, DesseSized)]
+struct ColorsFileHeaderV1 {
+    magic: [u8; 16],
+    version: u64,
+    index_offset: u64,
+    colors_count: u64,
+    subsets_count: u64,
+    total_size: u64,
+    total_uncompressed_size: u64,
+}
+
+#[derive(Deserialize)]
+struct ColorsIndexEntryV1 {
+    start_index: ColorIndexType,
+    file_offset: u64,
+}
+
+#[derive(Deserialize)]
+struct ColorsIndexMapV1 {
+    pairs: Vec<ColorsIndexEntryV1>,
+    subsets_count: u64,
+}
+
+/// Converts a colormap to the current format version, adding the integrity checksums.
+/// The build parameters are not known for older colormaps, so they are left empty.
+/// Returns false if the colormap can already be read without upgrading it
+pub fn upgrade_colormap(path: impl AsRef<Path>) -> anyhow::Result<bool> {
+    let path = path.as_ref();
+    let mut file = File::open(path)
+        .log_unrecoverable_error_with_data("Cannot open colors file", path.display())?;
+
+    let mut header_buffer = [0; ColorsFileHeaderV1::SIZE];
+    file.read_exact(&mut header_buffer)
+        .log_unrecoverable_error_with_data("Cannot read header", path.display())?;
+    let header = ColorsFileHeaderV1::deserialize_from(&header_buffer);
+
+    if ColorsCodec::from_magic(&header.magic).is_none() {
+        anyhow::bail!(
+            "Colormap {} has an unknown colors codec magic {:?}",
+            path.display(),
+            header.magic
+        );
+    }
+    if header.version >= MIN_STORAGE_VERSION {
+        return Ok(false);
+    }
+    if header.version != 1 {
+        anyhow::bail!(
+            "Cannot upgrade colormap {} from the format version {}",
+            path.display(),
+            header.version
+        );
+    }
+    if file.metadata()?.len() != header.total_size {
+        anyhow::bail!(
+            "Colormap {} is truncated, it cannot be upgraded",
+            path.display()
+        );
+    }
+
+    file.seek(SeekFrom::Start(header.index_offset))?;
+    let index: ColorsIndexMapV1 = bincode::deserialize_from(&mut file)
+        .log_unrecoverable_error_with_data("Cannot deserialize color index", path.display())?;
+
+    // Chunks are stored contiguously between the color names and the index
+    let mut chunks: Vec<_> = index
+        .pairs
+        .iter()
+        .map(|p| (p.file_offset, p.start_index))
+        .collect();
+    chunks.sort_unstable();
+    let chunks_start = chunks
+        .first()
+        .map(|(offset, _)| *offset)
+        .unwrap_or(header.index_offset);
+
+    let mut temp_file = path.as_os_str().to_owned();
+    temp_file.push(".tmp");
+    let mut writer = BufWriter::new(
+        File::create(&temp_file)
+            .log_unrecoverable_error_with_data("Cannot create colormap file", path.display())?,
+    );
+    writer.write_all(&ColorsFileHeader::default().serialize())?;
+
+    // The color names are encoded again, followed by the (empty) colors metadata
+    file.seek(SeekFrom::Start(ColorsFileHeaderV1::SIZE as u64))?;
+    let color_names: Vec<String> = bincode::deserialize_from(lz4::Decoder::new(&mut file)?)
+        .log_unrecoverable_error_with_data("Cannot deserialize color names", path.display())?;
+    let colors = ColorsFileHeader::encode_colors(&color_names, &ColorsMetadata::default())?;
+    writer.write_all(&colors)?;
+
+    // Offset to add to the positions of the chunks after the new header and colors
+    let offsets_shift = (ColorsFileHeader::SIZE + colors.len()) as i64 - chunks_start as i64;
+
+    let mut new_index = ColorsIndexMap {
+        pairs: Vec::with_capacity(chunks.len()),
+        subsets_count: index.subsets_count,
+    };
+    let mut chunk = vec![];
+    for (i, &(file_offset, start_index)) in chunks.iter().enumerate() {
+        let end_offset = chunks
+            .get(i + 1)
+            .map(|(offset, _)| *offset)
+            .unwrap_or(header.index_offset);
+        chunk.resize(end_offset.saturating_sub(file_offset) as usize, 0);
+        file.seek(SeekFrom::Start(file_offset))?;
+        file.read_exact(&mut chunk)?;
+        writer.write_all(&chunk)?;
+
+        new_index.pairs.push(ColorsIndexEntry {
+            start_index,
+            file_offset: (file_offset as i64 + offsets_shift) as u64,
+            checksum: crc32fast::hash(&chunk),
+        });
+    }
+    new_index.pairs.sort();
+
+    let index_offset = writer.stream_position()?;
+    let index_checksum = new_index.write(&mut writer)?;
+    let total_size = writer.stream_position()?;
+
+    let mut new_header = ColorsFileHeader {
+        magic: header.magic,
+        version: STORAGE_VERSION,
+        index_offset,
+        colors_count: header.colors_count,
+        subsets_count: header.subsets_count,
+        total_size,
+        total_uncompressed_size: header.total_uncompressed_size,
+        k: 0,
+        hash_type: [0; 32],
+        color_names_checksum: crc32fast::hash(&colors),
+        index_checksum,
+        header_checksum: 0,
+    };
+    new_header.header_checksum = new_header.compute_checksum();
+
+    writer.seek(SeekFrom::Start(0))?;
+    writer.write_all(&new_header.serialize())?;
+    writer.flush()?;
+    drop(writer);
+
+    std::fs::rename(&temp_file, path)
+        .log_unrecoverable_error_with_data("Cannot replace colormap file", path.display())?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colors_manager::ColorMapReader;
+    use crate::storage::deserializer::ColorsDeserializer;
+    use crate::storage::run_length::RunLengthColorsSerializer;
+    use crate::storage::write_test_colormap;
+    use std::path::PathBuf;
+
+    fn temp_colormap(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "ggcat-upgrade-{}-{}.colors.dat",
+            name,
+            std::process::id()
+        ))
+    }
+
+    fn color_names() -> Vec<String> {
+        (0..12).map(|color| format!("sample_{}", color)).collect()
+    }
+
+    fn test_subsets() -> Vec<Vec<ColorIndexType>> {
+        (0..200)
+            .map(|subset| (0..12).filter(|color| (subset * color) % 7 < 3).collect())
+            .collect()
+    }
+
+    /// Writes a colormap with the format version 1, by rewriting a current one without the checksums
+    fn write_v1_colormap(path: &Path) {
+        write_test_colormap::<RunLengthColorsSerializer>(path, &color_names(), &test_subsets());
+        let data = std::fs::read(path).unwrap();
+        let mut file = File::open(path).unwrap();
+        let header = ColorsFileHeader::read(&mut file, path).unwrap();
+        let index = ColorsIndexMap::read(&mut file, &header, path).unwrap();
+        let chunks_start = index.pairs.iter().map(|p| p.file_offset).min().unwrap();
+
+        let mut names_stream = lz4::EncoderBuilder::new().build(vec![]).unwrap();
+        bincode::serialize_into(&mut names_stream, &color_names()).unwrap();
+        let (names, res) = names_stream.finish();
+        res.unwrap();
+
+        let offsets_shift = (ColorsFileHeaderV1::SIZE + names.len()) as i64 - chunks_start as i64;
+        let chunks = &data[chunks_start as usize..header.index_offset as usize];
+        let index_v1 = bincode::serialize(&(
+            index
+                .pairs
+                .iter()
+                .map(|p| (p.start_index, (p.file_offset as i64 + offsets_shift) as u64))
+                .collect::<Vec<_>>(),
+            index.subsets_count,
+        ))
+        .unwrap();
+
+        let index_offset = (ColorsFileHeaderV1::SIZE + names.len() + chunks.len()) as u64;
+        let mut v1 = ColorsFileHeaderV1 {
+            magic: header.magic,
+            version: 1,
+            index_offset,
+            colors_count: header.colors_count,
+            subsets_count: header.subsets_count,
+            total_size: index_offset + index_v1.len() as u64,
+            total_uncompressed_size: header.total_uncompressed_size,
+        }
+        .serialize()
+        .to_vec();
+        v1.extend_from_slice(&names);
+        v1.extend_from_slice(chunks);
+        v1.extend_from_slice(&index_v1);
+        std::fs::write(path, &v1).unwrap();
+    }
+
+    #[test]
+    fn v1_colormap_is_upgraded() {
+        let path = temp_colormap("v1");
+        write_v1_colormap(&path);
+        let err = ColorsDeserializer::new(&path, true).err().unwrap();
+        assert!(err.to_string().contains("colormap upgrade"), "{}", err);
+
+        assert!(upgrade_colormap(&path).unwrap());
+        let mut deserializer = ColorsDeserializer::new(&path, true).unwrap();
+        assert_eq!(deserializer.colors_count(), 12);
+        assert_eq!(deserializer.get_color_name(3, false), "sample_3");
+        assert_eq!(deserializer.build_params().k, 0);
+        assert!(deserializer.colors_metadata().is_empty());
+
+        let mut colors = vec![];
+        for (subset, expected) in test_subsets().iter().enumerate() {
+            deserializer
+                .get_color_mappings(subset as ColorIndexType, &mut colors)
+                .unwrap();
+            assert_eq!(&colors, expected);
+            colors.clear();
+        }
+
+        // Already in the current format
+        assert!(!upgrade_colormap(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn truncated_v1_colormap_is_not_upgraded() {
+        let path = temp_colormap("v1-truncated");
+        write_v1_colormap(&path);
+        let data = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &data[..data.len() - 3]).unwrap();
+
+        assert!(upgrade_colormap(&path).is_err());
+        // The original file is left untouched
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().len(),
+            data.len() as u64 - 3
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,160 @@
+use config::ColorIndexType;
+
+/// Predicate on the colors of a subset, used to dump only the unitigs with matching colors.
+/// All the conditions must be satisfied, the empty ones are ignored
+#[derive(Clone, Debug, Default)]
+pub struct ColorsPredicate {
+    /// Colors that must all be present
+    pub all_of: Vec<ColorIndexType>,
+    /// Colors of which at least one must be present
+    pub any_of: Vec<ColorIndexType>,
+    /// Colors that must all be absent
+    pub none_of: Vec<ColorIndexType>,
+    /// Minimum number of colors of `at_least_of` that must be present
+    pub at_least: usize,
+    /// Colors counted by `at_least`, all the colors if empty
+    pub at_least_of: Vec<ColorIndexType>,
+}
+
+impl ColorsPredicate {
+    /// Checks the predicate on a sorted list of colors
+    pub fn matches(&self, colors: &[ColorIndexType]) -> bool {
+        let is_present = |color: &ColorIndexType| colors.binary_search(color).is_ok();
+
+        self.all_of.iter().all(is_present)
+            && (self.any_of.is_empty() || self.any_of.iter().any(is_present))
+            && !self.none_of.iter().any(is_present)
+            && (self.at_least == 0
+                || if self.at_least_of.is_empty() {
+                    colors.len() >= self.at_least
+                } else {
+                    self.at_least_of.iter().filter(|c| is_present(c)).count() >= self.at_least
+                })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::edit_colors::{lock_test_memory_fs, write_test_graph};
+    use parking_lot::Mutex;
+
+    #[test]
+    fn predicate_matches() {
+        let colors = [1, 3, 4, 8];
+
+        assert!(ColorsPredicate::default().matches(&colors));
+        assert!(ColorsPredicate::default().matches(&[]));
+
+        let all_of = |all_of: Vec<_>| ColorsPredicate {
+            all_of,
+            ..Default::default()
+        };
+        assert!(all_of(vec![1, 8]).matches(&colors));
+        assert!(!all_of(vec![1, 2]).matches(&colors));
+
+        let any_of = |any_of: Vec<_>| ColorsPredicate {
+            any_of,
+            ..Default::default()
+        };
+        assert!(any_of(vec![0, 4]).matches(&colors));
+        assert!(!any_of(vec![0, 2]).matches(&colors));
+
+        let none_of = |none_of: Vec<_>| ColorsPredicate {
+            none_of,
+            ..Default::default()
+        };
+        assert!(none_of(vec![0, 2]).matches(&colors));
+        assert!(!none_of(vec![2, 3]).matches(&colors));
+    }
+
+    #[test]
+    fn at_least_matches() {
+        let colors = [1, 3, 4, 8];
+        let at_least = |at_least, at_least_of: Vec<_>| ColorsPredicate {
+            at_least,
+            at_least_of,
+            ..Default::default()
+        };
+
+        // Counting all the colors
+        assert!(at_least(4, vec![]).matches(&colors));
+        assert!(!at_least(5, vec![]).matches(&colors));
+
+        assert!(at_least(2, vec![0, 3, 8]).matches(&colors));
+        assert!(!at_least(3, vec![0, 3, 8]).matches(&colors));
+    }
+
+    #[test]
+    fn conditions_are_combined() {
+        let predicate = ColorsPredicate {
+            all_of: vec![1],
+            any_of: vec![3, 5],
+            none_of: vec![7],
+            at_least: 3,
+            at_least_of: vec![],
+        };
+        assert!(predicate.matches(&[1, 3, 4]));
+        assert!(predicate.matches(&[0, 1, 5]));
+        // Failing a single condition
+        assert!(!predicate.matches(&[3, 4, 5]));
+        assert!(!predicate.matches(&[1, 2, 4]));
+        assert!(!predicate.matches(&[1, 3, 7]));
+        assert!(!predicate.matches(&[1, 3]));
+    }
+
+    #[test]
+    fn dump_matching_unitigs() {
+        let temp_dir =
+            std::env::temp_dir().join(format!("ggcat-colors-predicate-{}", std::process::id()));
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        let _memory_fs = lock_test_memory_fs();
+
+        let path = temp_dir.join("graph.fa");
+        write_test_graph(
+            &path,
+            5,
+            &["A", "B", "C"],
+            &[
+                ("ACGTTGCAT", &[(&["A"], 2), (&["A", "B"], 3)]),
+                ("GGATCCTA", &[(&["C"], 1), (&["B", "C"], 3)]),
+                ("TTGACCAGT", &[(&["A", "C"], 5)]),
+            ],
+        );
+
+        let dumped = Mutex::new(vec![]);
+        crate::dump_unitigs(
+            5,
+            3,
+            &path,
+            Some(temp_dir.clone()),
+            Some(1),
+            2,
+            true,
+            None,
+            Some(&ColorsPredicate {
+                all_of: vec![1],
+                ..Default::default()
+            }),
+            |sequence, colors, _| {
+                dumped.lock().push((
+                    String::from_utf8(sequence.to_vec()).unwrap(),
+                    colors.to_vec(),
+                ))
+            },
+        )
+        .unwrap();
+        let _ = std::fs::remove_dir_all(&temp_dir);
+
+        // Only the runs of the subsets with the color B, extended with the kmers overlaps
+        let mut dumped = dumped.into_inner();
+        dumped.sort();
+        assert_eq!(
+            dumped,
+            [
+                ("GATCCTA".to_string(), vec![1, 2]),
+                ("GTTGCAT".to_string(), vec![0, 1]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,983 @@
+use crate::dump_indexed_unitigs;
+use crate::graph_stats::{graph_buckets_count, pack_overlap};
+use colors::colors_manager::ColorMapReader;
+use colors::colors_memmap_writer::ColorsMemMapWriter;
+use colors::storage::deserializer::ColorsDeserializer;
+use config::ColorIndexType;
+use ggcat_logging::UnrecoverableErrorLogging;
+use hashbrown::{HashMap, HashSet};
+use nightly_quirks::slice_group_by::SliceGroupBy;
+use parking_lot::Mutex;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::mem::size_of;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use structs::unitigs_graph::{compact_unitigs_chains, UnitigSide, UnitigsGraph, BEGIN, END};
+
+/// Changes applied to the colors of an existing graph by `edit_colors`
+#[derive(Clone, Debug, Default)]
+pub struct ColorsEdit {
+    /// New names of the colors, as (current name, new name) pairs
+    pub rename: Vec<(String, String)>,
+    /// Colors removed from the graph
+    pub drop: Vec<String>,
+    /// Colors kept in the graph, all of them if empty
+    pub keep: Vec<String>,
+}
+
+impl ColorsEdit {
+    /// Reads the new names of the colors from a TSV file with a <CURRENT_NAME><TAB><NEW_NAME> line for each color
+    pub fn read_renames(path: impl AsRef<Path>) -> anyhow::Result<Vec<(String, String)>> {
+        let path = path.as_ref();
+        std::fs::read_to_string(path)
+            .log_unrecoverable_error_with_data("Cannot read colors renames", path.display())?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| match line.rsplit_once('\t') {
+                Some((name, new_name)) => Ok((name.to_string(), new_name.to_string())),
+                None => Err(anyhow::anyhow!(
+                    "Invalid line in colors renames {}: {}",
+                    path.display(),
+                    line
+                )),
+            })
+            .collect()
+    }
+}
+
+/// Summary of the changes made by `edit_colors`
+#[derive(Clone, Debug, Default)]
+pub struct ColorsEditStats {
+    pub colors_count: u64,
+    pub removed_colors: u64,
+    pub subsets_count: u64,
+    /// Kmers removed from the graph as they were left without colors
+    pub removed_kmers: u64,
+    pub unitigs_count: u64,
+    /// Unitigs joined with others after removing the kmers without colors
+    pub merged_unitigs: u64,
+}
+
+/// Subset of the kmers left without colors
+const NO_COLORS: ColorIndexType = ColorIndexType::MAX;
+
+/// A part of a graph unitig left after removing the kmers without colors,
+/// joinable with other pieces only at the unitig ends where some kmers were removed
+struct Piece {
+    bases: Range<usize>,
+    /// The (subset, kmers count) runs of the piece, in the sequence order
+    runs: Range<usize>,
+    /// Whether the BEGIN and the END sides can be joined with other pieces
+    joinable: [bool; 2],
+}
+
+/// The pieces of the unitigs that can be recompacted, with an end on a (k-1)-mer that lost some of its links
+struct EditedGraph {
+    k: usize,
+    forward_only: bool,
+    bases: Vec<u8>,
+    runs: Vec<(ColorIndexType, u64)>,
+    pieces: Vec<Piece>,
+    /// Overlaps entering a piece, in both orientations if not forward only
+    entering: HashMap<Box<[u8]>, Vec<UnitigSide>>,
+    /// Overlaps exiting a piece, used only if forward only
+    exiting: HashMap<Box<[u8]>, Vec<UnitigSide>>,
+}
+
+impl EditedGraph {
+    fn piece_bases(&self, piece: usize) -> &[u8] {
+        &self.bases[self.pieces[piece].bases.clone()]
+    }
+
+    fn add_piece(&mut self, bases: &[u8], runs: &[(ColorIndexType, u64)], joinable: [bool; 2]) {
+        let bases_start = self.bases.len();
+        self.bases.extend_from_slice(bases);
+        let runs_start = self.runs.len();
+        self.runs.extend_from_slice(runs);
+        self.pieces.push(Piece {
+            bases: bases_start..self.bases.len(),
+            runs: runs_start..self.runs.len(),
+            joinable,
+        });
+    }
+
+    fn index_overlaps(&mut self) {
+        for piece in 0..self.pieces.len() {
+            let bases = &self.bases[self.pieces[piece].bases.clone()];
+            let prefix = &bases[..self.k - 1];
+            let suffix = &bases[bases.len() - self.k + 1..];
+            let joinable = self.pieces[piece].joinable;
+
+            if joinable[BEGIN] {
+                self.entering
+                    .entry(pack_overlap(prefix, false))
+                    .or_default()
+                    .push((piece, BEGIN));
+            }
+            if !joinable[END] {
+                continue;
+            }
+            if self.forward_only {
+                self.exiting
+                    .entry(pack_overlap(suffix, false))
+                    .or_default()
+                    .push((piece, END));
+            } else {
+                self.entering
+                    .entry(pack_overlap(suffix, true))
+                    .or_default()
+                    .push((piece, END));
+            }
+        }
+    }
+}
+
+impl UnitigsGraph for EditedGraph {
+    fn unitigs_count(&self) -> usize {
+        self.pieces.len()
+    }
+
+    fn is_removed(&self, _piece: usize) -> bool {
+        // The kmers without colors are not part of any piece
+        false
+    }
+
+    fn links(&self, piece: usize, side: usize) -> Vec<UnitigSide> {
+        // The links of the other sides did not change, and the unitigs were already maximal there
+        if !self.pieces[piece].joinable[side] {
+            return vec![];
+        }
+
+        let bases = self.piece_bases(piece);
+        let links = if side == END {
+            self.entering
+                .get(&pack_overlap(&bases[bases.len() - self.k + 1..], false))
+        } else if self.forward_only {
+            self.exiting.get(&pack_overlap(&bases[..self.k - 1], false))
+        } else {
+            self.entering.get(&pack_overlap(&bases[..self.k - 1], true))
+        };
+
+        let mut result = links.cloned().unwrap_or_default();
+        result.sort_unstable();
+        result.dedup();
+        result
+    }
+
+    fn is_self_complemental(&self, piece: usize) -> bool {
+        let bases = self.piece_bases(piece);
+        !self.forward_only && bases.iter().copied().eq(reverse_complement(bases))
+    }
+}
+
+fn reverse_complement(bases: &[u8]) -> impl Iterator<Item = u8> + '_ {
+    bases.iter().rev().map(|base| match base {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        base => *base,
+    })
+}
+
+/// Adds a run of kmers with the same subset, joining it with the last one if they have the same subset
+fn push_run(runs: &mut Vec<(ColorIndexType, u64)>, subset: ColorIndexType, count: u64) {
+    match runs.last_mut() {
+        Some((last_subset, last_count)) if *last_subset == subset => *last_count += count,
+        _ => runs.push((subset, count)),
+    }
+}
+
+/// Writes a unitig with its color runs, in the format of the colored graphs
+fn write_unitig(
+    output: &mut impl Write,
+    index: u64,
+    sequence: &[u8],
+    runs: &[(ColorIndexType, u64)],
+) -> std::io::Result<()> {
+    write!(output, ">{} LN:i:{}", index, sequence.len())?;
+    for (subset, count) in runs {
+        write!(output, " C:{:x}:{}", subset, count)?;
+    }
+    output.write_all(b"\n")?;
+    output.write_all(sequence)?;
+    output.write_all(b"\n")
+}
+
+/// Parses a `C:<subset>:<kmers_count>` tag of a colored graph, with the subset in hexadecimal
+pub(crate) fn parse_color_run(tag: &[u8]) -> Option<(ColorIndexType, u64)> {
+    let (subset, count) = std::str::from_utf8(tag.strip_prefix(b"C:")?)
+        .ok()?
+        .split_once(':')?;
+    Some((
+        ColorIndexType::from_str_radix(subset, 16).ok()?,
+        count.parse().ok()?,
+    ))
+}
+
+/// Initializes the memory fs once for the tests running the colored dump, and runs them one at a time
+#[cfg(test)]
+pub(crate) fn lock_test_memory_fs() -> parking_lot::MutexGuard<'static, ()> {
+    use parallel_processor::memory_data_size::MemoryDataSize;
+    use parallel_processor::memory_fs::MemoryFs;
+
+    static DUMP_TESTS_LOCK: Mutex<()> = parking_lot::const_mutex(());
+    static MEMORY_FS_INIT: std::sync::Once = std::sync::Once::new();
+    MEMORY_FS_INIT.call_once(|| MemoryFs::init(MemoryDataSize::from_mebioctets(64), 16, 1, 1024));
+    DUMP_TESTS_LOCK.lock()
+}
+
+/// Writes a colored graph and its colormap, with the color names of each run of kmers of the unitigs
+#[cfg(test)]
+pub(crate) fn write_test_graph(
+    path: &Path,
+    k: usize,
+    color_names: &[&str],
+    unitigs: &[(&str, &[(&[&str], u64)])],
+) {
+    let color_names: Vec<_> = color_names.iter().map(|c| c.to_string()).collect();
+    let colors_writer = ColorsMemMapWriter::new(
+        path.with_extension("colors.dat"),
+        &color_names,
+        &Default::default(),
+        colors::storage::ColorsCodec::RunLength,
+        &colors::storage::ColormapBuildParams {
+            k: k as u64,
+            hash_type: "test".to_string(),
+        },
+    )
+    .unwrap();
+
+    let mut graph = vec![];
+    for (index, (sequence, runs)) in unitigs.iter().enumerate() {
+        let runs: Vec<_> = runs
+            .iter()
+            .map(|(colors, count)| {
+                let colors: Vec<_> = colors
+                    .iter()
+                    .map(|c| color_names.iter().position(|n| n == c).unwrap() as ColorIndexType)
+                    .collect();
+                (colors_writer.get_id(&colors), *count)
+            })
+            .collect();
+        write_unitig(&mut graph, index as u64, sequence.as_bytes(), &runs).unwrap();
+    }
+    drop(colors_writer);
+    std::fs::write(path, graph).unwrap();
+}
+
+/// A sequence of a unitig split at the color changes by the colored dump, with its edited color subset.
+/// The bases are kept only if the sequence has some colors left
+struct SequenceRecord<'a> {
+    unitig_index: u64,
+    kmer_offset: u64,
+    kmers_count: u64,
+    subset: ColorIndexType,
+    is_last: bool,
+    bases: &'a [u8],
+}
+
+impl<'a> SequenceRecord<'a> {
+    const HEADER_SIZE: usize = 3 * size_of::<u64>() + size_of::<ColorIndexType>() + 1;
+
+    fn write(&self, output: &mut impl Write) -> std::io::Result<()> {
+        output.write_all(&self.unitig_index.to_le_bytes())?;
+        output.write_all(&self.kmer_offset.to_le_bytes())?;
+        output.write_all(&self.kmers_count.to_le_bytes())?;
+        output.write_all(&self.subset.to_le_bytes())?;
+        output.write_all(&[self.is_last as u8])?;
+        output.write_all(self.bases)
+    }
+
+    /// Reads all the records of a bucket
+    fn read_all(buffer: &'a [u8], k: usize) -> Vec<Self> {
+        let mut records = vec![];
+        let mut position = 0;
+        while position < buffer.len() {
+            let header = &buffer[position..position + Self::HEADER_SIZE];
+            let read_u64 = |offset: usize| {
+                u64::from_le_bytes(
+                    header[offset..offset + size_of::<u64>()]
+                        .try_into()
+                        .unwrap(),
+                )
+            };
+            let subset_offset = 3 * size_of::<u64>();
+            let mut record = SequenceRecord {
+                unitig_index: read_u64(0),
+                kmer_offset: read_u64(size_of::<u64>()),
+                kmers_count: read_u64(2 * size_of::<u64>()),
+                subset: ColorIndexType::from_le_bytes(
+                    header[subset_offset..subset_offset + size_of::<ColorIndexType>()]
+                        .try_into()
+                        .unwrap(),
+                ),
+                is_last: header[Self::HEADER_SIZE - 1] != 0,
+                bases: &[],
+            };
+            position += Self::HEADER_SIZE;
+            if record.subset != NO_COLORS {
+                let bases_count = record.kmers_count as usize + k - 1;
+                record.bases = &buffer[position..position + bases_count];
+                position += bases_count;
+            }
+            records.push(record);
+        }
+        records
+    }
+}
+
+/// Packs a (k-1)-mer in the same way for both of its orientations, if not forward only
+fn canonical_overlap(bases: &[u8], forward_only: bool) -> Box<[u8]> {
+    let forward = pack_overlap(bases, false);
+    if forward_only {
+        forward
+    } else {
+        forward.min(pack_overlap(bases, true))
+    }
+}
+
+/// State of the colored dump of the input graph, updated from a single thread at a time
+struct DumpState {
+    buckets: Vec<BufWriter<File>>,
+    /// The edited subset of the last dumped sequence
+    new_subset: ColorIndexType,
+    new_colors: Vec<ColorIndexType>,
+    /// The (k-1)-mers at the unitig ends next to a removed kmer, where the unitigs can be joined after the edit
+    changed_overlaps: HashSet<Box<[u8]>>,
+    removed_kmers: u64,
+    subsets_count: u64,
+    result: std::io::Result<()>,
+}
+
+/// Renames, drops or keeps a subset of the colors of a graph, writing the edited graph and its colormap.
+/// The kmers left without colors are removed, and the remaining unitigs are recompacted where possible.
+/// The graph is split at the color changes by the colored dump, remapping the colors once for each subset
+/// while reading the colormap, and its sequences are bucketed on disk to join back the unitigs one bucket at a time.
+/// Only the unitigs ending on a (k-1)-mer that lost some of its kmers are kept in memory to be recompacted.
+/// Links and kmer counters are not kept
+pub fn edit_colors(
+    k: usize,
+    m: usize,
+    graph_input: impl AsRef<Path>,
+    graph_output: impl AsRef<Path>,
+    temp_dir: Option<PathBuf>,
+    buckets_count_log: Option<usize>,
+    threads_count: usize,
+    default_compression_level: Option<u32>,
+    forward_only: bool,
+    edit: &ColorsEdit,
+) -> anyhow::Result<ColorsEditStats> {
+    let graph_input = graph_input.as_ref();
+    let graph_output = graph_output.as_ref();
+    if !graph_input.is_file() {
+        anyhow::bail!("Cannot find graph file {}", graph_input.display());
+    }
+    if graph_output.with_extension("colors.dat") == graph_input.with_extension("colors.dat") {
+        anyhow::bail!(
+            "The edited graph {} must be written to a different file than the input graph",
+            graph_output.display()
+        );
+    }
+
+    let colormap_file = graph_input.with_extension("colors.dat");
+    let deserializer = ColorsDeserializer::new(&colormap_file, true)?;
+    deserializer
+        .build_params()
+        .check_graph(&colormap_file, k, None)?;
+
+    let colors_count = deserializer.colors_count();
+    let names: Vec<_> = (0..colors_count)
+        .map(|color| {
+            deserializer
+                .get_color_name(color as ColorIndexType, false)
+                .to_string()
+        })
+        .collect();
+    let colors_by_name: HashMap<_, _> = names
+        .iter()
+        .enumerate()
+        .map(|(color, name)| (name.as_str(), color))
+        .collect();
+    let find_color = |name: &str| {
+        colors_by_name.get(name).copied().ok_or_else(|| {
+            anyhow::anyhow!(
+                "Unknown color {} in colormap {}",
+                name,
+                colormap_file.display()
+            )
+        })
+    };
+
+    let mut kept = vec![edit.keep.is_empty(); colors_count];
+    for name in &edit.keep {
+        kept[find_color(name)?] = true;
+    }
+    for name in &edit.drop {
+        kept[find_color(name)?] = false;
+    }
+
+    let mut new_names = names.clone();
+    for (name, new_name) in &edit.rename {
+        new_names[find_color(name)?] = new_name.clone();
+    }
+
+    let kept_colors: Vec<_> = (0..colors_count)
+        .filter(|color| kept[*color])
+        .map(|color| color as ColorIndexType)
+        .collect();
+    if kept_colors.is_empty() {
+        anyhow::bail!("No colors left in the edited graph");
+    }
+
+    let mut new_indexes = vec![NO_COLORS; colors_count];
+    for (new_index, color) in kept_colors.iter().enumerate() {
+        new_indexes[*color as usize] = new_index as ColorIndexType;
+    }
+    let new_names: Vec<_> = kept_colors
+        .iter()
+        .map(|color| new_names[*color as usize].clone())
+        .collect();
+
+    for (_, new_name) in &edit.rename {
+        if new_names.iter().filter(|name| *name == new_name).count() > 1 {
+            anyhow::bail!("Color name {} is used by more than one color", new_name);
+        }
+    }
+
+    let mut stats = ColorsEditStats {
+        colors_count: kept_colors.len() as u64,
+        removed_colors: (colors_count - kept_colors.len()) as u64,
+        ..Default::default()
+    };
+
+    let temp_dir = temp_dir.unwrap_or_default();
+    let buckets_paths: Vec<_> = (0..graph_buckets_count(graph_input)?)
+        .map(|bucket| temp_dir.join(format!("edit-colors-unitigs.{}", bucket)))
+        .collect();
+    let buckets = buckets_paths
+        .iter()
+        .map(|path| Ok(BufWriter::new(File::create(path)?)))
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    let colors_writer = ColorsMemMapWriter::new(
+        graph_output.with_extension("colors.dat"),
+        &new_names,
+        &deserializer.colors_metadata().select_colors(&kept_colors),
+        deserializer.codec(),
+        deserializer.build_params(),
+    )?;
+    drop(deserializer);
+
+    let dump_state = Mutex::new(DumpState {
+        buckets,
+        new_subset: NO_COLORS,
+        new_colors: vec![],
+        changed_overlaps: HashSet::new(),
+        removed_kmers: 0,
+        subsets_count: 0,
+        result: Ok(()),
+    });
+
+    dump_indexed_unitigs(
+        k,
+        m,
+        graph_input,
+        Some(temp_dir),
+        buckets_count_log,
+        threads_count,
+        true,
+        default_compression_level,
+        None,
+        |info, sequence, colors, same_colors| {
+            let mut state = dump_state.lock();
+            let state = &mut *state;
+
+            // The sequences with the same subset are dumped together, remap its colors only once
+            if !same_colors {
+                state.new_colors.clear();
+                state.new_colors.extend(
+                    colors
+                        .iter()
+                        .map(|color| new_indexes[*color as usize])
+                        .filter(|color| *color != NO_COLORS),
+                );
+                state.new_subset = if state.new_colors.is_empty() {
+                    NO_COLORS
+                } else {
+                    let new_subset = colors_writer.get_id(&state.new_colors);
+                    state.subsets_count = state.subsets_count.max(new_subset as u64 + 1);
+                    new_subset
+                };
+            }
+
+            let kmers_count = (sequence.len() + 1 - k) as u64;
+            if state.new_subset == NO_COLORS {
+                state.removed_kmers += kmers_count;
+                if info.kmer_offset == 0 {
+                    state
+                        .changed_overlaps
+                        .insert(canonical_overlap(&sequence[..k - 1], forward_only));
+                }
+                if info.is_last {
+                    state.changed_overlaps.insert(canonical_overlap(
+                        &sequence[sequence.len() - k + 1..],
+                        forward_only,
+                    ));
+                }
+            }
+
+            if state.result.is_ok() {
+                let bucket = info.unitig_index as usize % state.buckets.len();
+                state.result = SequenceRecord {
+                    unitig_index: info.unitig_index,
+                    kmer_offset: info.kmer_offset,
+                    kmers_count,
+                    subset: state.new_subset,
+                    is_last: info.is_last,
+                    bases: if state.new_subset == NO_COLORS {
+                        &[]
+                    } else {
+                        sequence
+                    },
+                }
+                .write(&mut state.buckets[bucket]);
+            }
+        },
+    )?;
+    drop(colors_writer);
+
+    let DumpState {
+        buckets,
+        changed_overlaps,
+        removed_kmers,
+        subsets_count,
+        result,
+        ..
+    } = dump_state.into_inner();
+    result?;
+    for mut bucket in buckets {
+        bucket.flush()?;
+    }
+    stats.removed_kmers = removed_kmers;
+    stats.subsets_count = subsets_count;
+
+    let mut output = BufWriter::new(
+        File::create(graph_output).log_unrecoverable_error_with_data(
+            "Cannot create graph file",
+            graph_output.display(),
+        )?,
+    );
+
+    let mut graph = EditedGraph {
+        k,
+        forward_only,
+        bases: vec![],
+        runs: vec![],
+        pieces: vec![],
+        entering: HashMap::new(),
+        exiting: HashMap::new(),
+    };
+
+    let mut sequence = vec![];
+    let mut runs = vec![];
+    for path in &buckets_paths {
+        let buffer = std::fs::read(path)?;
+        let _ = std::fs::remove_file(path);
+        let mut records = SequenceRecord::read_all(&buffer, k);
+        records.sort_unstable_by_key(|record| (record.unitig_index, record.kmer_offset));
+
+        for unitig_records in records.nq_group_by(|a, b| a.unitig_index == b.unitig_index) {
+            let mut next_kmer = 0;
+            for record in unitig_records {
+                if record.kmer_offset != next_kmer {
+                    break;
+                }
+                next_kmer += record.kmers_count;
+            }
+            let last_record = unitig_records.last().unwrap();
+            if next_kmer != last_record.kmer_offset + last_record.kmers_count
+                || !last_record.is_last
+            {
+                anyhow::bail!(
+                    "Unitig {} of graph {} was not dumped completely, please check the k-mers length",
+                    last_record.unitig_index,
+                    graph_input.display()
+                );
+            }
+
+            // Each piece left after removing the kmers without colors
+            for piece in unitig_records.split(|record| record.subset == NO_COLORS) {
+                let (Some(first_record), Some(last_record)) = (piece.first(), piece.last()) else {
+                    continue;
+                };
+
+                sequence.clear();
+                runs.clear();
+                for (index, record) in piece.iter().enumerate() {
+                    // Consecutive sequences overlap by k - 1 bases
+                    let skipped_bases = if index == 0 { 0 } else { k - 1 };
+                    sequence.extend_from_slice(&record.bases[skipped_bases..]);
+                    push_run(&mut runs, record.subset, record.kmers_count);
+                }
+
+                let joinable = [
+                    first_record.kmer_offset == 0
+                        && changed_overlaps
+                            .contains(&canonical_overlap(&sequence[..k - 1], forward_only)),
+                    last_record.is_last
+                        && changed_overlaps.contains(&canonical_overlap(
+                            &sequence[sequence.len() - k + 1..],
+                            forward_only,
+                        )),
+                ];
+
+                if joinable == [false, false] {
+                    write_unitig(&mut output, stats.unitigs_count, &sequence, &runs)?;
+                    stats.unitigs_count += 1;
+                } else {
+                    graph.add_piece(&sequence, &runs, joinable);
+                }
+            }
+        }
+    }
+
+    graph.index_overlaps();
+
+    let mut result = Ok(());
+    compact_unitigs_chains(&graph, |chain| {
+        if result.is_err() {
+            return;
+        }
+        stats.merged_unitigs += chain.len() as u64 - 1;
+
+        sequence.clear();
+        runs.clear();
+        for (position, &(piece, forward)) in chain.iter().enumerate() {
+            let bases = graph.piece_bases(piece);
+            let piece_runs = &graph.runs[graph.pieces[piece].runs.clone()];
+            let bases_offset = if position == 0 { 0 } else { k - 1 };
+
+            if forward {
+                sequence.extend_from_slice(&bases[bases_offset..]);
+                for (subset, count) in piece_runs {
+                    push_run(&mut runs, *subset, *count);
+                }
+            } else {
+                sequence.extend(reverse_complement(bases).skip(bases_offset));
+                for (subset, count) in piece_runs.iter().rev() {
+                    push_run(&mut runs, *subset, *count);
+                }
+            }
+        }
+
+        result = write_unitig(&mut output, stats.unitigs_count, &sequence, &runs);
+        stats.unitigs_count += 1;
+    });
+    result?;
+
+    output
+        .flush()
+        .log_unrecoverable_error_with_data("Cannot write graph file", graph_output.display())?;
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use io::sequences_reader::SequencesReader;
+
+    const K: usize = 5;
+    const M: usize = 3;
+
+    /// A colored unitig, with the color names of each run of kmers
+    type TestUnitig = (String, Vec<(Vec<String>, u64)>);
+
+    fn temp_graph(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "ggcat-edit-colors-{}-{}.fa",
+            name,
+            std::process::id()
+        ))
+    }
+
+    /// Writes a colored graph with the colors a, b and c
+    fn write_graph(name: &str, unitigs: &[(&str, &[(&[&str], u64)])]) -> PathBuf {
+        let path = temp_graph(name);
+        write_test_graph(&path, K, &["a", "b", "c"], unitigs);
+        path
+    }
+
+    /// Reads the unitigs of an edited graph, with the names of the colors of each run
+    fn read_graph(path: &Path) -> (Vec<String>, Vec<TestUnitig>) {
+        let mut deserializer =
+            ColorsDeserializer::new(path.with_extension("colors.dat"), true).unwrap();
+        let names: Vec<_> = (0..deserializer.colors_count())
+            .map(|c| {
+                deserializer
+                    .get_color_name(c as ColorIndexType, false)
+                    .to_string()
+            })
+            .collect();
+
+        let mut unitigs = vec![];
+        SequencesReader::new().process_file_extended(
+            path,
+            |seq| {
+                let runs = seq
+                    .ident_data
+                    .split(|c| *c == b' ')
+                    .filter(|tag| tag.starts_with(b"C:"))
+                    .map(|tag| parse_color_run(tag).unwrap())
+                    .collect::<Vec<_>>();
+                unitigs.push((String::from_utf8(seq.seq.to_vec()).unwrap(), runs));
+            },
+            None,
+            true,
+            false,
+        );
+
+        let mut colors = vec![];
+        let unitigs = unitigs
+            .into_iter()
+            .map(|(sequence, runs)| {
+                let runs = runs
+                    .into_iter()
+                    .map(|(subset, count)| {
+                        colors.clear();
+                        deserializer
+                            .get_color_mappings(subset, &mut colors)
+                            .unwrap();
+                        (
+                            colors.iter().map(|c| names[*c as usize].clone()).collect(),
+                            count,
+                        )
+                    })
+                    .collect();
+                (sequence, runs)
+            })
+            .collect();
+        (names, unitigs)
+    }
+
+    fn remove_graph(path: &Path) {
+        let _ = std::fs::remove_file(path);
+        let _ = std::fs::remove_file(path.with_extension("colors.dat"));
+    }
+
+    fn unitig(sequence: &str, runs: &[(&[&str], u64)]) -> TestUnitig {
+        (
+            sequence.to_string(),
+            runs.iter()
+                .map(|(colors, count)| (colors.iter().map(|c| c.to_string()).collect(), *count))
+                .collect(),
+        )
+    }
+
+    /// ACCTGAGT branches into GAGTCCAT, colored with a, and into GAGTTTGC, colored with c
+    fn branching_graph(name: &str) -> PathBuf {
+        write_graph(
+            name,
+            &[
+                ("ACCTGAGT", &[(&["a", "b"], 4)]),
+                ("GAGTCCAT", &[(&["a"], 4)]),
+                ("GAGTTTGC", &[(&["c"], 4)]),
+            ],
+        )
+    }
+
+    fn edit_graph(
+        input: &Path,
+        output: &Path,
+        edit: &ColorsEdit,
+    ) -> anyhow::Result<ColorsEditStats> {
+        let _memory_fs = lock_test_memory_fs();
+        let temp_dir = output.with_extension("temp");
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        let stats = edit_colors(
+            K,
+            M,
+            input,
+            output,
+            Some(temp_dir.clone()),
+            None,
+            2,
+            None,
+            false,
+            edit,
+        );
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        stats
+    }
+
+    fn edit(
+        input: &Path,
+        name: &str,
+        edit: ColorsEdit,
+    ) -> (ColorsEditStats, Vec<String>, Vec<TestUnitig>) {
+        let output = temp_graph(name);
+        let stats = edit_graph(input, &output, &edit).unwrap();
+        let (names, unitigs) = read_graph(&output);
+        remove_graph(&output);
+        (stats, names, unitigs)
+    }
+
+    #[test]
+    fn renamed_colors() {
+        let input = branching_graph("rename-input");
+        let (stats, names, unitigs) = edit(
+            &input,
+            "rename",
+            ColorsEdit {
+                rename: vec![("a".into(), "alpha".into())],
+                ..Default::default()
+            },
+        );
+        assert_eq!(names, ["alpha", "b", "c"]);
+        assert_eq!((stats.removed_kmers, stats.merged_unitigs), (0, 0));
+        assert_eq!(
+            unitigs,
+            [
+                unitig("ACCTGAGT", &[(&["alpha", "b"], 4)]),
+                unitig("GAGTCCAT", &[(&["alpha"], 4)]),
+                unitig("GAGTTTGC", &[(&["c"], 4)]),
+            ]
+        );
+
+        let output = temp_graph("rename-duplicate");
+        let err = edit_graph(
+            &input,
+            &output,
+            &ColorsEdit {
+                rename: vec![("a".into(), "b".into())],
+                ..Default::default()
+            },
+        )
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("more than one color"), "{}", err);
+        remove_graph(&output);
+        remove_graph(&input);
+    }
+
+    #[test]
+    fn dropped_color_merges_unitigs() {
+        let input = branching_graph("drop-input");
+        let (stats, names, unitigs) = edit(
+            &input,
+            "drop",
+            ColorsEdit {
+                drop: vec!["c".into()],
+                ..Default::default()
+            },
+        );
+        assert_eq!(names, ["a", "b"]);
+        assert_eq!(stats.removed_colors, 1);
+        assert_eq!(stats.removed_kmers, 4);
+        assert_eq!((stats.unitigs_count, stats.merged_unitigs), (1, 1));
+        assert_eq!(
+            unitigs,
+            [unitig("ACCTGAGTCCAT", &[(&["a", "b"], 4), (&["a"], 4)])]
+        );
+        remove_graph(&input);
+    }
+
+    #[test]
+    fn kept_colors() {
+        let input = branching_graph("keep-input");
+        let (stats, names, unitigs) = edit(
+            &input,
+            "keep",
+            ColorsEdit {
+                keep: vec!["b".into()],
+                ..Default::default()
+            },
+        );
+        assert_eq!(names, ["b"]);
+        assert_eq!((stats.colors_count, stats.removed_colors), (1, 2));
+        assert_eq!(stats.removed_kmers, 8);
+        assert_eq!(unitigs, [unitig("ACCTGAGT", &[(&["b"], 4)])]);
+
+        let output = temp_graph("keep-none");
+        let err = edit_graph(
+            &input,
+            &output,
+            &ColorsEdit {
+                drop: vec!["a".into(), "b".into(), "c".into()],
+                ..Default::default()
+            },
+        )
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("No colors left"), "{}", err);
+        remove_graph(&output);
+        remove_graph(&input);
+    }
+
+    #[test]
+    fn colorless_kmers_split_unitigs() {
+        let input = write_graph(
+            "split-input",
+            &[("TTAGCCGATA", &[(&["a"], 2), (&["c"], 2), (&["a", "b"], 2)])],
+        );
+        let (stats, _, unitigs) = edit(
+            &input,
+            "split",
+            ColorsEdit {
+                drop: vec!["c".into()],
+                ..Default::default()
+            },
+        );
+        assert_eq!(stats.removed_kmers, 2);
+        assert_eq!((stats.unitigs_count, stats.merged_unitigs), (2, 0));
+        assert_eq!(
+            unitigs,
+            [
+                unitig("TTAGCC", &[(&["a"], 2)]),
+                unitig("CCGATA", &[(&["a", "b"], 2)]),
+            ]
+        );
+        remove_graph(&input);
+    }
+
+    #[test]
+    fn only_changed_ends_are_recompacted() {
+        // GCGCAAAA and TTGGAAAA enter AAAACCGT, that branches into CCGTAGGAT and CCGTTAAC,
+        // CCGTAGGAT branches into GGATTCAC and GGATGAGA. The unitigs colored with c are dropped
+        let input = write_graph(
+            "recompact-input",
+            &[
+                ("AAAACCGT", &[(&["a"], 4)]),
+                ("CCGTAGGAT", &[(&["a"], 5)]),
+                ("CCGTTAAC", &[(&["a"], 4)]),
+                ("GGATTCAC", &[(&["c"], 4)]),
+                ("GGATGAGA", &[(&["a"], 4)]),
+                ("GCGCAAAA", &[(&["a"], 4)]),
+                ("TTGGAAAA", &[(&["c"], 4)]),
+            ],
+        );
+        let (stats, _, mut unitigs) = edit(
+            &input,
+            "recompact",
+            ColorsEdit {
+                drop: vec!["c".into()],
+                ..Default::default()
+            },
+        );
+        unitigs.sort();
+        assert_eq!(stats.removed_kmers, 8);
+        // The branch at CCGT is left as it was
+        assert_eq!((stats.unitigs_count, stats.merged_unitigs), (3, 2));
+        assert_eq!(
+            unitigs,
+            [
+                unitig("CCGTAGGATGAGA", &[(&["a"], 9)]),
+                unitig("CCGTTAAC", &[(&["a"], 4)]),
+                unitig("GCGCAAAACCGT", &[(&["a"], 8)]),
+            ]
+        );
+        remove_graph(&input);
+    }
+}
@@ -0,0 +1,426 @@
+use colors::colors_manager::ColorMapReader;
+use colors::storage::deserializer::ColorsDeserializer;
+use hashbrown::hash_map::DefaultHashBuilder;
+use hashbrown::HashMap;
+use io::sequences_reader::SequencesReader;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::hash::BuildHasher;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Number of sequences with length in [min, max]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct LengthHistogramBin {
+    pub min: u64,
+    pub max: u64,
+    pub count: u64,
+}
+
+/// Statistics of a compacted graph, computed from its output sequences
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct GraphStats {
+    pub k: usize,
+    pub unitigs_count: u64,
+    pub total_length: u64,
+    pub kmers_count: u64,
+    pub min_length: u64,
+    pub max_length: u64,
+    pub n50: u64,
+    pub genome_size: Option<u64>,
+    pub ng50: Option<u64>,
+    /// Counts of the sequences by length, using power of two bins
+    pub length_histogram: Vec<LengthHistogramBin>,
+    /// Unitig ends without any overlapping unitig
+    pub dead_ends: u64,
+    /// Unitig ends overlapping with more than one unitig
+    pub branching_nodes: u64,
+    pub colors_count: Option<u64>,
+    pub color_subsets_count: Option<u64>,
+}
+
+/// Packs a (k-1)-mer with 2 bits per base, optionally reverse complemented
+pub(crate) fn pack_overlap(bases: &[u8], reverse_complement: bool) -> Box<[u8]> {
+    let mut packed = Vec::with_capacity(bases.len().div_ceil(4));
+    write_packed_overlap(&mut packed, bases, reverse_complement);
+    packed.into_boxed_slice()
+}
+
+/// Appends a (k-1)-mer packed with 2 bits per base to a buffer, optionally reverse complemented
+fn write_packed_overlap(buffer: &mut Vec<u8>, bases: &[u8], reverse_complement: bool) {
+    let start = buffer.len();
+    buffer.resize(start + bases.len().div_ceil(4), 0);
+    let packed = &mut buffer[start..];
+    for i in 0..bases.len() {
+        let base = if reverse_complement {
+            bases[bases.len() - 1 - i]
+        } else {
+            bases[i]
+        };
+        let code = match base {
+            b'A' => 0,
+            b'C' => 1,
+            b'G' => 2,
+            _ => 3,
+        };
+        let code = if reverse_complement { 3 - code } else { code };
+        packed[i / 4] |= code << ((i % 4) * 2);
+    }
+}
+
+/// Approximate size of the graph sequences written to the same temporary bucket,
+/// only one bucket is loaded in memory at a time
+const GRAPH_BUCKET_SIZE: u64 = 64 * 1024 * 1024;
+const MAX_GRAPH_BUCKETS_COUNT: u64 = 1024;
+
+/// Number of temporary buckets used to process a graph one part at a time
+pub(crate) fn graph_buckets_count(graph_input: &Path) -> anyhow::Result<usize> {
+    Ok((graph_input.metadata()?.len() / GRAPH_BUCKET_SIZE + 1)
+        .next_power_of_two()
+        .min(MAX_GRAPH_BUCKETS_COUNT) as usize)
+}
+
+/// Kinds of the (k-1)-mer records in the ends buckets: the overlaps entering or exiting a unitig,
+/// and the unitig ends counting the unitigs entering or exiting them
+const ENTERING_OVERLAP: u8 = 0;
+const EXITING_OVERLAP: u8 = 1;
+const ENTERING_NEIGHBORS_END: u8 = 2;
+const EXITING_NEIGHBORS_END: u8 = 3;
+
+impl GraphStats {
+    /// Formats the statistics as a JSON object
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn write_json(&self, output_file: impl AsRef<Path>) -> anyhow::Result<()> {
+        std::fs::write(output_file.as_ref(), self.to_json() + "\n").map_err(|err| {
+            anyhow::anyhow!(
+                "Cannot write stats file {}: {}",
+                output_file.as_ref().display(),
+                err
+            )
+        })
+    }
+}
+
+/// Computes the statistics of an existing FASTA or GFA graph, reading its colormap if present.
+/// Dead ends and branching nodes are computed from the (k-1)-overlaps between the graph sequences,
+/// bucketing the unitig ends in temporary files to count the overlaps of one bucket at a time
+pub fn compute_graph_stats(
+    graph_input: impl AsRef<Path>,
+    k: usize,
+    forward_only: bool,
+    genome_size: Option<u64>,
+    temp_dir: impl AsRef<Path>,
+) -> anyhow::Result<GraphStats> {
+    let graph_input = graph_input.as_ref();
+    if !graph_input.is_file() {
+        anyhow::bail!("Cannot find graph file {}", graph_input.display());
+    }
+
+    compute_bucketed_graph_stats(
+        graph_input,
+        k,
+        forward_only,
+        genome_size,
+        temp_dir.as_ref(),
+        graph_buckets_count(graph_input)?,
+    )
+}
+
+fn compute_bucketed_graph_stats(
+    graph_input: &Path,
+    k: usize,
+    forward_only: bool,
+    genome_size: Option<u64>,
+    temp_dir: &Path,
+    buckets_count: usize,
+) -> anyhow::Result<GraphStats> {
+    std::fs::create_dir_all(temp_dir)
+        .map_err(|err| anyhow::anyhow!("Cannot create temp dir {}: {}", temp_dir.display(), err))?;
+    let buckets_paths: Vec<_> = (0..buckets_count)
+        .map(|bucket| temp_dir.join(format!("graph-stats-ends.{}", bucket)))
+        .collect();
+    let mut buckets = buckets_paths
+        .iter()
+        .map(|path| Ok(BufWriter::new(File::create(path)?)))
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    let mut stats = GraphStats {
+        k,
+        genome_size,
+        ..Default::default()
+    };
+    // Counts of the sequences by length
+    let mut lengths: BTreeMap<u64, u64> = BTreeMap::new();
+
+    let buckets_hasher = DefaultHashBuilder::default();
+    let mut record = vec![];
+    let mut write_result = Ok(());
+    let mut write_record = |kind: u8, overlap: &[u8], reverse_complement: bool| {
+        record.clear();
+        record.push(kind);
+        write_packed_overlap(&mut record, overlap, reverse_complement);
+        let bucket = buckets_hasher.hash_one(&record[1..]) as usize % buckets_count;
+        if write_result.is_ok() {
+            write_result = buckets[bucket].write_all(&record);
+        }
+    };
+
+    SequencesReader::new().process_file_extended(
+        graph_input,
+        |seq| {
+            let seq = seq.seq;
+            *lengths.entry(seq.len() as u64).or_insert(0) += 1;
+            if seq.len() < k {
+                return;
+            }
+
+            let prefix = &seq[..k - 1];
+            let suffix = &seq[seq.len() - k + 1..];
+
+            // The successors of the unitig enter its suffix, its predecessors exit its prefix
+            // or, if not forward only, enter its reverse complemented prefix
+            write_record(ENTERING_OVERLAP, prefix, false);
+            write_record(ENTERING_NEIGHBORS_END, suffix, false);
+            if forward_only {
+                write_record(EXITING_OVERLAP, suffix, false);
+                write_record(EXITING_NEIGHBORS_END, prefix, false);
+            } else {
+                write_record(ENTERING_OVERLAP, suffix, true);
+                write_record(ENTERING_NEIGHBORS_END, prefix, true);
+            }
+        },
+        None,
+        false,
+        false,
+    );
+    write_result?;
+    for mut bucket in buckets {
+        bucket.flush()?;
+    }
+
+    let record_size = 1 + (k - 1).div_ceil(4);
+    for path in &buckets_paths {
+        let records = std::fs::read(path)?;
+        let _ = std::fs::remove_file(path);
+
+        let mut overlaps: HashMap<&[u8], [u32; 2]> = HashMap::new();
+        for record in records.chunks_exact(record_size) {
+            if let ENTERING_OVERLAP | EXITING_OVERLAP = record[0] {
+                overlaps.entry(&record[1..]).or_default()[record[0] as usize] += 1;
+            }
+        }
+
+        for record in records.chunks_exact(record_size) {
+            if let ENTERING_NEIGHBORS_END | EXITING_NEIGHBORS_END = record[0] {
+                let neighbors = overlaps.get(&record[1..]).map_or(0, |counts| {
+                    counts[(record[0] - ENTERING_NEIGHBORS_END) as usize]
+                });
+                match neighbors {
+                    0 => stats.dead_ends += 1,
+                    1 => {}
+                    _ => stats.branching_nodes += 1,
+                }
+            }
+        }
+    }
+
+    for (length, count) in &lengths {
+        stats.unitigs_count += count;
+        stats.total_length += length * count;
+        stats.kmers_count += (length + 1).saturating_sub(k as u64) * count;
+    }
+    stats.min_length = lengths.keys().next().copied().unwrap_or(0);
+    stats.max_length = lengths.keys().next_back().copied().unwrap_or(0);
+
+    let find_nx = |target: u64| {
+        let mut cumulative = 0;
+        lengths.iter().rev().find_map(|(length, count)| {
+            cumulative += length * count;
+            (cumulative * 2 >= target).then_some(*length)
+        })
+    };
+    stats.n50 = find_nx(stats.total_length).unwrap_or(0);
+    stats.ng50 = genome_size.and_then(find_nx);
+
+    for (length, count) in &lengths {
+        let min = 1u64 << (63 - length.max(&1).leading_zeros());
+        match stats.length_histogram.last_mut() {
+            Some(bin) if bin.min == min => bin.count += count,
+            _ => stats.length_histogram.push(LengthHistogramBin {
+                min,
+                max: min * 2 - 1,
+                count: *count,
+            }),
+        }
+    }
+
+    let colormap_file = graph_input.with_extension("colors.dat");
+    if colormap_file.is_file() {
+        let colors_deserializer = ColorsDeserializer::new(&colormap_file, true)?;
+        stats.colors_count = Some(colors_deserializer.colors_count() as u64);
+        stats.color_subsets_count = Some(colors_deserializer.colors_subsets_count());
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn write_graph(name: &str, sequences: &[&str]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "ggcat-graph-stats-{}-{}.fa",
+            name,
+            std::process::id()
+        ));
+        let content: String = sequences
+            .iter()
+            .enumerate()
+            .map(|(i, seq)| format!(">{}\n{}\n", i, seq))
+            .collect();
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn forward_only_graph_stats() {
+        // AAACCC branches into CCCGGG and CCCTTT, GATTACAGATTACA is isolated
+        let graph = write_graph("forward", &["AAACCC", "CCCGGG", "CCCTTT", "GATTACAGATTACA"]);
+        let stats =
+            compute_graph_stats(&graph, 4, true, Some(20), graph.with_extension("temp")).unwrap();
+        let _ = std::fs::remove_file(&graph);
+        let _ = std::fs::remove_dir_all(graph.with_extension("temp"));
+
+        assert_eq!(stats.unitigs_count, 4);
+        assert_eq!(stats.total_length, 32);
+        assert_eq!(stats.kmers_count, 20);
+        assert_eq!((stats.min_length, stats.max_length), (6, 14));
+        assert_eq!(stats.n50, 6);
+        assert_eq!(stats.ng50, Some(14));
+        assert_eq!(
+            stats.length_histogram,
+            vec![
+                LengthHistogramBin {
+                    min: 4,
+                    max: 7,
+                    count: 3
+                },
+                LengthHistogramBin {
+                    min: 8,
+                    max: 15,
+                    count: 1
+                }
+            ]
+        );
+        assert_eq!(stats.dead_ends, 5);
+        assert_eq!(stats.branching_nodes, 1);
+        assert_eq!(stats.colors_count, None);
+    }
+
+    #[test]
+    fn reverse_complement_links_stats() {
+        // The end of AAACCG is linked to the reverse complemented end of TTACGG
+        let graph = write_graph("canonical", &["AAACCG", "TTACGG"]);
+        let stats =
+            compute_graph_stats(&graph, 4, false, None, graph.with_extension("temp")).unwrap();
+        let _ = std::fs::remove_file(&graph);
+        let _ = std::fs::remove_dir_all(graph.with_extension("temp"));
+
+        assert_eq!(stats.dead_ends, 2);
+        assert_eq!(stats.branching_nodes, 0);
+        assert_eq!(stats.ng50, None);
+    }
+
+    #[test]
+    fn bucketed_ends_stats() {
+        let graph = write_graph(
+            "buckets",
+            &[
+                "AAACCC",
+                "CCCGGG",
+                "CCCTTT",
+                "GGGTAA",
+                "TTACCC",
+                "GATTACAGATTACA",
+            ],
+        );
+        let temp_dir = graph.with_extension("temp");
+        let stats = [1, 4, 16].map(|buckets_count| {
+            let stats =
+                compute_bucketed_graph_stats(&graph, 4, false, None, &temp_dir, buckets_count)
+                    .unwrap();
+            (stats.dead_ends, stats.branching_nodes)
+        });
+        let _ = std::fs::remove_file(&graph);
+        // The buckets files are removed after being counted
+        assert_eq!(std::fs::read_dir(&temp_dir).unwrap().count(), 0);
+        let _ = std::fs::remove_dir_all(&temp_dir);
+
+        // Same dead ends and branching nodes with any number of buckets
+        assert_eq!(stats, [(6, 6); 3]);
+    }
+
+    #[test]
+    fn gfa_graph_stats() {
+        let fasta_graph = write_graph("gfa", &["AAACCC", "CCCGGG", "CCCTTT", "GATTACAGATTACA"]);
+        let gfa_v1 = fasta_graph.with_extension("v1.gfa");
+        std::fs::write(
+            &gfa_v1,
+            "S\t0\tAAACCC\tLN:i:6\nL\t0\t+\t1\t+\t3M\nL\t0\t+\t2\t+\t3M\n\
+             S\t1\tCCCGGG\tLN:i:6\nS\t2\tCCCTTT\tLN:i:6\nS\t3\tGATTACAGATTACA\tLN:i:14\n",
+        )
+        .unwrap();
+        let gfa_v2 = fasta_graph.with_extension("v2.gfa");
+        std::fs::write(
+            &gfa_v2,
+            "S\t0\t6\tAAACCC\nE\t0\t0+\t1+\t3\t6$\t0\t3\t3M\nS\t1\t6\tCCCGGG\n\
+             S\t2\t6\tCCCTTT\nS\t3\t14\tGATTACAGATTACA\n",
+        )
+        .unwrap();
+
+        let [fasta_stats, v1_stats, v2_stats] = [&fasta_graph, &gfa_v1, &gfa_v2].map(|graph| {
+            let stats = compute_graph_stats(graph, 4, true, Some(20), graph.with_extension("temp"))
+                .unwrap();
+            let _ = std::fs::remove_file(graph);
+            let _ = std::fs::remove_dir_all(graph.with_extension("temp"));
+            stats
+        });
+
+        for stats in [v1_stats, v2_stats] {
+            assert_eq!(stats.to_json(), fasta_stats.to_json());
+        }
+        assert_eq!(fasta_stats.unitigs_count, 4);
+        assert_eq!(fasta_stats.dead_ends, 5);
+    }
+
+    #[test]
+    fn graph_stats_json() {
+        let stats = GraphStats {
+            k: 31,
+            unitigs_count: 2,
+            length_histogram: vec![LengthHistogramBin {
+                min: 32,
+                max: 63,
+                count: 2,
+            }],
+            colors_count: Some(3),
+            ..Default::default()
+        };
+
+        let json: serde_json::Value = serde_json::from_str(&stats.to_json()).unwrap();
+        assert_eq!(json["k"], 31);
+        assert_eq!(json["unitigs_count"], 2);
+        assert_eq!(json["length_histogram"][0]["min"], 32);
+        assert_eq!(json["length_histogram"][0]["max"], 63);
+        assert_eq!(json["length_histogram"][0]["count"], 2);
+        assert_eq!(json["ng50"], serde_json::Value::Null);
+        assert_eq!(json["colors_count"], 3);
+        assert_eq!(json["color_subsets_count"], serde_json::Value::Null);
+    }
+}
@@ -1,7 +1,6 @@
 use crate::pipeline::dumper_minimizer_bucketing::minimizer_bucketing;
 use colors::bundles::graph_querying::ColorBundleGraphQuerying;
 use colors::colors_manager::{ColorMapReader, ColorsManager, ColorsMergeManager};
-use colors::DefaultColorsSerializer;
 use config::{
     ColorIndexType, INTERMEDIATE_COMPRESSION_LEVEL_FAST, INTERMEDIATE_COMPRESSION_LEVEL_SLOW,
 };
@@ -10,12 +9,25 @@ use io::sequences_stream::general::GeneralSequenceBlockData;
 use parallel_processor::memory_fs::MemoryFs;
 use parallel_processor::phase_times_monitor::PHASES_TIMES_MONITOR;
 use pipeline::dumper_colormap_querying::colormap_query;
-use pipeline::dumper_colormap_reading::colormap_reading;
+use pipeline::dumper_colormap_reading::{colormap_reading, DumpedSequenceInfo};
 use std::fs::remove_file;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::Ordering;
 
+mod colors_predicate;
+mod edit_colors;
+mod graph_stats;
+mod maximal_unitigs;
 mod pipeline;
+mod presence_matrix;
+mod similarity;
+
+pub use colors_predicate::ColorsPredicate;
+pub use edit_colors::{edit_colors, ColorsEdit, ColorsEditStats};
+pub use graph_stats::{compute_graph_stats, GraphStats, LengthHistogramBin};
+pub use maximal_unitigs::dump_maximal_unitigs;
+pub use presence_matrix::dump_presence_matrix;
+pub use similarity::{compute_colors_similarity, ColorsSimilarity};
 
 #[derive(Copy, Clone, Debug, PartialOrd, PartialEq)]
 pub enum QuerierStartingStep {
@@ -40,16 +52,49 @@ pub fn dump_unitigs(
     threads_count: usize,
     single_thread_output_function: bool,
     default_compression_level: Option<u32>,
+    colors_predicate: Option<&ColorsPredicate>,
     output_function: impl Fn(&[u8], &[ColorIndexType], bool) + Send + Sync,
+) -> anyhow::Result<()> {
+    dump_indexed_unitigs(
+        k,
+        m,
+        graph_input,
+        temp_dir,
+        buckets_count_log,
+        threads_count,
+        single_thread_output_function,
+        default_compression_level,
+        colors_predicate,
+        |_, sequence, colors, same_colors| output_function(sequence, colors, same_colors),
+    )
+}
+
+/// Same as `dump_unitigs`, also passing to the output function the position in the graph
+/// and the color subset of each sequence
+pub(crate) fn dump_indexed_unitigs(
+    k: usize,
+    m: usize,
+    graph_input: impl AsRef<Path>,
+    temp_dir: Option<PathBuf>,
+    buckets_count_log: Option<usize>,
+    threads_count: usize,
+    single_thread_output_function: bool,
+    default_compression_level: Option<u32>,
+    colors_predicate: Option<&ColorsPredicate>,
+    output_function: impl Fn(DumpedSequenceInfo, &[u8], &[ColorIndexType], bool) + Send + Sync,
 ) -> anyhow::Result<()> {
     let temp_dir = temp_dir.unwrap_or(PathBuf::new());
 
     PHASES_TIMES_MONITOR.write().init();
 
+    let colormap_file = graph_input.as_ref().with_extension("colors.dat");
     let color_map =
         <ColorBundleGraphQuerying as ColorsManager>::ColorsMergeManagerType::open_colors_table(
-            graph_input.as_ref().with_extension("colors.dat"),
+            &colormap_file,
         )?;
+    color_map
+        .build_params()
+        .check_graph(&colormap_file, k, None)?;
 
     // TODO: Support GFA input
     let file_stats = compute_stats_from_input_blocks(&[GeneralSequenceBlockData::FASTA((
@@ -80,11 +125,11 @@ pub fn dump_unitigs(
     MemoryFs::flush_all_to_disk();
     MemoryFs::free_memory();
 
-    let colormap_file = graph_input.as_ref().with_extension("colors.dat");
-    colormap_reading::<ColorBundleGraphQuerying, DefaultColorsSerializer>(
+    colormap_reading::<ColorBundleGraphQuerying>(
         colormap_file,
         reorganized_unitigs,
         single_thread_output_function,
+        colors_predicate,
         output_function,
     )
 }
@@ -97,7 +142,7 @@ pub fn dump_colormap_query(
 ) -> anyhow::Result<()> {
     PHASES_TIMES_MONITOR.write().init();
 
-    colormap_query::<ColorBundleGraphQuerying, DefaultColorsSerializer>(
+    colormap_query::<ColorBundleGraphQuerying>(
         colormap_file,
         color_subsets,
         single_thread_output_function,
@@ -0,0 +1,255 @@
+use crate::dump_indexed_unitigs;
+use crate::pipeline::dumper_colormap_reading::DumpedSequenceInfo;
+use config::ColorIndexType;
+use hashbrown::HashMap;
+use parking_lot::Mutex;
+use std::path::{Path, PathBuf};
+
+/// The sequences of a unitig received so far
+#[derive(Default)]
+struct PartialUnitig {
+    /// The (first kmer index, subset, bases) of each sequence
+    sequences: Vec<(u64, ColorIndexType, Vec<u8>)>,
+    kmers_count: u64,
+    /// Known after receiving the last sequence of the unitig
+    total_kmers_count: Option<u64>,
+}
+
+struct MaximalUnitigsBuilder<F> {
+    k: usize,
+    partial_unitigs: HashMap<u64, PartialUnitig>,
+    sequence: Vec<u8>,
+    runs: Vec<(usize, usize, ColorIndexType)>,
+    output_function: F,
+}
+
+impl<F: FnMut(u64, &[u8], &[(usize, usize, ColorIndexType)])> MaximalUnitigsBuilder<F> {
+    fn new(k: usize, output_function: F) -> Self {
+        Self {
+            k,
+            partial_unitigs: HashMap::new(),
+            sequence: vec![],
+            runs: vec![],
+            output_function,
+        }
+    }
+
+    /// Adds a dumped sequence of a unitig, outputting the unitig once all its sequences are received
+    fn add_sequence(&mut self, info: DumpedSequenceInfo, sequence: &[u8]) {
+        let k = self.k;
+        let kmers_count = (sequence.len() + 1 - k) as u64;
+
+        if info.kmer_offset == 0 && info.is_last {
+            // The unitig has a single color subset
+            (self.output_function)(
+                info.unitig_index,
+                sequence,
+                &[(0, kmers_count as usize, info.subset)],
+            );
+            return;
+        }
+
+        let partial_unitig = self.partial_unitigs.entry(info.unitig_index).or_default();
+        partial_unitig
+            .sequences
+            .push((info.kmer_offset, info.subset, sequence.to_vec()));
+        partial_unitig.kmers_count += kmers_count;
+        if info.is_last {
+            partial_unitig.total_kmers_count = Some(info.kmer_offset + kmers_count);
+        }
+
+        if partial_unitig.total_kmers_count != Some(partial_unitig.kmers_count) {
+            return;
+        }
+
+        let mut partial_unitig = self.partial_unitigs.remove(&info.unitig_index).unwrap();
+        partial_unitig.sequences.sort_unstable_by_key(|s| s.0);
+
+        self.sequence.clear();
+        self.runs.clear();
+        for (kmer_offset, subset, bases) in &partial_unitig.sequences {
+            let kmer_offset = *kmer_offset as usize;
+            // Consecutive sequences overlap by k - 1 bases
+            let skipped_bases = if kmer_offset == 0 { 0 } else { k - 1 };
+            self.sequence.extend_from_slice(&bases[skipped_bases..]);
+            self.runs
+                .push((kmer_offset, kmer_offset + bases.len() + 1 - k, *subset));
+        }
+        (self.output_function)(info.unitig_index, &self.sequence, &self.runs);
+    }
+
+    /// Checks that all the unitigs were output
+    fn finish(self) -> anyhow::Result<()> {
+        if let Some(unitig_index) = self.partial_unitigs.keys().next() {
+            anyhow::bail!(
+                "Unitig {} was not dumped completely, please check the k-mers length",
+                unitig_index
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Dumps the maximal unitigs of a colored graph, joining back the sequences split by the colored dump
+/// at the color changes. The output function is called once for each unitig, in no particular order,
+/// with its index in the graph, its sequence and its color runs as (first kmer, last kmer + 1, color subset),
+/// where the colors of the subsets can be read with `dump_colormap_query`.
+/// The unitigs split in more sequences are kept in memory until all of them are dumped
+pub fn dump_maximal_unitigs(
+    k: usize,
+    m: usize,
+    graph_input: impl AsRef<Path>,
+    temp_dir: Option<PathBuf>,
+    buckets_count_log: Option<usize>,
+    threads_count: usize,
+    default_compression_level: Option<u32>,
+    output_function: impl FnMut(u64, &[u8], &[(usize, usize, ColorIndexType)]) + Send,
+) -> anyhow::Result<()> {
+    let builder = Mutex::new(MaximalUnitigsBuilder::new(k, output_function));
+
+    dump_indexed_unitigs(
+        k,
+        m,
+        graph_input,
+        temp_dir,
+        buckets_count_log,
+        threads_count,
+        false,
+        default_compression_level,
+        None,
+        |info, sequence, _, _| builder.lock().add_sequence(info, sequence),
+    )?;
+
+    builder.into_inner().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::dumper_minimizer_bucketing::{
+        DumperKmersReferenceData, DumperMinimizerBucketingExecutorFactory,
+        DumperMinimizerBucketingGlobalData, ReadTypeBuffered,
+    };
+    use colors::bundles::graph_querying::ColorBundleGraphQuerying;
+    use io::concurrent::temp_reads::extra_data::SequenceExtraDataConsecutiveCompression;
+    use io::sequences_reader::{DnaSequence, DnaSequencesFileType};
+    use io::sequences_stream::SequenceInfo;
+    use minimizer_bucketing::{
+        MinimizerBucketingCommonData, MinimizerBucketingExecutor, MinimizerBucketingExecutorFactory,
+    };
+    use std::sync::Arc;
+
+    const K: usize = 5;
+
+    /// Splits a colored unitig at its color changes as the dumper bucketing does, returning the
+    /// sequences with their position info after encoding and decoding it as in the temporary buckets
+    fn split_unitig(
+        unitig_index: u64,
+        ident: &[u8],
+        sequence: &[u8],
+    ) -> Vec<(DumpedSequenceInfo, Vec<u8>)> {
+        let mut executor = DumperMinimizerBucketingExecutorFactory::<ColorBundleGraphQuerying>::new(
+            &Arc::new(MinimizerBucketingCommonData::new(
+                K,
+                3,
+                4,
+                0,
+                1,
+                DumperMinimizerBucketingGlobalData {
+                    colors_count: 16,
+                    buckets_count_log: 2,
+                },
+            )),
+        );
+        let mut preprocess_info = ReadTypeBuffered::<ColorBundleGraphQuerying>::default();
+        executor.preprocess_dna_sequence(
+            &(),
+            SequenceInfo { color: None },
+            unitig_index,
+            &DnaSequence {
+                ident_data: ident,
+                seq: sequence,
+                format: DnaSequencesFileType::FASTA,
+            },
+            &mut preprocess_info,
+        );
+
+        let mut sequences = vec![];
+        executor.process_sequence(
+            &preprocess_info,
+            sequence,
+            0..sequence.len(),
+            0,
+            2,
+            0,
+            |_, _, part: &[u8], _, data: DumperKmersReferenceData<ColorIndexType>, _| {
+                let mut buffer = vec![];
+                data.encode_extended(&(), &mut buffer, Default::default());
+                let data = DumperKmersReferenceData::<ColorIndexType>::decode_extended(
+                    &mut (),
+                    &mut buffer.as_slice(),
+                    Default::default(),
+                )
+                .unwrap();
+                sequences.push((
+                    DumpedSequenceInfo {
+                        unitig_index: data.unitig_index,
+                        kmer_offset: data.kmer_offset,
+                        is_last: data.is_last,
+                        subset: data.color,
+                    },
+                    part.to_vec(),
+                ));
+            },
+        );
+        sequences
+    }
+
+    #[test]
+    fn colored_unitigs_are_joined_back() {
+        let first = b"ACGTTGCATGCAA";
+        let second = b"GGATCCTA";
+        let mut sequences = split_unitig(0, b"0 LN:i:13 C:2:3 C:7:2 C:2:4", first);
+        assert_eq!(
+            sequences
+                .iter()
+                .map(|(info, part)| (info.kmer_offset, info.is_last, info.subset, part.len()))
+                .collect::<Vec<_>>(),
+            [(0, false, 2, 7), (3, false, 7, 6), (5, true, 2, 8)]
+        );
+        sequences.extend(split_unitig(1, b"1 LN:i:8 C:b:4", second));
+        assert_eq!(sequences[3].0.unitig_index, 1);
+
+        // The sequences are received in any order
+        sequences.swap(0, 3);
+        sequences.swap(1, 2);
+
+        let mut unitigs = vec![];
+        let mut builder = MaximalUnitigsBuilder::new(K, |index, sequence: &[u8], runs: &[_]| {
+            unitigs.push((index, sequence.to_vec(), runs.to_vec()))
+        });
+        for (info, part) in sequences {
+            builder.add_sequence(info, &part);
+        }
+        builder.finish().unwrap();
+
+        assert_eq!(
+            unitigs,
+            [
+                (1, second.to_vec(), vec![(0, 4, 0xb)]),
+                (0, first.to_vec(), vec![(0, 3, 2), (3, 5, 7), (5, 9, 2)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn incomplete_unitig_is_reported() {
+        let sequences = split_unitig(0, b"0 LN:i:13 C:2:3 C:7:2 C:2:4", b"ACGTTGCATGCAA");
+        let mut builder = MaximalUnitigsBuilder::new(K, |_, _: &[u8], _: &[_]| {});
+        for (info, part) in sequences.into_iter().skip(1) {
+            builder.add_sequence(info, &part);
+        }
+        let err = builder.finish().err().unwrap();
+        assert!(err.to_string().contains("Unitig 0"), "{}", err);
+    }
+}
@@ -1,6 +1,6 @@
 use colors::colors_manager::ColorsManager;
 use colors::storage::deserializer::ColorsDeserializer;
-use colors::storage::ColorsSerializerTrait;
+use colors::storage::mapped::ColorsMappedReader;
 use config::ColorIndexType;
 use parallel_processor::phase_times_monitor::PHASES_TIMES_MONITOR;
 use parallel_processor::utils::scoped_thread_local::ScopedThreadLocal;
@@ -8,10 +8,7 @@ use parking_lot::Mutex;
 use rayon::prelude::*;
 use std::path::PathBuf;
 
-pub fn colormap_query<
-    CX: ColorsManager<SingleKmerColorDataType = ColorIndexType>,
-    CD: ColorsSerializerTrait,
->(
+pub fn colormap_query<CX: ColorsManager<SingleKmerColorDataType = ColorIndexType>>(
     colormap_file: PathBuf,
     mut color_subsets: Vec<ColorIndexType>,
     single_thread_output_function: bool,
@@ -21,17 +18,45 @@ pub fn colormap_query<
         .write()
         .start_phase("phase: colormap query".to_string());
 
-    // Try to build a color deserializer to check colormap correctness
-    let _ = ColorsDeserializer::<CD>::new(&colormap_file, false)?;
-
-    let tlocal_colormap_decoder = ScopedThreadLocal::new(move || {
-        ColorsDeserializer::<CD>::new(&colormap_file, false).unwrap()
-    });
+    color_subsets.sort_unstable();
+    color_subsets.dedup();
 
     let single_thread_lock = Mutex::new(());
 
-    color_subsets.sort_unstable();
-    color_subsets.dedup();
+    // With the colormap index each subset is decoded independently, without scanning the colormap chunks
+    if let Some(mapped_colormap) = ColorsMappedReader::open(&colormap_file) {
+        if let Some(&subset) = color_subsets
+            .last()
+            .filter(|s| **s as u64 >= mapped_colormap.subsets_count())
+        {
+            anyhow::bail!(
+                "Color subset {} not found, the colormap has {} subsets",
+                subset,
+                mapped_colormap.subsets_count()
+            );
+        }
+
+        color_subsets
+            .par_iter()
+            .for_each_init(Vec::new, |temp_colors_buffer, &color| {
+                mapped_colormap.get_color_mappings(color, temp_colors_buffer);
+
+                let _lock = if single_thread_output_function {
+                    Some(single_thread_lock.lock())
+                } else {
+                    None
+                };
+
+                output_function(color, &temp_colors_buffer[..]);
+            });
+        return Ok(());
+    }
+
+    // Try to build a color deserializer to check colormap correctness
+    let _ = ColorsDeserializer::new(&colormap_file, false)?;
+
+    let tlocal_colormap_decoder =
+        ScopedThreadLocal::new(move || ColorsDeserializer::new(&colormap_file, false).unwrap());
 
     let threads_count = rayon::current_num_threads();
 
@@ -40,13 +65,13 @@ pub fn colormap_query<
     color_subsets
         .chunks(chunks_size)
         .par_bridge()
-        .for_each(|subsets| {
+        .try_for_each(|subsets| {
             let mut colormap_decoder = tlocal_colormap_decoder.get();
             let mut temp_colors_buffer = Vec::new();
 
             for &color in subsets {
                 temp_colors_buffer.clear();
-                colormap_decoder.get_color_mappings(color, &mut temp_colors_buffer);
+                colormap_decoder.get_color_mappings(color, &mut temp_colors_buffer)?;
 
                 let _lock = if single_thread_output_function {
                     Some(single_thread_lock.lock())
@@ -56,6 +81,6 @@ pub fn colormap_query<
 
                 output_function(color, &temp_colors_buffer[..]);
             }
-        });
-    Ok(())
+            anyhow::Ok(())
+        })
 }
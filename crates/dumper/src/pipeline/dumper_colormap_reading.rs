@@ -1,8 +1,8 @@
+use crate::colors_predicate::ColorsPredicate;
 use crate::pipeline::dumper_minimizer_bucketing::DumperKmersReferenceData;
 use colors::colors_manager::color_types::SingleKmerColorDataType;
 use colors::colors_manager::ColorsManager;
 use colors::storage::deserializer::ColorsDeserializer;
-use colors::storage::ColorsSerializerTrait;
 use config::{ColorIndexType, DEFAULT_PREFETCH_AMOUNT, KEEP_FILES};
 use io::compressed_read::CompressedReadIndipendent;
 use io::concurrent::temp_reads::creads_utils::{
@@ -22,29 +22,40 @@ use std::marker::PhantomData;
 use std::path::PathBuf;
 use std::sync::atomic::Ordering;
 
-pub fn colormap_reading<
-    CX: ColorsManager<SingleKmerColorDataType = ColorIndexType>,
-    CD: ColorsSerializerTrait,
->(
+/// Position in the graph and color subset of a dumped sequence
+#[derive(Copy, Clone, Debug)]
+pub struct DumpedSequenceInfo {
+    /// Index of the unitig in the graph file
+    pub unitig_index: u64,
+    /// Index of the first kmer of the sequence in the unitig
+    pub kmer_offset: u64,
+    /// Whether the sequence is the last one of the unitig
+    pub is_last: bool,
+    pub subset: ColorIndexType,
+}
+
+pub fn colormap_reading<CX: ColorsManager<SingleKmerColorDataType = ColorIndexType>>(
     colormap_file: PathBuf,
     colored_unitigs_buckets: Vec<SingleBucket>,
     single_thread_output_function: bool,
-    output_function: impl Fn(&[u8], &[ColorIndexType], bool) + Send + Sync,
+    // Checked once on each subset found in a bucket, the sequences of the subsets not matching it are skipped without decompressing them
+    colors_predicate: Option<&ColorsPredicate>,
+    // Called with the position of the sequence in the graph, the sequence, the colors and whether they are the same of the last call
+    output_function: impl Fn(DumpedSequenceInfo, &[u8], &[ColorIndexType], bool) + Send + Sync,
 ) -> anyhow::Result<()> {
     PHASES_TIMES_MONITOR
         .write()
         .start_phase("phase: colormap reading".to_string());
 
     // Try to build a color deserializer to check colormap correctness
-    let _ = ColorsDeserializer::<CD>::new(&colormap_file, false)?;
+    let _ = ColorsDeserializer::new(&colormap_file, false)?;
 
-    let tlocal_colormap_decoder = ScopedThreadLocal::new(move || {
-        ColorsDeserializer::<CD>::new(&colormap_file, false).unwrap()
-    });
+    let tlocal_colormap_decoder =
+        ScopedThreadLocal::new(move || ColorsDeserializer::new(&colormap_file, false).unwrap());
 
     let single_thread_lock = Mutex::new(());
 
-    colored_unitigs_buckets.par_iter().for_each(|input| {
+    colored_unitigs_buckets.par_iter().try_for_each(|input| {
         let mut colormap_decoder = tlocal_colormap_decoder.get();
         let mut temp_colors_buffer = Vec::new();
         let mut temp_decompressed_sequence = Vec::new();
@@ -89,7 +100,7 @@ pub fn colormap_reading<
                     DumperKmersReferenceData<SingleKmerColorDataType<CX>>,
                 ),
             ) -> std::cmp::Ordering {
-                left.1.cmp(&right.1)
+                left.1.color.cmp(&right.1.color)
             }
 
             fn get_shifted(
@@ -105,10 +116,14 @@ pub fn colormap_reading<
 
         fast_smart_radix_sort::<_, ColoredUnitigsCompare<CX>, false>(&mut temp_sequences[..]);
 
-        for unitigs_by_color in temp_sequences.nq_group_by_mut(|a, b| a.1 == b.1) {
+        for unitigs_by_color in temp_sequences.nq_group_by_mut(|a, b| a.1.color == b.1.color) {
             let color = unitigs_by_color[0].1.color;
             temp_colors_buffer.clear();
-            colormap_decoder.get_color_mappings(color, &mut temp_colors_buffer);
+            colormap_decoder.get_color_mappings(color, &mut temp_colors_buffer)?;
+
+            if colors_predicate.is_some_and(|predicate| !predicate.matches(&temp_colors_buffer)) {
+                continue;
+            }
 
             let mut same_color = false;
 
@@ -122,10 +137,20 @@ pub fn colormap_reading<
                 let read = unitig.0.as_reference(&temp_bases);
                 temp_decompressed_sequence.clear();
                 temp_decompressed_sequence.extend(read.as_bases_iter());
-                output_function(&temp_decompressed_sequence, &temp_colors_buffer, same_color);
+                output_function(
+                    DumpedSequenceInfo {
+                        unitig_index: unitig.1.unitig_index,
+                        kmer_offset: unitig.1.kmer_offset,
+                        is_last: unitig.1.is_last,
+                        subset: color,
+                    },
+                    &temp_decompressed_sequence,
+                    &temp_colors_buffer,
+                    same_color,
+                );
                 same_color = true;
             }
         }
-    });
-    Ok(())
+        anyhow::Ok(())
+    })
 }
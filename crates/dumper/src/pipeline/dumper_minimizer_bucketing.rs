@@ -1,3 +1,4 @@
+use byteorder::ReadBytesExt;
 use colors::colors_manager::color_types::{
     MinimizerBucketingSeqColorDataType, SingleKmerColorDataType,
 };
@@ -11,6 +12,7 @@ use io::concurrent::temp_reads::extra_data::{
 use io::sequences_reader::{DnaSequence, DnaSequencesFileType};
 use io::sequences_stream::fasta::FastaFileSequencesStream;
 use io::sequences_stream::SequenceInfo;
+use io::varint::{decode_varint, encode_varint, VARINT_MAX_SIZE};
 use minimizer_bucketing::resplit_bucket::RewriteBucketCompute;
 use minimizer_bucketing::{
     GenericMinimizerBucketing, MinimizerBucketingCommonData, MinimizerBucketingExecutor,
@@ -30,6 +32,12 @@ pub struct DumperKmersReferenceData<
     CX: SequenceExtraDataConsecutiveCompression<TempBuffer = ()> + Clone + FastSortable,
 > {
     pub(crate) color: CX,
+    /// Index of the unitig in the graph file
+    pub(crate) unitig_index: u64,
+    /// Index of the first kmer of the sequence in the unitig
+    pub(crate) kmer_offset: u64,
+    /// Whether the sequence is the last one of the unitig
+    pub(crate) is_last: bool,
 }
 
 impl<CX: SequenceExtraDataConsecutiveCompression<TempBuffer = ()> + Clone + FastSortable>
@@ -48,8 +56,14 @@ impl<CX: SequenceExtraDataConsecutiveCompression<TempBuffer = ()> + Clone + Fast
         reader: &mut impl Read,
         last_data: CX::LastData,
     ) -> Option<Self> {
+        let color = CX::decode_extended(&mut (), reader, last_data)?;
+        let unitig_index = decode_varint(|| reader.read_u8().ok())?;
+        let kmer_offset = decode_varint(|| reader.read_u8().ok())?;
         Some(Self {
-            color: CX::decode_extended(&mut (), reader, last_data)?,
+            color,
+            unitig_index,
+            kmer_offset: kmer_offset >> 1,
+            is_last: kmer_offset & 1 != 0,
         })
     }
 
@@ -61,11 +75,17 @@ impl<CX: SequenceExtraDataConsecutiveCompression<TempBuffer = ()> + Clone + Fast
         last_data: CX::LastData,
     ) {
         CX::encode_extended(&self.color, &(), writer, last_data);
+        encode_varint(|b| writer.write(b).ok(), self.unitig_index).unwrap();
+        encode_varint(
+            |b| writer.write(b).ok(),
+            (self.kmer_offset << 1) | self.is_last as u64,
+        )
+        .unwrap();
     }
 
     #[inline(always)]
     fn max_size(&self) -> usize {
-        self.color.max_size()
+        self.color.max_size() + 2 * VARINT_MAX_SIZE
     }
 
     fn obtain_last_data(&self, last_data: Self::LastData) -> Self::LastData {
@@ -81,6 +101,7 @@ pub struct ReadTypeBuffered<CX: ColorsManager> {
 #[derive(Clone)]
 pub struct ReadData<CX: ColorsManager> {
     colors: MinimizerBucketingSeqColorDataType<CX>,
+    unitig_index: u64,
 }
 
 impl<CX: ColorsManager> Default for ReadTypeBuffered<CX> {
@@ -93,8 +114,8 @@ impl<CX: ColorsManager> Default for ReadTypeBuffered<CX> {
 }
 
 pub struct DumperMinimizerBucketingGlobalData {
-    colors_count: u64,
-    buckets_count_log: u32,
+    pub(crate) colors_count: u64,
+    pub(crate) buckets_count_log: u32,
 }
 
 pub struct DumperMinimizerBucketingExecutor<CX: ColorsManager> {
@@ -157,7 +178,7 @@ impl<CX: ColorsManager> MinimizerBucketingExecutor<DumperMinimizerBucketingExecu
         &mut self,
         _stream_info: &<DumperMinimizerBucketingExecutorFactory<CX> as MinimizerBucketingExecutorFactory>::StreamInfo,
         sequence_info: SequenceInfo,
-        _read_index: u64,
+        read_index: u64,
         sequence: &DnaSequence,
         preprocess_info: &mut <DumperMinimizerBucketingExecutorFactory<CX> as MinimizerBucketingExecutorFactory>::PreprocessInfo,
     ) {
@@ -198,7 +219,10 @@ impl<CX: ColorsManager> MinimizerBucketingExecutor<DumperMinimizerBucketingExecu
                     );
                 }
 
-                Some(ReadData { colors: color })
+                Some(ReadData {
+                    colors: color,
+                    unitig_index: read_index,
+                })
             }
         }
     }
@@ -226,10 +250,8 @@ impl<CX: ColorsManager> MinimizerBucketingExecutor<DumperMinimizerBucketingExecu
         _second_bits: usize,
         mut push_sequence: F,
     ){
-        let mut rolling_iter = preprocess_info
-            .read_data
-            .as_ref()
-            .unwrap()
+        let read_data = preprocess_info.read_data.as_ref().unwrap();
+        let mut rolling_iter = read_data
             .colors
             .get_iterator(&preprocess_info.colors_buffer.0);
 
@@ -247,7 +269,12 @@ impl<CX: ColorsManager> MinimizerBucketingExecutor<DumperMinimizerBucketingExecu
                     0,
                     sequence.get_subslice(last_index..(index + self.global_data.k)),
                     0,
-                    DumperKmersReferenceData { color: last_color },
+                    DumperKmersReferenceData {
+                        color: last_color,
+                        unitig_index: read_data.unitig_index,
+                        kmer_offset: last_index as u64,
+                        is_last: false,
+                    },
                     &(),
                 );
 
@@ -265,7 +292,12 @@ impl<CX: ColorsManager> MinimizerBucketingExecutor<DumperMinimizerBucketingExecu
             0,
             sequence.get_subslice(last_index..sequence.seq_len()),
             0,
-            DumperKmersReferenceData { color: last_color },
+            DumperKmersReferenceData {
+                color: last_color,
+                unitig_index: read_data.unitig_index,
+                kmer_offset: last_index as u64,
+                is_last: true,
+            },
             &(),
         );
     }
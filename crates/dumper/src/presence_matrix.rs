@@ -0,0 +1,310 @@
+use crate::dump_indexed_unitigs;
+use config::ColorIndexType;
+use hashbrown::HashMap;
+use parking_lot::Mutex;
+use std::path::{Path, PathBuf};
+
+/// Pattern of the unitigs not yet seen in the graph
+const NO_PATTERN: u32 = u32::MAX;
+
+/// The distinct sets of colors (patterns) of the unitigs, each one stored once
+#[derive(Default)]
+struct ColorPatterns {
+    patterns: Vec<Box<[ColorIndexType]>>,
+    patterns_map: HashMap<Box<[ColorIndexType]>, u32>,
+}
+
+impl ColorPatterns {
+    fn get_id(&mut self, colors: &[ColorIndexType]) -> u32 {
+        if let Some(pattern) = self.patterns_map.get(colors) {
+            return *pattern;
+        }
+        let pattern = self.patterns.len() as u32;
+        self.patterns.push(colors.into());
+        self.patterns_map.insert(colors.into(), pattern);
+        pattern
+    }
+}
+
+/// The colors of a unitig split in more sequences, received so far
+#[derive(Default)]
+struct PartialUnitig {
+    colors: Vec<ColorIndexType>,
+    kmers_count: u64,
+    /// Known after receiving the last sequence of the unitig
+    total_kmers_count: Option<u64>,
+}
+
+/// Adds the sorted colors to the sorted colors of a unitig
+fn merge_colors(
+    unitig_colors: &mut Vec<ColorIndexType>,
+    colors: &[ColorIndexType],
+    buffer: &mut Vec<ColorIndexType>,
+) {
+    buffer.clear();
+    let (mut first_colors, mut second_colors) =
+        (unitig_colors.iter().peekable(), colors.iter().peekable());
+    while let (Some(first_color), Some(second_color)) = (first_colors.peek(), second_colors.peek())
+    {
+        let color = (**first_color).min(**second_color);
+        if **first_color == color {
+            first_colors.next();
+        }
+        if **second_color == color {
+            second_colors.next();
+        }
+        buffer.push(color);
+    }
+    buffer.extend(first_colors.chain(second_colors));
+    std::mem::swap(unitig_colors, buffer);
+}
+
+/// Collects the pattern of each unitig from its dumped sequences. Only the final patterns of the
+/// unitigs are stored, the colors of the unitigs split in more sequences are kept apart until all
+/// their sequences are received
+#[derive(Default)]
+struct PresenceMatrixBuilder {
+    patterns: ColorPatterns,
+    /// The pattern of each unitig, by unitig index
+    unitigs: Vec<u32>,
+    partial_unitigs: HashMap<u64, PartialUnitig>,
+    /// The pattern of the last dumped sequence, reused when the colors are the same
+    last_pattern: u32,
+    merge_buffer: Vec<ColorIndexType>,
+}
+
+impl PresenceMatrixBuilder {
+    fn new() -> Self {
+        Self {
+            last_pattern: NO_PATTERN,
+            ..Default::default()
+        }
+    }
+
+    fn set_pattern(&mut self, unitig_index: u64, pattern: u32) {
+        let unitig_index = unitig_index as usize;
+        if self.unitigs.len() <= unitig_index {
+            self.unitigs.resize(unitig_index + 1, NO_PATTERN);
+        }
+        self.unitigs[unitig_index] = pattern;
+    }
+
+    /// Adds a dumped sequence of a unitig, with its kmers count and its sorted colors
+    fn add_sequence(
+        &mut self,
+        unitig_index: u64,
+        kmer_offset: u64,
+        is_last: bool,
+        kmers_count: u64,
+        colors: &[ColorIndexType],
+        same_colors: bool,
+    ) {
+        if kmer_offset == 0 && is_last {
+            // The unitig has a single color subset
+            if !same_colors || self.last_pattern == NO_PATTERN {
+                self.last_pattern = self.patterns.get_id(colors);
+            }
+            self.set_pattern(unitig_index, self.last_pattern);
+            return;
+        }
+        if !same_colors {
+            self.last_pattern = NO_PATTERN;
+        }
+
+        // A unitig split in sequences with different colors is present in all of them
+        let partial_unitig = self.partial_unitigs.entry(unitig_index).or_default();
+        merge_colors(&mut partial_unitig.colors, colors, &mut self.merge_buffer);
+        partial_unitig.kmers_count += kmers_count;
+        if is_last {
+            partial_unitig.total_kmers_count = Some(kmer_offset + kmers_count);
+        }
+
+        if partial_unitig.total_kmers_count == Some(partial_unitig.kmers_count) {
+            let partial_unitig = self.partial_unitigs.remove(&unitig_index).unwrap();
+            let pattern = self.patterns.get_id(&partial_unitig.colors);
+            self.set_pattern(unitig_index, pattern);
+        }
+    }
+
+    /// Calls the output function for each row of the matrix, see `dump_presence_matrix`
+    fn write_rows(
+        self,
+        collapse_patterns: bool,
+        mut output_function: impl FnMut(&[u64], &[ColorIndexType]),
+    ) -> anyhow::Result<()> {
+        if let Some(unitig_index) = self.partial_unitigs.keys().next() {
+            anyhow::bail!(
+                "Unitig {} was not dumped completely, please check the k-mers length",
+                unitig_index
+            );
+        }
+        let patterns = &self.patterns.patterns;
+
+        if collapse_patterns {
+            let mut rows_map = vec![NO_PATTERN; patterns.len()];
+            let mut rows: Vec<(u32, Vec<u64>)> = vec![];
+            for (unitig, pattern) in self.unitigs.iter().enumerate() {
+                if *pattern == NO_PATTERN {
+                    continue;
+                }
+                let row = &mut rows_map[*pattern as usize];
+                if *row == NO_PATTERN {
+                    *row = rows.len() as u32;
+                    rows.push((*pattern, vec![]));
+                }
+                rows[*row as usize].1.push(unitig as u64);
+            }
+
+            for (pattern, row_unitigs) in rows {
+                output_function(&row_unitigs, &patterns[pattern as usize]);
+            }
+        } else {
+            for (unitig, pattern) in self.unitigs.iter().enumerate() {
+                if *pattern != NO_PATTERN {
+                    output_function(&[unitig as u64], &patterns[*pattern as usize]);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Computes the unitigs x colors presence/absence matrix of a colored graph, where a unitig is present
+/// in a color if any of its kmers has that color. The unitigs are identified by their index in the graph.
+/// The output function is called once for each row, with the unitigs of the row and their sorted colors:
+/// a row for each unitig, in the graph order, or, if `collapse_patterns` is set, a row for each distinct
+/// set of colors with all the unitigs having it, in the order of their first unitig.
+/// The rows are written after reading the whole graph, keeping in memory a pattern index for each unitig
+/// and each distinct set of colors of the unitigs once, bounded by the number of unitigs
+pub fn dump_presence_matrix(
+    k: usize,
+    m: usize,
+    graph_input: impl AsRef<Path>,
+    temp_dir: Option<PathBuf>,
+    buckets_count_log: Option<usize>,
+    threads_count: usize,
+    default_compression_level: Option<u32>,
+    collapse_patterns: bool,
+    output_function: impl FnMut(&[u64], &[ColorIndexType]),
+) -> anyhow::Result<()> {
+    let builder = Mutex::new(PresenceMatrixBuilder::new());
+
+    dump_indexed_unitigs(
+        k,
+        m,
+        graph_input,
+        temp_dir,
+        buckets_count_log,
+        threads_count,
+        true,
+        default_compression_level,
+        None,
+        |info, sequence, colors, same_colors| {
+            builder.lock().add_sequence(
+                info.unitig_index,
+                info.kmer_offset,
+                info.is_last,
+                (sequence.len() + 1 - k) as u64,
+                colors,
+                same_colors,
+            );
+        },
+    )?;
+
+    builder
+        .into_inner()
+        .write_rows(collapse_patterns, output_function)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(
+        builder: PresenceMatrixBuilder,
+        collapse_patterns: bool,
+    ) -> Vec<(Vec<u64>, Vec<ColorIndexType>)> {
+        let mut rows = vec![];
+        builder
+            .write_rows(collapse_patterns, |unitigs, colors| {
+                rows.push((unitigs.to_vec(), colors.to_vec()))
+            })
+            .unwrap();
+        rows
+    }
+
+    /// Unitig 2 with the colors [0, 2], unitig 0 with [1] and unitig 3 split in three sequences
+    fn test_builder() -> PresenceMatrixBuilder {
+        let mut builder = PresenceMatrixBuilder::new();
+        builder.add_sequence(2, 0, true, 5, &[0, 2], false);
+        builder.add_sequence(3, 4, false, 2, &[2, 3], false);
+        builder.add_sequence(0, 0, true, 3, &[1], false);
+        builder.add_sequence(3, 6, true, 4, &[1], true);
+        builder.add_sequence(3, 0, false, 4, &[0, 3], false);
+        builder
+    }
+
+    #[test]
+    fn split_unitigs_colors_union() {
+        let builder = test_builder();
+        // The partial colors of unitig 3 are not stored as patterns
+        assert_eq!(builder.patterns.patterns.len(), 3);
+        assert!(builder.partial_unitigs.is_empty());
+
+        assert_eq!(
+            rows(builder, false),
+            vec![
+                (vec![0], vec![1]),
+                (vec![2], vec![0, 2]),
+                (vec![3], vec![0, 1, 2, 3]),
+            ]
+        );
+    }
+
+    #[test]
+    fn collapsed_patterns_rows() {
+        let mut builder = test_builder();
+        builder.add_sequence(5, 0, true, 2, &[0, 2], false);
+        builder.add_sequence(4, 0, true, 2, &[0, 2], true);
+        builder.add_sequence(1, 0, true, 2, &[0, 1, 2, 3], false);
+
+        // Numbered in the order of their first unitig
+        assert_eq!(
+            rows(builder, true),
+            vec![
+                (vec![0], vec![1]),
+                (vec![1, 3], vec![0, 1, 2, 3]),
+                (vec![2, 4, 5], vec![0, 2]),
+            ]
+        );
+    }
+
+    #[test]
+    fn same_colors_reuse_the_last_pattern() {
+        let mut builder = PresenceMatrixBuilder::new();
+        builder.add_sequence(0, 0, false, 2, &[4], false);
+        // Same colors of a split unitig, not stored as a pattern
+        builder.add_sequence(1, 0, true, 2, &[4], true);
+        builder.add_sequence(0, 2, true, 2, &[5], false);
+        builder.add_sequence(2, 0, true, 2, &[5], true);
+
+        assert_eq!(
+            rows(builder, false),
+            vec![
+                (vec![0], vec![4, 5]),
+                (vec![1], vec![4]),
+                (vec![2], vec![5]),
+            ]
+        );
+    }
+
+    #[test]
+    fn incomplete_unitig_is_reported() {
+        let mut builder = PresenceMatrixBuilder::new();
+        builder.add_sequence(0, 0, true, 2, &[1], false);
+        builder.add_sequence(1, 3, true, 2, &[1], false);
+
+        let err = builder.write_rows(false, |_, _| {}).err().unwrap();
+        assert!(err.to_string().contains("Unitig 1"), "{}", err);
+    }
+}
@@ -0,0 +1,256 @@
+use crate::edit_colors::parse_color_run;
+use colors::colors_manager::ColorMapReader;
+use colors::storage::deserializer::ColorsDeserializer;
+use config::ColorIndexType;
+use io::sequences_reader::SequencesReader;
+use std::path::Path;
+
+/// Shared kmers between all the pairs of colors of a graph
+#[derive(Clone, Debug, Default)]
+pub struct ColorsSimilarity {
+    color_names: Vec<String>,
+    /// The kmers count of each color
+    kmers: Vec<u64>,
+    /// The kmers shared by each pair of different colors, as the upper triangle of the matrix in row order
+    intersections: Vec<u64>,
+}
+
+impl ColorsSimilarity {
+    fn pair_index(&self, first: usize, second: usize) -> usize {
+        let (first, second) = (first.min(second), first.max(second));
+        let colors_count = self.kmers.len();
+        first * colors_count - first * (first + 1) / 2 + (second - first - 1)
+    }
+
+    pub fn colors_count(&self) -> usize {
+        self.kmers.len()
+    }
+
+    pub fn color_names(&self) -> &[String] {
+        &self.color_names
+    }
+
+    pub fn kmers_count(&self, color: ColorIndexType) -> u64 {
+        self.kmers[color as usize]
+    }
+
+    /// The kmers present in both the colors
+    pub fn intersection(&self, first: ColorIndexType, second: ColorIndexType) -> u64 {
+        let (first, second) = (first as usize, second as usize);
+        if first == second {
+            self.kmers[first]
+        } else {
+            self.intersections[self.pair_index(first, second)]
+        }
+    }
+
+    /// The kmers present in both the colors divided by the kmers present in any of them
+    pub fn jaccard(&self, first: ColorIndexType, second: ColorIndexType) -> f64 {
+        let intersection = self.intersection(first, second);
+        let union = self.kmers_count(first) + self.kmers_count(second) - intersection;
+        if union == 0 {
+            0.0
+        } else {
+            intersection as f64 / union as f64
+        }
+    }
+
+    /// The fraction of the kmers of the first color that are also present in the second one
+    pub fn containment(&self, first: ColorIndexType, second: ColorIndexType) -> f64 {
+        let kmers = self.kmers_count(first);
+        if kmers == 0 {
+            0.0
+        } else {
+            self.intersection(first, second) as f64 / kmers as f64
+        }
+    }
+}
+
+/// Computes the kmers shared by all the pairs of colors of a colored graph, adding up the kmers count
+/// of each color subset from the graph and then reading each subset of the colormap once
+pub fn compute_colors_similarity(
+    graph_input: impl AsRef<Path>,
+    k: usize,
+) -> anyhow::Result<ColorsSimilarity> {
+    let graph_input = graph_input.as_ref();
+    if !graph_input.is_file() {
+        anyhow::bail!("Cannot find graph file {}", graph_input.display());
+    }
+
+    let colormap_file = graph_input.with_extension("colors.dat");
+    let mut deserializer = ColorsDeserializer::new(&colormap_file, true)?;
+    deserializer
+        .build_params()
+        .check_graph(&colormap_file, k, None)?;
+
+    let subsets_count = deserializer.colors_subsets_count() as usize;
+    let mut subsets_kmers = vec![0u64; subsets_count];
+
+    let mut error = None;
+    SequencesReader::new().process_file_extended(
+        graph_input,
+        |seq| {
+            if error.is_some() {
+                return;
+            }
+            for tag in seq.ident_data.split(|c| *c == b' ') {
+                if !tag.starts_with(b"C:") {
+                    continue;
+                }
+                match parse_color_run(tag) {
+                    Some((subset, count)) if (subset as usize) < subsets_count => {
+                        subsets_kmers[subset as usize] += count
+                    }
+                    _ => {
+                        error = Some(anyhow::anyhow!(
+                            "Invalid color tag {} in graph {}",
+                            String::from_utf8_lossy(tag),
+                            graph_input.display()
+                        ));
+                        return;
+                    }
+                }
+            }
+        },
+        None,
+        true,
+        false,
+    );
+    if let Some(error) = error {
+        return Err(error);
+    }
+
+    let colors_count = deserializer.colors_count();
+    let mut similarity = ColorsSimilarity {
+        color_names: (0..colors_count)
+            .map(|color| {
+                deserializer
+                    .get_color_name(color as ColorIndexType, false)
+                    .to_string()
+            })
+            .collect(),
+        kmers: vec![0; colors_count],
+        intersections: vec![0; colors_count * colors_count.saturating_sub(1) / 2],
+    };
+
+    let mut colors = vec![];
+    for (subset, kmers) in subsets_kmers.into_iter().enumerate() {
+        if kmers == 0 {
+            continue;
+        }
+        deserializer.get_color_mappings(subset as ColorIndexType, &mut colors)?;
+        for (position, first) in colors.iter().enumerate() {
+            similarity.kmers[*first as usize] += kmers;
+            for second in &colors[position + 1..] {
+                let pair = similarity.pair_index(*first as usize, *second as usize);
+                similarity.intersections[pair] += kmers;
+            }
+        }
+    }
+
+    Ok(similarity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::edit_colors::write_test_graph;
+    use std::path::PathBuf;
+
+    const K: usize = 5;
+
+    fn temp_graph(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "ggcat-similarity-{}-{}.fa",
+            name,
+            std::process::id()
+        ))
+    }
+
+    fn remove_graph(path: &Path) {
+        let _ = std::fs::remove_file(path);
+        let _ = std::fs::remove_file(path.with_extension("colors.dat"));
+    }
+
+    #[test]
+    fn pairs_similarity() {
+        let graph = temp_graph("pairs");
+        write_test_graph(
+            &graph,
+            K,
+            &["a", "b", "c", "d"],
+            &[
+                ("ACCTGAGT", &[(&["a", "b"], 4)]),
+                ("GAGTCCAT", &[(&["a"], 4)]),
+                ("TTAGCCGATA", &[(&["a"], 2), (&["c"], 2), (&["a", "b"], 2)]),
+            ],
+        );
+        let similarity = compute_colors_similarity(&graph, K).unwrap();
+        remove_graph(&graph);
+
+        assert_eq!(similarity.colors_count(), 4);
+        assert_eq!(similarity.color_names(), ["a", "b", "c", "d"]);
+        assert_eq!(
+            (0..4)
+                .map(|color| similarity.kmers_count(color))
+                .collect::<Vec<_>>(),
+            [12, 6, 2, 0]
+        );
+        assert_eq!(similarity.intersection(0, 1), 6);
+        assert_eq!(similarity.intersection(1, 0), 6);
+        assert_eq!(similarity.intersection(0, 2), 0);
+        assert_eq!(similarity.intersection(2, 2), 2);
+
+        assert_eq!(similarity.jaccard(0, 1), 0.5);
+        assert_eq!(similarity.jaccard(1, 1), 1.0);
+        assert_eq!(similarity.jaccard(0, 2), 0.0);
+        assert_eq!(similarity.containment(1, 0), 1.0);
+        assert_eq!(similarity.containment(0, 1), 0.5);
+        // Colors without kmers
+        assert_eq!(similarity.jaccard(3, 3), 0.0);
+        assert_eq!(similarity.containment(3, 0), 0.0);
+    }
+
+    #[test]
+    fn pairs_are_indexed_once() {
+        let similarity = ColorsSimilarity {
+            kmers: vec![0; 5],
+            intersections: vec![0; 10],
+            ..Default::default()
+        };
+        let mut indexes: Vec<_> = (0..5)
+            .flat_map(|first| (first + 1..5).map(move |second| (first, second)))
+            .map(|(first, second)| {
+                assert_eq!(
+                    similarity.pair_index(first, second),
+                    similarity.pair_index(second, first)
+                );
+                similarity.pair_index(first, second)
+            })
+            .collect();
+        indexes.sort_unstable();
+        assert_eq!(indexes, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn invalid_color_subset_is_reported() {
+        let graph = temp_graph("invalid");
+        write_test_graph(&graph, K, &["a"], &[("ACCTGAGT", &[(&["a"], 4)])]);
+        std::fs::write(&graph, ">0 LN:i:8 C:5:4\nACCTGAGT\n").unwrap();
+
+        let err = compute_colors_similarity(&graph, K).err().unwrap();
+        assert!(
+            err.to_string().contains("Invalid color tag C:5:4"),
+            "{}",
+            err
+        );
+        remove_graph(&graph);
+
+        let err = compute_colors_similarity(&graph, K).err().unwrap();
+        assert!(
+            err.to_string().contains("Cannot find graph file"),
+            "{}",
+            err
+        );
+    }
+}
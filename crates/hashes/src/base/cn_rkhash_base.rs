@@ -176,6 +176,7 @@ impl HashFunctionFactory for CanonicalRabinKarpHashFactory {
 
     const NULL_BASE: u8 = 0;
     const USABLE_HASH_BITS: usize = size_of::<Self::HashTypeUnextendable>() * 8 - 1; // -1 because the hash is always odd
+    const NAME: &'static str = module_path!();
 
     fn initialize(k: usize) {
         unsafe {
@@ -116,6 +116,7 @@ impl HashFunctionFactory for CanonicalSeqHashFactory {
 
     const NULL_BASE: u8 = 0;
     const USABLE_HASH_BITS: usize = size_of::<Self::HashTypeUnextendable>() * 8;
+    const NAME: &'static str = module_path!();
 
     fn initialize(_k: usize) {}
 
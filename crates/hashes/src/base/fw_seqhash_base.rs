@@ -104,6 +104,7 @@ impl HashFunctionFactory for ForwardSeqHashFactory {
 
     const NULL_BASE: u8 = 0;
     const USABLE_HASH_BITS: usize = size_of::<Self::HashTypeUnextendable>() * 8;
+    const NAME: &'static str = module_path!();
 
     fn initialize(_k: usize) {}
 
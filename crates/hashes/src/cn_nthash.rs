@@ -118,6 +118,7 @@ impl HashFunctionFactory for CanonicalNtHashIteratorFactory {
     // Corresponds to 'N' hash (zero)
     const NULL_BASE: u8 = 4;
     const USABLE_HASH_BITS: usize = size_of::<Self::HashTypeUnextendable>() * 8;
+    const NAME: &'static str = module_path!();
 
     fn initialize(_k: usize) {}
 
@@ -81,6 +81,9 @@ pub trait HashFunctionFactory: Sized + Clone + Debug + Send + Sync + 'static {
     const NULL_BASE: u8;
     const USABLE_HASH_BITS: usize;
 
+    /// Identifies the hash function, to check that a graph is read with the one used to build it
+    const NAME: &'static str;
+
     fn initialize(k: usize);
     fn new<N: HashableSequence>(seq: N, k: usize) -> Self::HashIterator<N>;
 
@@ -63,6 +63,7 @@ impl SequencesReader {
     ) {
         const FASTQ_EXTS: &[&str] = &["fq", "fastq"];
         const FASTA_EXTS: &[&str] = &["fa", "fasta", "fna", "ffn"];
+        const GFA_EXTS: &[&str] = &["gfa"];
 
         let mut file_type = None;
         let mut tmp = source.as_ref().file_name().unwrap().to_str().unwrap();
@@ -77,6 +78,10 @@ impl SequencesReader {
                 file_type = Some(DnaSequencesFileType::FASTA);
                 break;
             }
+            if GFA_EXTS.contains(&ext.to_str().unwrap()) {
+                file_type = Some(DnaSequencesFileType::GFA);
+                break;
+            }
             tmp = &tmp[0..tmp.len() - ext.len() - 1];
             path = tmp.as_ref()
         }
@@ -94,7 +99,7 @@ impl SequencesReader {
                     self.process_fastq(source, func, remove_file);
                 }
                 DnaSequencesFileType::GFA => {
-                    todo!()
+                    self.process_gfa(source, func, copy_ident, remove_file);
                 }
                 DnaSequencesFileType::BINARY => {
                     todo!()
@@ -178,6 +183,54 @@ impl SequencesReader {
         );
     }
 
+    /// Reads the sequences of the segment lines, in the third field for GFA1 (S <name> <sequence>)
+    /// and in the fourth one for GFA2 (S <name> <length> <sequence>), the ident is the segment name
+    fn process_gfa(
+        &mut self,
+        source: impl AsRef<Path>,
+        mut func: impl FnMut(DnaSequence),
+        copy_ident: bool,
+        remove_file: bool,
+    ) {
+        let mut line_buffer = Vec::new();
+        let mut sequence = Vec::new();
+
+        self.lines_reader.process_lines(
+            source,
+            |line: &[u8], partial, _finished| {
+                line_buffer.extend_from_slice(line);
+                if partial {
+                    return;
+                }
+
+                let mut fields = line_buffer.split(|c| *c == b'\t');
+                if fields.next() == Some(b"S") {
+                    let name = fields.next().unwrap_or(&[]);
+                    let mut seq = fields.next().unwrap_or(&[]);
+                    if let Some(gfa2_seq) = fields
+                        .next()
+                        .filter(|_| !seq.is_empty() && seq.iter().all(|c| c.is_ascii_digit()))
+                    {
+                        seq = gfa2_seq;
+                    }
+
+                    if !seq.is_empty() && seq != b"*" {
+                        sequence.clear();
+                        sequence.extend_from_slice(seq);
+                        Self::normalize_sequence(&mut sequence);
+                        func(DnaSequence {
+                            ident_data: if copy_ident { name } else { &[] },
+                            seq: &sequence,
+                            format: DnaSequencesFileType::GFA,
+                        });
+                    }
+                }
+                line_buffer.clear();
+            },
+            remove_file,
+        );
+    }
+
     fn process_fastq(
         &mut self,
         source: impl AsRef<Path>,
@@ -25,6 +25,7 @@ use parallel_processor::phase_times_monitor::PHASES_TIMES_MONITOR;
 use parking_lot::Mutex;
 use reads_buffer::ReadsVector;
 use std::cmp::{max, min};
+use std::collections::HashSet;
 use std::marker::PhantomData;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
@@ -142,6 +143,8 @@ pub struct KmersTransformContext<F: KmersTransformExecutorFactory> {
     read_threads_count: usize,
     max_second_buckets_count_log2: usize,
     temp_dir: PathBuf,
+    /// Input files that are read without being removed, as they are reused after the transform
+    persistent_inputs: HashSet<PathBuf>,
 
     total_sequences: AtomicU64,
     total_kmers: AtomicU64,
@@ -153,6 +156,7 @@ pub struct KmersTransformContext<F: KmersTransformExecutorFactory> {
 impl<F: KmersTransformExecutorFactory> KmersTransform<F> {
     pub fn new(
         file_inputs: Vec<MultiChunkBucket>,
+        persistent_inputs: HashSet<PathBuf>,
         temp_dir: &Path,
         buckets_counters_path: PathBuf,
         buckets_count: usize,
@@ -273,6 +277,7 @@ impl<F: KmersTransformExecutorFactory> KmersTransform<F> {
             read_threads_count,
             max_second_buckets_count_log2: MAXIMUM_SECOND_BUCKETS_COUNT.ilog2() as usize,
             temp_dir: temp_dir.to_path_buf(),
+            persistent_inputs,
             total_sequences: AtomicU64::new(0),
             total_kmers: AtomicU64::new(0),
             unique_kmers: AtomicU64::new(0),
@@ -147,7 +147,8 @@ impl<F: KmersTransformExecutorFactory> KmersTransformReader<F> {
                     path,
                     true,
                     RemoveFileMode::Remove {
-                        remove_fs: file.rewritten || !KEEP_FILES.load(Ordering::Relaxed),
+                        remove_fs: (file.rewritten || !KEEP_FILES.load(Ordering::Relaxed))
+                            && !global_context.persistent_inputs.contains(path),
                     },
                     DEFAULT_PREFETCH_AMOUNT,
                 )
@@ -54,6 +54,43 @@ impl CountersAnalyzer {
         }
     }
 
+    /// Sums the counters of two bucketing runs with the same buckets count.
+    /// If the sub-buckets counts differ, the larger ones are folded on the smaller ones,
+    /// as the sub-bucket index is taken from the lower bits of the minimizer hash
+    pub fn merge(&self, other: &Self) -> Self {
+        assert_eq!(self.counters.len(), other.counters.len());
+
+        let counters = self
+            .counters
+            .iter()
+            .zip(other.counters.iter())
+            .map(|(first, second)| {
+                let (short, long) = if first.len() <= second.len() {
+                    (first, second)
+                } else {
+                    (second, first)
+                };
+                let mut merged = short.clone();
+                for (index, counter) in long.iter().enumerate() {
+                    merged[index % short.len()].count += counter.count;
+                }
+                merged
+                    .into_iter()
+                    .map(|counter| AtomicU64::new(counter.count))
+                    .collect()
+            })
+            .collect();
+
+        let offsets = self
+            .compaction_offsets
+            .iter()
+            .zip(other.compaction_offsets.iter())
+            .map(|(first, second)| AtomicI64::new(first + second))
+            .collect();
+
+        Self::new(counters, offsets)
+    }
+
     pub fn get_counters_for_bucket(&self, bucket: BucketIndexType) -> &Vec<BucketCounter> {
         &self.counters[bucket as usize]
     }
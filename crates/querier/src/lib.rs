@@ -1,26 +1,34 @@
+use crate::multi_graph::query_multiple_graphs;
 use crate::pipeline::colored_query_output::colored_query_output;
 use crate::pipeline::colormap_reading::colormap_reading;
 use crate::pipeline::counters_sorting::counters_sorting;
+use crate::pipeline::matched_unitigs_output::matched_unitigs_output;
 use crate::pipeline::parallel_kmers_query::parallel_kmers_counting;
-use crate::pipeline::querier_minimizer_bucketing::minimizer_bucketing;
+use crate::pipeline::querier_minimizer_bucketing::{minimizer_bucketing, QueriesInfo};
 use ::dynamic_dispatch::dynamic_dispatch;
 use colors::colors_manager::{ColorMapReader, ColorsManager, ColorsMergeManager};
-use colors::DefaultColorsSerializer;
-use config::{INTERMEDIATE_COMPRESSION_LEVEL_FAST, INTERMEDIATE_COMPRESSION_LEVEL_SLOW};
+use colors::metadata::ColorsSelection;
+use colors::storage::ColormapBuildParams;
+use config::{
+    ColorIndexType, INTERMEDIATE_COMPRESSION_LEVEL_FAST, INTERMEDIATE_COMPRESSION_LEVEL_SLOW,
+};
 use hashes::default::MNHFactory;
 use hashes::HashFunctionFactory;
-use io::sequences_reader::SequencesReader;
 use io::sequences_stream::general::GeneralSequenceBlockData;
 use io::{compute_stats_from_input_blocks, generate_bucket_names};
 use parallel_processor::phase_times_monitor::PHASES_TIMES_MONITOR;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
 use std::path::PathBuf;
 use std::sync::atomic::Ordering;
 
+mod multi_graph;
 mod pipeline;
+mod query_engine;
+pub mod query_index;
 mod structs;
 
+pub use query_engine::{QueryEngine, QueryMatches};
+pub use query_index::QueryIndex;
+
 #[derive(Copy, Clone, Debug, PartialOrd, PartialEq)]
 pub enum QuerierStartingStep {
     MinimizerBucketing = 0,
@@ -33,6 +41,91 @@ pub enum QuerierStartingStep {
 pub enum ColoredQueryOutputFormat {
     JsonLinesWithNumbers,
     JsonLinesWithNames,
+    /// Long format table, with a (query, color, matched_kmers, match_fraction) row for each match
+    Tsv,
+    /// Sparse query by color matrix of the matched kmers, in the Matrix Market coordinate format
+    MatrixMarket,
+}
+
+impl ColoredQueryOutputFormat {
+    /// The extension used for the output file when the output prefix does not have one
+    pub fn default_extension(&self) -> &'static str {
+        match self {
+            ColoredQueryOutputFormat::JsonLinesWithNumbers
+            | ColoredQueryOutputFormat::JsonLinesWithNames => "jsonl",
+            ColoredQueryOutputFormat::Tsv => "tsv",
+            ColoredQueryOutputFormat::MatrixMarket => "mtx",
+        }
+    }
+}
+
+/// Information reported for each query
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum QueryOutputMode {
+    /// Number of query kmers matched by each color
+    MatchCounts,
+    /// Run-length encoded segments of the query kmer positions, with the colors matched by each segment
+    KmerPositions,
+    /// Graph unitigs matched by the query kmers, with their orientation, in the query order
+    MatchedUnitigs,
+}
+
+/// Filters applied to the colors matched by each query, before writing the colored query output
+#[derive(Clone, Debug)]
+pub struct QueryOutputFilter {
+    /// Minimum fraction of the query kmers that must be matched by a color
+    pub min_match_fraction: f64,
+    /// Minimum number of query kmers that must be matched by a color
+    pub min_matched_kmers: u64,
+    /// Keep only the colors with the most matched kmers for each query
+    pub top_colors: Option<usize>,
+    /// Keep only the colors selected by their metadata
+    pub colors_selection: Option<ColorsSelection>,
+}
+
+impl Default for QueryOutputFilter {
+    fn default() -> Self {
+        Self {
+            min_match_fraction: 0.0,
+            min_matched_kmers: 0,
+            top_colors: None,
+            colors_selection: None,
+        }
+    }
+}
+
+/// Function called with the query index, the query name and the (color, matched_kmers) list of a query
+pub type QueryResultsFunction<'a> =
+    dyn Fn(usize, &str, &[(ColorIndexType, u64)]) + Send + Sync + 'a;
+
+/// Passes the match counts of each query to a function, instead of writing them to the output file
+#[derive(Copy, Clone)]
+pub struct QueryResultsCallback<'a> {
+    pub output_function: &'a QueryResultsFunction<'a>,
+    /// Call the output function from a single thread at a time, in the queries order
+    pub single_thread_output_function: bool,
+}
+
+/// Adds the given extension to the output file path, if it does not have one
+fn with_default_extension(output_file: PathBuf, extension: &str) -> PathBuf {
+    if output_file.extension().is_none() {
+        output_file.with_extension(extension)
+    } else {
+        output_file
+    }
+}
+
+/// Extracts the query name from a FASTA/FASTQ header, as the first word after the '>' or '@' marker
+fn get_query_name(ident: &[u8]) -> String {
+    let ident = ident
+        .strip_prefix(b">")
+        .or_else(|| ident.strip_prefix(b"@"))
+        .unwrap_or(ident);
+    let name = ident
+        .split(|c| c.is_ascii_whitespace())
+        .next()
+        .unwrap_or(&[]);
+    String::from_utf8_lossy(name).into_owned()
 }
 
 #[dynamic_dispatch(MergingHash = [
@@ -57,18 +150,98 @@ pub enum ColoredQueryOutputFormat {
 pub fn run_query<MergingHash: HashFunctionFactory, QuerierColorsManager: ColorsManager>(
     k: usize,
     m: usize,
+    forward_only: bool,
     step: QuerierStartingStep,
-    graph_input: PathBuf,
-    query_input: PathBuf,
+    graph_inputs: Vec<PathBuf>,
+    query_input: Vec<GeneralSequenceBlockData>,
     output_file_prefix: PathBuf,
     temp_dir: Option<PathBuf>,
     buckets_count_log: Option<usize>,
     threads_count: usize,
     default_compression_level: Option<u32>,
     colored_query_output_format: ColoredQueryOutputFormat,
+    output_filter: QueryOutputFilter,
+    output_mode: QueryOutputMode,
+    query_index: Option<PathBuf>,
+    output_callback: Option<QueryResultsCallback<'_>>,
 ) -> anyhow::Result<PathBuf> {
     let temp_dir = temp_dir.unwrap_or(PathBuf::new());
 
+    let Some(graph_input) = graph_inputs.first().cloned() else {
+        anyhow::bail!("No input graphs were given");
+    };
+
+    if graph_inputs.len() > 1 {
+        if !QuerierColorsManager::COLORS_ENABLED {
+            anyhow::bail!("Querying multiple graphs requires colored graphs");
+        }
+        if output_mode != QueryOutputMode::MatchCounts {
+            anyhow::bail!(
+                "The {:?} query output is not supported when querying multiple graphs",
+                output_mode
+            );
+        }
+        if query_index.is_some() {
+            anyhow::bail!("A query index cannot be used when querying multiple graphs");
+        }
+        if step > QuerierStartingStep::MinimizerBucketing {
+            anyhow::bail!("Multiple graphs can be queried only from the minimizer bucketing step");
+        }
+    }
+
+    let query_index = match query_index {
+        Some(index_dir) => {
+            let query_index = QueryIndex::load(index_dir)?;
+            query_index.check_params(
+                &graph_input,
+                k,
+                m,
+                MergingHash::NAME,
+                forward_only,
+                QuerierColorsManager::COLORS_ENABLED,
+            )?;
+            if step > QuerierStartingStep::MinimizerBucketing {
+                anyhow::bail!("A query index can be used only when starting from the minimizer bucketing step");
+            }
+            Some(query_index)
+        }
+        None => None,
+    };
+
+    // The graphs queried together are checked when querying them
+    if QuerierColorsManager::COLORS_ENABLED && graph_inputs.len() == 1 {
+        let colormap_file = graph_input.with_extension("colors.dat");
+        ColormapBuildParams::read(&colormap_file)?.check_graph(
+            &colormap_file,
+            k,
+            Some(MergingHash::NAME),
+        )?;
+    }
+
+    if output_mode == QueryOutputMode::KmerPositions && !QuerierColorsManager::COLORS_ENABLED {
+        anyhow::bail!("The kmer positions query output requires a colored graph");
+    }
+    if output_mode != QueryOutputMode::MatchCounts
+        && !matches!(
+            colored_query_output_format,
+            ColoredQueryOutputFormat::JsonLinesWithNumbers
+                | ColoredQueryOutputFormat::JsonLinesWithNames
+        )
+    {
+        anyhow::bail!(
+            "The {:?} query output supports only the json lines formats",
+            output_mode
+        );
+    }
+
+    if output_callback.is_some() && output_mode != QueryOutputMode::MatchCounts {
+        anyhow::bail!("Only the match counts can be passed to a query output function");
+    }
+
+    if query_input.is_empty() {
+        anyhow::bail!("No query sequences were given");
+    }
+
     PHASES_TIMES_MONITOR.write().init();
 
     MNHFactory::initialize(k);
@@ -78,13 +251,21 @@ pub fn run_query<MergingHash: HashFunctionFactory, QuerierColorsManager: ColorsM
         graph_input.with_extension("colors.dat"),
     )?;
 
-    // TODO: Support GFA input
-    let file_stats = compute_stats_from_input_blocks(&[
-        GeneralSequenceBlockData::FASTA((graph_input.clone(), None)),
-        GeneralSequenceBlockData::FASTA((query_input.clone(), None)),
-    ])?;
-
-    let buckets_count_log = buckets_count_log.unwrap_or_else(|| file_stats.best_buckets_count_log);
+    let buckets_count_log = match (&query_index, buckets_count_log) {
+        (Some(query_index), _) => query_index.buckets_count_log,
+        (None, Some(buckets_count_log)) => buckets_count_log,
+        (None, None) => {
+            // TODO: Support GFA input
+            compute_stats_from_input_blocks(
+                &graph_inputs
+                    .iter()
+                    .map(|graph_input| GeneralSequenceBlockData::FASTA((graph_input.clone(), None)))
+                    .chain(query_input.iter().cloned())
+                    .collect::<Vec<_>>(),
+            )?
+            .best_buckets_count_log
+        }
+    };
 
     if let Some(default_compression_level) = default_compression_level {
         INTERMEDIATE_COMPRESSION_LEVEL_SLOW.store(default_compression_level, Ordering::Relaxed);
@@ -93,61 +274,125 @@ pub fn run_query<MergingHash: HashFunctionFactory, QuerierColorsManager: ColorsM
 
     let buckets_count = 1 << buckets_count_log;
 
-    let ((buckets, counters), queries_count) = if step <= QuerierStartingStep::MinimizerBucketing {
-        minimizer_bucketing::<QuerierColorsManager>(
-            graph_input.clone(),
-            query_input.clone(),
+    if graph_inputs.len() > 1 {
+        let (colored_buckets, color_map, queries) =
+            query_multiple_graphs::<MergingHash, QuerierColorsManager>(
+                &graph_inputs,
+                query_input,
+                temp_dir.as_path(),
+                buckets_count,
+                threads_count,
+                k,
+                m,
+            )?;
+
+        colored_query_output::<MergingHash, QuerierColorsManager>(
+            &color_map,
+            colored_buckets,
+            output_file_prefix.clone(),
+            temp_dir,
+            &queries.kmers_counts,
+            &queries.names,
+            colored_query_output_format,
+            output_filter,
+            output_mode,
+            output_callback,
+        )?;
+
+        PHASES_TIMES_MONITOR
+            .write()
+            .print_stats("Query completed.".to_string());
+
+        return Ok(with_default_extension(
+            output_file_prefix,
+            colored_query_output_format.default_extension(),
+        ));
+    }
+
+    let ((buckets, counters), queries) = if step <= QuerierStartingStep::MinimizerBucketing {
+        let ((buckets, counters), queries) = minimizer_bucketing::<QuerierColorsManager>(
+            match query_index {
+                Some(_) => None,
+                None => Some(graph_input.clone()),
+            },
+            query_input,
             temp_dir.as_path(),
             buckets_count,
             threads_count,
             k,
             m,
+            output_mode == QueryOutputMode::MatchedUnitigs,
+        );
+
+        (
+            match &query_index {
+                Some(query_index) => query_index.join_query_buckets(buckets, counters)?,
+                None => (
+                    buckets.into_iter().map(|x| x.to_multi_chunk()).collect(),
+                    counters,
+                ),
+            },
+            queries,
         )
     } else {
         (
             (
-                generate_bucket_names(temp_dir.join("bucket"), buckets_count, None),
+                generate_bucket_names(temp_dir.join("bucket"), buckets_count, None)
+                    .into_iter()
+                    .map(|x| x.to_multi_chunk())
+                    .collect(),
                 temp_dir.join("buckets-counters.dat"),
             ),
-            {
-                let queries_count = BufReader::new(File::open(&query_input).unwrap())
-                    .lines()
-                    .count() as u64
-                    / 2;
-                queries_count
-            },
+            // The queries were numbered in their blocks when bucketed
+            QueriesInfo::read(&query_input, k),
         )
     };
 
-    let counters_buckets = if step <= QuerierStartingStep::KmersCounting {
+    let (counters_buckets, unitig_matches_buckets) = if step <= QuerierStartingStep::KmersCounting {
         parallel_kmers_counting::<MergingHash, QuerierColorsManager, _>(
             buckets,
+            query_index
+                .as_ref()
+                .map(|query_index| query_index.bucket_paths())
+                .unwrap_or_default(),
             counters,
             buckets_count,
             temp_dir.as_path(),
             k,
             m,
             threads_count,
+            output_mode,
+            queries.numbering.clone(),
         )
     } else {
-        generate_bucket_names(temp_dir.join("counters"), buckets_count, None)
+        (
+            generate_bucket_names(temp_dir.join("counters"), buckets_count, None),
+            if output_mode == QueryOutputMode::MatchedUnitigs {
+                generate_bucket_names(temp_dir.join("unitig_matches"), buckets_count, None)
+            } else {
+                vec![]
+            },
+        )
     };
 
     let colored_buckets_prefix = temp_dir.join("color_counters");
 
-    let query_kmers_count = {
-        let mut sequences_lengths = vec![];
-        SequencesReader::new().process_file_extended(
-            &query_input,
-            |seq| {
-                sequences_lengths.push((seq.seq.len().saturating_sub(k - 1)) as u64);
-            },
-            None,
-            false,
-            false,
-        );
-        sequences_lengths
-    };
+    if output_mode == QueryOutputMode::MatchedUnitigs {
+        let output_file_name = with_default_extension(output_file_prefix, "jsonl");
+
+        matched_unitigs_output(
+            unitig_matches_buckets,
+            output_file_name.clone(),
+            &queries.kmers_counts,
+            &queries.names,
+        )?;
+
+        PHASES_TIMES_MONITOR
+            .write()
+            .print_stats("Query completed.".to_string());
+
+        return Ok(output_file_name);
+    }
 
     let colored_buckets = if step <= QuerierStartingStep::CountersSorting {
         counters_sorting::<QuerierColorsManager>(
@@ -156,7 +401,10 @@ pub fn run_query<MergingHash: HashFunctionFactory, QuerierColorsManager: ColorsM
             colored_buckets_prefix,
             color_map.colors_subsets_count(),
             output_file_prefix.clone(),
-            &query_kmers_count,
+            &queries.kmers_counts,
+            &queries.names,
+            output_mode,
+            output_callback,
         )
     } else {
         generate_bucket_names(colored_buckets_prefix, buckets_count, None)
@@ -164,20 +412,29 @@ pub fn run_query<MergingHash: HashFunctionFactory, QuerierColorsManager: ColorsM
 
     if QuerierColorsManager::COLORS_ENABLED {
         let colormap_file = graph_input.with_extension("colors.dat");
-        let remapped_query_color_buckets = colormap_reading::<DefaultColorsSerializer>(
+        let remapped_query_color_buckets = colormap_reading(
             colormap_file,
             colored_buckets,
             temp_dir.clone(),
-            queries_count,
+            queries.queries_count(),
+            0,
+            output_mode,
         )?;
 
         colored_query_output::<MergingHash, QuerierColorsManager>(
             &color_map,
-            remapped_query_color_buckets,
+            remapped_query_color_buckets
+                .into_iter()
+                .map(|bucket| bucket.to_multi_chunk())
+                .collect(),
             output_file_prefix.clone(),
             temp_dir,
-            &query_kmers_count,
+            &queries.kmers_counts,
+            &queries.names,
             colored_query_output_format,
+            output_filter,
+            output_mode,
+            output_callback,
         )?;
     }
 
@@ -185,15 +442,14 @@ pub fn run_query<MergingHash: HashFunctionFactory, QuerierColorsManager: ColorsM
         .write()
         .print_stats("Query completed.".to_string());
 
-    let output_file_name = if output_file_prefix.extension().is_none() {
+    let output_file_name = with_default_extension(
+        output_file_prefix,
         if QuerierColorsManager::COLORS_ENABLED {
-            output_file_prefix.with_extension("jsonl")
+            colored_query_output_format.default_extension()
         } else {
-            output_file_prefix.with_extension("csv")
-        }
-    } else {
-        output_file_prefix
-    };
+            "csv"
+        },
+    );
 
     Ok(output_file_name)
 }
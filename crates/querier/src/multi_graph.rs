@@ -0,0 +1,353 @@
+use crate::pipeline::colormap_reading::colormap_reading;
+use crate::pipeline::counters_sorting::counters_sorting;
+use crate::pipeline::parallel_kmers_query::parallel_kmers_counting;
+use crate::pipeline::querier_minimizer_bucketing::{minimizer_bucketing, QueriesInfo};
+use crate::QueryOutputMode;
+use colors::colors_manager::{json_escape, ColorMapReader, ColorsManager, ColorsMergeManager};
+use colors::metadata::ColorsMetadata;
+use colors::storage::ColormapBuildParams;
+use config::{ColorIndexType, KEEP_FILES, PREFER_MEMORY};
+use hashes::HashFunctionFactory;
+use io::sequences_stream::general::GeneralSequenceBlockData;
+use minimizer_bucketing::counters_analyzer::CountersAnalyzer;
+use parallel_processor::buckets::{MultiChunkBucket, SingleBucket};
+use parallel_processor::memory_fs::MemoryFs;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+
+/// Colors of several graphs queried together, numbered in order across the graphs.
+/// The names found in more than one graph are prefixed with the graph path, to keep them unique
+pub(crate) struct MultiGraphColorMap {
+    color_names: Vec<String>,
+    json_escaped_color_names: Vec<String>,
+    colors_metadata: ColorsMetadata,
+    colors_subsets_count: u64,
+}
+
+impl MultiGraphColorMap {
+    fn new(graphs: &[(PathBuf, impl ColorMapReader)]) -> Self {
+        // For each name, the first graph having it and whether other graphs have it too
+        let mut names_graphs: HashMap<&str, (usize, bool)> = HashMap::new();
+        for (graph_index, (_, colormap)) in graphs.iter().enumerate() {
+            for color in 0..colormap.colors_count() {
+                let name = colormap.get_color_name(color as ColorIndexType, false);
+                let (first_graph, shared) =
+                    names_graphs.entry(name).or_insert((graph_index, false));
+                *shared |= *first_graph != graph_index;
+            }
+        }
+
+        let mut color_names = vec![];
+        for (graph, colormap) in graphs {
+            for color in 0..colormap.colors_count() {
+                let name = colormap.get_color_name(color as ColorIndexType, false);
+                color_names.push(if names_graphs[name].1 {
+                    format!("{}:{}", graph.display(), name)
+                } else {
+                    name.to_string()
+                });
+            }
+        }
+
+        let json_escaped_color_names = color_names.iter().map(|name| json_escape(name)).collect();
+
+        Self {
+            color_names,
+            json_escaped_color_names,
+            colors_metadata: ColorsMetadata::concat(
+                graphs
+                    .iter()
+                    .map(|(_, colormap)| (colormap.colors_metadata(), colormap.colors_count())),
+            ),
+            colors_subsets_count: graphs
+                .iter()
+                .map(|(_, colormap)| colormap.colors_subsets_count())
+                .sum(),
+        }
+    }
+}
+
+impl ColorMapReader for MultiGraphColorMap {
+    fn get_color_name(&self, index: ColorIndexType, json_escaped: bool) -> &str {
+        if json_escaped {
+            &self.json_escaped_color_names[index as usize]
+        } else {
+            &self.color_names[index as usize]
+        }
+    }
+
+    fn colors_count(&self) -> usize {
+        self.color_names.len()
+    }
+
+    fn colors_subsets_count(&self) -> u64 {
+        self.colors_subsets_count
+    }
+
+    fn colors_metadata(&self) -> &ColorsMetadata {
+        &self.colors_metadata
+    }
+}
+
+/// Checks that all the graphs queried together were built with the given k and hash,
+/// as their kmers are counted and their colors are joined in the same query
+fn check_graphs_build_params(
+    graphs: &[(PathBuf, ColormapBuildParams)],
+    k: usize,
+    hash_type: &str,
+) -> anyhow::Result<()> {
+    for (colormap_file, build_params) in graphs {
+        build_params.check_graph(colormap_file, k, Some(hash_type))?;
+    }
+    Ok(())
+}
+
+/// Joins the buckets of a graph with the query buckets, merging their sub-buckets counters.
+/// The query buckets must be kept by the kmers counting, to be joined with the next graphs
+fn join_graph_buckets(
+    graph_buckets: Vec<SingleBucket>,
+    graph_counters: PathBuf,
+    query_buckets: &[SingleBucket],
+    query_counters: &Path,
+) -> (Vec<MultiChunkBucket>, PathBuf) {
+    let mut joined_buckets = Vec::with_capacity(graph_buckets.len());
+
+    for (graph_bucket, query_bucket) in graph_buckets.into_iter().zip(query_buckets) {
+        assert_eq!(graph_bucket.index, query_bucket.index);
+
+        joined_buckets.push(MultiChunkBucket {
+            index: graph_bucket.index,
+            chunks: vec![graph_bucket.path, query_bucket.path.clone()],
+            was_compacted: false,
+        });
+    }
+
+    let counters = CountersAnalyzer::load_from_file(&graph_counters, true)
+        .merge(&CountersAnalyzer::load_from_file(query_counters, false));
+    counters.serialize_to_file(&graph_counters);
+
+    (joined_buckets, graph_counters)
+}
+
+/// Queries several graphs built with the same parameters, bucketing the queries only once.
+/// Returns the colored counters of all the graphs, grouped by bucket, with the colors numbered across the graphs,
+/// and the kmers counts and names of the queries
+pub(crate) fn query_multiple_graphs<MH: HashFunctionFactory, CX: ColorsManager>(
+    graph_inputs: &[PathBuf],
+    query_blocks: Vec<GeneralSequenceBlockData>,
+    temp_dir: &Path,
+    buckets_count: usize,
+    threads_count: usize,
+    k: usize,
+    m: usize,
+) -> anyhow::Result<(Vec<MultiChunkBucket>, MultiGraphColorMap, QueriesInfo)> {
+    let mut graphs_build_params = Vec::with_capacity(graph_inputs.len());
+    for graph_input in graph_inputs {
+        let colormap_file = graph_input.with_extension("colors.dat");
+        let build_params = ColormapBuildParams::read(&colormap_file)?;
+        graphs_build_params.push((colormap_file, build_params));
+    }
+    check_graphs_build_params(&graphs_build_params, k, MH::NAME)?;
+
+    let mut color_maps = Vec::with_capacity(graph_inputs.len());
+    for graph_input in graph_inputs {
+        color_maps.push((
+            graph_input.clone(),
+            CX::ColorsMergeManagerType::open_colors_table(
+                graph_input.with_extension("colors.dat"),
+            )?,
+        ));
+    }
+
+    // The query buckets must be on disk to be read again for each graph
+    let queries_dir = temp_dir.join("queries");
+    std::fs::create_dir_all(&queries_dir)?;
+    let prefer_memory = PREFER_MEMORY.swap(false, Ordering::Relaxed);
+    let ((query_buckets, query_counters), queries) = minimizer_bucketing::<CX>(
+        None,
+        query_blocks,
+        &queries_dir,
+        buckets_count,
+        threads_count,
+        k,
+        m,
+        false,
+    );
+    MemoryFs::flush_all_to_disk();
+    PREFER_MEMORY.store(prefer_memory, Ordering::Relaxed);
+
+    let mut colored_buckets: Vec<_> = (0..buckets_count)
+        .map(|index| MultiChunkBucket {
+            index,
+            chunks: vec![],
+            was_compacted: false,
+        })
+        .collect();
+
+    let mut color_offset = 0;
+    for (graph_index, (graph_input, color_map)) in color_maps.iter().enumerate() {
+        ggcat_logging::info!("Querying graph {}", graph_input.display());
+
+        let graph_temp_dir = temp_dir.join(format!("graph-{}", graph_index));
+        std::fs::create_dir_all(&graph_temp_dir)?;
+
+        let ((graph_buckets, graph_counters), _) = minimizer_bucketing::<CX>(
+            Some(graph_input.clone()),
+            vec![],
+            &graph_temp_dir,
+            buckets_count,
+            threads_count,
+            k,
+            m,
+            false,
+        );
+
+        let (buckets, counters) = join_graph_buckets(
+            graph_buckets,
+            graph_counters,
+            &query_buckets,
+            &query_counters,
+        );
+
+        let (counters_buckets, _) = parallel_kmers_counting::<MH, CX, _>(
+            buckets,
+            query_buckets
+                .iter()
+                .map(|bucket| bucket.path.clone())
+                .collect(),
+            counters,
+            buckets_count,
+            graph_temp_dir.as_path(),
+            k,
+            m,
+            threads_count,
+            QueryOutputMode::MatchCounts,
+            queries.numbering.clone(),
+        );
+
+        let graph_colored_buckets = counters_sorting::<CX>(
+            k,
+            counters_buckets,
+            graph_temp_dir.join("color_counters"),
+            color_map.colors_subsets_count(),
+            PathBuf::new(),
+            &queries.kmers_counts,
+            &queries.names,
+            QueryOutputMode::MatchCounts,
+            None,
+        );
+
+        let remapped_query_color_buckets = colormap_reading(
+            graph_input.with_extension("colors.dat"),
+            graph_colored_buckets,
+            graph_temp_dir,
+            queries.queries_count(),
+            color_offset,
+            QueryOutputMode::MatchCounts,
+        )?;
+
+        for bucket in remapped_query_color_buckets {
+            colored_buckets[bucket.index].chunks.push(bucket.path);
+        }
+
+        color_offset += color_map.colors_count() as ColorIndexType;
+    }
+
+    if !KEEP_FILES.load(Ordering::Relaxed) {
+        let _ = std::fs::remove_dir_all(&queries_dir);
+    }
+
+    Ok((
+        colored_buckets,
+        MultiGraphColorMap::new(&color_maps),
+        queries,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestColorMap {
+        names: Vec<String>,
+        subsets_count: u64,
+        metadata: ColorsMetadata,
+    }
+
+    impl TestColorMap {
+        fn new(names: &[&str], subsets_count: u64) -> Self {
+            Self {
+                names: names.iter().map(|name| name.to_string()).collect(),
+                subsets_count,
+                metadata: ColorsMetadata::default(),
+            }
+        }
+    }
+
+    impl ColorMapReader for TestColorMap {
+        fn get_color_name(&self, index: ColorIndexType, _json_escaped: bool) -> &str {
+            &self.names[index as usize]
+        }
+
+        fn colors_count(&self) -> usize {
+            self.names.len()
+        }
+
+        fn colors_subsets_count(&self) -> u64 {
+            self.subsets_count
+        }
+
+        fn colors_metadata(&self) -> &ColorsMetadata {
+            &self.metadata
+        }
+    }
+
+    #[test]
+    fn colors_numbered_across_graphs() {
+        let color_map = MultiGraphColorMap::new(&[
+            (
+                PathBuf::from("first.fa"),
+                TestColorMap::new(&["a", "b \"1\""], 3),
+            ),
+            (
+                PathBuf::from("second.fa"),
+                TestColorMap::new(&["b \"1\"", "c"], 4),
+            ),
+        ]);
+
+        assert_eq!(color_map.colors_count(), 4);
+        assert_eq!(color_map.colors_subsets_count(), 7);
+
+        // The names found in both graphs are prefixed with the graph path
+        let names: Vec<_> = (0..4)
+            .map(|color| color_map.get_color_name(color, false))
+            .collect();
+        assert_eq!(names, ["a", "first.fa:b \"1\"", "second.fa:b \"1\"", "c"]);
+        assert_eq!(color_map.get_color_name(2, true), "second.fa:b \\\"1\\\"");
+    }
+
+    #[test]
+    fn graphs_build_params_check() {
+        let params = |k: u64, hash_type: &str| ColormapBuildParams {
+            k,
+            hash_type: hash_type.to_string(),
+        };
+        let graphs = |second: ColormapBuildParams| {
+            vec![
+                (PathBuf::from("first.colors.dat"), params(31, "hash")),
+                (PathBuf::from("second.colors.dat"), second),
+            ]
+        };
+
+        assert!(check_graphs_build_params(&graphs(params(31, "hash")), 31, "hash").is_ok());
+        // Colormaps upgraded from older versions do not know their parameters
+        assert!(check_graphs_build_params(&graphs(params(0, "")), 31, "hash").is_ok());
+
+        let error = check_graphs_build_params(&graphs(params(27, "hash")), 31, "hash")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("second.colors.dat") && error.contains("k=27"));
+        assert!(check_graphs_build_params(&graphs(params(31, "other")), 31, "hash").is_err());
+    }
+}
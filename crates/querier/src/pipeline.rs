@@ -1,5 +1,6 @@
 pub mod colored_query_output;
 pub mod colormap_reading;
 pub mod counters_sorting;
+pub mod matched_unitigs_output;
 pub mod parallel_kmers_query;
 pub mod querier_minimizer_bucketing;
@@ -1,7 +1,7 @@
 use crate::structs::query_colored_counters::{ColorsRange, QueryColoredCountersSerializer};
-use crate::ColoredQueryOutputFormat;
+use crate::{ColoredQueryOutputFormat, QueryOutputFilter, QueryOutputMode, QueryResultsCallback};
 use colors::colors_manager::ColorMapReader;
-use colors::colors_manager::{ColorsManager, ColorsMergeManager};
+use colors::colors_manager::{write_json_escaped, write_tsv_escaped, ColorsManager};
 use config::{
     get_compression_level_info, get_memory_mode, ColorIndexType, SwapPriority,
     DEFAULT_PREFETCH_AMOUNT, KEEP_FILES, QUERIES_COUNT_MIN_BATCH,
@@ -13,7 +13,7 @@ use nightly_quirks::prelude::*;
 use parallel_processor::buckets::readers::compressed_binary_reader::CompressedBinaryReader;
 use parallel_processor::buckets::readers::BucketReader;
 use parallel_processor::buckets::writers::compressed_binary_writer::CompressedBinaryWriter;
-use parallel_processor::buckets::{LockFreeBucket, SingleBucket};
+use parallel_processor::buckets::{LockFreeBucket, MultiChunkBucket};
 use parallel_processor::memory_fs::RemoveFileMode;
 use parallel_processor::phase_times_monitor::PHASES_TIMES_MONITOR;
 use parking_lot::{Condvar, Mutex};
@@ -22,10 +22,10 @@ use std::fs::File;
 use std::io::BufWriter;
 use std::io::Write;
 use std::ops::DerefMut;
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
-enum QueryOutputFileWriter {
+pub(crate) enum QueryOutputFileWriter {
     Plain(File),
     LZ4Compressed(lz4::Encoder<File>),
     GzipCompressed(flate2::write::GzEncoder<File>),
@@ -49,13 +49,132 @@ impl Write for QueryOutputFileWriter {
     }
 }
 
+pub(crate) fn create_query_output_writer(
+    output_file: &Path,
+) -> anyhow::Result<BufWriter<QueryOutputFileWriter>> {
+    let query_output_file = File::create(output_file)
+        .log_unrecoverable_error_with_data("Cannot create output file", output_file.display())?;
+
+    Ok(BufWriter::new(
+        match output_file.extension().map(|e| e.to_str()).flatten() {
+            Some("lz4") => QueryOutputFileWriter::LZ4Compressed(
+                lz4::EncoderBuilder::new()
+                    .level(4)
+                    .build(query_output_file)
+                    .unwrap(),
+            ),
+            Some("gz") => QueryOutputFileWriter::GzipCompressed(
+                flate2::GzBuilder::new().write(query_output_file, Compression::default()),
+            ),
+            _ => QueryOutputFileWriter::Plain(query_output_file),
+        },
+    ))
+}
+
+/// Writes a string as a quoted JSON string, escaping the special characters
+pub(crate) fn write_json_string(buffer: &mut Vec<u8>, value: &str) {
+    buffer.push(b'"');
+    write_json_escaped(buffer, value);
+    buffer.push(b'"');
+}
+
+/// Starts the json object of a query, writing its index and its name
+pub(crate) fn write_json_query_start(buffer: &mut Vec<u8>, query: usize, query_name: &str) {
+    write!(buffer, "{{\"query_index\":{}, \"query_name\":", query).unwrap();
+    write_json_string(buffer, query_name);
+}
+
+/// Sums the matched kmers of each color of a query and keeps the colors passing the output filter, sorted by color
+fn filter_query_colors(
+    colors_list: &mut [(ColorIndexType, u64)],
+    query_kmers: u64,
+    output_filter: &QueryOutputFilter,
+    selected_colors: Option<&[bool]>,
+    filtered_colors_list: &mut Vec<(ColorIndexType, u64)>,
+) {
+    colors_list.sort_unstable_by_key(|r| r.0);
+
+    filtered_colors_list.clear();
+    filtered_colors_list.extend(
+        colors_list
+            .nq_group_by(|a, b| a.0 == b.0)
+            .map(|qc| (qc[0].0, qc.iter().map(|x| x.1).sum::<u64>()))
+            .filter(|(color, color_presence)| {
+                selected_colors.is_none_or(|selected| selected[*color as usize])
+                    && *color_presence >= output_filter.min_matched_kmers
+                    && (*color_presence as f64)
+                        >= output_filter.min_match_fraction * query_kmers as f64
+            }),
+    );
+
+    if let Some(top_colors) = output_filter.top_colors {
+        if filtered_colors_list.len() > top_colors {
+            filtered_colors_list.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+            filtered_colors_list.truncate(top_colors);
+            filtered_colors_list.sort_unstable_by_key(|r| r.0);
+        }
+    }
+}
+
+/// Applies the output filter to the (start, length, colors) matched segments of a query, sorted by position,
+/// and merges the adjacent segments left with the same colors into (start, end, colors) segments.
+/// The filter thresholds are checked against the kmers matched by each color in the whole query
+fn filter_query_segments(
+    segments: &[(u64, u64, Vec<ColorIndexType>)],
+    query_kmers: u64,
+    output_filter: &QueryOutputFilter,
+    selected_colors: Option<&[bool]>,
+) -> Vec<(u64, u64, Vec<ColorIndexType>)> {
+    let mut colors_list: Vec<_> = segments
+        .iter()
+        .flat_map(|(_, length, colors)| colors.iter().map(|color| (*color, *length)))
+        .collect();
+    let mut filtered_colors_list = vec![];
+    filter_query_colors(
+        &mut colors_list,
+        query_kmers,
+        output_filter,
+        selected_colors,
+        &mut filtered_colors_list,
+    );
+
+    let mut merged_segments: Vec<(u64, u64, Vec<ColorIndexType>)> = vec![];
+    for (start, length, colors) in segments {
+        let colors: Vec<_> = colors
+            .iter()
+            .copied()
+            .filter(|color| {
+                filtered_colors_list
+                    .binary_search_by_key(color, |(color, _)| *color)
+                    .is_ok()
+            })
+            .collect();
+
+        if colors.is_empty() {
+            continue;
+        }
+
+        match merged_segments.last_mut() {
+            Some((_, end, last_colors)) if *end == *start && *last_colors == colors => {
+                *end = start + length;
+            }
+            _ => merged_segments.push((*start, start + length, colors)),
+        }
+    }
+    merged_segments
+}
+
 pub fn colored_query_output<MH: HashFunctionFactory, CX: ColorsManager>(
-    colormap: &<CX::ColorsMergeManagerType as ColorsMergeManager>::GlobalColorsTableReader,
-    mut colored_query_buckets: Vec<SingleBucket>,
+    colormap: &(impl ColorMapReader + Sync),
+    mut colored_query_buckets: Vec<MultiChunkBucket>,
     output_file: PathBuf,
     temp_dir: PathBuf,
     query_kmers_count: &[u64],
+    query_names: &[String],
     colored_query_output_format: ColoredQueryOutputFormat,
+    output_filter: QueryOutputFilter,
+    output_mode: QueryOutputMode,
+    output_callback: Option<QueryResultsCallback<'_>>,
 ) -> anyhow::Result<()> {
     PHASES_TIMES_MONITOR
         .write()
@@ -73,32 +192,47 @@ pub fn colored_query_output<MH: HashFunctionFactory, CX: ColorsManager>(
     colored_query_buckets.reverse();
     let buckets_channel = Mutex::new(colored_query_buckets);
 
+    let selected_colors = output_filter
+        .colors_selection
+        .as_ref()
+        .map(|selection| selection.select(colormap))
+        .transpose()?;
+
     let output_file = if output_file.extension().is_none() {
-        output_file.with_extension("jsonl")
+        output_file.with_extension(colored_query_output_format.default_extension())
     } else {
         output_file
     };
 
-    let query_output_file = File::create(&output_file)
-        .log_unrecoverable_error_with_data("Cannot create output file", output_file.display())?;
+    // The matrix market size line needs the count of the matches,
+    // so the matrix entries are written to a temporary file first
+    let matrix_entries_file = temp_dir.join("query-matrix-entries.txt");
+    let matrix_entries_count = AtomicU64::new(0);
+
+    // No output file is written when the results are passed to the output function
+    let mut query_output_writer = if output_callback.is_some() {
+        None
+    } else if colored_query_output_format == ColoredQueryOutputFormat::MatrixMarket {
+        Some(BufWriter::new(QueryOutputFileWriter::Plain(
+            File::create(&matrix_entries_file).log_unrecoverable_error_with_data(
+                "Cannot create temporary matrix file",
+                matrix_entries_file.display(),
+            )?,
+        )))
+    } else {
+        Some(create_query_output_writer(&output_file)?)
+    };
+
+    if let Some(query_output_writer) = &mut query_output_writer {
+        if colored_query_output_format == ColoredQueryOutputFormat::Tsv {
+            writeln!(
+                query_output_writer,
+                "query_index\tquery_name\tcolor\tmatched_kmers\tmatch_fraction"
+            )?;
+        }
+    }
 
-    let query_output = Mutex::new((
-        BufWriter::new(
-            match output_file.extension().map(|e| e.to_str()).flatten() {
-                Some("lz4") => QueryOutputFileWriter::LZ4Compressed(
-                    lz4::EncoderBuilder::new()
-                        .level(4)
-                        .build(query_output_file)
-                        .unwrap(),
-                ),
-                Some("gz") => QueryOutputFileWriter::GzipCompressed(
-                    flate2::GzBuilder::new().write(query_output_file, Compression::default()),
-                ),
-                _ => QueryOutputFileWriter::Plain(query_output_file),
-            },
-        ),
-        0,
-    ));
+    let query_output = Mutex::new((query_output_writer, 0));
     let output_sync_condvar = Condvar::new();
 
     (0..rayon::current_num_threads())
@@ -115,6 +249,13 @@ pub fn colored_query_output<MH: HashFunctionFactory, CX: ColorsManager>(
             let mut queries_results =
                 vec![(0u32 /* epoch */, 0usize /* list index */); max_bucket_queries_count];
             let mut temp_colors_list = vec![];
+            let mut filtered_colors_list = vec![];
+            // Results of the current bucket, delivered in order when the output function is single threaded
+            let mut callback_results: Vec<(usize, Vec<(ColorIndexType, u64)>)> = vec![];
+
+            // Matched segments as (query, position, length, colors ranges), for the kmer positions output
+            let mut query_segments: Vec<(usize, u64, u64, std::ops::Range<usize>)> = vec![];
+            let mut segments_colors_ranges = vec![];
 
             let mut epoch = 0;
 
@@ -126,49 +267,70 @@ pub fn colored_query_output<MH: HashFunctionFactory, CX: ColorsManager>(
             } {
                 epoch += 1;
                 queries_colors_list_pool.clear();
+                query_segments.clear();
+                segments_colors_ranges.clear();
 
                 let start_query_index =
                     input.index as usize * max_bucket_queries_count / buckets_count;
 
-                CompressedBinaryReader::new(
-                    &input.path,
-                    RemoveFileMode::Remove {
-                        remove_fs: !KEEP_FILES.load(Ordering::Relaxed),
-                    },
-                    DEFAULT_PREFETCH_AMOUNT,
-                )
-                .decode_all_bucket_items::<QueryColoredCountersSerializer, _>(
-                    (Vec::new(), Vec::new()),
-                    &mut (),
-                    |counters, _| {
-                        for query in counters.queries {
-                            let (entry_epoch, colors_map_index) = &mut queries_results
-                                [query.query_index as usize - start_query_index - 1];
-
-                            if *entry_epoch != epoch {
-                                *entry_epoch = epoch;
-                                *colors_map_index = usize::MAX;
+                // Each chunk holds the colored counters of a different graph
+                for chunk in &input.chunks {
+                    CompressedBinaryReader::new(
+                        chunk,
+                        RemoveFileMode::Remove {
+                            remove_fs: !KEEP_FILES.load(Ordering::Relaxed),
+                        },
+                        DEFAULT_PREFETCH_AMOUNT,
+                    )
+                    .decode_all_bucket_items::<QueryColoredCountersSerializer, _>(
+                        (Vec::new(), Vec::new()),
+                        &mut { output_mode == QueryOutputMode::KmerPositions },
+                        |counters, _| {
+                            if output_mode == QueryOutputMode::KmerPositions {
+                                let colors_start = segments_colors_ranges.len();
+                                segments_colors_ranges.extend_from_slice(counters.colors);
+                                let colors_ranges = colors_start..segments_colors_ranges.len();
+
+                                for query in counters.queries {
+                                    query_segments.push((
+                                        query.query_index as usize - 1,
+                                        query.position,
+                                        query.count,
+                                        colors_ranges.clone(),
+                                    ));
+                                }
+                                return;
                             }
 
-                            assert_eq!(counters.colors.len() % 2, 0);
-                            for range in counters.colors.chunks(2) {
-                                let ColorsRange::Range(range) = ColorsRange::from_slice(range);
+                            for query in counters.queries {
+                                let (entry_epoch, colors_map_index) = &mut queries_results
+                                    [query.query_index as usize - start_query_index - 1];
 
-                                OPS_COUNT.fetch_add(1, Ordering::Relaxed);
-                                COL_COUNT.fetch_add(range.len(), Ordering::Relaxed);
+                                if *entry_epoch != epoch {
+                                    *entry_epoch = epoch;
+                                    *colors_map_index = usize::MAX;
+                                }
 
-                                for color in range {
-                                    queries_colors_list_pool.push(QueryColorListItem {
-                                        color,
-                                        count: query.count,
-                                        next_index: *colors_map_index,
-                                    });
-                                    *colors_map_index = queries_colors_list_pool.len() - 1;
+                                assert_eq!(counters.colors.len() % 2, 0);
+                                for range in counters.colors.chunks(2) {
+                                    let ColorsRange::Range(range) = ColorsRange::from_slice(range);
+
+                                    OPS_COUNT.fetch_add(1, Ordering::Relaxed);
+                                    COL_COUNT.fetch_add(range.len(), Ordering::Relaxed);
+
+                                    for color in range {
+                                        queries_colors_list_pool.push(QueryColorListItem {
+                                            color,
+                                            count: query.count,
+                                            next_index: *colors_map_index,
+                                        });
+                                        *colors_map_index = queries_colors_list_pool.len() - 1;
+                                    }
                                 }
                             }
-                        }
-                    },
-                );
+                        },
+                    );
+                }
 
                 let bucket_index = input.index;
 
@@ -183,7 +345,7 @@ pub fn colored_query_output<MH: HashFunctionFactory, CX: ColorsManager>(
                     &(),
                 );
 
-                let mut jsonline_buffer = vec![];
+                let mut output_buffer = vec![];
                 for (query, mut query_colors_list_index) in
                     queries_results.iter().enumerate().filter_map(|(i, r)| {
                         if r.0 != epoch {
@@ -193,53 +355,180 @@ pub fn colored_query_output<MH: HashFunctionFactory, CX: ColorsManager>(
                         }
                     })
                 {
-                    jsonline_buffer.clear();
-                    write!(
-                        jsonline_buffer,
-                        "{{\"query_index\":{}, \"matches\":{{",
-                        query
-                    )
-                    .unwrap();
-
                     temp_colors_list.clear();
                     while query_colors_list_index != usize::MAX {
                         let el = &queries_colors_list_pool[query_colors_list_index];
                         temp_colors_list.push((el.color, el.count));
                         query_colors_list_index = el.next_index;
                     }
-                    temp_colors_list.sort_unstable_by_key(|r| r.0);
-
-                    for (i, qc) in temp_colors_list.nq_group_by(|a, b| a.0 == b.0).enumerate() {
-                        let color_index = qc[0].0;
-                        let color_presence = qc.iter().map(|x| x.1).sum::<u64>();
+                    let query_kmers = query_kmers_count[query as usize];
+
+                    filter_query_colors(
+                        &mut temp_colors_list,
+                        query_kmers,
+                        &output_filter,
+                        selected_colors.as_deref(),
+                        &mut filtered_colors_list,
+                    );
+
+                    // Do not write the queries without any color left after filtering
+                    if filtered_colors_list.is_empty() {
+                        continue;
+                    }
 
-                        if i != 0 {
-                            write!(jsonline_buffer, ",").unwrap();
+                    if let Some(output_callback) = &output_callback {
+                        if output_callback.single_thread_output_function {
+                            callback_results.push((query, filtered_colors_list.clone()));
+                        } else {
+                            (output_callback.output_function)(
+                                query,
+                                &query_names[query],
+                                &filtered_colors_list,
+                            );
                         }
+                        continue;
+                    }
+
+                    output_buffer.clear();
+                    match colored_query_output_format {
+                        ColoredQueryOutputFormat::JsonLinesWithNumbers
+                        | ColoredQueryOutputFormat::JsonLinesWithNames => {
+                            write_json_query_start(&mut output_buffer, query, &query_names[query]);
+                            write!(output_buffer, ", \"matches\":{{").unwrap();
+
+                            for (i, &(color_index, color_presence)) in
+                                filtered_colors_list.iter().enumerate()
+                            {
+                                if i != 0 {
+                                    write!(output_buffer, ",").unwrap();
+                                }
+
+                                if colored_query_output_format
+                                    == ColoredQueryOutputFormat::JsonLinesWithNames
+                                {
+                                    write_json_string(
+                                        &mut output_buffer,
+                                        colormap.get_color_name(color_index, false),
+                                    );
+                                } else {
+                                    write!(output_buffer, "\"{}\"", color_index).unwrap();
+                                }
 
-                        match colored_query_output_format {
-                            ColoredQueryOutputFormat::JsonLinesWithNumbers => {
-                                write!(jsonline_buffer, "\"{}\"", color_index)
-                            }
-                            ColoredQueryOutputFormat::JsonLinesWithNames => {
                                 write!(
-                                    jsonline_buffer,
-                                    "\"{}\"",
-                                    colormap.get_color_name(color_index, true)
+                                    output_buffer,
+                                    ": {:.2}",
+                                    (color_presence as f64) / (query_kmers as f64)
                                 )
+                                .unwrap();
                             }
+                            writeln!(output_buffer, "}}}}").unwrap();
+                        }
+                        ColoredQueryOutputFormat::Tsv => {
+                            for &(color_index, color_presence) in filtered_colors_list.iter() {
+                                write!(output_buffer, "{}\t", query).unwrap();
+                                write_tsv_escaped(&mut output_buffer, &query_names[query]);
+                                output_buffer.push(b'\t');
+                                write_tsv_escaped(
+                                    &mut output_buffer,
+                                    colormap.get_color_name(color_index, false),
+                                );
+                                writeln!(
+                                    output_buffer,
+                                    "\t{}\t{:.2}",
+                                    color_presence,
+                                    (color_presence as f64) / (query_kmers as f64)
+                                )
+                                .unwrap();
+                            }
+                        }
+                        ColoredQueryOutputFormat::MatrixMarket => {
+                            // Matrix market indices are 1-based
+                            for &(color_index, color_presence) in filtered_colors_list.iter() {
+                                writeln!(
+                                    output_buffer,
+                                    "{} {} {}",
+                                    query + 1,
+                                    color_index + 1,
+                                    color_presence
+                                )
+                                .unwrap();
+                            }
+                            matrix_entries_count
+                                .fetch_add(filtered_colors_list.len() as u64, Ordering::Relaxed);
+                        }
+                    }
+                    compressed_stream.write_data(&output_buffer);
+                }
+
+                query_segments.sort_unstable_by_key(|s| (s.0, s.1));
+                for segments in query_segments.nq_group_by(|a, b| a.0 == b.0) {
+                    let query = segments[0].0;
+
+                    let segments: Vec<_> = segments
+                        .iter()
+                        .map(|(_, start, length, colors_ranges)| {
+                            let colors = segments_colors_ranges[colors_ranges.clone()]
+                                .chunks(2)
+                                .flat_map(|range| {
+                                    let ColorsRange::Range(range) = ColorsRange::from_slice(range);
+                                    range
+                                })
+                                .collect();
+                            (*start, *length, colors)
+                        })
+                        .collect();
+
+                    let merged_segments = filter_query_segments(
+                        &segments,
+                        query_kmers_count[query],
+                        &output_filter,
+                        selected_colors.as_deref(),
+                    );
+
+                    // Do not write the queries without any color left after filtering
+                    if merged_segments.is_empty() {
+                        continue;
+                    }
+
+                    output_buffer.clear();
+                    write_json_query_start(&mut output_buffer, query, &query_names[query]);
+                    write!(
+                        output_buffer,
+                        ", \"query_kmers\":{}, \"segments\":[",
+                        query_kmers_count[query]
+                    )
+                    .unwrap();
+
+                    for (i, (start, end, colors)) in merged_segments.iter().enumerate() {
+                        if i != 0 {
+                            write!(output_buffer, ",").unwrap();
                         }
-                        .unwrap();
 
                         write!(
-                            jsonline_buffer,
-                            ": {:.2}",
-                            (color_presence as f64) / (query_kmers_count[query as usize] as f64)
+                            output_buffer,
+                            "{{\"start\":{}, \"end\":{}, \"colors\":[",
+                            start, end
                         )
                         .unwrap();
+                        for (i, &color_index) in colors.iter().enumerate() {
+                            if i != 0 {
+                                write!(output_buffer, ",").unwrap();
+                            }
+                            if colored_query_output_format
+                                == ColoredQueryOutputFormat::JsonLinesWithNames
+                            {
+                                write_json_string(
+                                    &mut output_buffer,
+                                    colormap.get_color_name(color_index, false),
+                                );
+                            } else {
+                                write!(output_buffer, "{}", color_index).unwrap();
+                            }
+                        }
+                        write!(output_buffer, "]}}").unwrap();
                     }
-                    writeln!(jsonline_buffer, "}}}}").unwrap();
-                    compressed_stream.write_data(&jsonline_buffer);
+                    writeln!(output_buffer, "]}}").unwrap();
+                    compressed_stream.write_data(&output_buffer);
                 }
 
                 let stream_path = compressed_stream.get_path();
@@ -260,7 +549,16 @@ pub fn colored_query_output<MH: HashFunctionFactory, CX: ColorsManager>(
                     queries_lock.deref_mut()
                 };
 
-                std::io::copy(&mut decompress_stream.get_single_stream(), queries_file).unwrap();
+                if let Some(queries_file) = queries_file {
+                    std::io::copy(&mut decompress_stream.get_single_stream(), queries_file)
+                        .unwrap();
+                }
+
+                if let Some(output_callback) = &output_callback {
+                    for (query, colors) in callback_results.drain(..) {
+                        (output_callback.output_function)(query, &query_names[query], &colors);
+                    }
+                }
 
                 *query_write_index += 1;
                 output_sync_condvar.notify_all();
@@ -272,5 +570,193 @@ pub fn colored_query_output<MH: HashFunctionFactory, CX: ColorsManager>(
         OPS_COUNT.load(Ordering::Relaxed),
         COL_COUNT.load(Ordering::Relaxed)
     );
+
+    let (query_output_writer, _) = query_output.into_inner();
+    let Some(mut query_output_writer) = query_output_writer else {
+        return Ok(());
+    };
+    query_output_writer.flush()?;
+    drop(query_output_writer);
+
+    if colored_query_output_format == ColoredQueryOutputFormat::MatrixMarket {
+        let mut matrix_output = create_query_output_writer(&output_file)?;
+        writeln!(
+            matrix_output,
+            "%%MatrixMarket matrix coordinate integer general"
+        )?;
+        writeln!(
+            matrix_output,
+            "% rows: queries, columns: colors, values: matched kmers"
+        )?;
+        writeln!(
+            matrix_output,
+            "{} {} {}",
+            query_kmers_count.len(),
+            colormap.colors_count(),
+            matrix_entries_count.load(Ordering::Relaxed)
+        )?;
+        std::io::copy(&mut File::open(&matrix_entries_file)?, &mut matrix_output)?;
+        matrix_output.flush()?;
+        drop(matrix_output);
+        let _ = std::fs::remove_file(&matrix_entries_file);
+
+        // Rows and columns labels, one per line in the matrix order
+        write_matrix_labels(
+            &output_file.with_extension("queries.txt"),
+            query_names.iter().map(|name| name.as_str()),
+        )?;
+        write_matrix_labels(
+            &output_file.with_extension("colors.txt"),
+            (0..colormap.colors_count())
+                .map(|color| colormap.get_color_name(color as ColorIndexType, false)),
+        )?;
+    }
+
     Ok(())
 }
+
+/// Writes the matrix market labels one per line, escaping the newlines and the tabs in the names
+fn write_matrix_labels<'a>(
+    path: &Path,
+    labels: impl Iterator<Item = &'a str>,
+) -> anyhow::Result<()> {
+    let mut labels_file = BufWriter::new(File::create(path)?);
+    let mut buffer = vec![];
+    for label in labels {
+        buffer.clear();
+        write_tsv_escaped(&mut buffer, label);
+        buffer.push(b'\n');
+        labels_file.write_all(&buffer)?;
+    }
+    labels_file.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_query_start_keeps_the_index() {
+        let mut buffer = vec![];
+        write_json_query_start(&mut buffer, 3, "read\"3\"");
+        write!(buffer, "}}").unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            r#"{"query_index":3, "query_name":"read\"3\""}"#
+        );
+    }
+
+    fn filter(
+        min_match_fraction: f64,
+        min_matched_kmers: u64,
+        top_colors: Option<usize>,
+    ) -> QueryOutputFilter {
+        QueryOutputFilter {
+            min_match_fraction,
+            min_matched_kmers,
+            top_colors,
+            colors_selection: None,
+        }
+    }
+
+    fn filter_colors(
+        mut colors_list: Vec<(ColorIndexType, u64)>,
+        output_filter: &QueryOutputFilter,
+        selected_colors: Option<&[bool]>,
+    ) -> Vec<(ColorIndexType, u64)> {
+        let mut filtered_colors_list = vec![];
+        filter_query_colors(
+            &mut colors_list,
+            10,
+            output_filter,
+            selected_colors,
+            &mut filtered_colors_list,
+        );
+        filtered_colors_list
+    }
+
+    #[test]
+    fn query_colors_filtering() {
+        let colors_list = vec![(2, 3), (0, 4), (1, 1), (2, 5), (3, 6)];
+
+        assert_eq!(
+            filter_colors(colors_list.clone(), &QueryOutputFilter::default(), None),
+            vec![(0, 4), (1, 1), (2, 8), (3, 6)]
+        );
+        assert_eq!(
+            filter_colors(colors_list.clone(), &filter(0.5, 0, None), None),
+            vec![(2, 8), (3, 6)]
+        );
+        assert_eq!(
+            filter_colors(colors_list.clone(), &filter(0.0, 4, Some(2)), None),
+            vec![(2, 8), (3, 6)]
+        );
+        assert_eq!(
+            filter_colors(
+                colors_list,
+                &filter(0.0, 2, None),
+                Some(&[true, true, false, true])
+            ),
+            vec![(0, 4), (3, 6)]
+        );
+    }
+
+    #[test]
+    fn query_segments_filtering() {
+        let segments = vec![
+            (0, 3, vec![0, 1]),
+            (3, 2, vec![0]),
+            (5, 4, vec![0, 2]),
+            (12, 1, vec![1]),
+        ];
+
+        // Adjacent segments with the same colors are merged
+        assert_eq!(
+            filter_query_segments(&segments, 13, &filter(0.0, 0, Some(1)), None),
+            vec![(0, 9, vec![0])]
+        );
+
+        // Color 1 matches 4 kmers and color 2 matches 4 kmers, both are removed
+        assert_eq!(
+            filter_query_segments(&segments, 13, &filter(0.0, 5, None), None),
+            vec![(0, 9, vec![0])]
+        );
+
+        assert_eq!(
+            filter_query_segments(
+                &segments,
+                13,
+                &filter(0.0, 4, None),
+                Some(&[false, true, true])
+            ),
+            vec![(0, 3, vec![1]), (5, 9, vec![2]), (12, 13, vec![1])]
+        );
+
+        assert_eq!(
+            filter_query_segments(&segments, 13, &filter(1.0, 0, None), None),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn tsv_fields_escaping() {
+        let mut buffer = vec![];
+        write_tsv_escaped(&mut buffer, "a\tb\nc\\d");
+        assert_eq!(String::from_utf8(buffer).unwrap(), r"a\tb\nc\\d");
+    }
+
+    #[test]
+    fn matrix_labels_escaping() {
+        let path = std::env::temp_dir().join(format!("ggcat-matrix-labels-{}", std::process::id()));
+        write_matrix_labels(
+            &path,
+            ["read 1", "read\n2\tpaired", "sample\\3"].into_iter(),
+        )
+        .unwrap();
+        let labels = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(labels, "read 1\nread\\n2\\tpaired\nsample\\\\3\n");
+        assert_eq!(labels.lines().count(), 3);
+    }
+}
@@ -2,8 +2,8 @@ use crate::pipeline::counters_sorting::{CounterEntry, CounterEntrySerializer};
 use crate::structs::query_colored_counters::{
     ColorsRange, QueryColorDesc, QueryColoredCounters, QueryColoredCountersSerializer,
 };
+use crate::QueryOutputMode;
 use colors::storage::deserializer::ColorsDeserializer;
-use colors::storage::ColorsSerializerTrait;
 use config::{
     get_compression_level_info, get_memory_mode, BucketIndexType, ColorIndexType, SwapPriority,
     DEFAULT_PER_CPU_BUFFER_SIZE, DEFAULT_PREFETCH_AMOUNT, KEEP_FILES,
@@ -25,17 +25,21 @@ use std::path::PathBuf;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
-pub fn colormap_reading<CD: ColorsSerializerTrait>(
+pub fn colormap_reading(
     colormap_file: PathBuf,
     colored_query_buckets: Vec<SingleBucket>,
     temp_dir: PathBuf,
     queries_count: u64,
+    // Added to the color indexes, to number the colors across several graphs
+    color_offset: ColorIndexType,
+    output_mode: QueryOutputMode,
 ) -> anyhow::Result<Vec<SingleBucket>> {
     PHASES_TIMES_MONITOR
         .write()
         .start_phase("phase: colormap reading".to_string());
 
     let buckets_count = colored_query_buckets.len();
+    let with_positions = output_mode == QueryOutputMode::KmerPositions;
     let buckets_prefix_path = temp_dir.join("query_colors");
 
     let correct_color_buckets = Arc::new(MultiThreadBuckets::<CompressedBinaryWriter>::new(
@@ -55,13 +59,12 @@ pub fn colormap_reading<CD: ColorsSerializerTrait>(
     });
 
     // Try to build a color deserializer to check colormap correctness
-    let _ = ColorsDeserializer::<CD>::new(&colormap_file, false)?;
+    let _ = ColorsDeserializer::new(&colormap_file, false)?;
 
-    let tlocal_colormap_decoder = ScopedThreadLocal::new(move || {
-        ColorsDeserializer::<CD>::new(&colormap_file, false).unwrap()
-    });
+    let tlocal_colormap_decoder =
+        ScopedThreadLocal::new(move || ColorsDeserializer::new(&colormap_file, false).unwrap());
 
-    colored_query_buckets.par_iter().for_each(|input| {
+    colored_query_buckets.par_iter().try_for_each(|input| {
         let mut colormap_decoder = tlocal_colormap_decoder.get();
         let mut temp_colors_buffer = Vec::new();
         let mut temp_queries_buffer = Vec::new();
@@ -84,7 +87,7 @@ pub fn colormap_reading<CD: ColorsSerializerTrait>(
         )
         .decode_all_bucket_items::<CounterEntrySerializer<ColorIndexType>, _>(
             (),
-            &mut (),
+            &mut { with_positions },
             |h, _| {
                 counters_vec.push(h);
             },
@@ -109,17 +112,22 @@ pub fn colormap_reading<CD: ColorsSerializerTrait>(
 
         fast_smart_radix_sort::<_, CountersCompare, false>(&mut counters_vec[..]);
 
+        let mut result = Ok(());
         for queries_by_color in counters_vec.nq_group_by_mut(|a, b| a.1 == b.1) {
             let color = queries_by_color[0].1;
             temp_colors_buffer.clear();
-            colormap_decoder.get_color_mappings(color, &mut temp_colors_buffer);
+            if let Err(err) = colormap_decoder.get_color_mappings(color, &mut temp_colors_buffer) {
+                // Stop at the first corrupted chunk, still releasing the thread buffer
+                result = Err(err);
+                break;
+            }
 
             {
                 temp_encoded_buffer.clear();
                 let mut range_start = ColorIndexType::MAX;
                 let mut range_end = ColorIndexType::MAX;
 
-                for color in temp_colors_buffer.iter().copied() {
+                for color in temp_colors_buffer.iter().map(|color| color + color_offset) {
                     // Different range
                     if color != range_end {
                         if range_start != ColorIndexType::MAX {
@@ -137,6 +145,7 @@ pub fn colormap_reading<CD: ColorsSerializerTrait>(
                 temp_queries_buffer.clear();
                 temp_queries_buffer.extend(queries_by_color.iter().map(|q| QueryColorDesc {
                     query_index: q.0.query_index,
+                    position: q.0.position,
                     count: q.0.counter,
                 }));
 
@@ -162,9 +171,10 @@ pub fn colormap_reading<CD: ColorsSerializerTrait>(
                 get_query_bucket(a.query_index) == get_query_bucket(b.query_index)
             }) {
                 let bucket = get_query_bucket(entries[0].query_index);
-                colored_buckets_writer.add_element(
+                colored_buckets_writer.add_element_extended(
                     bucket,
                     &(),
+                    &with_positions,
                     &QueryColoredCounters {
                         queries: entries,
                         colors: &temp_encoded_buffer,
@@ -173,7 +183,8 @@ pub fn colormap_reading<CD: ColorsSerializerTrait>(
             }
         }
         thread_buffer.put_back(colored_buckets_writer.finalize().0);
-    });
+        result
+    })?;
 
     Ok(correct_color_buckets.finalize_single())
 }
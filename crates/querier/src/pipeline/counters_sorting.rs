@@ -1,3 +1,4 @@
+use crate::{QueryOutputMode, QueryResultsCallback};
 use byteorder::ReadBytesExt;
 use colors::colors_manager::color_types::SingleKmerColorDataType;
 use colors::colors_manager::ColorsManager;
@@ -31,10 +32,13 @@ use std::sync::Arc;
 #[derive(Debug, Clone)]
 pub struct CounterEntry<CX: SequenceExtraDataConsecutiveCompression<TempBuffer = ()>> {
     pub query_index: u64,
+    /// Position of the first matched kmer in the query, used only for the kmer positions output
+    pub position: u64,
     pub counter: u64,
     pub _phantom: PhantomData<CX>,
 }
 
+/// The extra data buffer tells if the positions are serialized, as they are needed only by the kmer positions output
 pub struct CounterEntrySerializer<CX: SequenceExtraDataConsecutiveCompression<TempBuffer = ()>>(
     CX::LastData,
 );
@@ -44,7 +48,7 @@ impl<CX: SequenceExtraDataConsecutiveCompression<TempBuffer = ()>> BucketItemSer
 {
     type InputElementType<'a> = CounterEntry<CX>;
     type ExtraData = CX;
-    type ExtraDataBuffer = ();
+    type ExtraDataBuffer = bool;
     type ReadBuffer = ();
     type ReadType<'a> = (CounterEntry<CX>, CX);
 
@@ -66,9 +70,12 @@ impl<CX: SequenceExtraDataConsecutiveCompression<TempBuffer = ()>> BucketItemSer
         element: &Self::InputElementType<'_>,
         bucket: &mut Vec<u8>,
         extra_data: &Self::ExtraData,
-        _: &Self::ExtraDataBuffer,
+        with_positions: &Self::ExtraDataBuffer,
     ) {
         encode_varint(|b| bucket.extend_from_slice(b), element.query_index);
+        if *with_positions {
+            encode_varint(|b| bucket.extend_from_slice(b), element.position);
+        }
         encode_varint(|b| bucket.extend_from_slice(b), element.counter);
         extra_data.encode(bucket, self.0);
         self.0 = extra_data.obtain_last_data(self.0);
@@ -78,15 +85,21 @@ impl<CX: SequenceExtraDataConsecutiveCompression<TempBuffer = ()>> BucketItemSer
         &mut self,
         mut stream: S,
         _read_buffer: &'a mut Self::ReadBuffer,
-        _: &mut Self::ExtraDataBuffer,
+        with_positions: &mut Self::ExtraDataBuffer,
     ) -> Option<Self::ReadType<'a>> {
         let query_index = decode_varint(|| stream.read_u8().ok())?;
+        let position = if *with_positions {
+            decode_varint(|| stream.read_u8().ok())?
+        } else {
+            0
+        };
         let counter = decode_varint(|| stream.read_u8().ok())?;
         let color = CX::decode(&mut stream, self.0)?;
         self.0 = color.obtain_last_data(self.0);
         Some((
             CounterEntry {
                 query_index,
+                position,
                 counter,
                 _phantom: PhantomData,
             },
@@ -96,7 +109,7 @@ impl<CX: SequenceExtraDataConsecutiveCompression<TempBuffer = ()>> BucketItemSer
 
     #[inline(always)]
     fn get_size(&self, _: &Self::InputElementType<'_>, data: &Self::ExtraData) -> usize {
-        VARINT_MAX_SIZE * 2 + data.max_size()
+        VARINT_MAX_SIZE * 3 + data.max_size()
     }
 }
 
@@ -107,6 +120,9 @@ pub fn counters_sorting<CX: ColorsManager>(
     colors_count: u64,
     output_file: PathBuf,
     query_kmers_count: &[u64],
+    query_names: &[String],
+    output_mode: QueryOutputMode,
+    output_callback: Option<QueryResultsCallback<'_>>,
 ) -> Vec<SingleBucket> {
     PHASES_TIMES_MONITOR
         .write()
@@ -147,6 +163,7 @@ pub fn counters_sorting<CX: ColorsManager>(
     });
 
     let buckets_count_log = buckets_count.ilog2();
+    let with_positions = output_mode == QueryOutputMode::KmerPositions;
 
     file_counters_inputs.par_iter().for_each(|input| {
         let mut thread_buffer = thread_buffers.get();
@@ -168,7 +185,7 @@ pub fn counters_sorting<CX: ColorsManager>(
         )
         .decode_all_bucket_items::<CounterEntrySerializer<SingleKmerColorDataType<CX>>, _>(
             (),
-            &mut (),
+            &mut { with_positions },
             |h, _| {
                 counters_vec.push(h);
             },
@@ -200,14 +217,26 @@ pub fn counters_sorting<CX: ColorsManager>(
             query_results.sort_unstable_by(|x, y| x.1.cmp(&y.1));
             let query_index = query_results[0].0.query_index;
 
-            if CX::COLORS_ENABLED {
+            if CX::COLORS_ENABLED && with_positions {
+                // Keep each matched segment, to be merged with the adjacent ones in the output
+                for (entry, color) in query_results.iter() {
+                    colored_buckets_writer.add_element_extended(
+                        CX::get_bucket_from_color(color, colors_count, buckets_count_log),
+                        color,
+                        &true,
+                        entry,
+                    );
+                }
+            } else if CX::COLORS_ENABLED {
                 for entry in query_results.nq_group_by(|a, b| a.1 == b.1) {
                     let color = entry[0].1.clone();
-                    colored_buckets_writer.add_element(
+                    colored_buckets_writer.add_element_extended(
                         CX::get_bucket_from_color(&color, colors_count, buckets_count_log),
                         &color,
+                        &false,
                         &CounterEntry {
                             query_index,
+                            position: 0,
                             counter: entry.iter().map(|e| e.0.counter).sum(),
                             _phantom: PhantomData,
                         },
@@ -224,7 +253,19 @@ pub fn counters_sorting<CX: ColorsManager>(
         thread_buffer.put_back(colored_buckets_writer.finalize().0);
     });
 
-    if !CX::COLORS_ENABLED {
+    if let (false, Some(output_callback)) = (CX::COLORS_ENABLED, output_callback) {
+        // Without colors all the matches are reported for the single color 0
+        for (query_index, (counter, name)) in
+            final_counters.iter().zip(query_names.iter()).enumerate()
+        {
+            (output_callback.output_function)(
+                query_index,
+                name,
+                &[(0, counter.load(Ordering::Relaxed))],
+            );
+        }
+        vec![]
+    } else if !CX::COLORS_ENABLED {
         let output_file = if output_file.extension().is_none() {
             output_file.with_extension("csv")
         } else {
@@ -238,12 +279,14 @@ pub fn counters_sorting<CX: ColorsManager>(
                 "matched_kmers",
                 "query_kmers",
                 "match_percentage",
+                "query_name",
             ])
             .unwrap();
 
-        for (query_index, (info, counter)) in query_kmers_count
+        for (query_index, ((info, counter), name)) in query_kmers_count
             .iter()
             .zip(final_counters.iter())
+            .zip(query_names.iter())
             .enumerate()
         {
             writer
@@ -255,6 +298,7 @@ pub fn counters_sorting<CX: ColorsManager>(
                         "{:.2}",
                         (counter.load(Ordering::Relaxed) as f64 / *info as f64)
                     ),
+                    name.clone(),
                 ])
                 .unwrap();
         }
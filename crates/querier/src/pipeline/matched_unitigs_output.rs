@@ -0,0 +1,117 @@
+use crate::pipeline::colored_query_output::{create_query_output_writer, write_json_query_start};
+use crate::structs::unitig_matches::{UnitigMatch, UnitigMatchSerializer};
+use config::{DEFAULT_PREFETCH_AMOUNT, KEEP_FILES};
+use nightly_quirks::slice_group_by::SliceGroupBy;
+use parallel_processor::buckets::readers::lock_free_binary_reader::LockFreeBinaryReader;
+use parallel_processor::buckets::readers::BucketReader;
+use parallel_processor::buckets::SingleBucket;
+use parallel_processor::memory_fs::RemoveFileMode;
+use parallel_processor::phase_times_monitor::PHASES_TIMES_MONITOR;
+use rayon::prelude::*;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+
+/// Writes the output lines of the queries in a single bucket
+fn write_bucket_matches(
+    input: &SingleBucket,
+    query_kmers_count: &[u64],
+    query_names: &[String],
+) -> Vec<u8> {
+    let mut matches = vec![];
+    LockFreeBinaryReader::new(
+        &input.path,
+        RemoveFileMode::Remove {
+            remove_fs: !KEEP_FILES.load(Ordering::Relaxed),
+        },
+        DEFAULT_PREFETCH_AMOUNT,
+    )
+    .decode_all_bucket_items::<UnitigMatchSerializer, _>((), &mut (), |unitig_match, _| {
+        matches.push(unitig_match);
+    });
+
+    matches.sort_unstable_by_key(|m| (m.query_index, m.position));
+
+    let mut output_buffer = vec![];
+    for query_matches in matches.nq_group_by(|a, b| a.query_index == b.query_index) {
+        let query = query_matches[0].query_index as usize - 1;
+
+        write_json_query_start(&mut output_buffer, query, &query_names[query]);
+        write!(
+            output_buffer,
+            ", \"query_kmers\":{}, \"unitigs\":[",
+            query_kmers_count[query]
+        )
+        .unwrap();
+
+        // Merge the adjacent segments matching the same unitig, split between different buckets
+        let mut last: Option<UnitigMatch> = None;
+        let mut first = true;
+        for unitig_match in query_matches.iter().cloned().map(Some).chain([None]) {
+            match (&mut last, unitig_match) {
+                (Some(last), Some(next))
+                    if last.position + last.length == next.position
+                        && last.unitig == next.unitig
+                        && last.forward == next.forward =>
+                {
+                    last.length += next.length;
+                }
+                (last, next) => {
+                    if let Some(segment) = last.take() {
+                        if !first {
+                            write!(output_buffer, ",").unwrap();
+                        }
+                        first = false;
+                        write!(
+                            output_buffer,
+                            "{{\"start\":{}, \"end\":{}, \"unitig\":{}, \"orientation\":\"{}\"}}",
+                            segment.position,
+                            segment.position + segment.length,
+                            segment.unitig,
+                            if segment.forward { '+' } else { '-' }
+                        )
+                        .unwrap();
+                    }
+                    *last = next;
+                }
+            }
+        }
+        writeln!(output_buffer, "]}}").unwrap();
+    }
+    output_buffer
+}
+
+/// Writes, for each query, the graph unitigs matched by its kmers in the query order
+pub fn matched_unitigs_output(
+    unitig_matches_buckets: Vec<SingleBucket>,
+    output_file: PathBuf,
+    query_kmers_count: &[u64],
+    query_names: &[String],
+) -> anyhow::Result<()> {
+    PHASES_TIMES_MONITOR
+        .write()
+        .start_phase("phase: matched unitigs output".to_string());
+
+    let output_file = if output_file.extension().is_none() {
+        output_file.with_extension("jsonl")
+    } else {
+        output_file
+    };
+
+    let mut query_output = create_query_output_writer(&output_file)?;
+
+    // The buckets contain contiguous ranges of queries, so they are written in order
+    for buckets in unitig_matches_buckets.chunks(rayon::current_num_threads()) {
+        let outputs: Vec<_> = buckets
+            .par_iter()
+            .map(|input| write_bucket_matches(input, query_kmers_count, query_names))
+            .collect();
+
+        for output in outputs {
+            query_output.write_all(&output)?;
+        }
+    }
+
+    query_output.flush()?;
+    Ok(())
+}
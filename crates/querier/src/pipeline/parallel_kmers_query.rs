@@ -1,7 +1,9 @@
 use crate::pipeline::counters_sorting::CounterEntry;
 use crate::pipeline::querier_minimizer_bucketing::{
-    QuerierMinimizerBucketingExecutorFactory, QuerierMinimizerBucketingGlobalData,
+    QuerierMinimizerBucketingExecutorFactory, QuerierMinimizerBucketingGlobalData, QueriesNumbering,
 };
+use crate::structs::unitig_matches::{UnitigMatch, UnitigMatchSerializer};
+use crate::QueryOutputMode;
 use byteorder::{ReadBytesExt, WriteBytesExt};
 use colors::colors_manager::color_types::{
     MinimizerBucketingSeqColorDataType, SingleKmerColorDataType,
@@ -31,12 +33,13 @@ use minimizer_bucketing::resplit_bucket::RewriteBucketCompute;
 use minimizer_bucketing::{MinimizerBucketingCommonData, MinimizerBucketingExecutorFactory};
 use parallel_processor::buckets::concurrent::{BucketsThreadBuffer, BucketsThreadDispatcher};
 use parallel_processor::buckets::writers::lock_free_binary_writer::LockFreeBinaryWriter;
-use parallel_processor::buckets::{MultiThreadBuckets, SingleBucket};
+use parallel_processor::buckets::{MultiChunkBucket, MultiThreadBuckets, SingleBucket};
 use parallel_processor::execution_manager::memory_tracker::MemoryTracker;
 use parallel_processor::execution_manager::objects_pool::PoolObjectTrait;
 use parallel_processor::execution_manager::packet::{Packet, PacketTrait};
 use parallel_processor::phase_times_monitor::PHASES_TIMES_MONITOR;
 use std::cmp::min;
+use std::collections::HashSet;
 use std::io::{Read, Write};
 use std::marker::PhantomData;
 use std::mem::size_of;
@@ -49,8 +52,10 @@ use super::counters_sorting::CounterEntrySerializer;
 
 #[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
 pub enum QueryKmersReferenceData<CX: MinimizerBucketingSeqColorData> {
-    Graph(CX),
-    Query(NonZeroU64),
+    /// Colors and optionally index of the graph sequence
+    Graph(CX, Option<u64>),
+    /// Query index and position of the first kmer of the sequence in the query
+    Query(NonZeroU64, u64),
 }
 
 impl<CX: MinimizerBucketingSeqColorData> SequenceExtraDataTempBufferManagement
@@ -75,10 +80,13 @@ impl<CX: MinimizerBucketingSeqColorData> SequenceExtraDataTempBufferManagement
     #[inline(always)]
     fn copy_extra_from(extra: Self, src: &(CX::TempBuffer,), dst: &mut (CX::TempBuffer,)) -> Self {
         match extra {
-            QueryKmersReferenceData::Graph(color) => {
-                QueryKmersReferenceData::Graph(CX::copy_extra_from(color, &src.0, &mut dst.0))
+            QueryKmersReferenceData::Graph(color, unitig) => QueryKmersReferenceData::Graph(
+                CX::copy_extra_from(color, &src.0, &mut dst.0),
+                unitig,
+            ),
+            QueryKmersReferenceData::Query(index, position) => {
+                QueryKmersReferenceData::Query(index, position)
             }
-            QueryKmersReferenceData::Query(index) => QueryKmersReferenceData::Query(index),
         }
     }
 }
@@ -95,13 +103,20 @@ impl<CX: MinimizerBucketingSeqColorData> SequenceExtraDataConsecutiveCompression
         last_data: Self::LastData,
     ) -> Option<Self> {
         match reader.read_u8().ok()? {
-            0 => Some(Self::Graph(CX::decode_extended(
-                &mut buffer.0,
-                reader,
-                last_data,
-            )?)),
+            0 => Some(Self::Graph(
+                CX::decode_extended(&mut buffer.0, reader, last_data)?,
+                None,
+            )),
+            2 => {
+                let unitig = decode_varint(|| reader.read_u8().ok())?;
+                Some(Self::Graph(
+                    CX::decode_extended(&mut buffer.0, reader, last_data)?,
+                    Some(unitig),
+                ))
+            }
             _ => Some(Self::Query(
                 NonZeroU64::new(decode_varint(|| reader.read_u8().ok())? + 1).unwrap(),
+                decode_varint(|| reader.read_u8().ok())?,
             )),
         }
     }
@@ -114,13 +129,19 @@ impl<CX: MinimizerBucketingSeqColorData> SequenceExtraDataConsecutiveCompression
         last_data: Self::LastData,
     ) {
         match self {
-            Self::Graph(cx) => {
+            Self::Graph(cx, None) => {
                 writer.write_u8(0).unwrap();
                 CX::encode_extended(cx, &buffer.0, writer, last_data);
             }
-            Self::Query(val) => {
+            Self::Graph(cx, Some(unitig)) => {
+                writer.write_u8(2).unwrap();
+                encode_varint(|bytes| writer.write_all(bytes), *unitig).unwrap();
+                CX::encode_extended(cx, &buffer.0, writer, last_data);
+            }
+            Self::Query(val, position) => {
                 writer.write_u8(1).unwrap();
                 encode_varint(|bytes| writer.write_all(bytes), val.get() - 1).unwrap();
+                encode_varint(|bytes| writer.write_all(bytes), *position).unwrap();
             }
         }
     }
@@ -128,15 +149,15 @@ impl<CX: MinimizerBucketingSeqColorData> SequenceExtraDataConsecutiveCompression
     #[inline(always)]
     fn max_size(&self) -> usize {
         match self {
-            Self::Graph(cx) => cx.max_size() + 1,
-            Self::Query(_) => 10 + 1,
+            Self::Graph(cx, _) => cx.max_size() + 10 + 1,
+            Self::Query(_, _) => 10 + 10 + 1,
         }
     }
 
     fn obtain_last_data(&self, last_data: Self::LastData) -> Self::LastData {
         match self {
-            Self::Graph(cx) => cx.obtain_last_data(last_data),
-            Self::Query(_) => Self::LastData::default(),
+            Self::Graph(cx, _) => cx.obtain_last_data(last_data),
+            Self::Query(_, _) => Self::LastData::default(),
         }
     }
 }
@@ -146,6 +167,9 @@ struct GlobalQueryMergeData {
     m: usize,
     counters_buckets: Arc<MultiThreadBuckets<LockFreeBinaryWriter>>,
     global_resplit_data: Arc<MinimizerBucketingCommonData<QuerierMinimizerBucketingGlobalData>>,
+    output_mode: QueryOutputMode,
+    unitig_matches_buckets: Arc<MultiThreadBuckets<LockFreeBinaryWriter>>,
+    queries_numbering: QueriesNumbering,
 }
 
 impl KmersTransformGlobalExtraData for GlobalQueryMergeData {
@@ -199,11 +223,20 @@ impl<MH: HashFunctionFactory, CX: ColorsManager> KmersTransformExecutorFactory
             global_data.counters_buckets.count(),
         );
 
+        let unitig_matches_buffers = BucketsThreadBuffer::new(
+            DEFAULT_PER_CPU_BUFFER_SIZE,
+            global_data.unitig_matches_buckets.count(),
+        );
+
         Self::FinalExecutorType {
             counters_tmp: BucketsThreadDispatcher::new(
                 &global_data.counters_buckets,
                 counters_buffers,
             ),
+            unitig_matches_tmp: BucketsThreadDispatcher::new(
+                &global_data.unitig_matches_buckets,
+                unitig_matches_buffers,
+            ),
             query_map: HashMap::new(),
             _phantom: PhantomData,
         }
@@ -239,7 +272,10 @@ impl RewriteBucketCompute for RewriteBucketComputeQuery {
 
 struct ParallelKmersQueryMapPacket<MH: HashFunctionFactory, CX: Sync + Send + 'static> {
     phmap: HashMap<MH::HashTypeUnextendable, CX>,
-    query_reads: Vec<(u64, MH::HashTypeUnextendable)>,
+    /// Unitig index and kmer orientation of the graph kmers, used only for the matched unitigs output
+    unitigs_map: HashMap<MH::HashTypeUnextendable, (u64, bool)>,
+    /// Query index, kmer position in the query, kmer orientation and kmer hash
+    query_reads: Vec<(u64, u64, bool, MH::HashTypeUnextendable)>,
 }
 
 impl<MH: HashFunctionFactory, CX: Sync + Send + 'static> PoolObjectTrait
@@ -250,12 +286,14 @@ impl<MH: HashFunctionFactory, CX: Sync + Send + 'static> PoolObjectTrait
     fn allocate_new(_init_data: &Self::InitData) -> Self {
         Self {
             phmap: HashMap::new(),
+            unitigs_map: HashMap::new(),
             query_reads: Vec::new(),
         }
     }
 
     fn reset(&mut self) {
         self.phmap = HashMap::with_capacity(32768);
+        self.unitigs_map = HashMap::new();
         self.query_reads.clear();
         self.query_reads.shrink_to(32768);
     }
@@ -264,7 +302,8 @@ impl<MH: HashFunctionFactory, CX: Sync + Send + 'static> PacketTrait
     for ParallelKmersQueryMapPacket<MH, CX>
 {
     fn get_size(&self) -> usize {
-        (self.phmap.len() + self.query_reads.len()) * 16 // TODO: Compute correct values
+        (self.phmap.len() + self.unitigs_map.len() + self.query_reads.len()) * 16
+        // TODO: Compute correct values
     }
 }
 
@@ -306,7 +345,17 @@ impl<MH: HashFunctionFactory, CX: ColorsManager>
             kmers_count += (read.bases_count() - k + 1) as u64;
 
             match sequence_type {
-                QueryKmersReferenceData::Graph(col_info) => {
+                // The unitig indexes are always present in the graph kmers bucketed in a query index
+                QueryKmersReferenceData::Graph(_, Some(unitig))
+                    if global_data.output_mode == QueryOutputMode::MatchedUnitigs =>
+                {
+                    for hash in hashes.iter() {
+                        map_packet
+                            .unitigs_map
+                            .insert(hash.to_unextendable(), (*unitig, hash.is_forward()));
+                    }
+                }
+                QueryKmersReferenceData::Graph(col_info, _) => {
                     for (hash, color) in hashes
                         .iter()
                         .zip(col_info.get_iterator(&extra_data_buffer.0))
@@ -314,11 +363,14 @@ impl<MH: HashFunctionFactory, CX: ColorsManager>
                         map_packet.phmap.insert(hash.to_unextendable(), color);
                     }
                 }
-                QueryKmersReferenceData::Query(index) => {
-                    for hash in hashes.iter() {
-                        map_packet
-                            .query_reads
-                            .push((index.get(), hash.to_unextendable()));
+                QueryKmersReferenceData::Query(index, position) => {
+                    for (offset, hash) in hashes.iter().enumerate() {
+                        map_packet.query_reads.push((
+                            global_data.queries_numbering.query_index(index.get()),
+                            position + offset as u64,
+                            hash.is_forward(),
+                            hash.to_unextendable(),
+                        ));
                     }
                 }
             }
@@ -343,6 +395,7 @@ struct ParallelKmersQueryFinalExecutor<MH: HashFunctionFactory, CX: ColorsManage
         LockFreeBinaryWriter,
         CounterEntrySerializer<SingleKmerColorDataType<CX>>,
     >,
+    unitig_matches_tmp: BucketsThreadDispatcher<LockFreeBinaryWriter, UnitigMatchSerializer>,
     query_map: HashMap<(u64, SingleKmerColorDataType<CX>), u64>,
     _phantom: PhantomData<(MH, CX)>,
 }
@@ -355,30 +408,139 @@ impl<MH: HashFunctionFactory, CX: ColorsManager>
 
     fn process_map(
         &mut self,
-        _global_data: &GlobalQueryMergeData,
+        global_data: &GlobalQueryMergeData,
         map_struct: Packet<Self::MapStruct>,
     ) -> Packet<ParallelKmersQueryMapPacket<MH, SingleKmerColorDataType<CX>>> {
         let map_struct_ref = map_struct.deref();
+        let counters_buckets_count = global_data.counters_buckets.count() as u64;
+
+        match global_data.output_mode {
+            QueryOutputMode::MatchCounts => {
+                for (query_index, _, _, kmer_hash) in &map_struct_ref.query_reads {
+                    if let Some(entry_color) = map_struct_ref.phmap.get(kmer_hash) {
+                        *self
+                            .query_map
+                            .entry((*query_index, entry_color.clone()))
+                            .or_insert(0) += 1;
+                    }
+                }
 
-        for (query_index, kmer_hash) in &map_struct_ref.query_reads {
-            if let Some(entry_color) = map_struct_ref.phmap.get(kmer_hash) {
-                *self
-                    .query_map
-                    .entry((*query_index, entry_color.clone()))
-                    .or_insert(0) += 1;
+                for ((query_index, color_index), counter) in self.query_map.drain() {
+                    self.counters_tmp.add_element_extended(
+                        (query_index % counters_buckets_count) as BucketIndexType,
+                        &color_index,
+                        &false,
+                        &CounterEntry {
+                            query_index,
+                            position: 0,
+                            counter,
+                            _phantom: PhantomData,
+                        },
+                    );
+                }
             }
-        }
+            QueryOutputMode::KmerPositions => {
+                // The kmers of each query sequence are consecutive, so the matches with the same color
+                // on adjacent positions are merged in a single segment
+                let mut segment: Option<(u64, u64, u64, SingleKmerColorDataType<CX>)> = None;
+
+                let mut flush_segment = |segment: (u64, u64, u64, SingleKmerColorDataType<CX>)| {
+                    let (query_index, position, counter, color) = segment;
+                    self.counters_tmp.add_element_extended(
+                        (query_index % counters_buckets_count) as BucketIndexType,
+                        &color,
+                        &true,
+                        &CounterEntry {
+                            query_index,
+                            position,
+                            counter,
+                            _phantom: PhantomData,
+                        },
+                    );
+                };
+
+                for (query_index, position, _, kmer_hash) in &map_struct_ref.query_reads {
+                    let Some(entry_color) = map_struct_ref.phmap.get(kmer_hash) else {
+                        continue;
+                    };
+
+                    match &mut segment {
+                        Some((last_query, last_position, counter, last_color))
+                            if *last_query == *query_index
+                                && *last_position + *counter == *position
+                                && last_color == entry_color =>
+                        {
+                            *counter += 1;
+                        }
+                        _ => {
+                            if let Some(segment) = segment.take() {
+                                flush_segment(segment);
+                            }
+                            segment = Some((*query_index, *position, 1, *entry_color));
+                        }
+                    }
+                }
 
-        for ((query_index, color_index), counter) in self.query_map.drain() {
-            self.counters_tmp.add_element(
-                (query_index % 0xFF) as BucketIndexType,
-                &color_index,
-                &CounterEntry {
-                    query_index,
-                    counter,
-                    _phantom: PhantomData,
-                },
-            )
+                if let Some(segment) = segment {
+                    flush_segment(segment);
+                }
+            }
+            QueryOutputMode::MatchedUnitigs => {
+                // Queries are split in contiguous ranges, to write the output in the queries order
+                let unitig_matches_buckets_count =
+                    global_data.unitig_matches_buckets.count() as u64;
+                let get_query_bucket = |query_index: u64| {
+                    ((query_index - 1) * unitig_matches_buckets_count
+                        / global_data.queries_numbering.queries_count())
+                        as BucketIndexType
+                };
+
+                let mut segment: Option<UnitigMatch> = None;
+
+                for (query_index, position, query_forward, kmer_hash) in &map_struct_ref.query_reads
+                {
+                    let Some((unitig, unitig_forward)) = map_struct_ref.unitigs_map.get(kmer_hash)
+                    else {
+                        continue;
+                    };
+                    let forward = *query_forward == *unitig_forward;
+
+                    match &mut segment {
+                        Some(last)
+                            if last.query_index == *query_index
+                                && last.position + last.length == *position
+                                && last.unitig == *unitig
+                                && last.forward == forward =>
+                        {
+                            last.length += 1;
+                        }
+                        _ => {
+                            if let Some(last) = segment.take() {
+                                self.unitig_matches_tmp.add_element(
+                                    get_query_bucket(last.query_index),
+                                    &(),
+                                    &last,
+                                );
+                            }
+                            segment = Some(UnitigMatch {
+                                query_index: *query_index,
+                                position: *position,
+                                length: 1,
+                                unitig: *unitig,
+                                forward,
+                            });
+                        }
+                    }
+                }
+
+                if let Some(last) = segment {
+                    self.unitig_matches_tmp.add_element(
+                        get_query_bucket(last.query_index),
+                        &(),
+                        &last,
+                    );
+                }
+            }
         }
 
         map_struct
@@ -386,6 +548,7 @@ impl<MH: HashFunctionFactory, CX: ColorsManager>
 
     fn finalize(self, _global_data: &GlobalQueryMergeData) {
         self.counters_tmp.finalize();
+        self.unitig_matches_tmp.finalize();
     }
 }
 
@@ -394,14 +557,18 @@ pub fn parallel_kmers_counting<
     CX: ColorsManager,
     P: AsRef<Path> + Sync,
 >(
-    file_inputs: Vec<SingleBucket>,
+    file_inputs: Vec<MultiChunkBucket>,
+    // Input files that must be kept after the counting, as they are reused
+    persistent_inputs: HashSet<PathBuf>,
     buckets_counters_path: PathBuf,
     buckets_count: usize,
     out_directory: P,
     k: usize,
     m: usize,
     threads_count: usize,
-) -> Vec<SingleBucket> {
+    output_mode: QueryOutputMode,
+    queries_numbering: QueriesNumbering,
+) -> (Vec<SingleBucket>, Vec<SingleBucket>) {
     PHASES_TIMES_MONITOR
         .write()
         .start_phase("phase: kmers counting".to_string());
@@ -417,6 +584,21 @@ pub fn parallel_kmers_counting<
         &(),
     ));
 
+    let unitig_matches_buckets = if output_mode == QueryOutputMode::MatchedUnitigs {
+        Arc::new(MultiThreadBuckets::<LockFreeBinaryWriter>::new(
+            buckets_count,
+            out_directory.as_ref().join("unitig_matches"),
+            None,
+            &(
+                get_memory_mode(SwapPriority::QueryCounters),
+                LockFreeBinaryWriter::CHECKPOINT_SIZE_UNLIMITED,
+            ),
+            &(),
+        ))
+    } else {
+        Arc::new(MultiThreadBuckets::EMPTY)
+    };
+
     let global_data = Arc::new(GlobalQueryMergeData {
         k,
         m,
@@ -432,16 +614,18 @@ pub fn parallel_kmers_counting<
             0,
             1,
             QuerierMinimizerBucketingGlobalData {
-                queries_count: Default::default(),
+                queries: Default::default(),
+                track_unitigs: output_mode == QueryOutputMode::MatchedUnitigs,
             },
         )),
+        output_mode,
+        unitig_matches_buckets,
+        queries_numbering,
     });
 
     KmersTransform::<ParallelKmersQueryFactory<MH, CX>>::new(
-        file_inputs
-            .into_iter()
-            .map(|x| x.to_multi_chunk())
-            .collect(),
+        file_inputs,
+        persistent_inputs,
         out_directory.as_ref(),
         buckets_counters_path,
         buckets_count,
@@ -454,5 +638,8 @@ pub fn parallel_kmers_counting<
 
     let global_data =
         Arc::try_unwrap(global_data).unwrap_or_else(|_| panic!("Cannot unwrap global data!"));
-    global_data.counters_buckets.finalize_single()
+    (
+        global_data.counters_buckets.finalize_single(),
+        global_data.unitig_matches_buckets.finalize_single(),
+    )
 }
@@ -1,5 +1,5 @@
+use crate::get_query_name;
 use crate::pipeline::parallel_kmers_query::QueryKmersReferenceData;
-use byteorder::ReadBytesExt;
 use colors::colors_manager::color_types::MinimizerBucketingSeqColorDataType;
 use colors::colors_manager::{ColorsManager, MinimizerBucketingSeqColorData};
 use colors::parsers::{SequenceIdent, SingleSequenceInfo};
@@ -9,36 +9,35 @@ use hashes::rolling::minqueue::RollingMinQueue;
 use hashes::HashFunction;
 use hashes::MinimizerHashFunctionFactory;
 use hashes::{ExtendableHashTraitType, HashFunctionFactory};
-use io::concurrent::temp_reads::extra_data::{
-    HasEmptyExtraBuffer, SequenceExtraData, SequenceExtraDataTempBufferManagement,
-};
+use io::concurrent::temp_reads::extra_data::SequenceExtraDataTempBufferManagement;
 use io::sequences_reader::{DnaSequence, DnaSequencesFileType};
-use io::sequences_stream::fasta::FastaFileSequencesStream;
-use io::sequences_stream::SequenceInfo;
-use io::varint::{decode_varint, encode_varint, VARINT_MAX_SIZE};
+use io::sequences_stream::general::{GeneralSequenceBlockData, GeneralSequencesStream};
+use io::sequences_stream::{GenericSequencesStream, SequenceInfo};
 use minimizer_bucketing::{
     GenericMinimizerBucketing, MinimizerBucketingCommonData, MinimizerBucketingExecutor,
     MinimizerBucketingExecutorFactory, MinimizerInputSequence,
 };
 use parallel_processor::buckets::SingleBucket;
 use parallel_processor::phase_times_monitor::PHASES_TIMES_MONITOR;
-use std::io::{Read, Write};
+use parking_lot::Mutex;
 use std::marker::PhantomData;
 use std::num::NonZeroU64;
 use std::ops::Range;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use super::parallel_kmers_query::RewriteBucketComputeQuery;
 
-#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
-pub struct KmersQueryData(pub u64);
+/// Bits of the bucketed query ids used by the index of the query in its block, the upper bits are used by the block index
+const QUERY_BLOCK_SHIFT: u32 = 40;
 
 #[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
 pub enum FileType {
     Graph,
-    Query,
+    /// Query sequences, numbered in their block as the blocks sizes are known only after reading them
+    Query {
+        block_index: u64,
+    },
 }
 
 impl Default for FileType {
@@ -47,6 +46,108 @@ impl Default for FileType {
     }
 }
 
+/// Kmers counts and names of consecutive queries of a block, collected by a bucketing executor
+pub struct QueriesChunk {
+    block_index: u64,
+    first_read_index: u64,
+    queries: Vec<(u64, String)>,
+}
+
+/// Maps the query ids assigned while bucketing, relative to the query blocks, to the indexes of the queries in the input order
+#[derive(Clone, Debug, Default)]
+pub struct QueriesNumbering {
+    blocks_offsets: Vec<u64>,
+    queries_count: u64,
+}
+
+impl QueriesNumbering {
+    fn query_id(block_index: u64, read_index: u64) -> NonZeroU64 {
+        NonZeroU64::new((block_index << QUERY_BLOCK_SHIFT) + read_index + 1).unwrap()
+    }
+
+    /// Returns the 1-based index of the query with the given bucketing id
+    #[inline(always)]
+    pub fn query_index(&self, query_id: u64) -> u64 {
+        let query_id = query_id - 1;
+        self.blocks_offsets[(query_id >> QUERY_BLOCK_SHIFT) as usize]
+            + (query_id & ((1 << QUERY_BLOCK_SHIFT) - 1))
+            + 1
+    }
+
+    pub fn queries_count(&self) -> u64 {
+        self.queries_count
+    }
+}
+
+/// Kmers counts and names of all the queries, in the input order
+#[derive(Clone, Debug, Default)]
+pub struct QueriesInfo {
+    pub kmers_counts: Vec<u64>,
+    pub names: Vec<String>,
+    pub numbering: QueriesNumbering,
+}
+
+impl QueriesInfo {
+    fn from_chunks(mut chunks: Vec<QueriesChunk>, blocks_count: usize) -> Self {
+        chunks.sort_unstable_by_key(|chunk| (chunk.block_index, chunk.first_read_index));
+
+        let mut info = Self::default();
+        let mut chunks = chunks.into_iter().peekable();
+        for block_index in 0..blocks_count as u64 {
+            info.numbering.blocks_offsets.push(info.names.len() as u64);
+
+            let mut next_read_index = 0;
+            while let Some(chunk) = chunks.next_if(|chunk| chunk.block_index == block_index) {
+                assert_eq!(
+                    chunk.first_read_index, next_read_index,
+                    "BUG: Missing queries in block {}",
+                    block_index
+                );
+                next_read_index += chunk.queries.len() as u64;
+                for (kmers_count, name) in chunk.queries {
+                    info.kmers_counts.push(kmers_count);
+                    info.names.push(name);
+                }
+            }
+            assert!(
+                next_read_index < 1 << QUERY_BLOCK_SHIFT,
+                "Too many sequences in query block {}",
+                block_index
+            );
+        }
+        info.numbering.queries_count = info.names.len() as u64;
+        info
+    }
+
+    /// Reads the kmers counts and names of the queries, when they are not bucketed
+    pub fn read(query_blocks: &[GeneralSequenceBlockData], k: usize) -> Self {
+        let mut sequences_stream = GeneralSequencesStream::new();
+        let chunks = query_blocks
+            .iter()
+            .enumerate()
+            .map(|(block_index, block)| {
+                let mut queries = vec![];
+                sequences_stream.read_block(block, true, None, |seq, _| {
+                    queries.push((
+                        seq.seq.len().saturating_sub(k - 1) as u64,
+                        get_query_name(seq.ident_data),
+                    ));
+                });
+                QueriesChunk {
+                    block_index: block_index as u64,
+                    first_read_index: 0,
+                    queries,
+                }
+            })
+            .collect();
+        Self::from_chunks(chunks, query_blocks.len())
+    }
+
+    pub fn queries_count(&self) -> u64 {
+        self.numbering.queries_count
+    }
+}
+
 pub struct ReadTypeBuffered<CX: ColorsManager> {
     colors_buffer: <QueryKmersReferenceData<MinimizerBucketingSeqColorDataType<CX>> as SequenceExtraDataTempBufferManagement>::TempBuffer,
     read_type: ReadType<CX>,
@@ -56,8 +157,11 @@ pub struct ReadTypeBuffered<CX: ColorsManager> {
 pub enum ReadType<CX: ColorsManager> {
     Graph {
         color: MinimizerBucketingSeqColorDataType<CX>,
+        /// Index of the sequence in the graph file, tracked only for the matched unitigs output
+        unitig: Option<u64>,
     },
-    Query(NonZeroU64),
+    /// Query index and position of the first kmer of the sequence in the query
+    Query(NonZeroU64, u64),
 }
 
 impl<CX: ColorsManager> Default for ReadTypeBuffered<CX> {
@@ -65,39 +169,33 @@ impl<CX: ColorsManager> Default for ReadTypeBuffered<CX> {
         Self {
             colors_buffer:
                 QueryKmersReferenceData::<MinimizerBucketingSeqColorDataType<CX>>::new_temp_buffer(),
-            read_type: ReadType::Query(NonZeroU64::new(1).unwrap()),
+            read_type: ReadType::Query(NonZeroU64::new(1).unwrap(), 0),
         }
     }
 }
 
-impl HasEmptyExtraBuffer for KmersQueryData {}
-impl SequenceExtraData for KmersQueryData {
-    #[inline(always)]
-    fn decode_extended(_: &mut (), reader: &mut impl Read) -> Option<Self> {
-        Some(Self(decode_varint(|| reader.read_u8().ok())?))
-    }
-
-    #[inline(always)]
-    fn encode_extended(&self, _: &(), writer: &mut impl Write) {
-        encode_varint(|b| writer.write_all(b), self.0).unwrap();
-    }
-
-    #[inline(always)]
-    fn max_size(&self) -> usize {
-        VARINT_MAX_SIZE
-    }
-}
-
 pub struct QuerierMinimizerBucketingGlobalData {
-    pub queries_count: Arc<AtomicUsize>,
+    pub queries: Arc<Mutex<Vec<QueriesChunk>>>,
+    /// Keep the index of the graph sequence of each kmer
+    pub track_unitigs: bool,
 }
 
 pub struct QuerierMinimizerBucketingExecutor<CX: ColorsManager> {
     minimizer_queue: RollingMinQueue,
     global_data: Arc<MinimizerBucketingCommonData<QuerierMinimizerBucketingGlobalData>>,
+    /// Queries of the bucketed sequences packet, an executor is created for each packet
+    queries: Option<QueriesChunk>,
     _phantom: PhantomData<CX>,
 }
 
+impl<CX: ColorsManager> Drop for QuerierMinimizerBucketingExecutor<CX> {
+    fn drop(&mut self) {
+        if let Some(queries) = self.queries.take() {
+            self.global_data.global_data.queries.lock().push(queries);
+        }
+    }
+}
+
 pub struct QuerierMinimizerBucketingExecutorFactory<CX: ColorsManager>(PhantomData<CX>);
 
 impl<CX: ColorsManager> MinimizerBucketingExecutorFactory
@@ -122,6 +220,7 @@ impl<CX: ColorsManager> MinimizerBucketingExecutorFactory
         Self::ExecutorType {
             minimizer_queue: RollingMinQueue::new(global_data.k - global_data.m + 1),
             global_data: global_data.clone(),
+            queries: None,
             _phantom: PhantomData,
         }
     }
@@ -175,14 +274,30 @@ impl<CX: ColorsManager> MinimizerBucketingExecutor<QuerierMinimizerBucketingExec
                     );
                 }
 
-                ReadType::Graph { color }
+                ReadType::Graph {
+                    color,
+                    unitig: if self.global_data.global_data.track_unitigs {
+                        Some(read_index)
+                    } else {
+                        None
+                    },
+                }
             }
-            FileType::Query => {
-                self.global_data
-                    .global_data
-                    .queries_count
-                    .fetch_add(1, Ordering::Relaxed);
-                ReadType::Query(NonZeroU64::new(read_index + 1).unwrap())
+            FileType::Query { block_index } => {
+                let queries = self.queries.get_or_insert_with(|| QueriesChunk {
+                    block_index: *block_index,
+                    first_read_index: read_index,
+                    queries: vec![],
+                });
+                debug_assert_eq!(
+                    queries.first_read_index + queries.queries.len() as u64,
+                    read_index
+                );
+                queries.queries.push((
+                    sequence.seq.len().saturating_sub(self.global_data.k - 1) as u64,
+                    get_query_name(sequence.ident_data),
+                ));
+                ReadType::Query(QueriesNumbering::query_id(*block_index, read_index), 0)
             }
         }
     }
@@ -201,10 +316,11 @@ impl<CX: ColorsManager> MinimizerBucketingExecutor<QuerierMinimizerBucketingExec
         );
 
         preprocess_info.read_type = match extra_data {
-            QueryKmersReferenceData::Graph(color) => ReadType::Graph {
+            QueryKmersReferenceData::Graph(color, unitig) => ReadType::Graph {
                 color: color.clone(),
+                unitig: *unitig,
             },
-            QueryKmersReferenceData::Query(query) => ReadType::Query(*query),
+            QueryKmersReferenceData::Query(query, position) => ReadType::Query(*query, *position),
         }
     }
 
@@ -215,7 +331,7 @@ impl<CX: ColorsManager> MinimizerBucketingExecutor<QuerierMinimizerBucketingExec
         &mut self,
         preprocess_info: &<QuerierMinimizerBucketingExecutorFactory<CX> as MinimizerBucketingExecutorFactory>::PreprocessInfo,
         sequence: S,
-        _range: Range<usize>,
+        range: Range<usize>,
         used_bits: usize,
         first_bits: usize,
         second_bits: usize,
@@ -239,11 +355,15 @@ impl<CX: ColorsManager> MinimizerBucketingExecutor<QuerierMinimizerBucketingExec
                     sequence.get_subslice(last_index..(index + self.global_data.k)),
                     0,
                     match &preprocess_info.read_type {
-                        ReadType::Graph { color } => QueryKmersReferenceData::Graph(
+                        ReadType::Graph { color, unitig } => QueryKmersReferenceData::Graph(
                             color.get_subslice(last_index..(index + 1)),
+                            *unitig,
                         ),
 
-                        ReadType::Query(val) => QueryKmersReferenceData::Query(*val),
+                        ReadType::Query(val, position) => QueryKmersReferenceData::Query(
+                            *val,
+                            position + (range.start + last_index) as u64,
+                        ),
                     },
                     &preprocess_info.colors_buffer,
                 );
@@ -259,55 +379,305 @@ impl<CX: ColorsManager> MinimizerBucketingExecutor<QuerierMinimizerBucketingExec
             sequence.get_subslice(last_index..sequence.seq_len()),
             0,
             match &preprocess_info.read_type {
-                ReadType::Graph { color } => QueryKmersReferenceData::Graph(
+                ReadType::Graph { color, unitig } => QueryKmersReferenceData::Graph(
                     color.get_subslice(last_index..(sequence.seq_len() + 1 - self.global_data.k)),
+                    *unitig,
                 ),
 
-                ReadType::Query(val) => QueryKmersReferenceData::Query(*val),
+                ReadType::Query(val, position) => QueryKmersReferenceData::Query(
+                    *val,
+                    position + (range.start + last_index) as u64,
+                ),
             },
             &preprocess_info.colors_buffer,
         );
     }
 }
 
+/// Buckets the graph and the query sequences, skipping the graph if it was already bucketed in a query index.
+/// Returns the kmers counts and names of the queries, collected while bucketing them
 pub fn minimizer_bucketing<CX: ColorsManager>(
-    graph_file: PathBuf,
-    query_file: PathBuf,
+    graph_file: Option<PathBuf>,
+    query_blocks: Vec<GeneralSequenceBlockData>,
     output_path: &Path,
     buckets_count: usize,
     threads_count: usize,
     k: usize,
     m: usize,
-) -> ((Vec<SingleBucket>, PathBuf), u64) {
-    PHASES_TIMES_MONITOR
-        .write()
-        .start_phase("phase: graph + query bucketing".to_string());
-
-    let input_files = vec![
-        ((graph_file, None), FileType::Graph),
-        ((query_file, None), FileType::Query),
-    ];
+    track_unitigs: bool,
+) -> ((Vec<SingleBucket>, PathBuf), QueriesInfo) {
+    PHASES_TIMES_MONITOR.write().start_phase(format!(
+        "phase: {} bucketing",
+        match (&graph_file, query_blocks.is_empty()) {
+            (Some(_), false) => "graph + query",
+            (Some(_), true) => "graph",
+            (None, _) => "query",
+        }
+    ));
+
+    let query_blocks_count = query_blocks.len();
+    let input_files: Vec<_> = graph_file
+        .map(|graph_file| {
+            (
+                GeneralSequenceBlockData::FASTA((graph_file, None)),
+                FileType::Graph,
+            )
+        })
+        .into_iter()
+        .chain(
+            query_blocks
+                .into_iter()
+                .enumerate()
+                .map(|(block_index, block)| {
+                    (
+                        block,
+                        FileType::Query {
+                            block_index: block_index as u64,
+                        },
+                    )
+                }),
+        )
+        .collect();
 
-    let queries_count = Arc::new(AtomicUsize::new(0));
+    let queries = Arc::new(Mutex::new(vec![]));
 
+    let buckets = GenericMinimizerBucketing::do_bucketing_no_max_usage::<
+        QuerierMinimizerBucketingExecutorFactory<CX>,
+        GeneralSequencesStream,
+    >(
+        input_files.into_iter(),
+        output_path,
+        buckets_count,
+        threads_count,
+        k,
+        m,
+        QuerierMinimizerBucketingGlobalData {
+            queries: queries.clone(),
+            track_unitigs,
+        },
+        None,
+        // The identifiers are needed for the query names
+        true,
+        0,
+    );
+
+    let queries = std::mem::take(&mut *queries.lock());
     (
-        GenericMinimizerBucketing::do_bucketing_no_max_usage::<
-            QuerierMinimizerBucketingExecutorFactory<CX>,
-            FastaFileSequencesStream,
-        >(
-            input_files.into_iter(),
-            output_path,
-            buckets_count,
-            threads_count,
-            k,
-            m,
-            QuerierMinimizerBucketingGlobalData {
-                queries_count: queries_count.clone(),
+        buckets,
+        QueriesInfo::from_chunks(queries, query_blocks_count),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use colors::non_colored::NonColoredManager;
+
+    const K: usize = 15;
+    const M: usize = 7;
+
+    type QueryData = QueryKmersReferenceData<MinimizerBucketingSeqColorDataType<NonColoredManager>>;
+
+    fn executor_with_queries(
+        queries: Arc<Mutex<Vec<QueriesChunk>>>,
+    ) -> QuerierMinimizerBucketingExecutor<NonColoredManager> {
+        QuerierMinimizerBucketingExecutorFactory::<NonColoredManager>::new(&Arc::new(
+            MinimizerBucketingCommonData::new(
+                K,
+                M,
+                16,
+                0,
+                1,
+                QuerierMinimizerBucketingGlobalData {
+                    queries,
+                    track_unitigs: false,
+                },
+            ),
+        ))
+    }
+
+    fn executor() -> QuerierMinimizerBucketingExecutor<NonColoredManager> {
+        executor_with_queries(Arc::new(Mutex::new(vec![])))
+    }
+
+    fn test_query() -> Vec<u8> {
+        let mut state = 0x2545f4914f6cdd1du64;
+        let mut query: Vec<_> = (0..300)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                b"ACGT"[(state % 4) as usize]
+            })
+            .collect();
+        // Invalid bases split the query in several sequences
+        query[40] = b'N';
+        query[120..125].fill(b'N');
+        query
+    }
+
+    /// Returns the split sequences of a query sequence with the position of their first kmer
+    fn split_sequence<'a>(
+        executor: &mut QuerierMinimizerBucketingExecutor<NonColoredManager>,
+        preprocess_info: &ReadTypeBuffered<NonColoredManager>,
+        sequence: &'a [u8],
+        range: Range<usize>,
+    ) -> Vec<(&'a [u8], u64)> {
+        let mut parts = vec![];
+        executor.process_sequence(
+            preprocess_info,
+            sequence,
+            range,
+            0,
+            4,
+            0,
+            |_, _, part, _, extra: QueryData, _| {
+                let QueryKmersReferenceData::Query(query, position) = extra else {
+                    panic!("Query sequence with graph data");
+                };
+                assert_eq!(query.get(), 4);
+                parts.push((part, position));
             },
-            None,
-            CX::COLORS_ENABLED,
+        );
+        parts
+    }
+
+    #[test]
+    fn query_kmers_positions() {
+        let query = test_query();
+        let mut executor = executor();
+        let mut preprocess_info = ReadTypeBuffered::<NonColoredManager>::default();
+
+        executor.preprocess_dna_sequence(
+            &FileType::Query { block_index: 0 },
+            SequenceInfo { color: None },
+            3,
+            &DnaSequence {
+                ident_data: b"query",
+                seq: &query,
+                format: DnaSequencesFileType::FASTA,
+            },
+            &mut preprocess_info,
+        );
+
+        // Valid ranges of the query, as split by the minimizer bucketing
+        let mut parts = vec![];
+        for range in [0..40, 41..120, 125..300] {
+            parts.extend(split_sequence(
+                &mut executor,
+                &preprocess_info,
+                &query[range.clone()],
+                range,
+            ));
+        }
+
+        // Each part starts at its position in the query, and each valid kmer is in exactly one part
+        let mut kmers_positions = vec![];
+        for (part, position) in parts {
+            let position = position as usize;
+            assert_eq!(part, &query[position..position + part.len()]);
+            kmers_positions.extend(position..position + part.len() - K + 1);
+        }
+        kmers_positions.sort_unstable();
+
+        let valid_kmers: Vec<_> = (0..query.len() - K + 1)
+            .filter(|&position| !query[position..position + K].contains(&b'N'))
+            .collect();
+        assert_eq!(kmers_positions, valid_kmers);
+    }
+
+    #[test]
+    fn resplit_query_kmers_positions() {
+        let query = test_query();
+        let mut executor = executor();
+        let mut preprocess_info = ReadTypeBuffered::<NonColoredManager>::default();
+
+        // A bucketed sequence starting at position 130 of the query is split again
+        let sequence = &query[130..280];
+        executor.reprocess_sequence(
             0,
-        ),
-        queries_count.load(Ordering::Relaxed) as u64,
-    )
+            &QueryKmersReferenceData::Query(NonZeroU64::new(4).unwrap(), 130),
+            &QueryData::new_temp_buffer(),
+            &mut preprocess_info,
+        );
+
+        let parts = split_sequence(&mut executor, &preprocess_info, sequence, 0..sequence.len());
+        assert!(parts.len() > 1);
+
+        let mut next_position = 130;
+        for (part, position) in parts {
+            let position = position as usize;
+            assert_eq!(position, next_position);
+            assert_eq!(part, &query[position..position + part.len()]);
+            next_position = position + part.len() - K + 1;
+        }
+        assert_eq!(next_position, 280 - K + 1);
+    }
+
+    /// Bucketing of a packet of queries, returning their ids
+    fn bucket_queries(
+        queries: &Arc<Mutex<Vec<QueriesChunk>>>,
+        block_index: u64,
+        first_read_index: u64,
+        sequences: &[(&[u8], &[u8])],
+    ) -> Vec<u64> {
+        let mut executor = executor_with_queries(queries.clone());
+        let mut preprocess_info = ReadTypeBuffered::<NonColoredManager>::default();
+        sequences
+            .iter()
+            .enumerate()
+            .map(|(index, (ident, seq))| {
+                executor.preprocess_dna_sequence(
+                    &FileType::Query { block_index },
+                    SequenceInfo { color: None },
+                    first_read_index + index as u64,
+                    &DnaSequence {
+                        ident_data: ident,
+                        seq,
+                        format: DnaSequencesFileType::FASTA,
+                    },
+                    &mut preprocess_info,
+                );
+                let ReadType::Query(query_id, _) = preprocess_info.read_type else {
+                    panic!("Query sequence with graph data");
+                };
+                query_id.get()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn queries_collected_while_bucketing() {
+        let queries = Arc::new(Mutex::new(vec![]));
+        let kmer = [b'A'; K];
+        let long = [b'C'; K + 4];
+
+        // Packets of the blocks, processed out of order
+        let ids_1b = bucket_queries(&queries, 1, 2, &[(b">q4 desc", &long), (b">q5", &kmer)]);
+        let ids_0 = bucket_queries(&queries, 0, 0, &[(b">q0", &kmer), (b">q1", &[b'G'; 3])]);
+        let ids_1a = bucket_queries(&queries, 1, 0, &[(b">q2", &long), (b"@q3", &kmer)]);
+        // An empty query block is followed by a non-empty one
+        let ids_3 = bucket_queries(&queries, 3, 0, &[(b">q6", &kmer)]);
+
+        let info = QueriesInfo::from_chunks(std::mem::take(&mut *queries.lock()), 4);
+        assert_eq!(info.names, ["q0", "q1", "q2", "q3", "q4", "q5", "q6"]);
+        assert_eq!(info.kmers_counts, [1, 0, 5, 1, 5, 1, 1]);
+        assert_eq!(info.queries_count(), 7);
+
+        let indexes: Vec<_> = [ids_0, ids_1a, ids_1b, ids_3]
+            .concat()
+            .into_iter()
+            .map(|id| info.numbering.query_index(id))
+            .collect();
+        assert_eq!(indexes, [1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Missing queries")]
+    fn missing_queries_chunk() {
+        let queries = Arc::new(Mutex::new(vec![]));
+        bucket_queries(&queries, 0, 0, &[(b">q0", &[b'A'; K])]);
+        bucket_queries(&queries, 0, 2, &[(b">q2", &[b'A'; K])]);
+        QueriesInfo::from_chunks(std::mem::take(&mut *queries.lock()), 1);
+    }
 }
@@ -0,0 +1,738 @@
+use crate::pipeline::parallel_kmers_query::QueryKmersReferenceData;
+use crate::query_index::QueryIndex;
+use colors::bundles::graph_querying::ColorBundleGraphQuerying;
+use colors::colors_manager::color_types::MinimizerBucketingSeqColorDataType;
+use colors::colors_manager::{ColorMapReader, ColorsManager, MinimizerBucketingSeqColorData};
+use colors::metadata::ColorsMetadata;
+use colors::non_colored::NonColoredManager;
+use colors::storage::deserializer::ColorsDeserializer;
+use colors::storage::mapped::ColorsMappedReader;
+use config::{ColorIndexType, DEFAULT_PREFETCH_AMOUNT, USE_SECOND_BUCKET};
+use hashbrown::HashMap;
+use hashes::default::MNHFactory;
+use hashes::rolling::minqueue::RollingMinQueue;
+use hashes::{ExtendableHashTraitType, HashFunction, HashFunctionFactory, HashableSequence};
+use io::compressed_read::{CompressedRead, CompressedReadIndipendent};
+use io::concurrent::temp_reads::creads_utils::{
+    BucketModeFromBoolean, CompressedReadsBucketDataSerializer, NoMultiplicity,
+};
+use io::concurrent::temp_reads::extra_data::SequenceExtraDataTempBufferManagement;
+use io::sequences_reader::DnaSequence;
+use parallel_processor::buckets::readers::compressed_binary_reader::CompressedBinaryReader;
+use parallel_processor::buckets::readers::BucketReader;
+use parallel_processor::memory_fs::RemoveFileMode;
+use parallel_processor::utils::scoped_thread_local::ScopedThreadLocal;
+use parking_lot::Mutex;
+use rayon::prelude::*;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use utils::Utils;
+
+/// Number of independently locked shards of the engine caches
+const CACHE_SHARDS_COUNT: usize = 16;
+
+/// Maximum number of decoded color subsets kept in memory
+const CACHED_COLOR_SUBSETS_COUNT: usize = 1 << 16;
+
+/// Matches of a single query found by the query engine
+#[derive(Clone, Debug, Default)]
+pub struct QueryMatches {
+    /// Number of kmers of the query, including the ones with non ACGT bases
+    pub query_kmers: u64,
+    /// Number of query kmers found in the graph
+    pub matched_kmers: u64,
+    /// Number of query kmers matched by each color, sorted by color index. Empty if the graph is not colored
+    pub color_matches: Vec<(ColorIndexType, u64)>,
+}
+
+/// Bounded cache of decoded values, split in shards that are locked independently.
+/// When a shard is full, its oldest value is evicted
+struct ShardedCache<V: ?Sized> {
+    shards: Vec<Mutex<CacheShard<V>>>,
+    shard_capacity: usize,
+}
+
+struct CacheShard<V: ?Sized> {
+    values: HashMap<usize, Arc<V>>,
+    insertion_order: VecDeque<usize>,
+}
+
+impl<V: ?Sized> ShardedCache<V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            shards: (0..CACHE_SHARDS_COUNT)
+                .map(|_| {
+                    Mutex::new(CacheShard {
+                        values: HashMap::new(),
+                        insertion_order: VecDeque::new(),
+                    })
+                })
+                .collect(),
+            shard_capacity: capacity.div_ceil(CACHE_SHARDS_COUNT).max(1),
+        }
+    }
+
+    /// Returns the cached value of the key, decoding it if missing. The shard of the key stays locked
+    /// while decoding, so that each value is decoded only once
+    fn get_or_decode(&self, key: usize, decode: impl FnOnce() -> Arc<V>) -> Arc<V> {
+        match self.try_get_or_decode(key, || Ok::<_, Infallible>(decode())) {
+            Ok(value) => value,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Same as get_or_decode, with a decoding that can fail. The failed values are not cached
+    fn try_get_or_decode<E>(
+        &self,
+        key: usize,
+        decode: impl FnOnce() -> Result<Arc<V>, E>,
+    ) -> Result<Arc<V>, E> {
+        let mut shard = self.shards[key % self.shards.len()].lock();
+        if let Some(value) = shard.values.get(&key) {
+            return Ok(value.clone());
+        }
+
+        let value = decode()?;
+        if shard.values.len() >= self.shard_capacity {
+            let evicted = shard.insertion_order.pop_front().unwrap();
+            shard.values.remove(&evicted);
+        }
+        shard.values.insert(key, value.clone());
+        shard.insertion_order.push_back(key);
+        Ok(value)
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().values.len())
+            .sum()
+    }
+}
+
+/// Kmers of a query index bucket, sorted by hash. The kmers with the same hash are told apart
+/// by comparing their bases, so each lookup is exact for any k
+#[derive(Default)]
+struct BucketKmers {
+    bases: Vec<u8>,
+    sequences: Vec<CompressedReadIndipendent>,
+    /// Hash, sequence index, position in the sequence and color subset of each kmer
+    kmers: Vec<(u64, u32, u32, ColorIndexType)>,
+}
+
+/// Hashes of the kmers of a sequence, equal for reverse complementary kmers if not forward only
+fn kmers_hashes(sequence: impl HashableSequence, k: usize, forward_only: bool) -> Vec<u64> {
+    fn hashes<MH: HashFunctionFactory<HashTypeUnextendable = u64>>(
+        sequence: impl HashableSequence,
+        k: usize,
+    ) -> Vec<u64> {
+        MH::new(sequence, k)
+            .iter()
+            .map(|hash| hash.to_unextendable())
+            .collect()
+    }
+
+    if forward_only {
+        hashes::<hashes::fw_rkhash::u64::ForwardRabinKarpHashFactory>(sequence, k)
+    } else {
+        hashes::<hashes::cn_rkhash::u64::CanonicalRabinKarpHashFactory>(sequence, k)
+    }
+}
+
+/// Compares two kmers of compressed bases, also in reverse complement if not forward only
+fn kmers_equal(
+    first: impl HashableSequence,
+    second: impl HashableSequence,
+    forward_only: bool,
+) -> bool {
+    let k = first.bases_count();
+    let first = |index| unsafe { first.get_unchecked_cbase(index) };
+    let second = |index| unsafe { second.get_unchecked_cbase(index) };
+
+    (0..k).all(|index| first(index) == second(index))
+        || (!forward_only && (0..k).all(|index| first(index) == second(k - 1 - index) ^ 2))
+}
+
+/// Calls the function with the bucket, the hash and the position of each kmer of a sequence of
+/// compressed bases. The bucket is chosen by the kmer minimizer as in the query index bucketing
+fn for_each_kmer(
+    sequence: &[u8],
+    k: usize,
+    m: usize,
+    buckets_count_log: usize,
+    forward_only: bool,
+    minimizer_queue: &mut RollingMinQueue,
+    mut function: impl FnMut(usize, u64, usize),
+) {
+    let minimizers = minimizer_queue.make_iter(
+        MNHFactory::new(sequence, m)
+            .iter()
+            .map(|hash| hash.to_unextendable()),
+    );
+
+    for (position, (minimizer, hash)) in minimizers
+        .zip(kmers_hashes(sequence, k, forward_only))
+        .enumerate()
+    {
+        function(
+            MNHFactory::get_bucket(0, buckets_count_log, minimizer) as usize,
+            hash,
+            position,
+        );
+    }
+}
+
+impl BucketKmers {
+    /// Adds the kmers of a graph sequence, with the color subset of each kmer
+    fn add_sequence(
+        &mut self,
+        sequence: &CompressedRead,
+        mut colors: impl Iterator<Item = ColorIndexType>,
+        k: usize,
+        forward_only: bool,
+    ) {
+        let sequence_index = self.sequences.len() as u32;
+        self.sequences.push(CompressedReadIndipendent::from_read(
+            sequence,
+            &mut self.bases,
+        ));
+
+        for (position, hash) in kmers_hashes(*sequence, k, forward_only)
+            .into_iter()
+            .enumerate()
+        {
+            self.kmers.push((
+                hash,
+                sequence_index,
+                position as u32,
+                colors.next().unwrap_or(0),
+            ));
+        }
+    }
+
+    /// Sorts the kmers, keeping only the first occurrence of the duplicated ones
+    fn finalize(&mut self, k: usize, forward_only: bool) {
+        self.kmers.sort_by_key(|(hash, ..)| *hash);
+
+        let mut unique_count = 0;
+        for index in 0..self.kmers.len() {
+            let (hash, sequence, position, _) = self.kmers[index];
+            let is_duplicate = self.kmers[..unique_count]
+                .iter()
+                .rev()
+                .take_while(|(other_hash, ..)| *other_hash == hash)
+                .any(|&(_, other_sequence, other_position, _)| {
+                    kmers_equal(
+                        self.get_kmer(other_sequence, other_position, k),
+                        self.get_kmer(sequence, position, k),
+                        forward_only,
+                    )
+                });
+            if !is_duplicate {
+                self.kmers[unique_count] = self.kmers[index];
+                unique_count += 1;
+            }
+        }
+        self.kmers.truncate(unique_count);
+        self.kmers.shrink_to_fit();
+    }
+
+    fn get_kmer(&self, sequence: u32, position: u32, k: usize) -> CompressedRead<'_> {
+        let position = position as usize;
+        self.sequences[sequence as usize]
+            .as_reference(&self.bases)
+            .sub_slice(position..position + k)
+    }
+
+    /// Returns the color subset of a kmer, given as compressed bases, if it is in the bucket
+    fn get(&self, kmer: &[u8], hash: u64, forward_only: bool) -> Option<ColorIndexType> {
+        let start = self.kmers.partition_point(|(other, ..)| *other < hash);
+        self.kmers[start..]
+            .iter()
+            .take_while(|(other, ..)| *other == hash)
+            .find(|&&(_, sequence, position, _)| {
+                kmers_equal(
+                    kmer,
+                    self.get_kmer(sequence, position, kmer.len()),
+                    forward_only,
+                )
+            })
+            .map(|(.., subset)| *subset)
+    }
+
+    /// Reads the graph kmers of a query index bucket
+    fn read<CX: ColorsManager>(
+        bucket_path: &Path,
+        k: usize,
+        forward_only: bool,
+        kmer_color: impl Fn(
+            <MinimizerBucketingSeqColorDataType<CX> as MinimizerBucketingSeqColorData>::KmerColor,
+        ) -> ColorIndexType,
+    ) -> Self {
+        let mut bucket = Self::default();
+
+        CompressedBinaryReader::new(bucket_path, RemoveFileMode::Keep, DEFAULT_PREFETCH_AMOUNT)
+            .decode_all_bucket_items::<CompressedReadsBucketDataSerializer<
+            QueryKmersReferenceData<MinimizerBucketingSeqColorDataType<CX>>,
+            typenum::U0,
+            BucketModeFromBoolean<USE_SECOND_BUCKET>,
+            NoMultiplicity,
+        >, _>(
+            vec![],
+            &mut QueryKmersReferenceData::<MinimizerBucketingSeqColorDataType<CX>>::new_temp_buffer(
+            ),
+            |(_, _, extra, read, _), extra_buffer| {
+                if let QueryKmersReferenceData::Graph(color, _) = extra {
+                    bucket.add_sequence(
+                        &read,
+                        color.get_iterator(&extra_buffer.0).map(&kmer_color),
+                        k,
+                        forward_only,
+                    );
+                }
+            },
+        );
+
+        bucket.finalize(k, forward_only);
+        bucket
+    }
+}
+
+/// Decoder of the color subsets of the graph
+enum ColorSubsets {
+    /// Memory mapped colormap index, decoding each subset independently
+    Mapped(ColorsMappedReader),
+    /// A colormap decoder for each thread, with the recently decoded subsets
+    Decoded {
+        deserializers: ScopedThreadLocal<ColorsDeserializer>,
+        cache: ShardedCache<[ColorIndexType]>,
+    },
+}
+
+impl ColorSubsets {
+    fn get(&self, subset: ColorIndexType, colors: &mut Vec<ColorIndexType>) -> anyhow::Result<()> {
+        colors.clear();
+        match self {
+            ColorSubsets::Mapped(reader) => {
+                reader.get_color_mappings(subset, colors);
+            }
+            ColorSubsets::Decoded {
+                deserializers,
+                cache,
+            } => {
+                let decoded = cache.try_get_or_decode(subset as usize, || {
+                    let mut colors = vec![];
+                    deserializers
+                        .get()
+                        .get_color_mappings(subset, &mut colors)?;
+                    anyhow::Ok(colors.into())
+                })?;
+                colors.extend_from_slice(&decoded);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Graph kept loaded to answer small batches of queries at low latency. Each query kmer is looked up
+/// in the query index bucket of its minimizer, decoded on the first access and then kept in a bounded cache
+pub struct QueryEngine {
+    k: usize,
+    m: usize,
+    forward_only: bool,
+    colors: bool,
+    buckets_count_log: usize,
+    bucket_paths: Vec<PathBuf>,
+    buckets: ShardedCache<BucketKmers>,
+    color_names: Vec<String>,
+    colors_metadata: ColorsMetadata,
+    color_subsets: Option<ColorSubsets>,
+}
+
+impl QueryEngine {
+    /// Loads a (optionally) colored graph with its query index. If memory mapped, the colormap index
+    /// is saved next to the colormap on the first load, and mapped from the disk on the next ones
+    pub fn load(
+        graph_input: impl AsRef<Path>,
+        index_dir: impl AsRef<Path>,
+        k: usize,
+        forward_only: bool,
+        colors: bool,
+        memory_mapped: bool,
+        cached_buckets: usize,
+    ) -> anyhow::Result<Self> {
+        let graph_input = graph_input.as_ref();
+
+        let index = QueryIndex::load(index_dir)?;
+        let hash_type = index.hash_type.clone();
+        index.check_params(graph_input, k, index.m, &hash_type, forward_only, colors)?;
+
+        MNHFactory::initialize(k);
+        hashes::cn_rkhash::u64::CanonicalRabinKarpHashFactory::initialize(k);
+        hashes::fw_rkhash::u64::ForwardRabinKarpHashFactory::initialize(k);
+
+        let (color_names, colors_metadata, color_subsets) = if colors {
+            let colormap_file = graph_input.with_extension("colors.dat");
+            let deserializer = ColorsDeserializer::new(&colormap_file, true)?;
+            deserializer
+                .build_params()
+                .check_graph(&colormap_file, k, None)?;
+
+            let color_names = (0..deserializer.colors_count())
+                .map(|color| {
+                    deserializer
+                        .get_color_name(color as ColorIndexType, false)
+                        .to_string()
+                })
+                .collect();
+            let colors_metadata = deserializer.colors_metadata().clone();
+            drop(deserializer);
+
+            let color_subsets = if memory_mapped {
+                ColorSubsets::Mapped(ColorsMappedReader::open_or_build(&colormap_file)?)
+            } else {
+                ColorSubsets::Decoded {
+                    deserializers: ScopedThreadLocal::new(move || {
+                        ColorsDeserializer::new(&colormap_file, false).unwrap()
+                    }),
+                    cache: ShardedCache::new(CACHED_COLOR_SUBSETS_COUNT),
+                }
+            };
+            (color_names, colors_metadata, Some(color_subsets))
+        } else {
+            (vec![], ColorsMetadata::default(), None)
+        };
+
+        Ok(Self {
+            k,
+            m: index.m,
+            forward_only,
+            colors,
+            buckets_count_log: index.buckets_count_log,
+            bucket_paths: index.ordered_bucket_paths()?,
+            buckets: ShardedCache::new(cached_buckets),
+            color_names,
+            colors_metadata,
+            color_subsets,
+        })
+    }
+
+    pub fn kmer_length(&self) -> usize {
+        self.k
+    }
+
+    /// Names of the graph colors, ordered by color index. Empty if the graph is not colored
+    pub fn color_names(&self) -> &[String] {
+        &self.color_names
+    }
+
+    /// Metadata of the graph colors. Empty if the graph is not colored or has no metadata
+    pub fn colors_metadata(&self) -> &ColorsMetadata {
+        &self.colors_metadata
+    }
+
+    fn get_bucket(&self, bucket: usize) -> Arc<BucketKmers> {
+        self.buckets.get_or_decode(bucket, || {
+            let path = &self.bucket_paths[bucket];
+            Arc::new(if self.colors {
+                BucketKmers::read::<ColorBundleGraphQuerying>(
+                    path,
+                    self.k,
+                    self.forward_only,
+                    |subset| subset,
+                )
+            } else {
+                BucketKmers::read::<NonColoredManager>(path, self.k, self.forward_only, |_| 0)
+            })
+        })
+    }
+
+    /// Queries a batch of sequences, returning the matches of each one in the same order.
+    /// Fails if the colors of a matched subset cannot be read
+    pub fn query(&self, queries: &[DnaSequence]) -> anyhow::Result<Vec<QueryMatches>> {
+        queries
+            .par_iter()
+            .map_init(
+                || {
+                    (
+                        RollingMinQueue::new(self.k - self.m + 1),
+                        Vec::new(),
+                        Vec::new(),
+                    )
+                },
+                |(minimizer_queue, compressed_buffer, colors_buffer), query| {
+                    let mut matches = QueryMatches {
+                        query_kmers: (query.seq.len() + 1).saturating_sub(self.k) as u64,
+                        ..Default::default()
+                    };
+                    let mut subsets_counts: HashMap<ColorIndexType, u64> = HashMap::new();
+                    let mut last_bucket: Option<(usize, Arc<BucketKmers>)> = None;
+
+                    for part in query.seq.split(|base| {
+                        !matches!(base, b'A' | b'C' | b'G' | b'T' | b'a' | b'c' | b'g' | b't')
+                    }) {
+                        if part.len() < self.k {
+                            continue;
+                        }
+                        compressed_buffer.clear();
+                        compressed_buffer
+                            .extend(part.iter().map(|base| Utils::compress_base(*base)));
+                        let sequence = compressed_buffer.as_slice();
+
+                        for_each_kmer(
+                            sequence,
+                            self.k,
+                            self.m,
+                            self.buckets_count_log,
+                            self.forward_only,
+                            minimizer_queue,
+                            |bucket, hash, position| {
+                                if last_bucket.as_ref().map(|(index, _)| *index) != Some(bucket) {
+                                    last_bucket = Some((bucket, self.get_bucket(bucket)));
+                                }
+                                let kmers = &last_bucket.as_ref().unwrap().1;
+                                if let Some(subset) = kmers.get(
+                                    &sequence[position..position + self.k],
+                                    hash,
+                                    self.forward_only,
+                                ) {
+                                    matches.matched_kmers += 1;
+                                    *subsets_counts.entry(subset).or_insert(0) += 1;
+                                }
+                            },
+                        );
+                    }
+
+                    if let Some(color_subsets) = &self.color_subsets {
+                        let mut colors_counts: HashMap<ColorIndexType, u64> = HashMap::new();
+                        for (subset, count) in subsets_counts {
+                            color_subsets.get(subset, colors_buffer)?;
+                            for color in colors_buffer.iter() {
+                                *colors_counts.entry(*color).or_insert(0) += count;
+                            }
+                        }
+
+                        matches.color_matches = colors_counts.into_iter().collect();
+                        matches.color_matches.sort_unstable();
+                    }
+
+                    Ok(matches)
+                },
+            )
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::querier_minimizer_bucketing::{
+        FileType, QuerierMinimizerBucketingExecutorFactory, QuerierMinimizerBucketingGlobalData,
+        ReadTypeBuffered,
+    };
+    use io::sequences_reader::DnaSequencesFileType;
+    use io::sequences_stream::SequenceInfo;
+    use minimizer_bucketing::{
+        MinimizerBucketingCommonData, MinimizerBucketingExecutor, MinimizerBucketingExecutorFactory,
+    };
+
+    const K: usize = 15;
+    const M: usize = 7;
+
+    fn random_sequence(mut state: u64, length: usize) -> Vec<u8> {
+        (0..length)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                b"ACGT"[(state % 4) as usize]
+            })
+            .collect()
+    }
+
+    fn compress(sequence: &[u8]) -> Vec<u8> {
+        sequence
+            .iter()
+            .map(|base| Utils::compress_base(*base))
+            .collect()
+    }
+
+    fn reverse_complement(sequence: &[u8]) -> Vec<u8> {
+        sequence
+            .iter()
+            .rev()
+            .map(|base| match base {
+                b'A' => b'T',
+                b'C' => b'G',
+                b'G' => b'C',
+                _ => b'A',
+            })
+            .collect()
+    }
+
+    /// Builds the kmers of a bucket with the given sequences, each one with its own color subset
+    fn bucket_kmers(sequences: &[(&[u8], ColorIndexType)], forward_only: bool) -> BucketKmers {
+        hashes::cn_rkhash::u64::CanonicalRabinKarpHashFactory::initialize(K);
+        hashes::fw_rkhash::u64::ForwardRabinKarpHashFactory::initialize(K);
+
+        let mut bucket = BucketKmers::default();
+        let mut storage = vec![];
+        for (sequence, subset) in sequences {
+            storage.clear();
+            let read = CompressedReadIndipendent::from_plain(sequence, &mut storage);
+            bucket.add_sequence(
+                &read.as_reference(&storage),
+                std::iter::repeat(*subset),
+                K,
+                forward_only,
+            );
+        }
+        bucket.finalize(K, forward_only);
+        bucket
+    }
+
+    fn lookup(bucket: &BucketKmers, kmer: &[u8], forward_only: bool) -> Option<ColorIndexType> {
+        let kmer = compress(kmer);
+        let hash = kmers_hashes(kmer.as_slice(), K, forward_only)[0];
+        bucket.get(&kmer, hash, forward_only)
+    }
+
+    #[test]
+    fn bucket_kmers_lookup() {
+        let first = random_sequence(0x2545f4914f6cdd1d, 60);
+        let second = random_sequence(0x9e3779b97f4a7c15, 40);
+        let missing = random_sequence(0x123456789abcdef, 30);
+
+        let bucket = bucket_kmers(&[(&first, 1), (&second, 2)], false);
+        for (sequence, subset) in [(&first, 1), (&second, 2)] {
+            for kmer in sequence.windows(K) {
+                assert_eq!(lookup(&bucket, kmer, false), Some(subset));
+                assert_eq!(
+                    lookup(&bucket, &reverse_complement(kmer), false),
+                    Some(subset)
+                );
+            }
+        }
+        for kmer in missing.windows(K) {
+            assert_eq!(lookup(&bucket, kmer, false), None);
+        }
+
+        let bucket = bucket_kmers(&[(&first, 1)], true);
+        for kmer in first.windows(K) {
+            assert_eq!(lookup(&bucket, kmer, true), Some(1));
+            assert_eq!(lookup(&bucket, &reverse_complement(kmer), true), None);
+        }
+    }
+
+    #[test]
+    fn duplicated_kmers_are_kept_once() {
+        let sequence = random_sequence(0x2545f4914f6cdd1d, 50);
+        let bucket = bucket_kmers(
+            &[
+                (&sequence, 1),
+                (&sequence, 2),
+                (&reverse_complement(&sequence), 3),
+            ],
+            false,
+        );
+
+        assert_eq!(bucket.kmers.len(), sequence.len() - K + 1);
+        for kmer in sequence.windows(K) {
+            assert_eq!(lookup(&bucket, kmer, false), Some(1));
+        }
+    }
+
+    #[test]
+    fn sharded_cache_is_bounded() {
+        let cache = ShardedCache::<usize>::new(2 * CACHE_SHARDS_COUNT);
+        let mut decoded = 0;
+
+        for key in 0..100 {
+            let value = cache.get_or_decode(key, || {
+                decoded += 1;
+                Arc::new(key)
+            });
+            assert_eq!(*value, key);
+        }
+        assert_eq!(decoded, 100);
+        assert_eq!(cache.len(), 2 * CACHE_SHARDS_COUNT);
+
+        // The most recent keys are cached, the oldest ones are decoded again
+        cache.get_or_decode(99, || unreachable!());
+        cache.get_or_decode(0, || {
+            decoded += 1;
+            Arc::new(0)
+        });
+        assert_eq!(decoded, 101);
+    }
+
+    #[test]
+    fn kmers_buckets_match_the_index_bucketing() {
+        const BUCKETS_COUNT_LOG: usize = 4;
+
+        MNHFactory::initialize(K);
+        hashes::cn_rkhash::u64::CanonicalRabinKarpHashFactory::initialize(K);
+
+        let sequence = random_sequence(0x2545f4914f6cdd1d, 300);
+
+        // Buckets of the kmers assigned by the minimizer bucketing
+        let mut executor = QuerierMinimizerBucketingExecutorFactory::<NonColoredManager>::new(
+            &Arc::new(MinimizerBucketingCommonData::new(
+                K,
+                M,
+                1 << BUCKETS_COUNT_LOG,
+                0,
+                1,
+                QuerierMinimizerBucketingGlobalData {
+                    queries: Default::default(),
+                    track_unitigs: false,
+                },
+            )),
+        );
+        let mut preprocess_info = ReadTypeBuffered::<NonColoredManager>::default();
+        executor.preprocess_dna_sequence(
+            &FileType::Graph,
+            SequenceInfo { color: None },
+            0,
+            &DnaSequence {
+                ident_data: b"0",
+                seq: &sequence,
+                format: DnaSequencesFileType::FASTA,
+            },
+            &mut preprocess_info,
+        );
+
+        let mut expected_buckets = vec![];
+        executor.process_sequence(
+            &preprocess_info,
+            sequence.as_slice(),
+            0..sequence.len(),
+            0,
+            BUCKETS_COUNT_LOG,
+            0,
+            |bucket, _, part: &[u8], _, _, _| {
+                expected_buckets.extend(std::iter::repeat_n(bucket as usize, part.len() - K + 1));
+            },
+        );
+
+        let mut buckets = vec![];
+        for_each_kmer(
+            &compress(&sequence),
+            K,
+            M,
+            BUCKETS_COUNT_LOG,
+            false,
+            &mut RollingMinQueue::new(K - M + 1),
+            |bucket, _, position| {
+                assert_eq!(position, buckets.len());
+                buckets.push(bucket);
+            },
+        );
+
+        assert_eq!(buckets, expected_buckets);
+        assert!(buckets.iter().any(|bucket| *bucket != buckets[0]));
+    }
+}
@@ -0,0 +1,500 @@
+use crate::pipeline::querier_minimizer_bucketing::minimizer_bucketing;
+use ::dynamic_dispatch::dynamic_dispatch;
+use anyhow::Context;
+use colors::colors_manager::ColorsManager;
+use config::PREFER_MEMORY;
+use hashes::default::MNHFactory;
+use hashes::HashFunctionFactory;
+use io::compute_stats_from_input_blocks;
+use io::sequences_stream::general::GeneralSequenceBlockData;
+use minimizer_bucketing::counters_analyzer::CountersAnalyzer;
+use parallel_processor::buckets::{MultiChunkBucket, SingleBucket};
+use parallel_processor::memory_fs::MemoryFs;
+use parallel_processor::phase_times_monitor::PHASES_TIMES_MONITOR;
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::time::UNIX_EPOCH;
+
+pub const QUERY_INDEX_MANIFEST_NAME: &str = "query-index.manifest";
+const QUERY_INDEX_MANIFEST_HEADER: &str = "ggcat-query-index 2";
+const QUERY_INDEX_COUNTERS_NAME: &str = "buckets-counters.dat";
+
+/// Minimizer buckets of the graph kmers, with their color subsets and unitig indexes,
+/// saved once to be joined with the buckets of each query
+pub struct QueryIndex {
+    index_dir: PathBuf,
+    pub k: usize,
+    pub m: usize,
+    pub buckets_count_log: usize,
+    pub colors: bool,
+    pub forward_only: bool,
+    /// Name of the hash function used to merge the kmers
+    pub hash_type: String,
+    /// Size and modification time of the indexed graph file, used to detect an index built from a different graph
+    pub graph_signature: GraphFileSignature,
+    buckets: Vec<(usize, PathBuf)>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct GraphFileSignature {
+    pub size: u64,
+    /// Modification time in nanoseconds since the unix epoch
+    pub mtime: u64,
+}
+
+impl GraphFileSignature {
+    pub fn read(graph_input: &Path) -> anyhow::Result<Self> {
+        let metadata = std::fs::metadata(graph_input)
+            .with_context(|| format!("Cannot find graph file {}", graph_input.display()))?;
+        let mtime = metadata
+            .modified()
+            .with_context(|| {
+                format!(
+                    "Cannot read the modification time of graph file {}",
+                    graph_input.display()
+                )
+            })?
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or(0);
+        Ok(Self {
+            size: metadata.len(),
+            mtime,
+        })
+    }
+}
+
+impl QueryIndex {
+    fn manifest_path(index_dir: &Path) -> PathBuf {
+        index_dir.join(QUERY_INDEX_MANIFEST_NAME)
+    }
+
+    pub fn load(index_dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let index_dir = index_dir.as_ref();
+        let path = Self::manifest_path(index_dir);
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Cannot read query index manifest {}", path.display()))?;
+
+        let mut lines = content.lines();
+        match lines.next() {
+            Some(QUERY_INDEX_MANIFEST_HEADER) => {}
+            Some(header) if header.starts_with("ggcat-query-index ") => {
+                anyhow::bail!(
+                    "The query index {} was built by a different version of ggcat, build it again",
+                    index_dir.display()
+                );
+            }
+            _ => anyhow::bail!("Invalid query index manifest {}", path.display()),
+        }
+
+        let mut params = vec![];
+        let mut buckets = vec![];
+
+        for line in lines {
+            let mut columns = line.split('\t');
+            let invalid_line = || anyhow::anyhow!("Invalid query index manifest line: '{}'", line);
+
+            match columns.next() {
+                Some("param") => {
+                    let name = columns.next().ok_or_else(invalid_line)?;
+                    let value = columns.next().ok_or_else(invalid_line)?;
+                    params.push((name, value));
+                }
+                Some("bucket") => {
+                    let index = columns
+                        .next()
+                        .and_then(|index| index.parse().ok())
+                        .ok_or_else(invalid_line)?;
+                    let file_name = columns.next().ok_or_else(invalid_line)?;
+                    buckets.push((index, index_dir.join(file_name)));
+                }
+                Some("") | None => {}
+                Some(_) => return Err(invalid_line()),
+            }
+        }
+
+        let get_string_param = |name: &str| {
+            params
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, value)| *value)
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Parameter '{}' is missing from the query index manifest {}",
+                        name,
+                        path.display()
+                    )
+                })
+        };
+        let get_param = |name: &str| -> anyhow::Result<u64> {
+            let value = get_string_param(name)?;
+            value.parse().map_err(|_| {
+                anyhow::anyhow!(
+                    "Invalid value '{}' of parameter '{}' in the query index manifest {}",
+                    value,
+                    name,
+                    path.display()
+                )
+            })
+        };
+
+        let index = Self {
+            index_dir: index_dir.to_path_buf(),
+            k: get_param("k")? as usize,
+            m: get_param("m")? as usize,
+            buckets_count_log: get_param("buckets_count_log")? as usize,
+            colors: get_param("colors")? != 0,
+            forward_only: get_param("forward_only")? != 0,
+            hash_type: get_string_param("hash_type")?.to_string(),
+            graph_signature: GraphFileSignature {
+                size: get_param("graph_size")?,
+                mtime: get_param("graph_mtime")?,
+            },
+            buckets,
+        };
+
+        if index.buckets.len() != 1 << index.buckets_count_log {
+            anyhow::bail!("Missing buckets in the query index {}", index_dir.display());
+        }
+
+        for path in index
+            .buckets
+            .iter()
+            .map(|(_, path)| path)
+            .chain(std::iter::once(&index.counters_path()))
+        {
+            if !path.exists() {
+                anyhow::bail!("The query index file {} is missing", path.display());
+            }
+        }
+
+        Ok(index)
+    }
+
+    /// Atomically writes the manifest, making the index usable
+    fn save(&self) -> anyhow::Result<()> {
+        let mut content = String::new();
+        writeln!(content, "{}", QUERY_INDEX_MANIFEST_HEADER)?;
+        for (name, value) in [
+            ("k", self.k.to_string()),
+            ("m", self.m.to_string()),
+            ("buckets_count_log", self.buckets_count_log.to_string()),
+            ("colors", (self.colors as u8).to_string()),
+            ("forward_only", (self.forward_only as u8).to_string()),
+            ("hash_type", self.hash_type.clone()),
+            ("graph_size", self.graph_signature.size.to_string()),
+            ("graph_mtime", self.graph_signature.mtime.to_string()),
+        ] {
+            writeln!(content, "param\t{}\t{}", name, value)?;
+        }
+        for (index, path) in &self.buckets {
+            writeln!(
+                content,
+                "bucket\t{}\t{}",
+                index,
+                path.file_name().unwrap().to_string_lossy()
+            )?;
+        }
+
+        let path = Self::manifest_path(&self.index_dir);
+        let temp_path = path.with_extension("manifest.tmp");
+        std::fs::write(&temp_path, content)
+            .with_context(|| format!("Cannot write query index manifest {}", path.display()))?;
+        std::fs::rename(&temp_path, &path)
+            .with_context(|| format!("Cannot write query index manifest {}", path.display()))?;
+        Ok(())
+    }
+
+    fn counters_path(&self) -> PathBuf {
+        self.index_dir.join(QUERY_INDEX_COUNTERS_NAME)
+    }
+
+    /// Checks that the index was built from the given graph with the same query parameters
+    pub fn check_params(
+        &self,
+        graph_input: &Path,
+        k: usize,
+        m: usize,
+        hash_type: &str,
+        forward_only: bool,
+        colors: bool,
+    ) -> anyhow::Result<()> {
+        for (name, value, saved) in [
+            ("k", k.to_string(), self.k.to_string()),
+            ("m", m.to_string(), self.m.to_string()),
+            ("hash_type", hash_type.to_string(), self.hash_type.clone()),
+            (
+                "forward_only",
+                forward_only.to_string(),
+                self.forward_only.to_string(),
+            ),
+            ("colors", colors.to_string(), self.colors.to_string()),
+        ] {
+            if value != saved {
+                anyhow::bail!(
+                    "Cannot use the query index {}, parameter '{}' differs: {} (index: {})",
+                    self.index_dir.display(),
+                    name,
+                    value,
+                    saved
+                );
+            }
+        }
+
+        if GraphFileSignature::read(graph_input)? != self.graph_signature {
+            anyhow::bail!(
+                "Cannot use the query index {}, it was built from a different or modified graph than {}",
+                self.index_dir.display(),
+                graph_input.display()
+            );
+        }
+        Ok(())
+    }
+
+    /// Bucket files of the index, to be read by the kmers transform without removing them
+    pub(crate) fn bucket_paths(&self) -> HashSet<PathBuf> {
+        self.buckets.iter().map(|(_, path)| path.clone()).collect()
+    }
+
+    /// Bucket files of the index, ordered by bucket index
+    pub(crate) fn ordered_bucket_paths(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let mut buckets = self.buckets.clone();
+        buckets.sort_unstable();
+        for (position, (index, path)) in buckets.iter().enumerate() {
+            if *index != position {
+                anyhow::bail!("Invalid query index bucket {}", path.display());
+            }
+        }
+        Ok(buckets.into_iter().map(|(_, path)| path).collect())
+    }
+
+    /// Joins the index buckets with the query buckets, merging their sub-buckets counters
+    pub(crate) fn join_query_buckets(
+        &self,
+        query_buckets: Vec<SingleBucket>,
+        query_counters: PathBuf,
+    ) -> anyhow::Result<(Vec<MultiChunkBucket>, PathBuf)> {
+        let mut joined_buckets = Vec::with_capacity(query_buckets.len());
+
+        for query_bucket in query_buckets {
+            let (_, index_path) = self
+                .buckets
+                .iter()
+                .find(|(index, _)| *index == query_bucket.index)
+                .ok_or_else(|| {
+                    anyhow::anyhow!("Missing query index bucket {}", query_bucket.index)
+                })?;
+
+            joined_buckets.push(MultiChunkBucket {
+                index: query_bucket.index,
+                chunks: vec![index_path.clone(), query_bucket.path],
+                was_compacted: false,
+            });
+        }
+
+        let counters = CountersAnalyzer::load_from_file(&query_counters, true).merge(
+            &CountersAnalyzer::load_from_file(self.counters_path(), false),
+        );
+        counters.serialize_to_file(&query_counters);
+
+        Ok((joined_buckets, query_counters))
+    }
+}
+
+/// Buckets the graph kmers once, saving them in a query index that avoids processing the graph on each query
+#[dynamic_dispatch(MergingHash = [
+    #[cfg(not(feature = "devel-build"))] hashes::fw_seqhash::u16::ForwardSeqHashFactory,
+    #[cfg(not(feature = "devel-build"))] hashes::fw_seqhash::u32::ForwardSeqHashFactory,
+    #[cfg(not(feature = "devel-build"))] hashes::fw_seqhash::u64::ForwardSeqHashFactory,
+    #[cfg(not(feature = "devel-build"))] hashes::fw_seqhash::u128::ForwardSeqHashFactory,
+    #[cfg(not(feature = "devel-build"))] hashes::fw_rkhash::u32::ForwardRabinKarpHashFactory,
+    #[cfg(not(feature = "devel-build"))] hashes::fw_rkhash::u64::ForwardRabinKarpHashFactory,
+    #[cfg(not(feature = "devel-build"))] hashes::fw_rkhash::u128::ForwardRabinKarpHashFactory,
+    #[cfg(not(feature = "devel-build"))] hashes::cn_seqhash::u16::CanonicalSeqHashFactory,
+    #[cfg(not(feature = "devel-build"))] hashes::cn_seqhash::u32::CanonicalSeqHashFactory,
+    hashes::cn_seqhash::u64::CanonicalSeqHashFactory,
+    #[cfg(not(feature = "devel-build"))] hashes::cn_seqhash::u128::CanonicalSeqHashFactory,
+    #[cfg(not(feature = "devel-build"))] hashes::cn_rkhash::u32::CanonicalRabinKarpHashFactory,
+    #[cfg(not(feature = "devel-build"))] hashes::cn_rkhash::u64::CanonicalRabinKarpHashFactory,
+    #[cfg(not(feature = "devel-build"))] hashes::cn_rkhash::u128::CanonicalRabinKarpHashFactory,
+], QuerierColorsManager = [
+    #[cfg(not(feature = "devel-build"))] colors::bundles::graph_querying::ColorBundleGraphQuerying,
+    colors::non_colored::NonColoredManager,
+])]
+pub fn build_query_index<MergingHash: HashFunctionFactory, QuerierColorsManager: ColorsManager>(
+    k: usize,
+    m: usize,
+    forward_only: bool,
+    graph_input: PathBuf,
+    index_dir: PathBuf,
+    buckets_count_log: Option<usize>,
+    threads_count: usize,
+) -> anyhow::Result<PathBuf> {
+    PHASES_TIMES_MONITOR.write().init();
+
+    MNHFactory::initialize(k);
+
+    let graph_signature = GraphFileSignature::read(&graph_input)?;
+
+    std::fs::create_dir_all(&index_dir).with_context(|| {
+        format!(
+            "Cannot create query index directory {}",
+            index_dir.display()
+        )
+    })?;
+    // Invalidate any previous index until the new one is complete
+    let _ = std::fs::remove_file(QueryIndex::manifest_path(&index_dir));
+
+    let buckets_count_log = match buckets_count_log {
+        Some(buckets_count_log) => buckets_count_log,
+        None => {
+            compute_stats_from_input_blocks(&[GeneralSequenceBlockData::FASTA((
+                graph_input.clone(),
+                None,
+            ))])?
+            .best_buckets_count_log
+        }
+    };
+
+    // The index buckets must be written to disk to persist after the process exits
+    let prefer_memory = PREFER_MEMORY.swap(false, Ordering::Relaxed);
+
+    let ((buckets, counters), _) = minimizer_bucketing::<QuerierColorsManager>(
+        Some(graph_input),
+        vec![],
+        &index_dir,
+        1 << buckets_count_log,
+        threads_count,
+        k,
+        m,
+        true,
+    );
+    MemoryFs::flush_all_to_disk();
+
+    PREFER_MEMORY.store(prefer_memory, Ordering::Relaxed);
+
+    let index = QueryIndex {
+        index_dir: index_dir.clone(),
+        k,
+        m,
+        buckets_count_log,
+        colors: QuerierColorsManager::COLORS_ENABLED,
+        forward_only,
+        hash_type: MergingHash::NAME.to_string(),
+        graph_signature,
+        buckets: buckets
+            .into_iter()
+            .map(|bucket| (bucket.index, bucket.path))
+            .collect(),
+    };
+
+    debug_assert_eq!(counters, index.counters_path());
+    index.save()?;
+
+    PHASES_TIMES_MONITOR
+        .write()
+        .print_stats("Query index completed.".to_string());
+
+    Ok(index_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a graph and an index manifest with two empty buckets, as built with k=31, m=11
+    fn write_index(name: &str) -> (PathBuf, QueryIndex) {
+        let dir =
+            std::env::temp_dir().join(format!("ggcat-query-index-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let index_dir = dir.join("index");
+        std::fs::create_dir_all(&index_dir).unwrap();
+
+        let graph = dir.join("graph.fa");
+        std::fs::write(&graph, ">0\nACGTACGTACGTACGTACGTACGTACGTACGTACGT\n").unwrap();
+
+        let mut buckets = vec![];
+        for index in 0..2 {
+            let path = index_dir.join(format!("bucket.{}", index));
+            std::fs::write(&path, []).unwrap();
+            buckets.push((index, path));
+        }
+        std::fs::write(index_dir.join(QUERY_INDEX_COUNTERS_NAME), []).unwrap();
+
+        let index = QueryIndex {
+            index_dir,
+            k: 31,
+            m: 11,
+            buckets_count_log: 1,
+            colors: true,
+            forward_only: false,
+            hash_type: "hashes::cn_seqhash::u64".to_string(),
+            graph_signature: GraphFileSignature::read(&graph).unwrap(),
+            buckets,
+        };
+        index.save().unwrap();
+        (graph, index)
+    }
+
+    #[test]
+    fn manifest_round_trip() {
+        let (graph, index) = write_index("round-trip");
+        let loaded = QueryIndex::load(&index.index_dir).unwrap();
+
+        assert_eq!(loaded.k, 31);
+        assert_eq!(loaded.m, 11);
+        assert_eq!(loaded.buckets_count_log, 1);
+        assert!(loaded.colors);
+        assert!(!loaded.forward_only);
+        assert_eq!(loaded.hash_type, index.hash_type);
+        assert_eq!(loaded.graph_signature, index.graph_signature);
+        assert_eq!(loaded.bucket_paths(), index.bucket_paths());
+
+        loaded
+            .check_params(&graph, 31, 11, &index.hash_type, false, true)
+            .unwrap();
+    }
+
+    #[test]
+    fn different_params_are_rejected() {
+        let (graph, index) = write_index("params");
+
+        assert!(index
+            .check_params(&graph, 27, 11, &index.hash_type, false, true)
+            .is_err());
+        assert!(index
+            .check_params(&graph, 31, 11, "hashes::cn_rkhash::u64", false, true)
+            .is_err());
+        assert!(index
+            .check_params(&graph, 31, 11, &index.hash_type, true, true)
+            .is_err());
+        assert!(index
+            .check_params(&graph, 31, 11, &index.hash_type, false, false)
+            .is_err());
+    }
+
+    #[test]
+    fn modified_graph_is_rejected() {
+        let (graph, index) = write_index("modified");
+
+        // Same size, different content and modification time
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        std::fs::write(&graph, ">0\nTTTTACGTACGTACGTACGTACGTACGTACGTACGT\n").unwrap();
+        assert!(index
+            .check_params(&graph, 31, 11, &index.hash_type, false, true)
+            .is_err());
+    }
+
+    #[test]
+    fn incomplete_index_is_rejected() {
+        let (_, index) = write_index("incomplete");
+
+        std::fs::remove_file(&index.buckets[1].1).unwrap();
+        assert!(QueryIndex::load(&index.index_dir).is_err());
+
+        std::fs::remove_file(QueryIndex::manifest_path(&index.index_dir)).unwrap();
+        assert!(QueryIndex::load(&index.index_dir).is_err());
+    }
+}
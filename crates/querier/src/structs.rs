@@ -1 +1,2 @@
 pub mod query_colored_counters;
+pub mod unitig_matches;
@@ -26,6 +26,8 @@ impl ColorsRange {
 #[derive(Debug, Clone)]
 pub struct QueryColorDesc {
     pub query_index: u64,
+    /// Position of the first matched kmer in the query, used only for the kmer positions output
+    pub position: u64,
     pub count: u64,
 }
 
@@ -34,13 +36,14 @@ pub struct QueryColoredCounters<'a> {
     pub colors: &'a [ColorIndexType],
 }
 
+/// The extra data buffer tells if the positions are serialized, as they are needed only by the kmer positions output
 pub struct QueryColoredCountersSerializer;
 
 impl BucketItemSerializer for QueryColoredCountersSerializer {
     type InputElementType<'a> = QueryColoredCounters<'a>;
     type ExtraData = ();
     type ReadBuffer = (Vec<QueryColorDesc>, Vec<ColorIndexType>);
-    type ExtraDataBuffer = ();
+    type ExtraDataBuffer = bool;
     type ReadType<'b> = QueryColoredCounters<'b>;
 
     type CheckpointData = ();
@@ -56,7 +59,7 @@ impl BucketItemSerializer for QueryColoredCountersSerializer {
         element: &QueryColoredCounters<'_>,
         bucket: &mut Vec<u8>,
         _extra_data: &Self::ExtraData,
-        _extra_read_buffer: &Self::ExtraDataBuffer,
+        with_positions: &Self::ExtraDataBuffer,
     ) {
         encode_varint(
             |b| bucket.extend_from_slice(b),
@@ -64,6 +67,9 @@ impl BucketItemSerializer for QueryColoredCountersSerializer {
         );
         for query in element.queries.iter() {
             encode_varint(|b| bucket.extend_from_slice(b), query.query_index);
+            if *with_positions {
+                encode_varint(|b| bucket.extend_from_slice(b), query.position);
+            }
             encode_varint(|b| bucket.extend_from_slice(b), query.count);
         }
 
@@ -75,7 +81,7 @@ impl BucketItemSerializer for QueryColoredCountersSerializer {
         &mut self,
         mut stream: S,
         read_buffer: &'b mut Self::ReadBuffer,
-        _extra_read_buffer: &mut Self::ExtraDataBuffer,
+        with_positions: &mut Self::ExtraDataBuffer,
     ) -> Option<Self::ReadType<'b>> {
         read_buffer.0.clear();
         read_buffer.1.clear();
@@ -83,8 +89,17 @@ impl BucketItemSerializer for QueryColoredCountersSerializer {
         let queries_count = decode_varint(|| stream.read_u8().ok())?;
         for _ in 0..queries_count {
             let query_index = decode_varint(|| stream.read_u8().ok())?;
+            let position = if *with_positions {
+                decode_varint(|| stream.read_u8().ok())?
+            } else {
+                0
+            };
             let count = decode_varint(|| stream.read_u8().ok())?;
-            read_buffer.0.push(QueryColorDesc { query_index, count });
+            read_buffer.0.push(QueryColorDesc {
+                query_index,
+                position,
+                count,
+            });
         }
 
         ColorIndexSerializer::deserialize_colors(stream, &mut read_buffer.1)?;
@@ -98,3 +113,69 @@ impl BucketItemSerializer for QueryColoredCountersSerializer {
         (element.colors.len() + element.queries.len() + 1) * VARINT_MAX_SIZE * 4
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn serialize(with_positions: bool) -> Vec<u8> {
+        let mut serializer = QueryColoredCountersSerializer::new();
+        let mut buffer = vec![];
+        serializer.write_to(
+            &QueryColoredCounters {
+                queries: &[
+                    QueryColorDesc {
+                        query_index: 1,
+                        position: 300,
+                        count: 5,
+                    },
+                    QueryColorDesc {
+                        query_index: 7,
+                        position: 12,
+                        count: 2,
+                    },
+                ],
+                colors: &[0, 3, 8, 9],
+            },
+            &mut buffer,
+            &(),
+            &with_positions,
+        );
+        buffer
+    }
+
+    fn deserialize(buffer: &[u8], mut with_positions: bool) -> (Vec<(u64, u64, u64)>, Vec<u32>) {
+        let mut serializer = QueryColoredCountersSerializer::new();
+        let mut read_buffer = (vec![], vec![]);
+        let counters = serializer
+            .read_from(buffer, &mut read_buffer, &mut with_positions)
+            .unwrap();
+        (
+            counters
+                .queries
+                .iter()
+                .map(|q| (q.query_index, q.position, q.count))
+                .collect(),
+            counters.colors.to_vec(),
+        )
+    }
+
+    #[test]
+    fn counters_with_positions() {
+        let buffer = serialize(true);
+        assert_eq!(
+            deserialize(&buffer, true),
+            (vec![(1, 300, 5), (7, 12, 2)], vec![0, 3, 8, 9])
+        );
+    }
+
+    #[test]
+    fn counters_without_positions() {
+        let buffer = serialize(false);
+        assert!(buffer.len() < serialize(true).len());
+        assert_eq!(
+            deserialize(&buffer, false),
+            (vec![(1, 0, 5), (7, 0, 2)], vec![0, 3, 8, 9])
+        );
+    }
+}
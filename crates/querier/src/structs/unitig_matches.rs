@@ -0,0 +1,74 @@
+use byteorder::ReadBytesExt;
+use io::varint::{decode_varint, encode_varint, VARINT_MAX_SIZE};
+use parallel_processor::buckets::bucket_writer::BucketItemSerializer;
+use std::io::Read;
+
+/// Segment of consecutive query kmers matching consecutive kmers of the same graph unitig
+#[derive(Debug, Clone)]
+pub struct UnitigMatch {
+    pub query_index: u64,
+    /// Position of the first matched kmer in the query
+    pub position: u64,
+    pub length: u64,
+    /// Index of the unitig in the graph file
+    pub unitig: u64,
+    /// True if the query kmers have the same orientation of the unitig kmers
+    pub forward: bool,
+}
+
+pub struct UnitigMatchSerializer;
+
+impl BucketItemSerializer for UnitigMatchSerializer {
+    type InputElementType<'a> = UnitigMatch;
+    type ExtraData = ();
+    type ReadBuffer = ();
+    type ExtraDataBuffer = ();
+    type ReadType<'b> = UnitigMatch;
+
+    type CheckpointData = ();
+
+    fn new() -> Self {
+        Self
+    }
+
+    fn reset(&mut self) {}
+
+    fn write_to(
+        &mut self,
+        element: &UnitigMatch,
+        bucket: &mut Vec<u8>,
+        _extra_data: &Self::ExtraData,
+        _extra_read_buffer: &Self::ExtraDataBuffer,
+    ) {
+        encode_varint(|b| bucket.extend_from_slice(b), element.query_index);
+        encode_varint(|b| bucket.extend_from_slice(b), element.position);
+        encode_varint(|b| bucket.extend_from_slice(b), element.length);
+        encode_varint(
+            |b| bucket.extend_from_slice(b),
+            (element.unitig << 1) | (element.forward as u64),
+        );
+    }
+
+    fn read_from<'b, S: Read>(
+        &mut self,
+        mut stream: S,
+        _read_buffer: &'b mut Self::ReadBuffer,
+        _extra_read_buffer: &mut Self::ExtraDataBuffer,
+    ) -> Option<Self::ReadType<'b>> {
+        let query_index = decode_varint(|| stream.read_u8().ok())?;
+        let position = decode_varint(|| stream.read_u8().ok())?;
+        let length = decode_varint(|| stream.read_u8().ok())?;
+        let unitig = decode_varint(|| stream.read_u8().ok())?;
+        Some(UnitigMatch {
+            query_index,
+            position,
+            length,
+            unitig: unitig >> 1,
+            forward: (unitig & 1) != 0,
+        })
+    }
+
+    fn get_size(&self, _element: &Self::InputElementType<'_>, _extra: &Self::ExtraData) -> usize {
+        VARINT_MAX_SIZE * 4
+    }
+}
@@ -1,3 +1,4 @@
 pub mod map_entry;
 #[cfg(feature = "support_kmer_counters")]
 pub mod unitigs_counters;
+pub mod unitigs_graph;
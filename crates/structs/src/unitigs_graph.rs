@@ -0,0 +1,193 @@
+pub const BEGIN: usize = 0;
+pub const END: usize = 1;
+
+/// A unitig side, identified by the unitig index and by its side (BEGIN or END)
+pub type UnitigSide = (usize, usize);
+
+/// Topology of a graph of unitigs, used to recompact it after removing some of its kmers or unitigs
+pub trait UnitigsGraph {
+    fn unitigs_count(&self) -> usize;
+
+    fn is_removed(&self, unitig: usize) -> bool;
+
+    /// Returns the sorted (not removed) unitig sides linked to the given side of a unitig, without duplicates
+    fn links(&self, unitig: usize, side: usize) -> Vec<UnitigSide>;
+
+    fn is_self_complemental(&self, unitig: usize) -> bool;
+
+    /// Returns the only side linked to the given unitig side, if the connection can be compacted
+    fn compactable_link(&self, unitig: usize, side: usize) -> Option<UnitigSide> {
+        match self.links(unitig, side).as_slice() {
+            &[(other, other_side)] => {
+                if other == unitig
+                    || self.is_self_complemental(unitig)
+                    || self.is_self_complemental(other)
+                    || self.links(other, other_side).len() != 1
+                {
+                    None
+                } else {
+                    Some((other, other_side))
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Calls the output function for each maximal chain of compactable unitigs, with the (unitig, forward)
+/// pairs of the chain in the sequence order. Each unitig that was not removed is in exactly one chain,
+/// consecutive unitigs of a chain overlap by k - 1 bases
+pub fn compact_unitigs_chains(
+    graph: &impl UnitigsGraph,
+    mut output_function: impl FnMut(&[(usize, bool)]),
+) {
+    let mut chain = vec![];
+    let mut visited = vec![false; graph.unitigs_count()];
+
+    for unitig in 0..graph.unitigs_count() {
+        if graph.is_removed(unitig) || visited[unitig] {
+            continue;
+        }
+
+        // Find the start of the chain containing the current unitig
+        let (mut current, mut forward) = (unitig, true);
+        while let Some((other, other_side)) =
+            graph.compactable_link(current, if forward { BEGIN } else { END })
+        {
+            if other == unitig {
+                // Circular chain
+                break;
+            }
+            current = other;
+            forward = other_side == END;
+        }
+
+        chain.clear();
+        chain.push((current, forward));
+        visited[current] = true;
+
+        while let Some((other, other_side)) =
+            graph.compactable_link(current, if forward { END } else { BEGIN })
+        {
+            if visited[other] {
+                break;
+            }
+            current = other;
+            forward = other_side == BEGIN;
+            chain.push((current, forward));
+            visited[current] = true;
+        }
+
+        output_function(&chain);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct TestGraph {
+        unitigs_count: usize,
+        removed: Vec<usize>,
+        self_complemental: Vec<usize>,
+        links: HashMap<UnitigSide, Vec<UnitigSide>>,
+    }
+
+    impl TestGraph {
+        fn new(unitigs_count: usize, edges: &[(UnitigSide, UnitigSide)]) -> Self {
+            let mut graph = Self {
+                unitigs_count,
+                ..Default::default()
+            };
+            for &(first, second) in edges {
+                graph.links.entry(first).or_default().push(second);
+                graph.links.entry(second).or_default().push(first);
+            }
+            graph
+        }
+    }
+
+    impl UnitigsGraph for TestGraph {
+        fn unitigs_count(&self) -> usize {
+            self.unitigs_count
+        }
+
+        fn is_removed(&self, unitig: usize) -> bool {
+            self.removed.contains(&unitig)
+        }
+
+        fn links(&self, unitig: usize, side: usize) -> Vec<UnitigSide> {
+            let mut links: Vec<_> = self
+                .links
+                .get(&(unitig, side))
+                .into_iter()
+                .flatten()
+                .copied()
+                .filter(|(other, _)| !self.is_removed(*other))
+                .collect();
+            links.sort_unstable();
+            links.dedup();
+            links
+        }
+
+        fn is_self_complemental(&self, unitig: usize) -> bool {
+            self.self_complemental.contains(&unitig)
+        }
+    }
+
+    fn chains(graph: &TestGraph) -> Vec<Vec<(usize, bool)>> {
+        let mut chains = vec![];
+        compact_unitigs_chains(graph, |chain| chains.push(chain.to_vec()));
+        chains
+    }
+
+    #[test]
+    fn linear_chain_compaction() {
+        // 1 -> 0 -> 2(rc), the last unitig joined in reverse complement
+        let graph = TestGraph::new(3, &[((1, END), (0, BEGIN)), ((0, END), (2, END))]);
+        assert_eq!(chains(&graph), vec![vec![(1, true), (0, true), (2, false)]]);
+    }
+
+    #[test]
+    fn branching_nodes_are_not_compacted() {
+        // 0 -> 1 and 0 -> 2
+        let graph = TestGraph::new(3, &[((0, END), (1, BEGIN)), ((0, END), (2, BEGIN))]);
+        assert_eq!(
+            chains(&graph),
+            vec![vec![(0, true)], vec![(1, true)], vec![(2, true)]]
+        );
+    }
+
+    #[test]
+    fn removed_unitigs_allow_compaction() {
+        // 0 -> 1 and 0 -> 2, with 2 removed
+        let mut graph = TestGraph::new(3, &[((0, END), (1, BEGIN)), ((0, END), (2, BEGIN))]);
+        graph.removed.push(2);
+        assert_eq!(chains(&graph), vec![vec![(0, true), (1, true)]]);
+    }
+
+    #[test]
+    fn circular_chain_compaction() {
+        // 0 -> 1 -> 2 -> 0
+        let graph = TestGraph::new(
+            3,
+            &[
+                ((0, END), (1, BEGIN)),
+                ((1, END), (2, BEGIN)),
+                ((2, END), (0, BEGIN)),
+            ],
+        );
+        let chains = chains(&graph);
+        assert_eq!(chains.len(), 1);
+        assert_eq!(chains[0].len(), 3);
+    }
+
+    #[test]
+    fn self_complemental_unitigs_are_not_compacted() {
+        let mut graph = TestGraph::new(2, &[((0, END), (1, BEGIN))]);
+        graph.self_complemental.push(1);
+        assert_eq!(chains(&graph), vec![vec![(0, true)], vec![(1, true)]]);
+    }
+}